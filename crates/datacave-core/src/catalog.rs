@@ -1,5 +1,6 @@
-use crate::error::DatacaveError;
-use crate::types::Column;
+use crate::error::{sqlstate, DatacaveError};
+use crate::mvcc::Version;
+use crate::types::{Column, DataValue};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -8,6 +9,225 @@ pub struct TableSchema {
     pub name: String,
     pub columns: Vec<Column>,
     pub primary_key: Option<String>,
+    /// Name used in the table's row and sequence keys. Fixed when the table is created, so
+    /// `RENAME TO` leaves the stored rows where they are.
+    pub storage_name: String,
+    /// Bumped whenever the row layout changes. Rows are stored with the version they were
+    /// written under and projected onto `columns` when read.
+    pub version: u32,
+    /// Where each of `columns` lives in stored rows, index for index.
+    pub layout: Vec<ColumnLayout>,
+    /// Number of values in a row written at `version`, counting slots of dropped columns.
+    pub row_width: usize,
+    /// NOT NULL and DEFAULT of each of `columns`, index for index.
+    pub column_constraints: Vec<ColumnConstraints>,
+    /// UNIQUE constraints, including the primary key.
+    pub unique: Vec<UniqueConstraint>,
+    pub checks: Vec<CheckConstraint>,
+    /// Primary key columns, in key order, when rows are stored under their primary key.
+    /// Empty for tables keyed by a row id: those without a primary key and those created
+    /// before primary keys addressed rows.
+    pub key_columns: Vec<String>,
+    /// Secondary indexes created with `CREATE INDEX`.
+    pub indexes: Vec<TableIndex>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ColumnConstraints {
+    pub not_null: bool,
+    /// Constant DEFAULT, used when an INSERT leaves the column out.
+    pub default: Option<DataValue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UniqueConstraint {
+    pub name: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CheckConstraint {
+    pub name: String,
+    /// Boolean SQL expression over the row's columns. A row passes unless it is false.
+    pub expr: String,
+    /// Columns referenced by `expr`.
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TableIndex {
+    pub name: String,
+    /// Indexed columns, in index order.
+    pub columns: Vec<String>,
+    pub unique: bool,
+    /// Oldest snapshot that finds every row through the index, or `None` while existing
+    /// rows are backfilled. Writes maintain the index from its creation either way.
+    pub valid_since: Option<Version>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ColumnLayout {
+    /// Position of the column's value in stored rows. Slots are never reused, so a value
+    /// read from a slot always belongs to the same column.
+    pub slot: usize,
+    /// Schema version that added the column.
+    pub added_in: u32,
+    /// Value of the column in rows written before it was added.
+    pub backfill: DataValue,
+}
+
+impl TableSchema {
+    pub fn new(name: String, columns: Vec<Column>, primary_key: Option<String>) -> Self {
+        let layout = (0..columns.len())
+            .map(|slot| ColumnLayout {
+                slot,
+                added_in: 0,
+                backfill: DataValue::Null,
+            })
+            .collect();
+        Self {
+            storage_name: name.clone(),
+            name,
+            row_width: columns.len(),
+            column_constraints: vec![ColumnConstraints::default(); columns.len()],
+            columns,
+            primary_key,
+            version: 0,
+            layout,
+            unique: Vec::new(),
+            checks: Vec::new(),
+            key_columns: Vec::new(),
+            indexes: Vec::new(),
+        }
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    /// Appends `column`. Rows already stored read it as `backfill`; nothing is rewritten.
+    pub fn add_column(
+        &mut self,
+        column: Column,
+        constraints: ColumnConstraints,
+        backfill: DataValue,
+    ) -> Result<(), DatacaveError> {
+        if self.column_index(&column.name).is_some() {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::DUPLICATE_COLUMN,
+                format!(
+                    "column \"{}\" of relation \"{}\" already exists",
+                    column.name, self.name
+                ),
+            ));
+        }
+        self.version += 1;
+        self.layout.push(ColumnLayout {
+            slot: self.row_width,
+            added_in: self.version,
+            backfill,
+        });
+        self.row_width += 1;
+        self.columns.push(column);
+        self.column_constraints.push(constraints);
+        Ok(())
+    }
+
+    /// Removes a column, and with it the UNIQUE and CHECK constraints and the indexes that
+    /// use it. Its slot stays reserved in rows written afterwards.
+    pub fn drop_column(&mut self, name: &str) -> Result<(), DatacaveError> {
+        let idx = self.existing_column(name)?;
+        if self.key_columns.iter().any(|c| c == name) {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::FEATURE_NOT_SUPPORTED,
+                format!(
+                    "cannot drop column \"{name}\" of relation \"{}\": rows are keyed by it",
+                    self.name
+                ),
+            ));
+        }
+        self.columns.remove(idx);
+        self.layout.remove(idx);
+        self.column_constraints.remove(idx);
+        self.unique.retain(|u| !u.columns.iter().any(|c| c == name));
+        self.checks.retain(|c| !c.columns.iter().any(|c| c == name));
+        self.indexes.retain(|i| !i.columns.iter().any(|c| c == name));
+        if self.primary_key.as_deref() == Some(name) {
+            self.primary_key = None;
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    pub fn rename_column(&mut self, from: &str, to: &str) -> Result<(), DatacaveError> {
+        let idx = self.existing_column(from)?;
+        if self.column_index(to).is_some() {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::DUPLICATE_COLUMN,
+                format!("column \"{to}\" of relation \"{}\" already exists", self.name),
+            ));
+        }
+        self.columns[idx].name = to.to_string();
+        let constrained = self
+            .unique
+            .iter_mut()
+            .flat_map(|u| u.columns.iter_mut())
+            .chain(self.checks.iter_mut().flat_map(|c| c.columns.iter_mut()))
+            .chain(self.key_columns.iter_mut())
+            .chain(self.indexes.iter_mut().flat_map(|i| i.columns.iter_mut()));
+        for column in constrained.filter(|c| *c == from) {
+            *column = to.to_string();
+        }
+        if self.primary_key.as_deref() == Some(from) {
+            self.primary_key = Some(to.to_string());
+        }
+        Ok(())
+    }
+
+    /// Positions of `key_columns` in `columns`.
+    pub fn key_indices(&self) -> Vec<usize> {
+        self.key_columns
+            .iter()
+            .filter_map(|name| self.column_index(name))
+            .collect()
+    }
+
+    /// Value of column `idx` when an INSERT leaves it out.
+    pub fn default_value(&self, idx: usize) -> DataValue {
+        self.column_constraints[idx]
+            .default
+            .clone()
+            .unwrap_or(DataValue::Null)
+    }
+
+    /// Values of a row stored at `row_version`, in the order of `columns`.
+    pub fn project_row(&self, row_version: u32, stored: &[DataValue]) -> Vec<DataValue> {
+        self.layout
+            .iter()
+            .map(|layout| match stored.get(layout.slot) {
+                Some(value) if layout.added_in <= row_version => value.clone(),
+                _ => layout.backfill.clone(),
+            })
+            .collect()
+    }
+
+    /// Stored form of a row given in the order of `columns`, for writing at `version`.
+    pub fn store_row(&self, values: Vec<DataValue>) -> Vec<DataValue> {
+        let mut stored = vec![DataValue::Null; self.row_width];
+        for (layout, value) in self.layout.iter().zip(values) {
+            stored[layout.slot] = value;
+        }
+        stored
+    }
+
+    fn existing_column(&self, name: &str) -> Result<usize, DatacaveError> {
+        self.column_index(name).ok_or_else(|| {
+            DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_COLUMN,
+                format!("column \"{name}\" of relation \"{}\" does not exist", self.name),
+            )
+        })
+    }
 }
 
 #[derive(Debug, Default)]
@@ -33,11 +253,135 @@ impl Catalog {
         Ok(())
     }
 
+    /// Replaces the schema of `name`, which may be renamed to `schema.name`.
+    pub fn alter_table(&mut self, name: &str, schema: TableSchema) -> Result<(), DatacaveError> {
+        if schema.name != name && self.tables.contains_key(&schema.name) {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::DUPLICATE_TABLE,
+                format!("relation \"{}\" already exists", schema.name),
+            ));
+        }
+        self.tables
+            .remove(name)
+            .ok_or_else(|| undefined_table(name))?;
+        self.tables.insert(schema.name.clone(), schema);
+        Ok(())
+    }
+
+    /// Removes `name` and returns its last schema.
+    pub fn drop_table(&mut self, name: &str) -> Result<TableSchema, DatacaveError> {
+        self.tables.remove(name).ok_or_else(|| undefined_table(name))
+    }
+
     pub fn get_table(&self, name: &str) -> Option<&TableSchema> {
         self.tables.get(name)
     }
 
+    /// The table that has an index called `index`.
+    pub fn table_of_index(&self, index: &str) -> Option<&TableSchema> {
+        self.tables
+            .values()
+            .find(|t| t.indexes.iter().any(|i| i.name == index))
+    }
+
     pub fn list_tables(&self) -> Vec<TableSchema> {
         self.tables.values().cloned().collect()
     }
+
+    /// Storage name for a new table called `name`: the name itself, unless a renamed table
+    /// still keeps its rows under it.
+    pub fn unused_storage_name(&self, name: &str) -> String {
+        let taken = |candidate: &str| self.tables.values().any(|t| t.storage_name == candidate);
+        let mut candidate = name.to_string();
+        let mut suffix = 0;
+        while taken(&candidate) {
+            suffix += 1;
+            candidate = format!("{name}#{suffix}");
+        }
+        candidate
+    }
+}
+
+pub fn undefined_table(name: &str) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::UNDEFINED_TABLE,
+        format!("relation \"{name}\" does not exist"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CheckConstraint, ColumnConstraints, TableIndex, TableSchema, UniqueConstraint};
+    use crate::types::{Column, DataType, DataValue};
+
+    fn column(name: &str) -> Column {
+        Column {
+            name: name.into(),
+            data_type: DataType::Int,
+        }
+    }
+
+    #[test]
+    fn rows_written_before_an_alter_are_projected_onto_the_new_layout() {
+        let mut schema = TableSchema::new("t".into(), vec![column("a"), column("b")], None);
+        let v0 = schema.store_row(vec![DataValue::Int64(1), DataValue::Int64(2)]);
+
+        schema
+            .add_column(column("c"), ColumnConstraints::default(), DataValue::Int64(9))
+            .unwrap();
+        schema.drop_column("a").unwrap();
+        schema.rename_column("b", "bee").unwrap();
+        assert!(schema
+            .add_column(column("bee"), ColumnConstraints::default(), DataValue::Null)
+            .is_err());
+        assert!(schema.drop_column("a").is_err());
+
+        assert_eq!(schema.project_row(0, &v0), vec![DataValue::Int64(2), DataValue::Int64(9)]);
+        let v2 = schema.store_row(vec![DataValue::Int64(3), DataValue::Int64(4)]);
+        assert_eq!(v2, vec![DataValue::Null, DataValue::Int64(3), DataValue::Int64(4)]);
+        assert_eq!(
+            schema.project_row(schema.version, &v2),
+            vec![DataValue::Int64(3), DataValue::Int64(4)]
+        );
+
+        // A column added after a drop gets a fresh slot rather than the dropped one.
+        schema
+            .add_column(column("a"), ColumnConstraints::default(), DataValue::Null)
+            .unwrap();
+        assert_eq!(schema.layout[2].slot, 3);
+        assert_eq!(schema.project_row(0, &v0)[2], DataValue::Null);
+    }
+
+    #[test]
+    fn constraints_follow_renamed_and_dropped_columns() {
+        let mut schema = TableSchema::new("t".into(), vec![column("a"), column("b")], None);
+        schema.unique.push(UniqueConstraint {
+            name: "t_a_key".into(),
+            columns: vec!["a".into()],
+        });
+        schema.checks.push(CheckConstraint {
+            name: "t_b_check".into(),
+            expr: "b > 0".into(),
+            columns: vec!["b".into()],
+        });
+        schema.indexes.push(TableIndex {
+            name: "t_b_idx".into(),
+            columns: vec!["b".into()],
+            unique: false,
+            valid_since: Some(1),
+        });
+        schema.rename_column("a", "x").unwrap();
+        assert_eq!(schema.unique[0].columns, vec!["x".to_string()]);
+        schema.rename_column("b", "bee").unwrap();
+        assert_eq!(schema.indexes[0].columns, vec!["bee".to_string()]);
+        schema.drop_column("bee").unwrap();
+        assert!(schema.checks.is_empty());
+        assert!(schema.indexes.is_empty());
+        assert_eq!(schema.column_constraints.len(), 1);
+
+        schema.key_columns = vec!["x".into()];
+        schema.rename_column("x", "id").unwrap();
+        assert_eq!(schema.key_indices(), vec![0]);
+        assert_eq!(schema.drop_column("id").unwrap_err().code(), "0A000");
+    }
 }
@@ -12,4 +12,72 @@ pub enum DatacaveError {
     Protocol(String),
     #[error("not supported: {0}")]
     NotSupported(String),
+    /// An error reported to clients with a specific PostgreSQL SQLSTATE.
+    #[error("{message}")]
+    Sqlstate { code: &'static str, message: String },
+}
+
+impl DatacaveError {
+    pub fn sqlstate(code: &'static str, message: impl Into<String>) -> Self {
+        DatacaveError::Sqlstate {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// SQLSTATE sent in the ErrorResponse for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DatacaveError::Catalog(_) | DatacaveError::Sql(_) => sqlstate::SYNTAX_ERROR_OR_ACCESS_RULE_VIOLATION,
+            DatacaveError::Storage(_) => sqlstate::INTERNAL_ERROR,
+            DatacaveError::Protocol(_) => sqlstate::PROTOCOL_VIOLATION,
+            DatacaveError::NotSupported(_) => sqlstate::FEATURE_NOT_SUPPORTED,
+            DatacaveError::Sqlstate { code, .. } => code,
+        }
+    }
+}
+
+/// PostgreSQL SQLSTATE codes used by datacave.
+pub mod sqlstate {
+    pub const WARNING: &str = "01000";
+    pub const PROTOCOL_VIOLATION: &str = "08P01";
+    pub const FEATURE_NOT_SUPPORTED: &str = "0A000";
+    pub const STRING_DATA_RIGHT_TRUNCATION: &str = "22001";
+    pub const NUMERIC_VALUE_OUT_OF_RANGE: &str = "22003";
+    pub const INVALID_DATETIME_FORMAT: &str = "22007";
+    pub const DIVISION_BY_ZERO: &str = "22012";
+    pub const INVALID_PARAMETER_VALUE: &str = "22023";
+    pub const INVALID_TEXT_REPRESENTATION: &str = "22P02";
+    pub const BAD_COPY_FILE_FORMAT: &str = "22P04";
+    pub const NOT_NULL_VIOLATION: &str = "23502";
+    pub const UNIQUE_VIOLATION: &str = "23505";
+    pub const CHECK_VIOLATION: &str = "23514";
+    pub const INVALID_TRANSACTION_STATE: &str = "25000";
+    pub const ACTIVE_SQL_TRANSACTION: &str = "25001";
+    pub const NO_ACTIVE_SQL_TRANSACTION: &str = "25P01";
+    pub const IN_FAILED_SQL_TRANSACTION: &str = "25P02";
+    pub const INVALID_SQL_STATEMENT_NAME: &str = "26000";
+    pub const INVALID_AUTHORIZATION_SPECIFICATION: &str = "28000";
+    pub const INVALID_PASSWORD: &str = "28P01";
+    pub const DEPENDENT_OBJECTS_STILL_EXIST: &str = "2BP01";
+    pub const INVALID_CURSOR_NAME: &str = "34000";
+    pub const INVALID_SAVEPOINT_SPECIFICATION: &str = "3B001";
+    pub const SERIALIZATION_FAILURE: &str = "40001";
+    pub const DEADLOCK_DETECTED: &str = "40P01";
+    pub const SYNTAX_ERROR_OR_ACCESS_RULE_VIOLATION: &str = "42000";
+    pub const INSUFFICIENT_PRIVILEGE: &str = "42501";
+    pub const SYNTAX_ERROR: &str = "42601";
+    pub const DUPLICATE_COLUMN: &str = "42701";
+    pub const UNDEFINED_COLUMN: &str = "42703";
+    pub const UNDEFINED_OBJECT: &str = "42704";
+    pub const GROUPING_ERROR: &str = "42803";
+    pub const DATATYPE_MISMATCH: &str = "42804";
+    pub const UNDEFINED_FUNCTION: &str = "42883";
+    pub const UNDEFINED_TABLE: &str = "42P01";
+    pub const DUPLICATE_TABLE: &str = "42P07";
+    pub const INVALID_COLUMN_REFERENCE: &str = "42P10";
+    pub const INVALID_TABLE_DEFINITION: &str = "42P16";
+    pub const LOCK_NOT_AVAILABLE: &str = "55P03";
+    pub const IDLE_SESSION_TIMEOUT: &str = "57P05";
+    pub const INTERNAL_ERROR: &str = "XX000";
 }
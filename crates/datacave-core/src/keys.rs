@@ -0,0 +1,339 @@
+//! Memcomparable encoding of [`DataValue`] tuples: the byte order of encoded keys matches
+//! the SQL order of the tuples, so the storage layer can sort, seek and range-scan keys
+//! without decoding them.
+//!
+//! Each value is a type tag followed by a payload. No encoding is a prefix of another, so
+//! concatenated values compare column by column. A descending column is encoded ascending
+//! and then bit-inverted, which reverses the order of a prefix-free code.
+//!
+//! Values of different types order by type (bool, integer, float, string, bytes). Key
+//! columns are coerced to their declared type first, so this only matters for loosely
+//! typed callers.
+
+use crate::error::DatacaveError;
+use crate::types::DataValue;
+
+const TAG_NULL_LOW: u8 = 0x00;
+const TAG_BOOL: u8 = 0x01;
+const TAG_INT: u8 = 0x02;
+const TAG_FLOAT: u8 = 0x03;
+const TAG_STRING: u8 = 0x04;
+const TAG_BYTES: u8 = 0x05;
+const TAG_NULL_HIGH: u8 = 0x0F;
+
+const SIGN_BIT: u64 = 1 << 63;
+/// The one NaN every NaN is encoded as. Its encoding sorts above infinity, like PostgreSQL,
+/// which treats all NaNs as equal and greater than any other value.
+const CANONICAL_NAN: u64 = 0x7FF8_0000_0000_0000;
+
+/// Direction and NULL placement of one key column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl SortOrder {
+    /// `ASC`, with PostgreSQL's default of NULLs last.
+    pub const ASC: SortOrder = SortOrder {
+        descending: false,
+        nulls_first: false,
+    };
+    /// `DESC`, with PostgreSQL's default of NULLs first.
+    pub const DESC: SortOrder = SortOrder {
+        descending: true,
+        nulls_first: true,
+    };
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::ASC
+    }
+}
+
+/// Appends the encoding of `value` in `order` to `out`.
+pub fn encode_value(value: &DataValue, order: SortOrder, out: &mut Vec<u8>) {
+    let start = out.len();
+    match value {
+        DataValue::Null => out.push(if order.nulls_first != order.descending {
+            TAG_NULL_LOW
+        } else {
+            TAG_NULL_HIGH
+        }),
+        DataValue::Bool(b) => out.extend_from_slice(&[TAG_BOOL, u8::from(*b)]),
+        DataValue::Int64(n) => {
+            out.push(TAG_INT);
+            out.extend_from_slice(&((*n as u64) ^ SIGN_BIT).to_be_bytes());
+        }
+        DataValue::Float64(f) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&ordered_float_bits(*f).to_be_bytes());
+        }
+        DataValue::String(s) => {
+            out.push(TAG_STRING);
+            encode_bytes(s.as_bytes(), out);
+        }
+        DataValue::Bytes(b) => {
+            out.push(TAG_BYTES);
+            encode_bytes(b, out);
+        }
+    }
+    if order.descending {
+        for byte in &mut out[start..] {
+            *byte = !*byte;
+        }
+    }
+}
+
+/// Appends the encoding of `values`, every column ascending.
+pub fn encode_key(values: &[DataValue], out: &mut Vec<u8>) {
+    for value in values {
+        encode_value(value, SortOrder::ASC, out);
+    }
+}
+
+/// Appends the encoding of `values`, column `i` in `orders[i]` (ascending past the end).
+pub fn encode_key_with(values: &[DataValue], orders: &[SortOrder], out: &mut Vec<u8>) {
+    for (i, value) in values.iter().enumerate() {
+        encode_value(value, orders.get(i).copied().unwrap_or_default(), out);
+    }
+}
+
+/// Decodes one value in `order` from the front of `bytes`, returning it and the rest.
+/// Negative zero and NaN payloads come back in their canonical form.
+pub fn decode_value(bytes: &[u8], order: SortOrder) -> Result<(DataValue, &[u8]), DatacaveError> {
+    let mask = if order.descending { 0xFF } else { 0x00 };
+    let (&tag, rest) = bytes.split_first().ok_or_else(malformed)?;
+    match tag ^ mask {
+        TAG_NULL_LOW | TAG_NULL_HIGH => Ok((DataValue::Null, rest)),
+        TAG_BOOL => match rest.split_first() {
+            Some((&b, rest)) if b ^ mask <= 1 => Ok((DataValue::Bool(b ^ mask == 1), rest)),
+            _ => Err(malformed()),
+        },
+        tag @ (TAG_INT | TAG_FLOAT) => {
+            let (word, rest) = rest.split_first_chunk::<8>().ok_or_else(malformed)?;
+            let word = u64::from_be_bytes(word.map(|b| b ^ mask));
+            let value = if tag == TAG_INT {
+                DataValue::Int64((word ^ SIGN_BIT) as i64)
+            } else if word & SIGN_BIT != 0 {
+                DataValue::Float64(f64::from_bits(word ^ SIGN_BIT))
+            } else {
+                DataValue::Float64(f64::from_bits(!word))
+            };
+            Ok((value, rest))
+        }
+        TAG_STRING => {
+            let (raw, rest) = decode_bytes(rest, mask)?;
+            let s = String::from_utf8(raw).map_err(|_| malformed())?;
+            Ok((DataValue::String(s), rest))
+        }
+        TAG_BYTES => {
+            let (raw, rest) = decode_bytes(rest, mask)?;
+            Ok((DataValue::Bytes(raw), rest))
+        }
+        _ => Err(malformed()),
+    }
+}
+
+/// Decodes exactly `arity` ascending values; trailing bytes are an error.
+pub fn decode_key(bytes: &[u8], arity: usize) -> Result<Vec<DataValue>, DatacaveError> {
+    decode_key_with(bytes, &vec![SortOrder::ASC; arity])
+}
+
+/// Decodes one value per entry of `orders`; trailing bytes are an error.
+pub fn decode_key_with(mut bytes: &[u8], orders: &[SortOrder]) -> Result<Vec<DataValue>, DatacaveError> {
+    let mut values = Vec::with_capacity(orders.len());
+    for order in orders {
+        let (value, rest) = decode_value(bytes, *order)?;
+        values.push(value);
+        bytes = rest;
+    }
+    if !bytes.is_empty() {
+        return Err(malformed());
+    }
+    Ok(values)
+}
+
+/// Smallest byte string greater than every string starting with `prefix`, or `None` if
+/// there is none (the prefix is empty or all `0xFF`). Used as the exclusive end of a scan
+/// over every key extending `prefix`.
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// IEEE bits reordered so unsigned comparison matches numeric order: positives get the sign
+/// bit set, negatives are inverted. `-0.0` is folded into `0.0` and every NaN into one.
+fn ordered_float_bits(f: f64) -> u64 {
+    let bits = if f.is_nan() {
+        CANONICAL_NAN
+    } else if f == 0.0 {
+        0
+    } else {
+        f.to_bits()
+    };
+    if bits & SIGN_BIT != 0 {
+        !bits
+    } else {
+        bits | SIGN_BIT
+    }
+}
+
+/// NUL bytes are escaped as `00 FF` and the string ends with `00 01`, which sorts below any
+/// continuation, so a string sorts before every string it is a proper prefix of.
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        out.push(b);
+        if b == 0 {
+            out.push(0xFF);
+        }
+    }
+    out.extend_from_slice(&[0x00, 0x01]);
+}
+
+fn decode_bytes(bytes: &[u8], mask: u8) -> Result<(Vec<u8>, &[u8]), DatacaveError> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    loop {
+        let byte = bytes.get(i).ok_or_else(malformed)? ^ mask;
+        if byte != 0 {
+            out.push(byte);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1).map(|b| b ^ mask) {
+            Some(0xFF) => out.push(0),
+            Some(0x01) => return Ok((out, &bytes[i + 2..])),
+            _ => return Err(malformed()),
+        }
+        i += 2;
+    }
+}
+
+fn malformed() -> DatacaveError {
+    DatacaveError::Storage("malformed key encoding".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_key_with, encode_key_with, encode_value, prefix_end, SortOrder};
+    use crate::types::DataValue;
+
+    fn encoded(value: &DataValue, order: SortOrder) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_value(value, order, &mut out);
+        out
+    }
+
+    /// Asserts the encodings of `values`, given in SQL order, are strictly increasing.
+    fn assert_sorted(values: &[DataValue], order: SortOrder) {
+        for pair in values.windows(2) {
+            assert!(
+                encoded(&pair[0], order) < encoded(&pair[1], order),
+                "{:?} should sort before {:?} in {order:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn byte_order_matches_sql_order() {
+        let ints: Vec<DataValue> = [i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX]
+            .into_iter()
+            .map(DataValue::Int64)
+            .collect();
+        let floats: Vec<DataValue> = [
+            f64::NEG_INFINITY,
+            -1e300,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            0.0,
+            f64::MIN_POSITIVE,
+            2.5,
+            f64::INFINITY,
+            f64::NAN,
+        ]
+        .into_iter()
+        .map(DataValue::Float64)
+        .collect();
+        let strings: Vec<DataValue> = ["", "\0", "\0\0", "\0a", "a", "a\0", "ab", "b", "é"]
+            .into_iter()
+            .map(|s| DataValue::String(s.into()))
+            .collect();
+        let bytes: Vec<DataValue> = [&[][..], &[0], &[0, 0xFF], &[1], &[0xFF]]
+            .into_iter()
+            .map(|b| DataValue::Bytes(b.to_vec()))
+            .collect();
+        for values in [ints, floats, strings, bytes] {
+            assert_sorted(&values, SortOrder::ASC);
+            let mut reversed = values.clone();
+            reversed.reverse();
+            assert_sorted(&reversed, SortOrder::DESC);
+        }
+        assert_sorted(&[DataValue::Bool(false), DataValue::Bool(true)], SortOrder::ASC);
+    }
+
+    #[test]
+    fn nan_and_negative_zero_have_one_encoding() {
+        let order = SortOrder::ASC;
+        assert_eq!(
+            encoded(&DataValue::Float64(-0.0), order),
+            encoded(&DataValue::Float64(0.0), order)
+        );
+        assert_eq!(
+            encoded(&DataValue::Float64(-f64::NAN), order),
+            encoded(&DataValue::Float64(f64::NAN), order)
+        );
+    }
+
+    #[test]
+    fn nulls_go_where_the_sort_order_puts_them() {
+        let null = DataValue::Null;
+        let values = [DataValue::Int64(i64::MIN), DataValue::String("zzz".into())];
+        for (order, null_first) in [
+            (SortOrder::ASC, false),
+            (SortOrder::DESC, true),
+            (SortOrder { descending: false, nulls_first: true }, true),
+            (SortOrder { descending: true, nulls_first: false }, false),
+        ] {
+            for value in &values {
+                assert_eq!(encoded(&null, order) < encoded(value, order), null_first, "{order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn tuples_compare_column_by_column_and_roundtrip() {
+        let orders = [SortOrder::ASC, SortOrder::DESC];
+        let tuples = [
+            vec![DataValue::String("a".into()), DataValue::Null],
+            vec![DataValue::String("a".into()), DataValue::Int64(9)],
+            vec![DataValue::String("a".into()), DataValue::Int64(1)],
+            vec![DataValue::String("a\0".into()), DataValue::Int64(5)],
+            vec![DataValue::String("b".into()), DataValue::Int64(5)],
+        ];
+        let keys: Vec<Vec<u8>> = tuples
+            .iter()
+            .map(|tuple| {
+                let mut out = Vec::new();
+                encode_key_with(tuple, &orders, &mut out);
+                out
+            })
+            .collect();
+        for (key, tuple) in keys.iter().zip(&tuples) {
+            assert_eq!(&decode_key_with(key, &orders).expect("decode"), tuple);
+            assert!(decode_key_with(&key[..key.len() - 1], &orders).is_err());
+        }
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(prefix_end(b"t|\xff"), Some(b"t}".to_vec()));
+        assert_eq!(prefix_end(b"\xff"), None);
+    }
+}
@@ -1,9 +1,10 @@
 pub mod catalog;
 pub mod error;
+pub mod keys;
 pub mod mvcc;
 pub mod types;
 
 pub use catalog::{Catalog, TableSchema};
 pub use error::DatacaveError;
-pub use mvcc::{MvccManager, Snapshot, Version};
-pub use types::{Column, DataRow, DataValue, SqlResult};
+pub use mvcc::{CommitConflict, KeyRange, MvccManager, Snapshot, Version};
+pub use types::{Column, DataRow, DataType, DataValue, SqlResult};
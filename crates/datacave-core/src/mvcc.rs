@@ -1,38 +1,362 @@
+use crate::keys::prefix_end;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 pub type Version = u64;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Snapshot {
     pub version: Version,
 }
 
+/// Keys `start..end` read by a serializable transaction, unbounded above when `end` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Vec<u8>,
+    pub end: Option<Vec<u8>>,
+}
+
+impl KeyRange {
+    pub fn point(key: &[u8]) -> Self {
+        let mut end = key.to_vec();
+        end.push(0);
+        Self {
+            start: key.to_vec(),
+            end: Some(end),
+        }
+    }
+
+    /// Every key starting with `prefix`.
+    pub fn prefix(prefix: &[u8]) -> Self {
+        Self {
+            start: prefix.to_vec(),
+            end: prefix_end(prefix),
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= self.start.as_slice() && self.end.as_deref().is_none_or(|end| key < end)
+    }
+}
+
+/// Why a transaction could not commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitConflict {
+    /// Another transaction committed a write to this key after the snapshot.
+    WriteWrite(Vec<u8>),
+    /// Committing would leave a serializable transaction with read-write dependencies both
+    /// into and out of it, a structure every non-serializable schedule contains.
+    ReadWrite,
+}
+
+/// Hands out commit versions and snapshots, and detects write-write conflicts between
+/// transactions and dangerous read-write dependencies between serializable ones.
+///
+/// A version becomes visible to new snapshots only once it and every version below it have
+/// been published, so a snapshot never misses a commit that lands below it later.
 #[derive(Debug)]
 pub struct MvccManager {
     clock: AtomicU64,
+    state: Mutex<CommitState>,
+}
+
+#[derive(Debug, Default)]
+struct CommitState {
+    /// Highest version at or below which every handed-out version is published.
+    visible: Version,
+    /// Versions handed out and not yet published.
+    pending: BTreeSet<Version>,
+    /// Open snapshots, with the number of transactions reading each.
+    active: BTreeMap<Version, usize>,
+    /// Newest commit version of each key committed after the oldest open snapshot.
+    last_writes: HashMap<Vec<u8>, Version>,
+    /// Serializable transactions committed after the oldest open snapshot.
+    serializable: Vec<SerializableCommit>,
+}
+
+/// Reads and writes of a committed serializable transaction, kept while transactions
+/// concurrent with it may still commit.
+#[derive(Debug)]
+struct SerializableCommit {
+    version: Version,
+    reads: Vec<KeyRange>,
+    writes: Vec<Vec<u8>>,
+    /// A concurrent transaction read a key this one wrote.
+    has_in: bool,
+    /// This transaction read a key a concurrent one wrote.
+    has_out: bool,
+}
+
+impl SerializableCommit {
+    fn read_any(&self, keys: &[Vec<u8>]) -> bool {
+        self.reads.iter().any(|range| keys.iter().any(|key| range.contains(key)))
+    }
+
+    fn wrote_any(&self, reads: &[KeyRange]) -> bool {
+        reads.iter().any(|range| self.writes.iter().any(|key| range.contains(key)))
+    }
+}
+
+impl Default for MvccManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MvccManager {
     pub fn new() -> Self {
+        Self::resume_after(0)
+    }
+
+    /// Resumes the clock after a restart so new writes sort above `last_version`, the
+    /// highest version the storage engine recovered.
+    pub fn resume_after(last_version: Version) -> Self {
         Self {
-            clock: AtomicU64::new(1),
+            clock: AtomicU64::new(last_version.saturating_add(1).max(1)),
+            state: Mutex::new(CommitState {
+                visible: last_version,
+                ..CommitState::default()
+            }),
         }
     }
 
+    /// Allocates a version for a write. It stays invisible to new snapshots until passed
+    /// to [`MvccManager::publish`].
     pub fn next_version(&self) -> Version {
-        self.clock.fetch_add(1, Ordering::SeqCst)
+        let mut state = self.state.lock().unwrap();
+        let version = self.clock.fetch_add(1, Ordering::SeqCst);
+        state.pending.insert(version);
+        version
+    }
+
+    /// Allocates the versions up to `version` for a write whose versions were chosen
+    /// elsewhere, such as entries copied from another replica, so no later
+    /// [`MvccManager::next_version`] hands them out again. Returns `version` to publish once
+    /// written, or `None` when the clock is already past it.
+    pub fn claim_through(&self, version: Version) -> Option<Version> {
+        let mut state = self.state.lock().unwrap();
+        let clock = self.clock.load(Ordering::SeqCst);
+        if version < clock {
+            return None;
+        }
+        self.clock.store(version + 1, Ordering::SeqCst);
+        state.pending.insert(version);
+        Some(version)
+    }
+
+    /// Marks `version` as written, or abandoned, making it visible once every lower version
+    /// is too.
+    pub fn publish(&self, version: Version) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&version);
+        state.visible = match state.pending.first() {
+            Some(oldest) => oldest - 1,
+            None => self.clock.load(Ordering::SeqCst) - 1,
+        };
     }
 
+    /// The newest consistent snapshot, for a one-off read.
     pub fn snapshot(&self) -> Snapshot {
         Snapshot {
-            version: self.clock.load(Ordering::SeqCst),
+            version: self.state.lock().unwrap().visible,
+        }
+    }
+
+    /// Opens a snapshot for a transaction. Conflicts against it are tracked until it is
+    /// passed to [`MvccManager::end`].
+    pub fn begin(&self) -> Snapshot {
+        let mut state = self.state.lock().unwrap();
+        let version = state.visible;
+        *state.active.entry(version).or_default() += 1;
+        Snapshot { version }
+    }
+
+    pub fn end(&self, snapshot: Snapshot) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.active.get_mut(&snapshot.version) {
+            *count -= 1;
+            if *count == 0 {
+                state.active.remove(&snapshot.version);
+            }
+        }
+        match state.active.first_key_value().map(|(oldest, _)| *oldest) {
+            Some(oldest) => {
+                state.last_writes.retain(|_, version| *version > oldest);
+                state.serializable.retain(|commit| commit.version > oldest);
+            }
+            None => {
+                state.last_writes.clear();
+                state.serializable.clear();
+            }
+        }
+    }
+
+    /// Version of the oldest open snapshot, which reads must still be able to see.
+    pub fn oldest_active(&self) -> Option<Version> {
+        self.state
+            .lock()
+            .unwrap()
+            .active
+            .first_key_value()
+            .map(|(oldest, _)| *oldest)
+    }
+
+    /// Number of open snapshots older than `version`.
+    pub fn active_before(&self, version: Version) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .active
+            .range(..version)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Whether another transaction committed a write to `key` after `snapshot`, which must
+    /// be open.
+    pub fn written_since(&self, snapshot: Snapshot, key: &[u8]) -> bool {
+        check_write_conflicts(&self.state.lock().unwrap(), snapshot, &[key]).is_err()
+    }
+
+    /// Allocates the commit version of a transaction writing `keys`. With a `snapshot`,
+    /// fails with the first key another transaction committed after it (first committer
+    /// wins). The caller writes at the version and then publishes it.
+    pub fn commit<'a>(
+        &self,
+        snapshot: Option<Snapshot>,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<Version, CommitConflict> {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<&[u8]> = keys.into_iter().collect();
+        if let Some(snapshot) = snapshot {
+            check_write_conflicts(&state, snapshot, &keys)?;
+        }
+        Ok(self.allocate(&mut state, &keys))
+    }
+
+    /// Like [`MvccManager::commit`] for a serializable transaction that read `reads`. Also
+    /// fails when the transaction, or a serializable transaction committed concurrently with
+    /// it, would have read-write dependencies both into and out of it. Read-only
+    /// transactions pass no keys, and still need a version to order them among commits.
+    pub fn commit_serializable<'a>(
+        &self,
+        snapshot: Snapshot,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+        reads: Vec<KeyRange>,
+    ) -> Result<Version, CommitConflict> {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<&[u8]> = keys.into_iter().collect();
+        check_write_conflicts(&state, snapshot, &keys)?;
+        let writes: Vec<Vec<u8>> = keys.iter().map(|key| key.to_vec()).collect();
+        let (reads_from, read_by) = check_serializable(&state, snapshot, &writes, &reads)?;
+        let has_out = !reads_from.is_empty();
+        let has_in = !read_by.is_empty();
+        for idx in reads_from {
+            state.serializable[idx].has_in = true;
+        }
+        for idx in read_by {
+            state.serializable[idx].has_out = true;
+        }
+        let version = self.allocate(&mut state, &keys);
+        state.serializable.push(SerializableCommit {
+            version,
+            reads,
+            writes,
+            has_in,
+            has_out,
+        });
+        Ok(version)
+    }
+
+    /// Checks that a transaction could commit now, as [`MvccManager::commit`] would, or
+    /// [`MvccManager::commit_serializable`] when `reads` are given, without allocating a
+    /// version or recording anything. A transaction spanning several managers checks each
+    /// before committing in any of them.
+    pub fn validate<'a>(
+        &self,
+        snapshot: Snapshot,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+        reads: Option<&[KeyRange]>,
+    ) -> Result<(), CommitConflict> {
+        let state = self.state.lock().unwrap();
+        let keys: Vec<&[u8]> = keys.into_iter().collect();
+        check_write_conflicts(&state, snapshot, &keys)?;
+        if let Some(reads) = reads {
+            let writes: Vec<Vec<u8>> = keys.iter().map(|key| key.to_vec()).collect();
+            check_serializable(&state, snapshot, &writes, reads)?;
         }
+        Ok(())
+    }
+
+    fn allocate(&self, state: &mut CommitState, keys: &[&[u8]]) -> Version {
+        let version = self.clock.fetch_add(1, Ordering::SeqCst);
+        state.pending.insert(version);
+        if !state.active.is_empty() {
+            for key in keys {
+                state.last_writes.insert(key.to_vec(), version);
+            }
+        }
+        version
+    }
+}
+
+/// Finds the read-write dependencies a serializable transaction with `writes` and `reads`
+/// would have on the serializable transactions committed after its snapshot: the indexes
+/// of those it read from and of those that read from it. Fails when a transaction would
+/// end up with dependencies both into and out of it.
+fn check_serializable(
+    state: &CommitState,
+    snapshot: Snapshot,
+    writes: &[Vec<u8>],
+    reads: &[KeyRange],
+) -> Result<(Vec<usize>, Vec<usize>), CommitConflict> {
+    // Only transactions that committed after the snapshot are concurrent with this one;
+    // those still running are checked against it when they commit.
+    let mut reads_from = Vec::new();
+    let mut read_by = Vec::new();
+    for (idx, other) in state.serializable.iter().enumerate() {
+        if other.version <= snapshot.version {
+            continue;
+        }
+        if other.wrote_any(reads) {
+            reads_from.push(idx);
+            if other.has_out {
+                return Err(CommitConflict::ReadWrite);
+            }
+        }
+        if other.read_any(writes) {
+            read_by.push(idx);
+            if other.has_in {
+                return Err(CommitConflict::ReadWrite);
+            }
+        }
+    }
+    if !reads_from.is_empty() && !read_by.is_empty() {
+        return Err(CommitConflict::ReadWrite);
+    }
+    Ok((reads_from, read_by))
+}
+
+fn check_write_conflicts(
+    state: &CommitState,
+    snapshot: Snapshot,
+    keys: &[&[u8]],
+) -> Result<(), CommitConflict> {
+    match keys.iter().find(|key| {
+        state
+            .last_writes
+            .get(**key)
+            .is_some_and(|version| *version > snapshot.version)
+    }) {
+        Some(key) => Err(CommitConflict::WriteWrite(key.to_vec())),
+        None => Ok(()),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::MvccManager;
+    use super::{CommitConflict, KeyRange, MvccManager};
 
     #[test]
     fn versions_increase_monotonically() {
@@ -40,7 +364,109 @@ mod tests {
         let v1 = mvcc.next_version();
         let v2 = mvcc.next_version();
         assert!(v2 > v1);
+        mvcc.publish(v2);
+        assert!(mvcc.snapshot().version < v1, "v1 is still being written");
+        mvcc.publish(v1);
         let snap = mvcc.snapshot();
         assert!(snap.version >= v2);
     }
+
+    #[test]
+    fn claimed_versions_are_not_handed_out_again() {
+        let mvcc = MvccManager::resume_after(4);
+        assert_eq!(mvcc.claim_through(3), None);
+        assert_eq!(mvcc.claim_through(9), Some(9));
+        assert_eq!(mvcc.snapshot().version, 4, "9 is still being written");
+        assert_eq!(mvcc.next_version(), 10);
+        mvcc.publish(10);
+        mvcc.publish(9);
+        assert_eq!(mvcc.snapshot().version, 10);
+    }
+
+    #[test]
+    fn resumed_clock_starts_above_recovered_version() {
+        let mvcc = MvccManager::resume_after(41);
+        assert_eq!(mvcc.snapshot().version, 41);
+        assert_eq!(mvcc.next_version(), 42);
+        assert_eq!(MvccManager::resume_after(0).next_version(), 1);
+    }
+
+    #[test]
+    fn first_committer_wins() {
+        let mvcc = MvccManager::new();
+        let first = mvcc.begin();
+        let second = mvcc.begin();
+        let version = mvcc.commit(Some(first), [b"k".as_slice()]).expect("first commit");
+        mvcc.publish(version);
+        mvcc.end(first);
+        assert_eq!(
+            mvcc.commit(Some(second), [b"k".as_slice()]),
+            Err(CommitConflict::WriteWrite(b"k".to_vec()))
+        );
+        assert!(mvcc.commit(Some(second), [b"other".as_slice()]).is_ok());
+        mvcc.end(second);
+
+        // A snapshot taken after the commit does not conflict with it.
+        let later = mvcc.begin();
+        assert!(mvcc.commit(Some(later), [b"k".as_slice()]).is_ok());
+    }
+
+    #[test]
+    fn write_skew_between_serializable_transactions_aborts_one() {
+        // Each transaction reads both keys and writes the other's: fine under snapshot
+        // isolation, a cycle of read-write dependencies under serializable.
+        let mvcc = MvccManager::new();
+        let reads = || vec![KeyRange::prefix(b"on_call|")];
+        let first = mvcc.begin();
+        let second = mvcc.begin();
+        let version = mvcc
+            .commit_serializable(first, [b"on_call|alice".as_slice()], reads())
+            .expect("first commit");
+        mvcc.publish(version);
+        mvcc.end(first);
+        assert_eq!(
+            mvcc.commit_serializable(second, [b"on_call|bob".as_slice()], reads()),
+            Err(CommitConflict::ReadWrite)
+        );
+        mvcc.end(second);
+
+        // Disjoint reads and writes commit, as does a transaction that began afterwards.
+        let third = mvcc.begin();
+        let fourth = mvcc.begin();
+        for (snapshot, key) in [(third, b"a|1".as_slice()), (fourth, b"b|1".as_slice())] {
+            let reads = vec![KeyRange::point(key)];
+            let version = mvcc.commit_serializable(snapshot, [key], reads).expect("commit");
+            mvcc.publish(version);
+        }
+        let later = mvcc.begin();
+        assert!(mvcc.commit_serializable(later, [b"on_call|bob".as_slice()], reads()).is_ok());
+    }
+
+    #[test]
+    fn validation_reports_conflicts_without_recording_a_commit() {
+        let mvcc = MvccManager::new();
+        let reads = || vec![KeyRange::prefix(b"on_call|")];
+        let first = mvcc.begin();
+        let second = mvcc.begin();
+        let version = mvcc
+            .commit_serializable(first, [b"on_call|alice".as_slice()], reads())
+            .expect("first commit");
+        mvcc.publish(version);
+        let bob = [b"on_call|bob".as_slice()];
+        assert_eq!(
+            mvcc.validate(second, bob, Some(&reads())),
+            Err(CommitConflict::ReadWrite)
+        );
+        assert_eq!(
+            mvcc.validate(second, [b"on_call|alice".as_slice()], None),
+            Err(CommitConflict::WriteWrite(b"on_call|alice".to_vec()))
+        );
+        assert!(mvcc.validate(second, bob, None).is_ok());
+
+        // Validating allocates no version and registers no commit.
+        let third = mvcc.begin();
+        assert!(mvcc.validate(third, [b"x".as_slice()], Some(&reads())).is_ok());
+        assert_eq!(mvcc.state.lock().unwrap().serializable.len(), 1);
+        assert_eq!(mvcc.next_version(), version + 1);
+    }
 }
@@ -1,4 +1,6 @@
+use crate::error::{sqlstate, DatacaveError};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum DataValue {
@@ -10,10 +12,365 @@ pub enum DataValue {
     Bytes(Vec<u8>),
 }
 
+/// Declared SQL type of a column.
+///
+/// Serialized as its SQL spelling, so stored schemas stay readable as the enum grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum DataType {
+    SmallInt,
+    Int,
+    BigInt,
+    Real,
+    Double,
+    Numeric {
+        precision: Option<u32>,
+        scale: Option<u32>,
+    },
+    Boolean,
+    Text,
+    Varchar(Option<u32>),
+    Char(Option<u32>),
+    Bytea,
+}
+
+impl DataType {
+    /// Parses a SQL type name such as `INTEGER`, `VARCHAR(20)` or `NUMERIC(10, 2)`.
+    pub fn parse(sql: &str) -> Result<Self, DatacaveError> {
+        let upper = sql.trim().to_uppercase();
+        let (name, params) = match upper.split_once('(') {
+            Some((name, rest)) => {
+                // Parameters may be followed by more of the name: `TIMESTAMP(3) WITH TIME ZONE`.
+                let (inner, suffix) = rest
+                    .split_once(')')
+                    .ok_or_else(|| unsupported_type(sql))?;
+                let params = inner
+                    .split(',')
+                    .map(|p| p.trim().parse::<u32>().map_err(|_| unsupported_type(sql)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (format!("{name} {suffix}"), params)
+            }
+            None => (upper, Vec::new()),
+        };
+        let length = |params: &[u32]| match params {
+            [] => Ok(None),
+            [len] => Ok(Some(*len)),
+            _ => Err(unsupported_type(sql)),
+        };
+        let data_type = match name.split_whitespace().collect::<Vec<_>>().join(" ").as_str() {
+            "SMALLINT" | "INT2" => DataType::SmallInt,
+            "INT" | "INTEGER" | "INT4" => DataType::Int,
+            "BIGINT" | "INT8" => DataType::BigInt,
+            "REAL" | "FLOAT4" => DataType::Real,
+            "DOUBLE" | "DOUBLE PRECISION" | "FLOAT8" => DataType::Double,
+            "FLOAT" => match length(&params)? {
+                Some(bits) if bits > 24 => DataType::Double,
+                _ => DataType::Real,
+            },
+            "NUMERIC" | "DECIMAL" => match params.as_slice() {
+                [] => DataType::Numeric {
+                    precision: None,
+                    scale: None,
+                },
+                [precision] => DataType::Numeric {
+                    precision: Some(*precision),
+                    scale: Some(0),
+                },
+                [precision, scale] if scale <= precision => DataType::Numeric {
+                    precision: Some(*precision),
+                    scale: Some(*scale),
+                },
+                _ => return Err(unsupported_type(sql)),
+            },
+            "BOOLEAN" | "BOOL" => DataType::Boolean,
+            "TEXT" | "STRING" => DataType::Text,
+            "VARCHAR" | "CHARACTER VARYING" => DataType::Varchar(length(&params)?),
+            "CHAR" | "CHARACTER" => DataType::Char(length(&params)?),
+            "BYTEA" => DataType::Bytea,
+            // Date/time, UUID and JSON values have no type of their own yet and are kept as
+            // text, as they were before column types were checked.
+            "DATE" | "TIME" | "TIMETZ" | "TIMESTAMP" | "TIMESTAMPTZ" | "INTERVAL" | "UUID"
+            | "JSON" | "JSONB" | "TIME WITH TIME ZONE" | "TIME WITHOUT TIME ZONE"
+            | "TIMESTAMP WITH TIME ZONE" | "TIMESTAMP WITHOUT TIME ZONE" => DataType::Text,
+            _ => return Err(unsupported_type(sql)),
+        };
+        Ok(data_type)
+    }
+
+    /// Type of a computed value with no declared column behind it.
+    pub fn for_value(value: &DataValue) -> Self {
+        match value {
+            DataValue::Int64(_) => DataType::BigInt,
+            DataValue::Float64(_) => DataType::Double,
+            DataValue::Bool(_) => DataType::Boolean,
+            DataValue::Bytes(_) => DataType::Bytea,
+            DataValue::Null | DataValue::String(_) => DataType::Text,
+        }
+    }
+
+    /// Whether `value` has the `DataValue` variant this type is stored as. NULL fits any type.
+    pub fn accepts(&self, value: &DataValue) -> bool {
+        match value {
+            DataValue::Null => true,
+            DataValue::Int64(_) => self.is_integer(),
+            DataValue::Float64(_) => {
+                matches!(self, DataType::Real | DataType::Double | DataType::Numeric { .. })
+            }
+            DataValue::Bool(_) => *self == DataType::Boolean,
+            DataValue::String(_) => self.is_text(),
+            DataValue::Bytes(_) => *self == DataType::Bytea,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, DataType::SmallInt | DataType::Int | DataType::BigInt)
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, DataType::Text | DataType::Varchar(_) | DataType::Char(_))
+    }
+
+    /// Converts `value` to what a column of this type stores, following PostgreSQL's
+    /// assignment casts: numbers convert between numeric types (rounding into integers and
+    /// to a numeric's scale), quoted literals are parsed as the column type, and numbers and
+    /// booleans are accepted as text. Anything else is a datatype mismatch.
+    pub fn coerce(&self, column: &str, value: DataValue) -> Result<DataValue, DatacaveError> {
+        let mismatch = |value: &DataValue| {
+            DatacaveError::sqlstate(
+                sqlstate::DATATYPE_MISMATCH,
+                format!(
+                    "column \"{column}\" is of type {} but expression is of type {}",
+                    self.pg_name(),
+                    DataType::for_value(value).pg_name()
+                ),
+            )
+        };
+        match value {
+            DataValue::Null => Ok(DataValue::Null),
+            value if self.is_integer() => {
+                let integer = match value {
+                    DataValue::Int64(v) => v as i128,
+                    DataValue::Float64(v) if v.is_finite() => v.round() as i128,
+                    DataValue::Float64(_) => return Err(self.out_of_range()),
+                    DataValue::String(ref s) => s
+                        .trim()
+                        .parse::<i128>()
+                        .map_err(|_| self.invalid_input(s))?,
+                    other => return Err(mismatch(&other)),
+                };
+                self.check_integer(integer)
+            }
+            value if matches!(self, DataType::Real | DataType::Double | DataType::Numeric { .. }) => {
+                let float = match value {
+                    DataValue::Int64(v) => v as f64,
+                    DataValue::Float64(v) => v,
+                    DataValue::String(ref s) => s
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|_| self.invalid_input(s))?,
+                    other => return Err(mismatch(&other)),
+                };
+                self.check_float(float)
+            }
+            DataValue::Bool(b) if *self == DataType::Boolean => Ok(DataValue::Bool(b)),
+            DataValue::String(s) if *self == DataType::Boolean => {
+                match s.trim().to_lowercase().as_str() {
+                    "t" | "true" | "y" | "yes" | "on" | "1" => Ok(DataValue::Bool(true)),
+                    "f" | "false" | "n" | "no" | "off" | "0" => Ok(DataValue::Bool(false)),
+                    _ => Err(self.invalid_input(&s)),
+                }
+            }
+            DataValue::Bytes(bytes) if *self == DataType::Bytea => Ok(DataValue::Bytes(bytes)),
+            DataValue::String(s) if *self == DataType::Bytea => match s.strip_prefix("\\x") {
+                Some(hex) => decode_hex(hex)
+                    .map(DataValue::Bytes)
+                    .ok_or_else(|| self.invalid_input(&s)),
+                None => Ok(DataValue::Bytes(s.into_bytes())),
+            },
+            value if self.is_text() => {
+                let text = match value {
+                    DataValue::String(s) => s,
+                    DataValue::Int64(v) => v.to_string(),
+                    DataValue::Float64(v) => v.to_string(),
+                    DataValue::Bool(b) => b.to_string(),
+                    other => return Err(mismatch(&other)),
+                };
+                self.check_length(text)
+            }
+            other => Err(mismatch(&other)),
+        }
+    }
+
+    fn check_integer(&self, value: i128) -> Result<DataValue, DatacaveError> {
+        let (min, max) = match self {
+            DataType::SmallInt => (i16::MIN as i128, i16::MAX as i128),
+            DataType::Int => (i32::MIN as i128, i32::MAX as i128),
+            _ => (i64::MIN as i128, i64::MAX as i128),
+        };
+        if value < min || value > max {
+            return Err(self.out_of_range());
+        }
+        Ok(DataValue::Int64(value as i64))
+    }
+
+    fn check_float(&self, value: f64) -> Result<DataValue, DatacaveError> {
+        match self {
+            DataType::Real => {
+                let narrowed = value as f32;
+                if value.is_finite() && narrowed.is_infinite() {
+                    return Err(self.out_of_range());
+                }
+                Ok(DataValue::Float64(narrowed as f64))
+            }
+            DataType::Numeric { precision, scale } => {
+                let factor = 10f64.powi(scale.unwrap_or(0) as i32);
+                let rounded = match scale {
+                    Some(_) => (value * factor).round() / factor,
+                    None => value,
+                };
+                if let Some(precision) = precision {
+                    let limit = 10f64.powi(*precision as i32 - scale.unwrap_or(0) as i32);
+                    if rounded.abs() >= limit {
+                        return Err(DatacaveError::sqlstate(
+                            sqlstate::NUMERIC_VALUE_OUT_OF_RANGE,
+                            "numeric field overflow",
+                        ));
+                    }
+                }
+                Ok(DataValue::Float64(rounded))
+            }
+            _ => Ok(DataValue::Float64(value)),
+        }
+    }
+
+    /// Rejects text longer than a `VARCHAR(n)` or `CHAR(n)` limit. As in PostgreSQL, excess
+    /// characters that are all spaces are dropped instead. `CHAR(n)` values are stored
+    /// without blank padding.
+    fn check_length(&self, text: String) -> Result<DataValue, DatacaveError> {
+        let limit = match self {
+            DataType::Varchar(limit) => *limit,
+            DataType::Char(limit) => Some(limit.unwrap_or(1)),
+            _ => None,
+        };
+        let Some(limit) = limit.map(|l| l as usize) else {
+            return Ok(DataValue::String(text));
+        };
+        match text.char_indices().nth(limit) {
+            None => Ok(DataValue::String(text)),
+            Some((cut, _)) if text[cut..].chars().all(|c| c == ' ') => {
+                Ok(DataValue::String(text[..cut].to_string()))
+            }
+            Some(_) => Err(DatacaveError::sqlstate(
+                sqlstate::STRING_DATA_RIGHT_TRUNCATION,
+                format!("value too long for type {}({limit})", self.pg_name()),
+            )),
+        }
+    }
+
+    fn out_of_range(&self) -> DatacaveError {
+        DatacaveError::sqlstate(
+            sqlstate::NUMERIC_VALUE_OUT_OF_RANGE,
+            format!("{} out of range", self.pg_name()),
+        )
+    }
+
+    fn invalid_input(&self, text: &str) -> DatacaveError {
+        DatacaveError::sqlstate(
+            sqlstate::INVALID_TEXT_REPRESENTATION,
+            format!("invalid input syntax for type {}: \"{text}\"", self.pg_name()),
+        )
+    }
+
+    /// Type name as PostgreSQL spells it in error messages.
+    pub fn pg_name(&self) -> &'static str {
+        match self {
+            DataType::SmallInt => "smallint",
+            DataType::Int => "integer",
+            DataType::BigInt => "bigint",
+            DataType::Real => "real",
+            DataType::Double => "double precision",
+            DataType::Numeric { .. } => "numeric",
+            DataType::Boolean => "boolean",
+            DataType::Text => "text",
+            DataType::Varchar(_) => "character varying",
+            DataType::Char(_) => "character",
+            DataType::Bytea => "bytea",
+        }
+    }
+
+    /// PostgreSQL `pg_type.oid` used in RowDescription. Character types are reported as
+    /// `text`, which every client decodes the same way.
+    pub fn pg_oid(&self) -> i32 {
+        match self {
+            DataType::SmallInt => 21,
+            DataType::Int => 23,
+            DataType::BigInt => 20,
+            DataType::Real => 700,
+            DataType::Double => 701,
+            DataType::Numeric { .. } => 1700,
+            DataType::Boolean => 16,
+            DataType::Text | DataType::Varchar(_) | DataType::Char(_) => 25,
+            DataType::Bytea => 17,
+        }
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn unsupported_type(sql: &str) -> DatacaveError {
+    DatacaveError::NotSupported(format!("data type {}", sql.trim()))
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::SmallInt => write!(f, "SMALLINT"),
+            DataType::Int => write!(f, "INT"),
+            DataType::BigInt => write!(f, "BIGINT"),
+            DataType::Real => write!(f, "REAL"),
+            DataType::Double => write!(f, "DOUBLE PRECISION"),
+            DataType::Numeric {
+                precision: Some(precision),
+                scale,
+            } => write!(f, "NUMERIC({precision}, {})", scale.unwrap_or(0)),
+            DataType::Numeric { .. } => write!(f, "NUMERIC"),
+            DataType::Boolean => write!(f, "BOOLEAN"),
+            DataType::Text => write!(f, "TEXT"),
+            DataType::Varchar(Some(len)) => write!(f, "VARCHAR({len})"),
+            DataType::Varchar(None) => write!(f, "VARCHAR"),
+            DataType::Char(Some(len)) => write!(f, "CHAR({len})"),
+            DataType::Char(None) => write!(f, "CHAR"),
+            DataType::Bytea => write!(f, "BYTEA"),
+        }
+    }
+}
+
+impl From<DataType> for String {
+    fn from(data_type: DataType) -> Self {
+        data_type.to_string()
+    }
+}
+
+impl TryFrom<String> for DataType {
+    type Error = DatacaveError;
+
+    fn try_from(sql: String) -> Result<Self, Self::Error> {
+        DataType::parse(&sql)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
-    pub data_type: String,
+    pub data_type: DataType,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,3 +384,90 @@ pub struct SqlResult {
     pub rows: Vec<DataRow>,
     pub rows_affected: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DataType, DataValue};
+    use crate::error::DatacaveError;
+
+    #[test]
+    fn parses_sql_type_names_with_parameters() {
+        assert_eq!(DataType::parse("integer").unwrap(), DataType::Int);
+        assert_eq!(DataType::parse("DOUBLE  PRECISION").unwrap(), DataType::Double);
+        assert_eq!(DataType::parse("FLOAT(53)").unwrap(), DataType::Double);
+        assert_eq!(
+            DataType::parse("CHARACTER VARYING(20)").unwrap(),
+            DataType::Varchar(Some(20))
+        );
+        assert_eq!(
+            DataType::parse("DECIMAL(10,2)").unwrap(),
+            DataType::Numeric {
+                precision: Some(10),
+                scale: Some(2)
+            }
+        );
+        for sql in ["DATE", "TIMESTAMP(3) WITH TIME ZONE", "timestamptz", "UUID", "JSON", "JSONB"] {
+            assert_eq!(DataType::parse(sql).unwrap(), DataType::Text, "{sql}");
+        }
+        assert!(DataType::parse("GEOMETRY").is_err());
+        assert!(DataType::parse("NUMERIC(2, 5)").is_err());
+        for data_type in [DataType::Varchar(Some(8)), DataType::Double, DataType::Char(None)] {
+            assert_eq!(DataType::parse(&data_type.to_string()).unwrap(), data_type);
+        }
+    }
+
+    #[test]
+    fn maps_types_to_values_and_oids() {
+        assert!(DataType::Int.accepts(&DataValue::Int64(1)));
+        assert!(DataType::Int.accepts(&DataValue::Null));
+        assert!(!DataType::Int.accepts(&DataValue::String("1".into())));
+        assert!(DataType::Varchar(Some(3)).accepts(&DataValue::String("abc".into())));
+        assert_eq!(DataType::for_value(&DataValue::Float64(1.5)), DataType::Double);
+        assert_eq!(DataType::BigInt.pg_oid(), 20);
+        assert_eq!(DataType::Varchar(None).pg_oid(), 25);
+    }
+
+    fn coerce_code(data_type: DataType, value: DataValue) -> &'static str {
+        match data_type.coerce("c", value) {
+            Err(err @ DatacaveError::Sqlstate { .. }) => err.code(),
+            other => panic!("expected an error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn coerces_values_like_assignment_casts() {
+        assert_eq!(DataType::Double.coerce("c", DataValue::Int64(1)).unwrap(), DataValue::Float64(1.0));
+        assert_eq!(DataType::Int.coerce("c", DataValue::Float64(2.5)).unwrap(), DataValue::Int64(3));
+        assert_eq!(DataType::Int.coerce("c", DataValue::String(" 42 ".into())).unwrap(), DataValue::Int64(42));
+        assert_eq!(DataType::Text.coerce("c", DataValue::Int64(7)).unwrap(), DataValue::String("7".into()));
+        assert_eq!(DataType::Boolean.coerce("c", DataValue::String("on".into())).unwrap(), DataValue::Bool(true));
+        assert_eq!(
+            DataType::Numeric { precision: Some(5), scale: Some(2) }
+                .coerce("c", DataValue::Float64(1.005_1))
+                .unwrap(),
+            DataValue::Float64(1.01)
+        );
+        assert_eq!(
+            DataType::Bytea.coerce("c", DataValue::String("\\x0aff".into())).unwrap(),
+            DataValue::Bytes(vec![0x0a, 0xff])
+        );
+        assert_eq!(
+            DataType::Varchar(Some(2)).coerce("c", DataValue::String("ab  ".into())).unwrap(),
+            DataValue::String("ab".into())
+        );
+        assert_eq!(DataType::Int.coerce("c", DataValue::Null).unwrap(), DataValue::Null);
+
+        assert_eq!(coerce_code(DataType::Int, DataValue::String("abc".into())), "22P02");
+        assert_eq!(coerce_code(DataType::SmallInt, DataValue::Int64(40_000)), "22003");
+        assert_eq!(coerce_code(DataType::Int, DataValue::Bool(true)), "42804");
+        assert_eq!(coerce_code(DataType::Boolean, DataValue::Int64(1)), "42804");
+        assert_eq!(coerce_code(DataType::Varchar(Some(2)), DataValue::String("abc".into())), "22001");
+        assert_eq!(coerce_code(DataType::Char(None), DataValue::String("ab".into())), "22001");
+        assert_eq!(
+            coerce_code(DataType::Numeric { precision: Some(3), scale: Some(1) }, DataValue::Int64(100)),
+            "22003"
+        );
+        let err = DataType::Int.coerce("qty", DataValue::Bool(true)).unwrap_err();
+        assert_eq!(err.to_string(), "column \"qty\" is of type integer but expression is of type boolean");
+    }
+}
@@ -18,6 +18,7 @@ fn lsm_put_get_bench(c: &mut Criterion) {
                 memtable_max_bytes: 1024 * 1024,
                 encryption_key: None,
                 wal_enabled: true,
+                tiering: None,
             })
             .await
             .expect("open");
@@ -1,23 +1,57 @@
 use crate::encryption::DataEncryptor;
+use crate::engine::{range_tombstone_end, split_versioned_key, RangeTombstones};
+use crate::env::Env;
 use crate::sstable::{SstEntry, SSTable};
 use anyhow::Result;
-use std::collections::BTreeMap;
+use datacave_core::mvcc::Version;
+use std::collections::{BTreeMap, HashMap};
 
+/// Merges `tables` into one table at `output_path`. A version at or below `horizon` is
+/// dropped when a newer version of its key is also at or below it, since no read at
+/// `horizon` or later can see it, or when a range tombstone at or below the horizon hides
+/// it. Range tombstones are always kept, since older tables outside the merge may hold keys
+/// they delete. A `horizon` of 0 keeps every version.
 pub async fn compact_tables(
+    env: &dyn Env,
     output_path: &str,
     tables: &[SSTable],
     encryptor: Option<&DataEncryptor>,
+    horizon: Version,
 ) -> Result<()> {
     let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
     for table in tables {
-        for entry in table.load_with(encryptor).await? {
+        for entry in table.load_with(env, encryptor).await? {
             merged.insert(entry.key, entry.value);
         }
     }
+    // Versioned keys of different keys can interleave, so the newest version at or below
+    // the horizon is found per key before any entry is dropped.
+    let mut settled: HashMap<&[u8], Version> = HashMap::new();
+    for versioned_key in merged.keys() {
+        if let Some((key, version)) = split_versioned_key(versioned_key) {
+            if version <= horizon {
+                let newest = settled.entry(key).or_insert(version);
+                *newest = (*newest).max(version);
+            }
+        }
+    }
+    let mut tombstones = RangeTombstones::default();
+    tombstones.collect(merged.iter(), horizon);
     let entries: Vec<SstEntry> = merged
-        .into_iter()
-        .map(|(key, value)| SstEntry { key, value })
+        .iter()
+        .filter(|(versioned_key, value)| match split_versioned_key(versioned_key) {
+            _ if range_tombstone_end(value).is_some() => true,
+            Some((key, version)) => {
+                version > horizon
+                    || (settled.get(key) == Some(&version) && !tombstones.hides(key, version))
+            }
+            None => true,
+        })
+        .map(|(key, value)| SstEntry {
+            key: key.clone(),
+            value: value.clone(),
+        })
         .collect();
-    SSTable::write_with(output_path, &entries, encryptor).await?;
+    SSTable::write_with(env, output_path, &entries, encryptor).await?;
     Ok(())
 }
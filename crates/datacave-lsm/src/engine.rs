@@ -1,10 +1,20 @@
 use crate::compaction::compact_tables;
 use crate::encryption::DataEncryptor;
+use crate::env::{LocalEnv, SharedEnv};
+use crate::format::{check_readable, UpgradeReport, FORMAT_VERSION};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::ingest::SstBuilder;
 use crate::memtable::MemTable;
+use crate::scrub::{CorruptFile, ScrubReport};
 use crate::sstable::{SstEntry, SSTable};
+use crate::storage::{BatchOp, StorageEngine, StoredVersion, WriteBatch};
 use crate::wal::{Wal, WalOp};
-use anyhow::Result;
-use datacave_core::mvcc::Version;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use datacave_core::mvcc::{Snapshot, Version};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 use tokio::sync::Mutex;
 use tracing::info;
 
@@ -15,8 +25,24 @@ pub struct LsmOptions {
     pub memtable_max_bytes: usize,
     pub encryption_key: Option<Vec<u8>>,
     pub wal_enabled: bool,
+    /// Moves cold SSTables to a secondary directory when set.
+    pub tiering: Option<TieringOptions>,
 }
 
+/// Policy for moving SSTables out of `data_dir` into a slower, cheaper directory. A table
+/// is cold once it reaches `min_level` or is older than `min_age_secs`; cold tables are
+/// read in place and are compacted among themselves, in `cold_dir`, once there are more
+/// than [`MAX_COLD_TABLES`] of them.
+#[derive(Debug, Clone)]
+pub struct TieringOptions {
+    pub cold_dir: String,
+    pub min_level: Option<u32>,
+    pub min_age_secs: Option<u64>,
+}
+
+/// Cold tables kept before compaction merges them into one.
+pub const MAX_COLD_TABLES: usize = 4;
+
 #[derive(Debug)]
 pub struct LsmEngine {
     memtable: Mutex<MemTable>,
@@ -24,36 +50,100 @@ pub struct LsmEngine {
     sstables: Mutex<Vec<SSTable>>,
     options: LsmOptions,
     encryptor: Option<DataEncryptor>,
+    env: SharedEnv,
+    next_file_id: AtomicU64,
+    last_version: AtomicU64,
+    /// Upper bound on the versions stored in SSTables.
+    table_high_water: AtomicU64,
+    /// Oldest version reads may still ask for; see [`StorageEngine::set_history_horizon`].
+    history_horizon: AtomicU64,
 }
 
 impl LsmEngine {
     pub async fn open(options: LsmOptions) -> Result<Self> {
+        Self::open_with_env(options, LocalEnv::shared()).await
+    }
+
+    /// Opens the engine with all file I/O routed through `env`.
+    pub async fn open_with_env(options: LsmOptions, env: SharedEnv) -> Result<Self> {
         let encryptor = match options.encryption_key.as_ref() {
             Some(key_bytes) => Some(DataEncryptor::new(key_bytes)?),
             None => None,
         };
-        let wal = Wal::open(&options.wal_path, encryptor.clone()).await?;
+        let wal = Wal::open_with(env.as_ref(), &options.wal_path, encryptor.clone()).await?;
         let mut memtable = MemTable::new();
+        let mut last_version = 0;
         if options.wal_enabled {
-            let entries = Wal::replay(&options.wal_path, encryptor.clone()).await?;
+            let entries =
+                Wal::replay_with(env.as_ref(), &options.wal_path, encryptor.clone()).await?;
             for (op, key, value) in entries {
                 match op {
-                    WalOp::Put | WalOp::Delete => {
+                    WalOp::Put | WalOp::Delete | WalOp::DeleteRange => {
+                        if let Some((_, version)) = split_versioned_key(&key) {
+                            last_version = last_version.max(version);
+                        }
                         memtable.put(key, value);
                     }
                 }
             }
         }
         let mut sstables = Vec::new();
-        if let Ok(entries) = std::fs::read_dir(&options.data_dir) {
-            for entry in entries.flatten() {
-                if let Some(name) = entry.file_name().to_str() {
-                    if name.starts_with("sst-") && name.ends_with(".db") {
-                        sstables.push(SSTable::new(entry.path().to_string_lossy().to_string()));
+        let mut next_file_id = 0;
+        let mut has_manifest = false;
+        let mut dirs = vec![options.data_dir.clone()];
+        if let Some(tiering) = &options.tiering {
+            dirs.push(tiering.cold_dir.clone());
+        }
+        for dir in &dirs {
+            for name in env.list_dir(dir).await? {
+                let path = format!("{dir}/{name}");
+                if name.ends_with(".tmp") {
+                    // Left behind by a write that crashed before its rename.
+                    env.remove_file(&path).await?;
+                } else if *dir == options.data_dir && name == MANIFEST_FILE {
+                    has_manifest = true;
+                } else if name.starts_with("sst-") && name.ends_with(".db") {
+                    if let Some(id) = sstable_file_id(&name) {
+                        next_file_id = next_file_id.max(id + 1);
                     }
+                    sstables.push(SSTable::new(path));
                 }
             }
-            sstables.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        // A table in both tiers was moved but its hot copy not yet removed; keep the cold one.
+        let mut by_name: BTreeMap<String, SSTable> = BTreeMap::new();
+        for table in sstables {
+            if let Some(hot) = by_name.insert(table.file_name().to_string(), table) {
+                env.remove_file(&hot.path).await?;
+            }
+        }
+        let sstables: Vec<SSTable> = by_name.into_values().collect();
+        // The manifest bounds the versions on disk; the replayed WAL usually runs past it.
+        let mut table_high_water = 0;
+        if has_manifest {
+            let manifest = Manifest::load_with(env.as_ref(), &options.data_dir).await?;
+            check_readable(&Manifest::path(&options.data_dir), manifest.format_version)?;
+            last_version = last_version.max(manifest.last_version);
+            table_high_water = manifest.last_version;
+        } else if !sstables.is_empty() {
+            // Data directories written before the manifest existed: derive the high-water
+            // version from the tables once and record it.
+            let mut table_version = 0;
+            for table in &sstables {
+                for entry in table.load_with(env.as_ref(), encryptor.as_ref()).await? {
+                    if let Some((_, version)) = split_versioned_key(&entry.key) {
+                        table_version = table_version.max(version);
+                    }
+                }
+            }
+            Manifest {
+                last_version: table_version,
+                ..Manifest::default()
+            }
+            .store_with(env.as_ref(), &options.data_dir)
+            .await?;
+            last_version = last_version.max(table_version);
+            table_high_water = table_version;
         }
         Ok(Self {
             memtable: Mutex::new(memtable),
@@ -61,6 +151,11 @@ impl LsmEngine {
             sstables: Mutex::new(sstables),
             options,
             encryptor,
+            env,
+            next_file_id: AtomicU64::new(next_file_id),
+            last_version: AtomicU64::new(last_version),
+            table_high_water: AtomicU64::new(table_high_water),
+            history_horizon: AtomicU64::new(0),
         })
     }
 
@@ -68,18 +163,22 @@ impl LsmEngine {
         metrics::counter!("lsm_put").increment(1);
         let encoded_key = encode_versioned_key(key, version);
         let encoded_value = encode_value(Some(value));
+        // The WAL lock is held until the memtable insert so a concurrent flush cannot reset
+        // the log between the two and lose the record.
+        let mut wal = self.wal.lock().await;
         if self.options.wal_enabled {
-            self.wal
-                .lock()
-                .await
-                .append(WalOp::Put, &encoded_key, &encoded_value)
-                .await?;
+            wal.append(WalOp::Put, &encoded_key, &encoded_value).await?;
         }
-        self.memtable
-            .lock()
-            .await
-            .put(encoded_key, encoded_value);
-        if self.memtable.lock().await.approximate_bytes() >= self.options.memtable_max_bytes {
+        let mem_bytes = {
+            let mut mem = self.memtable.lock().await;
+            mem.put(encoded_key, encoded_value);
+            mem.approximate_bytes()
+        };
+        // Observed before the WAL lock is released, so a flush that takes it next records
+        // a high-water version covering this write.
+        self.observe_version(version);
+        drop(wal);
+        if mem_bytes >= self.options.memtable_max_bytes {
             self.flush().await?;
         }
         Ok(())
@@ -89,40 +188,193 @@ impl LsmEngine {
         metrics::counter!("lsm_delete").increment(1);
         let encoded_key = encode_versioned_key(key, version);
         let encoded_value = encode_value(None);
+        let mut wal = self.wal.lock().await;
         if self.options.wal_enabled {
-            self.wal
-                .lock()
-                .await
-                .append(WalOp::Delete, &encoded_key, &encoded_value)
-                .await?;
+            wal.append(WalOp::Delete, &encoded_key, &encoded_value).await?;
         }
         self.memtable
             .lock()
             .await
             .put(encoded_key, encoded_value);
+        self.observe_version(version);
+        drop(wal);
+        Ok(())
+    }
+
+    pub async fn write_batch(&self, batch: WriteBatch, version: Version) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        batch.check_ranges()?;
+        metrics::counter!("lsm_write_batch").increment(1);
+        let mut wal = self.wal.lock().await;
+        let mut records: Vec<(WalOp, Vec<u8>, Vec<u8>)> = Vec::with_capacity(batch.len());
+        for op in batch.ops() {
+            match op {
+                BatchOp::Put { key, value } => records.push((
+                    WalOp::Put,
+                    encode_versioned_key(key, version),
+                    encode_value(Some(value)),
+                )),
+                BatchOp::Delete { key } => records.push((
+                    WalOp::Delete,
+                    encode_versioned_key(key, version),
+                    encode_value(None),
+                )),
+                BatchOp::DeleteRange { start, end } => records.push((
+                    WalOp::DeleteRange,
+                    encode_versioned_key(start, version),
+                    encode_range_tombstone(end),
+                )),
+            }
+        }
+        if self.options.wal_enabled {
+            let refs: Vec<(WalOp, &[u8], &[u8])> = records
+                .iter()
+                .map(|(op, key, value)| (*op, key.as_slice(), value.as_slice()))
+                .collect();
+            wal.append_all(&refs).await?;
+        }
+        let mem_bytes = {
+            let mut mem = self.memtable.lock().await;
+            for (_, key, value) in records {
+                mem.put(key, value);
+            }
+            mem.approximate_bytes()
+        };
+        self.observe_version(version);
+        drop(wal);
+        if mem_bytes >= self.options.memtable_max_bytes {
+            self.flush().await?;
+        }
         Ok(())
     }
 
     pub async fn get(&self, key: &[u8], snapshot: Version) -> Result<Option<Vec<u8>>> {
         metrics::counter!("lsm_get").increment(1);
-        let mem = self.memtable.lock().await;
-        if let Some(value) = mem_get_latest(&mem, key, snapshot) {
-            return Ok(decode_value(value));
+        let mut tombstones = RangeTombstones::default();
+        let mut newest = {
+            let mem = self.memtable.lock().await;
+            tombstones.collect(mem.range_tombstones(), snapshot);
+            mem_get_versioned(&mem, key, snapshot).map(|(version, value)| (version, value.clone()))
+        };
+        // Ingested tables can carry versions above the memtable, so the memtable only wins
+        // outright when it is at least as new as everything on disk, range tombstones
+        // included.
+        if let Some((version, value)) = &newest {
+            if *version >= self.table_high_water.load(Ordering::SeqCst) {
+                if tombstones.hides(key, *version) {
+                    return Ok(None);
+                }
+                return Ok(decode_value(value));
+            }
         }
-        drop(mem);
 
+        // Compaction outputs can sort after tables flushed while they were being built, so
+        // pick the newest version across every table rather than trusting file order.
         let tables = self.sstables.lock().await.clone();
-        for table in tables.iter().rev() {
-            for entry in table.load_with(self.encryptor.as_ref()).await? {
-                if is_key_match(&entry.key, key, snapshot) {
-                    return Ok(decode_value(&entry.value));
+        for table in &tables {
+            let entries = table.load_with(self.env.as_ref(), self.encryptor.as_ref()).await?;
+            tombstones.collect(entries.iter().map(|entry| (&entry.key, &entry.value)), snapshot);
+            // Entries are sorted by versioned key, so the newest visible version comes last.
+            if let Some(entry) = entries
+                .into_iter()
+                .rev()
+                .find(|entry| is_key_match(&entry.key, key, snapshot))
+            {
+                let version = split_versioned_key(&entry.key).map_or(0, |(_, version)| version);
+                if newest.as_ref().is_none_or(|(seen, _)| version > *seen) {
+                    newest = Some((version, entry.value));
                 }
             }
         }
-        Ok(None)
+        Ok(newest
+            .filter(|(version, _)| !tombstones.hides(key, *version))
+            .and_then(|(_, value)| decode_value(&value)))
+    }
+
+    pub async fn scan(&self, prefix: &[u8], snapshot: Version) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        metrics::counter!("lsm_scan").increment(1);
+        let mut latest = BTreeMap::new();
+        let mut tombstones = RangeTombstones::default();
+        let tables = self.sstables.lock().await.clone();
+        for table in &tables {
+            let entries = table.load_with(self.env.as_ref(), self.encryptor.as_ref()).await?;
+            tombstones.collect(entries.iter().map(|entry| (&entry.key, &entry.value)), snapshot);
+            collect_latest(
+                entries.iter().map(|entry| (&entry.key, &entry.value)),
+                prefix,
+                snapshot,
+                &mut latest,
+            );
+        }
+        let mem = self.memtable.lock().await;
+        collect_latest(
+            mem.range_from(prefix.to_vec())
+                .take_while(|(key, _)| key.starts_with(prefix)),
+            prefix,
+            snapshot,
+            &mut latest,
+        );
+        tombstones.collect(mem.range_tombstones(), snapshot);
+        tombstones.apply(&mut latest);
+        Ok(into_live_entries(latest))
+    }
+
+    pub async fn scan_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        snapshot: Version,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        metrics::counter!("lsm_scan").increment(1);
+        let mut latest = BTreeMap::new();
+        let mut tombstones = RangeTombstones::default();
+        let tables = self.sstables.lock().await.clone();
+        for table in &tables {
+            let entries = table.load_with(self.env.as_ref(), self.encryptor.as_ref()).await?;
+            tombstones.collect(entries.iter().map(|entry| (&entry.key, &entry.value)), snapshot);
+            collect_latest_in_range(
+                entries.iter().map(|entry| (&entry.key, &entry.value)),
+                start,
+                end,
+                snapshot,
+                &mut latest,
+            );
+        }
+        let mem = self.memtable.lock().await;
+        let prefix = range_prefix(start, end);
+        collect_latest_in_range(
+            mem.range_from(start.to_vec())
+                .take_while(|(key, _)| key.starts_with(prefix)),
+            start,
+            end,
+            snapshot,
+            &mut latest,
+        );
+        tombstones.collect(mem.range_tombstones(), snapshot);
+        tombstones.apply(&mut latest);
+        Ok(into_live_entries(latest))
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: self.last_version.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Compaction keeps every version above `version` and the newest one at or below it.
+    pub fn set_history_horizon(&self, version: Version) {
+        self.history_horizon.fetch_max(version, Ordering::SeqCst);
+    }
+
+    fn observe_version(&self, version: Version) {
+        self.last_version.fetch_max(version, Ordering::SeqCst);
     }
 
     pub async fn flush(&self) -> Result<()> {
+        // Lock order matches the write path: WAL first, then memtable.
+        let mut wal = self.wal.lock().await;
         let mut mem = self.memtable.lock().await;
         if mem.iter().next().is_none() {
             return Ok(());
@@ -135,31 +387,396 @@ impl LsmEngine {
                 value: value.clone(),
             })
             .collect();
-        let sst_path = format!("{}/sst-{}.db", self.options.data_dir, chrono_suffix());
-        SSTable::write_with(&sst_path, &entries, self.encryptor.as_ref()).await?;
+        let sst_path = self.next_sstable_path();
+        SSTable::write_with(
+            self.env.as_ref(),
+            &sst_path,
+            &entries,
+            self.encryptor.as_ref(),
+        )
+        .await?;
+        // Record the high-water version before the WAL that carries it is reset.
+        let high_water = self.last_version.load(Ordering::SeqCst);
+        Manifest {
+            last_version: high_water,
+            ..Manifest::default()
+        }
+        .store_with(self.env.as_ref(), &self.options.data_dir)
+        .await?;
+        self.table_high_water.fetch_max(high_water, Ordering::SeqCst);
         self.sstables.lock().await.push(SSTable::new(sst_path));
         mem.clear();
         if self.options.wal_enabled {
-            self.wal.lock().await.reset().await?;
+            wal.reset().await?;
         }
         Ok(())
     }
 
+    /// Merges the tables in `data_dir`, then moves tables that have gone cold to the
+    /// secondary directory and merges the cold tables once there are too many.
     pub async fn compact(&self) -> Result<()> {
-        let tables = self.sstables.lock().await.clone();
-        if tables.len() < 2 {
+        self.compact_tier(false, 2).await?;
+        self.tier_cold_tables().await?;
+        if self.options.tiering.is_some() {
+            self.compact_tier(true, MAX_COLD_TABLES + 1).await?;
+        }
+        Ok(())
+    }
+
+    /// Merges the hot or the cold tables into one table in the same directory once there
+    /// are at least `min_tables` of them.
+    async fn compact_tier(&self, cold: bool, min_tables: usize) -> Result<()> {
+        let tables: Vec<SSTable> = self
+            .sstables
+            .lock()
+            .await
+            .iter()
+            .filter(|table| self.is_cold(table) == cold)
+            .cloned()
+            .collect();
+        if tables.len() < min_tables {
             return Ok(());
         }
         metrics::counter!("lsm_compact_total").increment(1);
-        let output = format!("{}/sst-compacted-{}.db", self.options.data_dir, chrono_suffix());
-        compact_tables(&output, &tables, self.encryptor.as_ref()).await?;
+        let level = tables.iter().map(SSTable::level).max().unwrap_or(0) + 1;
+        let output = match (&self.options.tiering, cold) {
+            (Some(tiering), true) => self.next_sstable_path_in(&tiering.cold_dir, level),
+            _ => self.next_sstable_path_at(level),
+        };
+        compact_tables(
+            self.env.as_ref(),
+            &output,
+            &tables,
+            self.encryptor.as_ref(),
+            self.history_horizon.load(Ordering::SeqCst),
+        )
+        .await?;
         info!("compacted {} tables into {}", tables.len(), output);
-        *self.sstables.lock().await = vec![SSTable::new(output)];
+        {
+            // Tables flushed while compaction ran are kept; only the inputs are replaced.
+            let mut current = self.sstables.lock().await;
+            current.retain(|table| !tables.iter().any(|input| input.path == table.path));
+            current.insert(0, SSTable::new(output));
+        }
+        for table in &tables {
+            self.env.remove_file(&table.path).await?;
+        }
         Ok(())
     }
+
+    /// Links an externally built SSTable (see [`crate::ingest::SstBuilder`]) into the engine,
+    /// bypassing the WAL and memtable. The file is copied into `data_dir`, so `path` may live
+    /// anywhere; its rows become visible all at once. Returns the highest version it contains.
+    pub async fn ingest_sstable(&self, path: &str) -> Result<Version> {
+        let entries = SSTable::new(path.to_string())
+            .load_with(self.env.as_ref(), self.encryptor.as_ref())
+            .await?;
+        if entries.is_empty() {
+            return Err(anyhow!("refusing to ingest empty sstable {path}"));
+        }
+        let mut max_version = 0;
+        for pair in entries.windows(2) {
+            if pair[0].key >= pair[1].key {
+                return Err(anyhow!("sstable {path} is not sorted"));
+            }
+        }
+        for entry in &entries {
+            let (_, version) = split_versioned_key(&entry.key)
+                .ok_or_else(|| anyhow!("sstable {path} contains an unversioned key"))?;
+            max_version = max_version.max(version);
+        }
+        metrics::counter!("lsm_ingest_total").increment(1);
+
+        let bytes = self.env.read_file(path).await?;
+        let dest = self.next_sstable_path();
+        let tmp_path = format!("{dest}.tmp");
+        let mut file = self.env.create(&tmp_path).await?;
+        file.append(&bytes).await?;
+        file.sync().await?;
+        drop(file);
+
+        // Serialises manifest updates with flush. The manifest is raised before the table
+        // becomes visible so a crash can never leave data above the recorded high-water mark.
+        let _wal = self.wal.lock().await;
+        let high_water = self.last_version.load(Ordering::SeqCst).max(max_version);
+        Manifest {
+            last_version: high_water,
+            ..Manifest::default()
+        }
+        .store_with(self.env.as_ref(), &self.options.data_dir)
+        .await?;
+        self.env.rename(&tmp_path, &dest).await?;
+        self.observe_version(max_version);
+        self.table_high_water.fetch_max(max_version, Ordering::SeqCst);
+        self.sstables.lock().await.push(SSTable::new(dest.clone()));
+        info!("ingested {} entries from {} as {}", entries.len(), path, dest);
+        Ok(max_version)
+    }
+
+    /// Moves every hot table matching the tiering policy into the cold directory and
+    /// returns how many were moved.
+    pub async fn tier_cold_tables(&self) -> Result<usize> {
+        let Some(tiering) = &self.options.tiering else {
+            return Ok(0);
+        };
+        let tables = self.sstables.lock().await.clone();
+        let mut moved = 0;
+        for table in tables.iter().filter(|table| !self.is_cold(table)) {
+            if !self.is_cold_candidate(table, tiering).await? {
+                continue;
+            }
+            let dest = format!("{}/{}", tiering.cold_dir, table.file_name());
+            let bytes = self.env.read_file(&table.path).await?;
+            let tmp_path = format!("{dest}.tmp");
+            let mut file = self.env.create(&tmp_path).await?;
+            file.append(&bytes).await?;
+            file.sync().await?;
+            drop(file);
+            self.env.rename(&tmp_path, &dest).await?;
+            {
+                let mut current = self.sstables.lock().await;
+                match current.iter_mut().find(|t| t.path == table.path) {
+                    Some(slot) => *slot = SSTable::new(dest.clone()),
+                    None => {
+                        // Compacted away while we copied it.
+                        drop(current);
+                        self.env.remove_file(&dest).await?;
+                        continue;
+                    }
+                }
+            }
+            self.env.remove_file(&table.path).await?;
+            info!("moved cold table {} to {}", table.path, dest);
+            moved += 1;
+        }
+        if moved > 0 {
+            metrics::counter!("lsm_tiered_tables_total").increment(moved as u64);
+        }
+        Ok(moved)
+    }
+
+    /// Rewrites every file older than [`FORMAT_VERSION`] in the current format: the memtable
+    /// is flushed so the WAL restarts with a header, legacy tables are re-encoded in place and
+    /// the manifest is stamped. Run offline, through the `upgrade` command.
+    pub async fn upgrade(&self) -> Result<UpgradeReport> {
+        let mut report = UpgradeReport::default();
+        let wal_path = &self.options.wal_path;
+        report.wal_rewritten =
+            Wal::format_version_with(self.env.as_ref(), wal_path).await? < FORMAT_VERSION;
+        self.flush().await?;
+        let mut wal = self.wal.lock().await;
+        if Wal::format_version_with(self.env.as_ref(), wal_path).await? < FORMAT_VERSION {
+            // Nothing was flushed, or the WAL is disabled and flush left it alone.
+            wal.reset().await?;
+        }
+        let tables = self.sstables.lock().await.clone();
+        for table in &tables {
+            if table.format_version_with(self.env.as_ref()).await? >= FORMAT_VERSION {
+                continue;
+            }
+            let entries = table
+                .load_with(self.env.as_ref(), self.encryptor.as_ref())
+                .await?;
+            SSTable::write_with(
+                self.env.as_ref(),
+                &table.path,
+                &entries,
+                self.encryptor.as_ref(),
+            )
+            .await?;
+            report.tables_rewritten += 1;
+        }
+        Manifest {
+            last_version: self.last_version.load(Ordering::SeqCst),
+            ..Manifest::default()
+        }
+        .store_with(self.env.as_ref(), &self.options.data_dir)
+        .await?;
+        info!(
+            "upgraded {} to format v{}: {} tables rewritten",
+            self.options.data_dir, FORMAT_VERSION, report.tables_rewritten
+        );
+        Ok(report)
+    }
+
+    /// Reads every SSTable and the WAL, verifying checksums, AES-GCM tags and record framing.
+    /// Files are checked one at a time, yielding in between so foreground work keeps priority.
+    pub async fn scrub(&self) -> Result<ScrubReport> {
+        let mut report = ScrubReport::default();
+        let tables = self.sstables.lock().await.clone();
+        for table in &tables {
+            let result = match table.load_with(self.env.as_ref(), self.encryptor.as_ref()).await {
+                Ok(_) => self.env.read_file(&table.path).await.map(|bytes| bytes.len() as u64),
+                Err(err) => Err(err),
+            };
+            record_scrub_result(&mut report, &table.path, result);
+            tokio::task::yield_now().await;
+        }
+        if self.options.wal_enabled {
+            // Holding the WAL lock keeps appends from racing the read.
+            let _wal = self.wal.lock().await;
+            let result =
+                Wal::verify_with(self.env.as_ref(), &self.options.wal_path, self.encryptor.as_ref())
+                    .await;
+            record_scrub_result(&mut report, &self.options.wal_path, result);
+        }
+        metrics::counter!("lsm_scrub_files_checked_total").increment(report.files_checked as u64);
+        metrics::counter!("lsm_scrub_corrupt_files_total").increment(report.corrupt.len() as u64);
+        Ok(report)
+    }
+
+    /// Renames a corrupt SSTable to `{path}.quarantined` and stops reading from it. The
+    /// active WAL cannot be quarantined.
+    pub async fn quarantine(&self, path: &str) -> Result<()> {
+        let mut tables = self.sstables.lock().await;
+        let idx = tables
+            .iter()
+            .position(|table| table.path == path)
+            .ok_or_else(|| anyhow!("{path} is not a live sstable"))?;
+        self.env.rename(path, &format!("{path}.quarantined")).await?;
+        tables.remove(idx);
+        metrics::counter!("lsm_quarantined_files_total").increment(1);
+        info!("quarantined corrupt table {path}");
+        Ok(())
+    }
+
+    /// Every stored version of the first `limit` keys after `after`; see
+    /// [`StorageEngine::scan_versions`]. At most `limit` keys are held while tables are read.
+    pub async fn scan_versions(&self, after: Option<&[u8]>, limit: usize) -> Result<Vec<StoredVersion>> {
+        let start = after.unwrap_or_default();
+        let versions = self
+            .collect_versions(start, |key| after.is_none_or(|after| key > after), limit)
+            .await?;
+        Ok(versions
+            .into_iter()
+            .flat_map(|(key, versions)| {
+                versions.into_iter().map(move |(version, value)| StoredVersion {
+                    key: key.clone(),
+                    version,
+                    range_end: range_tombstone_end(&value).map(<[u8]>::to_vec),
+                    value: decode_value(&value),
+                })
+            })
+            .collect())
+    }
+
+    /// Ingests the versions in `versions` this engine lacks, each at its own version, and
+    /// returns how many it wrote. Keys are never deleted, so local history and writes newer
+    /// than the copy survive.
+    pub async fn restore_versions(&self, versions: Vec<StoredVersion>) -> Result<usize> {
+        let (Some(first), Some(last)) = (
+            versions.iter().map(|entry| &entry.key).min(),
+            versions.iter().map(|entry| &entry.key).max(),
+        ) else {
+            return Ok(0);
+        };
+        let held = self
+            .collect_versions(first, |key| first.as_slice() <= key && key <= last.as_slice(), usize::MAX)
+            .await?;
+        let mut missing: Vec<&StoredVersion> = versions
+            .iter()
+            .filter(|entry| {
+                held.get(&entry.key)
+                    .is_none_or(|stored| !stored.contains_key(&entry.version))
+            })
+            .collect();
+        missing.sort_by_key(|entry| encode_versioned_key(&entry.key, entry.version));
+        missing.dedup_by(|a, b| a.key == b.key && a.version == b.version);
+        let Some(newest) = missing.iter().map(|entry| entry.version).max() else {
+            return Ok(0);
+        };
+        let id = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+        let path = format!("{}/repair-{:020}.sst", self.options.data_dir, id);
+        let mut builder =
+            SstBuilder::create_with(self.env.clone(), &path, newest, self.encryptor.as_ref())
+                .await?;
+        for entry in &missing {
+            let added = match &entry.range_end {
+                Some(end) => builder.delete_range_version(&entry.key, end, entry.version).await,
+                None => {
+                    builder
+                        .put_version(&entry.key, entry.version, entry.value.as_deref())
+                        .await
+                }
+            };
+            if let Err(err) = added {
+                builder.abandon().await?;
+                return Err(err);
+            }
+        }
+        builder.finish().await?;
+        let result = self.ingest_sstable(&path).await;
+        self.env.remove_file(&path).await?;
+        result?;
+        info!("restored {} versions in {}", missing.len(), self.options.data_dir);
+        Ok(missing.len())
+    }
+
+    /// Stored versions of the keys from `start` on that pass `in_range`, keeping the first
+    /// `limit` keys.
+    async fn collect_versions(
+        &self,
+        start: &[u8],
+        in_range: impl Fn(&[u8]) -> bool,
+        limit: usize,
+    ) -> Result<BTreeMap<Vec<u8>, BTreeMap<Version, Vec<u8>>>> {
+        let mut versions = BTreeMap::new();
+        let tables = self.sstables.lock().await.clone();
+        for table in &tables {
+            let entries = table.load_with(self.env.as_ref(), self.encryptor.as_ref()).await?;
+            collect_versions_into(
+                entries.iter().map(|entry| (&entry.key, &entry.value)),
+                &in_range,
+                limit,
+                &mut versions,
+            );
+        }
+        let mem = self.memtable.lock().await;
+        collect_versions_into(mem.range_from(start.to_vec()), &in_range, limit, &mut versions);
+        Ok(versions)
+    }
+
+    fn is_cold(&self, table: &SSTable) -> bool {
+        match &self.options.tiering {
+            Some(tiering) => table.path.starts_with(&format!("{}/", tiering.cold_dir)),
+            None => false,
+        }
+    }
+
+    async fn is_cold_candidate(&self, table: &SSTable, tiering: &TieringOptions) -> Result<bool> {
+        if tiering.min_level.is_some_and(|level| table.level() >= level) {
+            return Ok(true);
+        }
+        if let Some(min_age) = tiering.min_age_secs {
+            let modified = self.env.modified(&table.path).await?;
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default()
+                .as_secs();
+            return Ok(age >= min_age);
+        }
+        Ok(false)
+    }
+
+    fn next_sstable_path(&self) -> String {
+        self.next_sstable_path_at(0)
+    }
+
+    fn next_sstable_path_at(&self, level: u32) -> String {
+        self.next_sstable_path_in(&self.options.data_dir, level)
+    }
+
+    fn next_sstable_path_in(&self, dir: &str, level: u32) -> String {
+        let id = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+        if level == 0 {
+            format!("{dir}/sst-{id:020}.db")
+        } else {
+            format!("{dir}/sst-{id:020}-L{level}.db")
+        }
+    }
 }
 
-fn encode_value(value: Option<&[u8]>) -> Vec<u8> {
+pub(crate) fn encode_value(value: Option<&[u8]>) -> Vec<u8> {
     match value {
         Some(v) => {
             let mut out = Vec::with_capacity(1 + v.len());
@@ -171,7 +788,65 @@ fn encode_value(value: Option<&[u8]>) -> Vec<u8> {
     }
 }
 
-fn decode_value(value: &[u8]) -> Option<Vec<u8>> {
+/// Value tag of a range tombstone. The entry is keyed by the range's start at the
+/// tombstone's version and holds the exclusive end; it hides every older version of the
+/// keys in the range, the start key included.
+const RANGE_TOMBSTONE_TAG: u8 = 2;
+
+pub(crate) fn encode_range_tombstone(end: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + end.len());
+    out.push(RANGE_TOMBSTONE_TAG);
+    out.extend_from_slice(end);
+    out
+}
+
+/// End of the range a stored value deletes, if it is a range tombstone.
+pub(crate) fn range_tombstone_end(value: &[u8]) -> Option<&[u8]> {
+    match value.split_first() {
+        Some((&RANGE_TOMBSTONE_TAG, end)) => Some(end),
+        _ => None,
+    }
+}
+
+/// Range tombstones gathered from the memtable and tables, as `(start, end, version)`.
+#[derive(Debug, Default)]
+pub(crate) struct RangeTombstones(Vec<(Vec<u8>, Vec<u8>, Version)>);
+
+impl RangeTombstones {
+    /// Adds the range tombstones among `entries` written at or below `snapshot`.
+    pub(crate) fn collect<'a>(
+        &mut self,
+        entries: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+        snapshot: Version,
+    ) {
+        for (versioned_key, value) in entries {
+            let Some(end) = range_tombstone_end(value) else {
+                continue;
+            };
+            if let Some((start, version)) = split_versioned_key(versioned_key) {
+                if version <= snapshot {
+                    self.0.push((start.to_vec(), end.to_vec(), version));
+                }
+            }
+        }
+    }
+
+    /// Whether a tombstone newer than `version` covers `key`.
+    pub(crate) fn hides(&self, key: &[u8], version: Version) -> bool {
+        self.0.iter().any(|(start, end, deleted)| {
+            version < *deleted && start.as_slice() <= key && key < end.as_slice()
+        })
+    }
+
+    /// Drops the keys whose newest version in `latest` a tombstone hides.
+    pub(crate) fn apply(&self, latest: &mut BTreeMap<Vec<u8>, (Version, Vec<u8>)>) {
+        if !self.0.is_empty() {
+            latest.retain(|key, (version, _)| !self.hides(key, *version));
+        }
+    }
+}
+
+pub(crate) fn decode_value(value: &[u8]) -> Option<Vec<u8>> {
     if value.is_empty() {
         return None;
     }
@@ -182,7 +857,7 @@ fn decode_value(value: &[u8]) -> Option<Vec<u8>> {
     }
 }
 
-fn encode_versioned_key(key: &[u8], version: Version) -> Vec<u8> {
+pub(crate) fn encode_versioned_key(key: &[u8], version: Version) -> Vec<u8> {
     let mut out = Vec::with_capacity(key.len() + 8);
     out.extend_from_slice(key);
     out.extend_from_slice(&version.to_be_bytes());
@@ -201,21 +876,201 @@ fn is_key_match(versioned_key: &[u8], key: &[u8], snapshot: Version) -> bool {
     version <= snapshot
 }
 
-fn mem_get_latest<'a>(mem: &'a MemTable, key: &[u8], snapshot: Version) -> Option<&'a Vec<u8>> {
-    let mut upper = Vec::with_capacity(key.len() + 8);
-    upper.extend_from_slice(key);
-    upper.extend_from_slice(&snapshot.to_be_bytes());
+/// Newest entry for exactly `key` at or below `snapshot`, with its version.
+pub(crate) fn mem_get_versioned<'a>(
+    mem: &'a MemTable,
+    key: &[u8],
+    snapshot: Version,
+) -> Option<(Version, &'a Vec<u8>)> {
+    let upper = encode_versioned_key(key, snapshot);
     mem.range_up_to(upper)
         .rev()
-        .find(|(k, _)| k.starts_with(key))
-        .map(|(_, v)| v)
+        .take_while(|(k, _)| k.as_slice() >= key)
+        .find_map(|(k, v)| match split_versioned_key(k) {
+            Some((found, version)) if found == key => Some((version, v)),
+            _ => None,
+        })
+}
+
+pub(crate) fn split_versioned_key(versioned_key: &[u8]) -> Option<(&[u8], Version)> {
+    if versioned_key.len() < 8 {
+        return None;
+    }
+    let (key, version_bytes) = versioned_key.split_at(versioned_key.len() - 8);
+    let version = Version::from_be_bytes(version_bytes.try_into().ok()?);
+    Some((key, version))
 }
 
-fn chrono_suffix() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis();
-    ts.to_string()
+/// Folds versioned entries under `prefix` into `latest`, keeping the newest version at or
+/// below `snapshot` for each key. Later sources win ties, so callers feed oldest data first.
+pub(crate) fn collect_latest<'a>(
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+    prefix: &[u8],
+    snapshot: Version,
+    latest: &mut BTreeMap<Vec<u8>, (Version, Vec<u8>)>,
+) {
+    collect_latest_matching(entries, |key| key.starts_with(prefix), snapshot, latest)
+}
+
+/// Like [`collect_latest`] for the keys in `start..end`.
+pub(crate) fn collect_latest_in_range<'a>(
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+    start: &[u8],
+    end: &[u8],
+    snapshot: Version,
+    latest: &mut BTreeMap<Vec<u8>, (Version, Vec<u8>)>,
+) {
+    collect_latest_matching(entries, |key| start <= key && key < end, snapshot, latest)
+}
+
+/// Longest common prefix of `start` and `end`. Every key between them starts with it, and
+/// so do their versioned keys, which lets memtable range walks stop early.
+pub(crate) fn range_prefix<'a>(start: &'a [u8], end: &[u8]) -> &'a [u8] {
+    let len = start.iter().zip(end).take_while(|(a, b)| a == b).count();
+    &start[..len]
+}
+
+fn collect_latest_matching<'a>(
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+    matches: impl Fn(&[u8]) -> bool,
+    snapshot: Version,
+    latest: &mut BTreeMap<Vec<u8>, (Version, Vec<u8>)>,
+) {
+    for (versioned_key, value) in entries {
+        let Some((key, version)) = split_versioned_key(versioned_key) else {
+            continue;
+        };
+        if !matches(key) || version > snapshot {
+            continue;
+        }
+        match latest.get(key) {
+            Some((seen, _)) if *seen > version => {}
+            _ => {
+                latest.insert(key.to_vec(), (version, value.clone()));
+            }
+        }
+    }
+}
+
+fn collect_versions_into<'a>(
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a Vec<u8>)>,
+    in_range: impl Fn(&[u8]) -> bool,
+    limit: usize,
+    versions: &mut BTreeMap<Vec<u8>, BTreeMap<Version, Vec<u8>>>,
+) {
+    for (versioned_key, value) in entries {
+        let Some((key, version)) = split_versioned_key(versioned_key) else {
+            continue;
+        };
+        if !in_range(key) {
+            continue;
+        }
+        let full = versions.len() >= limit;
+        if full && versions.last_key_value().is_some_and(|(last, _)| key > last.as_slice()) {
+            continue;
+        }
+        versions
+            .entry(key.to_vec())
+            .or_default()
+            .insert(version, value.clone());
+        if versions.len() > limit {
+            versions.pop_last();
+        }
+    }
+}
+
+pub(crate) fn into_live_entries(
+    latest: BTreeMap<Vec<u8>, (Version, Vec<u8>)>,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    latest
+        .into_iter()
+        .filter_map(|(key, (_, value))| decode_value(&value).map(|value| (key, value)))
+        .collect()
+}
+
+/// Numeric id of an SSTable file name (`sst-{id}[-L{level}].db`); also accepts the older
+/// `sst-compacted-{ms}.db` form.
+fn sstable_file_id(name: &str) -> Option<u64> {
+    let stem = name.strip_prefix("sst-")?.strip_suffix(".db")?;
+    let stem = stem.strip_prefix("compacted-").unwrap_or(stem);
+    let stem = stem.split_once("-L").map_or(stem, |(id, _)| id);
+    stem.parse().ok()
+}
+
+fn record_scrub_result(report: &mut ScrubReport, path: &str, result: Result<u64>) {
+    report.files_checked += 1;
+    match result {
+        Ok(bytes) => report.bytes_checked += bytes,
+        Err(err) => {
+            tracing::warn!("scrub found corrupt file {path}: {err}");
+            report.corrupt.push(CorruptFile {
+                path: path.to_string(),
+                reason: err.to_string(),
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl StorageEngine for LsmEngine {
+    async fn get(&self, key: &[u8], snapshot: Version) -> Result<Option<Vec<u8>>> {
+        LsmEngine::get(self, key, snapshot).await
+    }
+
+    async fn put(&self, key: &[u8], value: &[u8], version: Version) -> Result<()> {
+        LsmEngine::put(self, key, value, version).await
+    }
+
+    async fn delete(&self, key: &[u8], version: Version) -> Result<()> {
+        LsmEngine::delete(self, key, version).await
+    }
+
+    async fn scan(&self, prefix: &[u8], snapshot: Version) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        LsmEngine::scan(self, prefix, snapshot).await
+    }
+
+    async fn scan_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        snapshot: Version,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        LsmEngine::scan_range(self, start, end, snapshot).await
+    }
+
+    async fn write_batch(&self, batch: WriteBatch, version: Version) -> Result<()> {
+        LsmEngine::write_batch(self, batch, version).await
+    }
+
+    async fn snapshot(&self) -> Result<Snapshot> {
+        Ok(LsmEngine::snapshot(self))
+    }
+
+    async fn flush(&self) -> Result<()> {
+        LsmEngine::flush(self).await
+    }
+
+    async fn compact(&self) -> Result<()> {
+        LsmEngine::compact(self).await
+    }
+
+    fn set_history_horizon(&self, version: Version) {
+        LsmEngine::set_history_horizon(self, version)
+    }
+
+    async fn scrub(&self) -> Result<ScrubReport> {
+        LsmEngine::scrub(self).await
+    }
+
+    async fn quarantine(&self, path: &str) -> Result<()> {
+        LsmEngine::quarantine(self, path).await
+    }
+
+    async fn scan_versions(&self, after: Option<&[u8]>, limit: usize) -> Result<Vec<StoredVersion>> {
+        LsmEngine::scan_versions(self, after, limit).await
+    }
+
+    async fn restore_versions(&self, versions: Vec<StoredVersion>) -> Result<usize> {
+        LsmEngine::restore_versions(self, versions).await
+    }
 }
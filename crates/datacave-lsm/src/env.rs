@@ -0,0 +1,149 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+pub type SharedEnv = Arc<dyn Env>;
+
+/// File system operations used by the WAL, SSTables and engine recovery.
+///
+/// Writes only become durable once [`WritableFile::sync`] returns; `rename` and
+/// `remove_file` are treated as durable when they return.
+#[async_trait]
+pub trait Env: Send + Sync + fmt::Debug {
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>>;
+
+    /// Opens `path` for appending, creating it if missing.
+    async fn open_append(&self, path: &str) -> Result<Box<dyn WritableFile>>;
+
+    /// Creates `path`, truncating any existing contents.
+    async fn create(&self, path: &str) -> Result<Box<dyn WritableFile>>;
+
+    /// Lists file names directly inside `dir`; a missing directory lists as empty.
+    async fn list_dir(&self, dir: &str) -> Result<Vec<String>>;
+
+    async fn rename(&self, from: &str, to: &str) -> Result<()>;
+
+    async fn remove_file(&self, path: &str) -> Result<()>;
+
+    /// Last modification time of `path`.
+    async fn modified(&self, path: &str) -> Result<SystemTime>;
+}
+
+#[async_trait]
+pub trait WritableFile: Send + Sync + fmt::Debug {
+    async fn append(&mut self, data: &[u8]) -> Result<()>;
+
+    async fn sync(&mut self) -> Result<()>;
+
+    /// Discards the file contents; subsequent appends start at offset zero.
+    async fn truncate(&mut self) -> Result<()>;
+}
+
+/// [`Env`] backed by the local file system through `tokio::fs`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalEnv;
+
+impl LocalEnv {
+    pub fn shared() -> SharedEnv {
+        Arc::new(LocalEnv)
+    }
+}
+
+#[derive(Debug)]
+struct LocalFile {
+    file: File,
+}
+
+#[async_trait]
+impl WritableFile for LocalFile {
+    async fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        self.file.sync_data().await?;
+        Ok(())
+    }
+
+    async fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0).await?;
+        self.file.seek(std::io::SeekFrom::Start(0)).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Env for LocalEnv {
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        Ok(tokio::fs::read(path).await?)
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)
+            .await?;
+        Ok(Box::new(LocalFile { file }))
+    }
+
+    async fn create(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        let file = File::create(path).await?;
+        Ok(Box::new(LocalFile { file }))
+    }
+
+    async fn list_dir(&self, dir: &str) -> Result<Vec<String>> {
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        tokio::fs::rename(from, to).await?;
+        sync_parent_dir(to).await
+    }
+
+    async fn remove_file(&self, path: &str) -> Result<()> {
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn modified(&self, path: &str) -> Result<SystemTime> {
+        Ok(tokio::fs::metadata(path).await?.modified()?)
+    }
+}
+
+#[cfg(unix)]
+async fn sync_parent_dir(path: &str) -> Result<()> {
+    let parent = match std::path::Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => return Ok(()),
+    };
+    File::open(parent).await?.sync_all().await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn sync_parent_dir(_path: &str) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use crate::env::{Env, WritableFile};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// File system operations that can be targeted by injected faults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultOp {
+    Read,
+    Create,
+    Append,
+    Sync,
+    Truncate,
+    ListDir,
+    Rename,
+    Remove,
+}
+
+impl FaultOp {
+    fn is_mutation(self) -> bool {
+        !matches!(self, FaultOp::Read | FaultOp::ListDir)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FaultRule {
+    op: FaultOp,
+    path_contains: Option<String>,
+    once: bool,
+}
+
+#[derive(Debug)]
+struct SimFile {
+    data: Vec<u8>,
+    synced_len: usize,
+    modified: SystemTime,
+}
+
+impl Default for SimFile {
+    fn default() -> Self {
+        Self {
+            data: Vec::new(),
+            synced_len: 0,
+            modified: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+    files: BTreeMap<String, SimFile>,
+    rules: Vec<FaultRule>,
+    crash_countdown: Option<usize>,
+    crashed: bool,
+    epoch: u64,
+}
+
+impl FaultState {
+    fn check(&mut self, op: FaultOp, path: &str) -> Result<()> {
+        if self.crashed {
+            return Err(anyhow!("simulated crash: {op:?} {path}"));
+        }
+        if op.is_mutation() {
+            if let Some(remaining) = self.crash_countdown.as_mut() {
+                if *remaining == 0 {
+                    self.crashed = true;
+                    self.crash_countdown = None;
+                    return Err(anyhow!("simulated crash: {op:?} {path}"));
+                }
+                *remaining -= 1;
+            }
+        }
+        let hit = self.rules.iter().position(|rule| {
+            rule.op == op
+                && rule
+                    .path_contains
+                    .as_deref()
+                    .map(|needle| path.contains(needle))
+                    .unwrap_or(true)
+        });
+        if let Some(idx) = hit {
+            if self.rules[idx].once {
+                self.rules.remove(idx);
+            }
+            return Err(anyhow!("injected fault: {op:?} {path}"));
+        }
+        Ok(())
+    }
+}
+
+/// In-memory [`Env`] that models power loss and injects I/O failures.
+///
+/// Every file tracks how much of its contents has been synced. [`FaultInjectionEnv::restart`]
+/// discards unsynced bytes and invalidates open handles, which is what an unclean shutdown
+/// does to a real disk.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjectionEnv {
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjectionEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails the next matching operation, optionally only for paths containing `path_contains`.
+    pub fn fail_once(&self, op: FaultOp, path_contains: Option<&str>) {
+        self.add_rule(op, path_contains, true);
+    }
+
+    /// Fails every matching operation until [`FaultInjectionEnv::clear_faults`].
+    pub fn fail_always(&self, op: FaultOp, path_contains: Option<&str>) {
+        self.add_rule(op, path_contains, false);
+    }
+
+    pub fn clear_faults(&self) {
+        self.state.lock().unwrap().rules.clear();
+    }
+
+    /// Lets `ops` more mutating operations succeed, then crashes on the next one.
+    pub fn crash_after(&self, ops: usize) {
+        self.state.lock().unwrap().crash_countdown = Some(ops);
+    }
+
+    /// Crashes immediately: every operation fails until [`FaultInjectionEnv::restart`].
+    pub fn crash(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.crashed = true;
+        state.crash_countdown = None;
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    /// Simulates a reboot after power loss: unsynced bytes are dropped and open handles die.
+    pub fn restart(&self) {
+        self.reboot(|_| 0);
+    }
+
+    /// Like [`FaultInjectionEnv::restart`], but keeps a random prefix of each file's unsynced
+    /// tail to model torn writes.
+    pub fn restart_torn(&self, rng: &mut impl Rng) {
+        self.reboot(|unsynced| rng.gen_range(0..=unsynced));
+    }
+
+    fn reboot(&self, mut keep_unsynced: impl FnMut(usize) -> usize) {
+        let mut state = self.state.lock().unwrap();
+        for file in state.files.values_mut() {
+            let unsynced = file.data.len().saturating_sub(file.synced_len);
+            let keep = keep_unsynced(unsynced).min(unsynced);
+            file.data.truncate(file.synced_len + keep);
+            file.synced_len = file.data.len();
+        }
+        state.crashed = false;
+        state.crash_countdown = None;
+        state.epoch += 1;
+    }
+
+    /// Current contents of `path`, including unsynced bytes.
+    pub fn file_contents(&self, path: &str) -> Option<Vec<u8>> {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .get(path)
+            .map(|file| file.data.clone())
+    }
+
+    /// Flips the bits of the byte at `offset`, simulating silent media corruption.
+    pub fn corrupt_byte(&self, path: &str, offset: usize) {
+        if let Some(byte) = self
+            .state
+            .lock()
+            .unwrap()
+            .files
+            .get_mut(path)
+            .and_then(|file| file.data.get_mut(offset))
+        {
+            *byte ^= 0xff;
+        }
+    }
+
+    /// Backdates or advances a file's modification time.
+    pub fn set_modified(&self, path: &str, modified: SystemTime) {
+        if let Some(file) = self.state.lock().unwrap().files.get_mut(path) {
+            file.modified = modified;
+        }
+    }
+
+    fn add_rule(&self, op: FaultOp, path_contains: Option<&str>, once: bool) {
+        self.state.lock().unwrap().rules.push(FaultRule {
+            op,
+            path_contains: path_contains.map(str::to_string),
+            once,
+        });
+    }
+
+    fn handle(&self, path: &str, epoch: u64) -> Box<dyn WritableFile> {
+        Box::new(FaultFile {
+            state: self.state.clone(),
+            path: path.to_string(),
+            epoch,
+        })
+    }
+}
+
+#[async_trait]
+impl Env for FaultInjectionEnv {
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        state.check(FaultOp::Read, path)?;
+        state
+            .files
+            .get(path)
+            .map(|file| file.data.clone())
+            .ok_or_else(|| anyhow!("file not found: {path}"))
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        let epoch = {
+            let mut state = self.state.lock().unwrap();
+            if !state.files.contains_key(path) {
+                state.check(FaultOp::Create, path)?;
+                state.files.insert(path.to_string(), SimFile::default());
+            }
+            state.epoch
+        };
+        Ok(self.handle(path, epoch))
+    }
+
+    async fn create(&self, path: &str) -> Result<Box<dyn WritableFile>> {
+        let epoch = {
+            let mut state = self.state.lock().unwrap();
+            state.check(FaultOp::Create, path)?;
+            state.files.insert(path.to_string(), SimFile::default());
+            state.epoch
+        };
+        Ok(self.handle(path, epoch))
+    }
+
+    async fn list_dir(&self, dir: &str) -> Result<Vec<String>> {
+        let mut state = self.state.lock().unwrap();
+        state.check(FaultOp::ListDir, dir)?;
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        Ok(state
+            .files
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(FaultOp::Rename, from)?;
+        let file = state
+            .files
+            .remove(from)
+            .ok_or_else(|| anyhow!("file not found: {from}"))?;
+        state.files.insert(to.to_string(), file);
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &str) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.check(FaultOp::Remove, path)?;
+        state.files.remove(path);
+        Ok(())
+    }
+
+    async fn modified(&self, path: &str) -> Result<SystemTime> {
+        let mut state = self.state.lock().unwrap();
+        state.check(FaultOp::Read, path)?;
+        state
+            .files
+            .get(path)
+            .map(|file| file.modified)
+            .ok_or_else(|| anyhow!("file not found: {path}"))
+    }
+}
+
+#[derive(Debug)]
+struct FaultFile {
+    state: Arc<Mutex<FaultState>>,
+    path: String,
+    epoch: u64,
+}
+
+impl FaultFile {
+    fn with_file<T>(&self, op: FaultOp, f: impl FnOnce(&mut SimFile) -> T) -> Result<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.epoch != self.epoch {
+            return Err(anyhow!("stale file handle: {}", self.path));
+        }
+        state.check(op, &self.path)?;
+        let file = state
+            .files
+            .get_mut(&self.path)
+            .ok_or_else(|| anyhow!("file removed: {}", self.path))?;
+        Ok(f(file))
+    }
+}
+
+#[async_trait]
+impl WritableFile for FaultFile {
+    async fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.with_file(FaultOp::Append, |file| {
+            file.data.extend_from_slice(data);
+            file.modified = SystemTime::now();
+        })
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        self.with_file(FaultOp::Sync, |file| file.synced_len = file.data.len())
+    }
+
+    async fn truncate(&mut self) -> Result<()> {
+        self.with_file(FaultOp::Truncate, |file| {
+            file.data.clear();
+            file.synced_len = 0;
+            file.modified = SystemTime::now();
+        })
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::{anyhow, Result};
+
+/// Version of the on-disk layout written by this build: SSTable and WAL framing, the
+/// versioned key (`key || version_be`) and the put/tombstone value tag. Bump it whenever
+/// any of those change, and teach the readers and [`crate::LsmEngine::upgrade`] about the
+/// previous layout.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Files written before format headers existed.
+pub const LEGACY_FORMAT_VERSION: u16 = 0;
+
+/// Files rewritten by [`crate::LsmEngine::upgrade`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpgradeReport {
+    pub tables_rewritten: usize,
+    pub wal_rewritten: bool,
+}
+
+const HEADER_LEN: usize = 6;
+
+/// `magic || format_version_le`, written at the start of a file.
+pub fn encode_header(magic: &[u8; 4]) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(magic);
+    header[4..].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header
+}
+
+/// Splits a header off `buf`, returning the format version and the remaining bytes.
+/// Data without the magic is [`LEGACY_FORMAT_VERSION`] and is returned whole.
+pub fn split_header<'a>(magic: &[u8; 4], buf: &'a [u8]) -> (u16, &'a [u8]) {
+    match buf.strip_prefix(magic.as_slice()) {
+        Some(rest) if rest.len() >= 2 => (u16::from_le_bytes([rest[0], rest[1]]), &rest[2..]),
+        _ => (LEGACY_FORMAT_VERSION, buf),
+    }
+}
+
+/// Fails when `path` was written by a newer build than this one.
+pub fn check_readable(path: &str, version: u16) -> Result<()> {
+    if version > FORMAT_VERSION {
+        return Err(anyhow!(
+            "{path} uses on-disk format v{version}, but this build reads up to v{FORMAT_VERSION}"
+        ));
+    }
+    Ok(())
+}
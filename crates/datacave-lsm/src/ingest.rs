@@ -0,0 +1,179 @@
+use crate::encryption::DataEncryptor;
+use crate::engine::{encode_range_tombstone, encode_value, encode_versioned_key};
+use crate::env::{LocalEnv, SharedEnv, WritableFile};
+use crate::format::encode_header;
+use crate::sstable::{SST_FOOTER_MAGIC, SST_MAGIC};
+use anyhow::{anyhow, Result};
+use datacave_core::mvcc::Version;
+use std::fmt;
+use std::sync::Arc;
+
+/// Encoded entries gathered in memory before they are appended to the table file.
+const WRITE_CHUNK_BYTES: usize = 1 << 20;
+
+/// Builds an SSTable offline from a sorted stream of rows, for [`crate::LsmEngine::ingest_sstable`].
+///
+/// Rows are stamped with the builder's version, so they become visible together
+/// once the table is ingested. Entries are written to `{path}.tmp` as they arrive and the
+/// file is renamed into place by [`SstBuilder::finish`]. An encrypted table is sealed as
+/// one AES-GCM message, so its entries stay in memory until then.
+pub struct SstBuilder {
+    version: Version,
+    env: SharedEnv,
+    path: String,
+    file: Box<dyn WritableFile>,
+    checksum: crc32fast::Hasher,
+    pending: Vec<u8>,
+    encryptor: Option<DataEncryptor>,
+    entries: usize,
+    last_key: Option<Vec<u8>>,
+}
+
+impl fmt::Debug for SstBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SstBuilder")
+            .field("version", &self.version)
+            .field("path", &self.path)
+            .field("entries", &self.entries)
+            .field("encrypted", &self.encryptor.is_some())
+            .finish()
+    }
+}
+
+impl SstBuilder {
+    pub async fn create(
+        path: &str,
+        version: Version,
+        encryptor: Option<&DataEncryptor>,
+    ) -> Result<Self> {
+        Self::create_with(Arc::new(LocalEnv), path, version, encryptor).await
+    }
+
+    /// Starts a table at `path` whose entries are all written at `version`.
+    pub async fn create_with(
+        env: SharedEnv,
+        path: &str,
+        version: Version,
+        encryptor: Option<&DataEncryptor>,
+    ) -> Result<Self> {
+        let header = encode_header(SST_MAGIC);
+        let mut file = env.create(&format!("{path}.tmp")).await?;
+        file.append(&header).await?;
+        let mut checksum = crc32fast::Hasher::new();
+        checksum.update(&header);
+        Ok(Self {
+            version,
+            env,
+            path: path.to_string(),
+            file,
+            checksum,
+            pending: Vec::new(),
+            encryptor: encryptor.cloned(),
+            entries: 0,
+            last_key: None,
+        })
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Adds a row; keys must arrive in strictly ascending order.
+    pub async fn put(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.push(encode_versioned_key(key, self.version), encode_value(Some(value)))
+            .await
+    }
+
+    /// Adds a tombstone; keys must arrive in strictly ascending order.
+    pub async fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.push(encode_versioned_key(key, self.version), encode_value(None))
+            .await
+    }
+
+    /// Adds `key` at `version` instead of the builder's version, as when copying history
+    /// from another replica; `None` adds a tombstone. Entries must arrive in the table's
+    /// order, by key and then by version.
+    pub async fn put_version(
+        &mut self,
+        key: &[u8],
+        version: Version,
+        value: Option<&[u8]>,
+    ) -> Result<()> {
+        self.push(encode_versioned_key(key, version), encode_value(value))
+            .await
+    }
+
+    /// Adds a tombstone at `version` deleting every key from `start` up to `end`, ordered
+    /// like [`SstBuilder::put_version`].
+    pub async fn delete_range_version(
+        &mut self,
+        start: &[u8],
+        end: &[u8],
+        version: Version,
+    ) -> Result<()> {
+        self.push(encode_versioned_key(start, version), encode_range_tombstone(end))
+            .await
+    }
+
+    async fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        if let Some(last) = &self.last_key {
+            if key <= *last {
+                return Err(anyhow!("sstable builder keys must be strictly ascending"));
+            }
+        }
+        self.last_key = Some(key.clone());
+        self.pending.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.pending.extend_from_slice(&key);
+        self.pending.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.pending.extend_from_slice(&value);
+        self.entries += 1;
+        if self.encryptor.is_none() && self.pending.len() >= WRITE_CHUNK_BYTES {
+            self.write_pending().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_pending(&mut self) -> Result<()> {
+        let bytes = match &self.encryptor {
+            Some(enc) => enc.encrypt(&self.pending)?,
+            None => std::mem::take(&mut self.pending),
+        };
+        self.checksum.update(&bytes);
+        self.file.append(&bytes).await?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Seals the table, syncs it and renames it into place, so a crash never leaves a
+    /// partially written table under its final name. Returns the number of entries.
+    pub async fn finish(mut self) -> Result<usize> {
+        let tmp_path = format!("{}.tmp", self.path);
+        if self.entries == 0 {
+            drop(self.file);
+            self.env.remove_file(&tmp_path).await?;
+            return Err(anyhow!("cannot build an empty sstable"));
+        }
+        self.write_pending().await?;
+        let mut footer = self.checksum.clone().finalize().to_le_bytes().to_vec();
+        footer.extend_from_slice(SST_FOOTER_MAGIC);
+        self.file.append(&footer).await?;
+        self.file.sync().await?;
+        drop(self.file);
+        self.env.rename(&tmp_path, &self.path).await?;
+        Ok(self.entries)
+    }
+
+    /// Stops building and removes the partially written file.
+    pub async fn abandon(self) -> Result<()> {
+        drop(self.file);
+        self.env.remove_file(&format!("{}.tmp", self.path)).await
+    }
+}
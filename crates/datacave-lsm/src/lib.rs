@@ -1,11 +1,27 @@
 pub mod compaction;
 pub mod encryption;
 pub mod engine;
+pub mod env;
+pub mod fault;
+pub mod format;
+pub mod ingest;
+pub mod manifest;
+pub mod memory;
 pub mod memtable;
+pub mod scrub;
 pub mod sstable;
+pub mod storage;
 pub mod wal;
 
-pub use engine::{LsmEngine, LsmOptions};
+pub use engine::{LsmEngine, LsmOptions, TieringOptions, MAX_COLD_TABLES};
+pub use env::{Env, LocalEnv, SharedEnv, WritableFile};
+pub use fault::{FaultInjectionEnv, FaultOp};
+pub use format::{UpgradeReport, FORMAT_VERSION};
+pub use ingest::SstBuilder;
+pub use scrub::{CorruptFile, ScrubReport};
+pub use memory::MemoryEngine;
+pub use storage::{BatchOp, SharedStorage, StorageEngine, StoredVersion, WriteBatch};
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
@@ -0,0 +1,75 @@
+use crate::env::Env;
+use crate::format::{FORMAT_VERSION, LEGACY_FORMAT_VERSION};
+use anyhow::{anyhow, Result};
+use datacave_core::mvcc::Version;
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+/// Engine metadata that must survive the WAL being reset after a flush.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Manifest {
+    /// Highest MVCC version ever written to an SSTable in this data directory.
+    pub last_version: Version,
+    /// On-disk format of the build that last wrote to this directory.
+    pub format_version: u16,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            last_version: 0,
+            format_version: FORMAT_VERSION,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn path(data_dir: &str) -> String {
+        format!("{data_dir}/{MANIFEST_FILE}")
+    }
+
+    pub async fn load_with(env: &dyn Env, data_dir: &str) -> Result<Self> {
+        let bytes = env.read_file(&Self::path(data_dir)).await?;
+        let text = String::from_utf8(bytes).map_err(|_| anyhow!("manifest is not utf-8"))?;
+        let mut manifest = Manifest {
+            last_version: 0,
+            format_version: LEGACY_FORMAT_VERSION,
+        };
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key.trim() {
+                "last_version" => {
+                    manifest.last_version = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| anyhow!("invalid last_version in manifest: {value}"))?;
+                }
+                "format_version" => {
+                    manifest.format_version = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| anyhow!("invalid format_version in manifest: {value}"))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Replaces the manifest atomically via a synced temp file and rename.
+    pub async fn store_with(&self, env: &dyn Env, data_dir: &str) -> Result<()> {
+        let path = Self::path(data_dir);
+        let tmp_path = format!("{path}.tmp");
+        let mut file = env.create(&tmp_path).await?;
+        let contents = format!(
+            "format_version={}\nlast_version={}\n",
+            self.format_version, self.last_version
+        );
+        file.append(contents.as_bytes()).await?;
+        file.sync().await?;
+        drop(file);
+        env.rename(&tmp_path, &path).await
+    }
+}
@@ -0,0 +1,128 @@
+use crate::engine::{
+    collect_latest, collect_latest_in_range, decode_value, encode_range_tombstone, encode_value,
+    encode_versioned_key, into_live_entries, mem_get_versioned, range_prefix, RangeTombstones,
+};
+use crate::memtable::MemTable;
+use crate::storage::{BatchOp, StorageEngine, WriteBatch};
+use anyhow::Result;
+use async_trait::async_trait;
+use datacave_core::mvcc::{Snapshot, Version};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Purely in-memory storage engine with the same versioned semantics as `LsmEngine`.
+///
+/// Nothing is written to disk, so contents are lost when the engine is dropped.
+#[derive(Debug, Default)]
+pub struct MemoryEngine {
+    memtable: Mutex<MemTable>,
+    last_version: AtomicU64,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        Self {
+            memtable: Mutex::new(MemTable::new()),
+            last_version: AtomicU64::new(0),
+        }
+    }
+
+    fn apply(&self, key: &[u8], value: Option<&[u8]>, version: Version) {
+        self.memtable
+            .lock()
+            .unwrap()
+            .put(encode_versioned_key(key, version), encode_value(value));
+        self.last_version.fetch_max(version, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl StorageEngine for MemoryEngine {
+    async fn get(&self, key: &[u8], snapshot: Version) -> Result<Option<Vec<u8>>> {
+        let mem = self.memtable.lock().unwrap();
+        let mut tombstones = RangeTombstones::default();
+        tombstones.collect(mem.range_tombstones(), snapshot);
+        Ok(mem_get_versioned(&mem, key, snapshot)
+            .filter(|(version, _)| !tombstones.hides(key, *version))
+            .and_then(|(_, value)| decode_value(value)))
+    }
+
+    async fn put(&self, key: &[u8], value: &[u8], version: Version) -> Result<()> {
+        self.apply(key, Some(value), version);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &[u8], version: Version) -> Result<()> {
+        self.apply(key, None, version);
+        Ok(())
+    }
+
+    async fn scan(&self, prefix: &[u8], snapshot: Version) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut latest = BTreeMap::new();
+        let mem = self.memtable.lock().unwrap();
+        collect_latest(
+            mem.range_from(prefix.to_vec())
+                .take_while(|(key, _)| key.starts_with(prefix)),
+            prefix,
+            snapshot,
+            &mut latest,
+        );
+        let mut tombstones = RangeTombstones::default();
+        tombstones.collect(mem.range_tombstones(), snapshot);
+        tombstones.apply(&mut latest);
+        Ok(into_live_entries(latest))
+    }
+
+    async fn scan_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        snapshot: Version,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut latest = BTreeMap::new();
+        let mem = self.memtable.lock().unwrap();
+        let prefix = range_prefix(start, end);
+        collect_latest_in_range(
+            mem.range_from(start.to_vec())
+                .take_while(|(key, _)| key.starts_with(prefix)),
+            start,
+            end,
+            snapshot,
+            &mut latest,
+        );
+        let mut tombstones = RangeTombstones::default();
+        tombstones.collect(mem.range_tombstones(), snapshot);
+        tombstones.apply(&mut latest);
+        Ok(into_live_entries(latest))
+    }
+
+    async fn write_batch(&self, batch: WriteBatch, version: Version) -> Result<()> {
+        batch.check_ranges()?;
+        let mut mem = self.memtable.lock().unwrap();
+        for op in batch.ops() {
+            match op {
+                BatchOp::Put { key, value } => {
+                    mem.put(encode_versioned_key(key, version), encode_value(Some(value)))
+                }
+                BatchOp::Delete { key } => {
+                    mem.put(encode_versioned_key(key, version), encode_value(None))
+                }
+                BatchOp::DeleteRange { start, end } => {
+                    mem.put(encode_versioned_key(start, version), encode_range_tombstone(end))
+                }
+            }
+        }
+        drop(mem);
+        if !batch.is_empty() {
+            self.last_version.fetch_max(version, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    async fn snapshot(&self) -> Result<Snapshot> {
+        Ok(Snapshot {
+            version: self.last_version.load(Ordering::SeqCst),
+        })
+    }
+}
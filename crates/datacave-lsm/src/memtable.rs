@@ -1,8 +1,11 @@
-use std::collections::BTreeMap;
+use crate::engine::range_tombstone_end;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, Default)]
 pub struct MemTable {
     entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Keys of the range tombstones in `entries`; every read checks them, whatever its key.
+    range_tombstones: BTreeSet<Vec<u8>>,
     bytes: usize,
 }
 
@@ -10,11 +13,17 @@ impl MemTable {
     pub fn new() -> Self {
         Self {
             entries: BTreeMap::new(),
+            range_tombstones: BTreeSet::new(),
             bytes: 0,
         }
     }
 
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        if range_tombstone_end(&value).is_some() {
+            self.range_tombstones.insert(key.clone());
+        } else {
+            self.range_tombstones.remove(&key);
+        }
         if let Some(prev) = self.entries.insert(key, value) {
             self.bytes = self.bytes.saturating_sub(prev.len());
         }
@@ -27,19 +36,33 @@ impl MemTable {
         self.entries.get(key)
     }
 
-    pub fn range_up_to<'a>(
-        &'a self,
+    pub fn range_up_to(
+        &self,
         upper: Vec<u8>,
-    ) -> impl DoubleEndedIterator<Item = (&'a Vec<u8>, &'a Vec<u8>)> {
+    ) -> impl DoubleEndedIterator<Item = (&Vec<u8>, &Vec<u8>)> {
         self.entries.range(..=upper)
     }
 
+    pub fn range_from(
+        &self,
+        lower: Vec<u8>,
+    ) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        self.entries.range(lower..)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
         self.entries.iter()
     }
 
+    pub fn range_tombstones(&self) -> impl Iterator<Item = (&Vec<u8>, &Vec<u8>)> {
+        self.range_tombstones
+            .iter()
+            .filter_map(|key| self.entries.get_key_value(key))
+    }
+
     pub fn clear(&mut self) {
         self.entries.clear();
+        self.range_tombstones.clear();
         self.bytes = 0;
     }
 
@@ -0,0 +1,20 @@
+/// A file that failed verification during a scrub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Outcome of one pass over an engine's persistent files.
+#[derive(Debug, Clone, Default)]
+pub struct ScrubReport {
+    pub files_checked: usize,
+    pub bytes_checked: u64,
+    pub corrupt: Vec<CorruptFile>,
+}
+
+impl ScrubReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty()
+    }
+}
@@ -1,7 +1,7 @@
 use crate::encryption::DataEncryptor;
-use anyhow::Result;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::env::{Env, LocalEnv};
+use crate::format::{check_readable, encode_header, split_header};
+use anyhow::{anyhow, Result};
 
 #[derive(Debug, Clone)]
 pub struct SstEntry {
@@ -19,16 +19,32 @@ impl SSTable {
         Self { path }
     }
 
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Compaction generation encoded in the file name (`sst-{id}-L{level}.db`); freshly
+    /// flushed or ingested tables are level 0.
+    pub fn level(&self) -> u32 {
+        self.file_name()
+            .strip_suffix(".db")
+            .and_then(|stem| stem.rsplit_once("-L"))
+            .and_then(|(_, level)| level.parse().ok())
+            .unwrap_or(0)
+    }
+
     pub async fn write(path: &str, entries: &[SstEntry]) -> Result<()> {
-        Self::write_with(path, entries, None).await
+        Self::write_with(&LocalEnv, path, entries, None).await
     }
 
+    /// Writes the table to `{path}.tmp`, syncs it and renames it into place, so a crash never
+    /// leaves a partially written table under its final name.
     pub async fn write_with(
+        env: &dyn Env,
         path: &str,
         entries: &[SstEntry],
         encryptor: Option<&DataEncryptor>,
     ) -> Result<()> {
-        let mut file = File::create(path).await?;
         let mut buffer = Vec::new();
         for entry in entries {
             let key_len = entry.key.len() as u32;
@@ -38,62 +54,98 @@ impl SSTable {
             buffer.extend_from_slice(&value_len.to_le_bytes());
             buffer.extend_from_slice(&entry.value);
         }
-        let bytes = if let Some(enc) = encryptor {
-            enc.encrypt(&buffer)?
+        let mut bytes = encode_header(SST_MAGIC).to_vec();
+        if let Some(enc) = encryptor {
+            bytes.extend_from_slice(&enc.encrypt(&buffer)?);
         } else {
-            buffer
-        };
-        file.write_all(&bytes).await?;
-        file.flush().await?;
+            bytes.extend_from_slice(&buffer);
+        }
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes.extend_from_slice(SST_FOOTER_MAGIC);
+        let tmp_path = format!("{path}.tmp");
+        let mut file = env.create(&tmp_path).await?;
+        file.append(&bytes).await?;
+        file.sync().await?;
+        drop(file);
+        env.rename(&tmp_path, path).await?;
         Ok(())
     }
 
     pub async fn load(&self) -> Result<Vec<SstEntry>> {
-        self.load_with(None).await
+        self.load_with(&LocalEnv, None).await
     }
 
+    /// Reads and fully validates the table: the footer checksum, the AES-GCM tag when
+    /// encrypted, and every entry boundary. Any damage is an error rather than a short read.
     pub async fn load_with(
         &self,
+        env: &dyn Env,
         encryptor: Option<&DataEncryptor>,
     ) -> Result<Vec<SstEntry>> {
-        let mut file = File::open(&self.path).await?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf).await?;
+        let buf = env.read_file(&self.path).await?;
+        let body = verify_footer(&buf).map_err(|err| anyhow!("{}: {err}", self.path))?;
+        let (version, payload) = split_header(SST_MAGIC, body);
+        check_readable(&self.path, version)?;
         let data = if let Some(enc) = encryptor {
-            enc.decrypt(&buf)?
+            enc.decrypt(payload)
+                .map_err(|err| anyhow!("{}: {err}", self.path))?
         } else {
-            buf
+            payload.to_vec()
         };
-        let mut entries = Vec::new();
-        let mut offset = 0usize;
-        while offset + 4 <= data.len() {
-            let key_len = u32::from_le_bytes(
-                data[offset..offset + 4]
-                    .try_into()
-                    .unwrap_or([0u8; 4]),
-            ) as usize;
-            offset += 4;
-            if offset + key_len > data.len() {
-                break;
-            }
-            let key = data[offset..offset + key_len].to_vec();
-            offset += key_len;
-            if offset + 4 > data.len() {
-                break;
-            }
-            let value_len = u32::from_le_bytes(
-                data[offset..offset + 4]
-                    .try_into()
-                    .unwrap_or([0u8; 4]),
-            ) as usize;
-            offset += 4;
-            if offset + value_len > data.len() {
-                break;
-            }
-            let value = data[offset..offset + value_len].to_vec();
-            offset += value_len;
-            entries.push(SstEntry { key, value });
-        }
-        Ok(entries)
+        decode_entries(&data).map_err(|err| anyhow!("{}: {err}", self.path))
+    }
+
+    /// On-disk format version recorded in the table's header.
+    pub async fn format_version_with(&self, env: &dyn Env) -> Result<u16> {
+        let buf = env.read_file(&self.path).await?;
+        Ok(split_header(SST_MAGIC, &buf).0)
+    }
+}
+
+/// Leading magic of every table, followed by the format version. Tables written before
+/// headers existed start directly with their (possibly encrypted) entries.
+pub const SST_MAGIC: &[u8; 4] = b"DCST";
+
+/// Trailer of every table: `crc32_le(header || payload) || SST_FOOTER_MAGIC`. Tables written before
+/// the trailer existed have no magic and are read unverified.
+pub const SST_FOOTER_MAGIC: &[u8; 4] = b"DCS1";
+
+fn verify_footer(buf: &[u8]) -> Result<&[u8]> {
+    if buf.len() < 8 || !buf.ends_with(SST_FOOTER_MAGIC) {
+        return Ok(buf);
+    }
+    let (payload, footer) = buf.split_at(buf.len() - 8);
+    let stored = u32::from_le_bytes(footer[..4].try_into().unwrap_or([0u8; 4]));
+    let actual = crc32fast::hash(payload);
+    if stored != actual {
+        return Err(anyhow!(
+            "checksum mismatch (stored {stored:08x}, computed {actual:08x})"
+        ));
     }
+    Ok(payload)
+}
+
+fn decode_entries(data: &[u8]) -> Result<Vec<SstEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0usize;
+    while offset < data.len() {
+        let key = read_chunk(data, &mut offset)?;
+        let value = read_chunk(data, &mut offset)?;
+        entries.push(SstEntry { key, value });
+    }
+    Ok(entries)
+}
+
+fn read_chunk(data: &[u8], offset: &mut usize) -> Result<Vec<u8>> {
+    let len_bytes = data
+        .get(*offset..*offset + 4)
+        .ok_or_else(|| anyhow!("truncated length at offset {offset}"))?;
+    let len = u32::from_le_bytes(len_bytes.try_into().unwrap_or([0u8; 4])) as usize;
+    let start = *offset + 4;
+    let chunk = data
+        .get(start..start + len)
+        .ok_or_else(|| anyhow!("length {len} at offset {offset} runs past end of table"))?;
+    *offset = start + len;
+    Ok(chunk.to_vec())
 }
@@ -0,0 +1,178 @@
+use crate::scrub::ScrubReport;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use datacave_core::mvcc::{Snapshot, Version};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+pub type SharedStorage = Arc<dyn StorageEngine>;
+
+/// A single mutation inside a [`WriteBatch`].
+#[derive(Debug, Clone)]
+pub enum BatchOp {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+    /// Deletes every key with `start <= key < end` that is live at the batch's version. Keys
+    /// written at later versions are not affected. The range is stored as one tombstone
+    /// under `start`, so a batch may not also write `start` or start another range there.
+    DeleteRange { start: Vec<u8>, end: Vec<u8> },
+}
+
+/// One stored version of a key, as copied between replicas. `value` is `None` for a
+/// tombstone; `range_end` is set when the tombstone deletes every key from `key` up to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredVersion {
+    pub key: Vec<u8>,
+    pub version: Version,
+    pub value: Option<Vec<u8>>,
+    pub range_end: Option<Vec<u8>>,
+}
+
+/// Group of mutations applied together at one version.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.ops.push(BatchOp::Put {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.ops.push(BatchOp::Delete { key: key.to_vec() });
+    }
+
+    /// Deletes the keys in `start..end`; see [`BatchOp::DeleteRange`]. Nothing is read, so
+    /// the cost does not grow with the number of keys deleted.
+    pub fn delete_range(&mut self, start: &[u8], end: &[u8]) {
+        self.ops.push(BatchOp::DeleteRange {
+            start: start.to_vec(),
+            end: end.to_vec(),
+        });
+    }
+
+    /// Fails if a range delete starts at a key the batch also writes or at the start of
+    /// another range delete; both would be stored under the same versioned key.
+    pub fn check_ranges(&self) -> Result<()> {
+        let mut starts = HashSet::new();
+        for op in &self.ops {
+            if let BatchOp::DeleteRange { start, .. } = op {
+                if !starts.insert(start.as_slice()) {
+                    return Err(anyhow!("batch deletes two ranges starting at the same key"));
+                }
+            }
+        }
+        for op in &self.ops {
+            if let BatchOp::Put { key, .. } | BatchOp::Delete { key } = op {
+                if starts.contains(key.as_slice()) {
+                    return Err(anyhow!("batch writes the start key of a range it deletes"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Versioned key-value store used by the SQL layer.
+///
+/// Every write is tagged with an MVCC version; reads at a version see the newest
+/// value written at or below it, so a version doubles as a consistent snapshot.
+#[async_trait]
+pub trait StorageEngine: Send + Sync + fmt::Debug {
+    async fn get(&self, key: &[u8], snapshot: Version) -> Result<Option<Vec<u8>>>;
+
+    async fn put(&self, key: &[u8], value: &[u8], version: Version) -> Result<()>;
+
+    async fn delete(&self, key: &[u8], version: Version) -> Result<()>;
+
+    /// Returns the live `(key, value)` pairs under `prefix` visible at `snapshot`, in key order.
+    async fn scan(&self, prefix: &[u8], snapshot: Version) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Returns the live `(key, value)` pairs with `start <= key < end` visible at `snapshot`,
+    /// in key order.
+    async fn scan_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        snapshot: Version,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Like [`StorageEngine::scan_range`], stopping after the first `limit` pairs.
+    async fn scan_range_limit(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        snapshot: Version,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = self.scan_range(start, end, snapshot).await?;
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    /// Applies every operation in `batch` at `version`.
+    async fn write_batch(&self, batch: WriteBatch, version: Version) -> Result<()>;
+
+    /// Snapshot at the highest version this engine has applied.
+    async fn snapshot(&self) -> Result<Snapshot>;
+
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Lets compaction discard versions that no read at `version` or later can see. Reads
+    /// below the horizon may then miss data. The horizon never moves back.
+    fn set_history_horizon(&self, version: Version) {
+        let _ = version;
+    }
+
+    /// Verifies every persistent file the engine owns. Engines without files report nothing.
+    async fn scrub(&self) -> Result<ScrubReport> {
+        Ok(ScrubReport::default())
+    }
+
+    /// Takes a corrupt file reported by [`StorageEngine::scrub`] out of service.
+    async fn quarantine(&self, path: &str) -> Result<()> {
+        Err(anyhow!("quarantine is not supported by this engine: {path}"))
+    }
+
+    /// Every stored version, tombstones included, of the first `limit` keys after `after`
+    /// (or from the start of the keyspace), ordered by key and then version.
+    async fn scan_versions(&self, after: Option<&[u8]>, limit: usize) -> Result<Vec<StoredVersion>> {
+        let _ = (after, limit);
+        Err(anyhow!("version scans are not supported by this engine"))
+    }
+
+    /// Writes each of `versions` at its own version unless the engine already holds that
+    /// version of the key, and returns how many were written. Nothing is deleted, so
+    /// history and newer writes survive.
+    async fn restore_versions(&self, versions: Vec<StoredVersion>) -> Result<usize> {
+        let _ = versions;
+        Err(anyhow!("repair is not supported by this engine"))
+    }
+}
@@ -1,8 +1,90 @@
 #[cfg(test)]
 mod tests {
-    use crate::engine::{LsmEngine, LsmOptions};
+    use crate::engine::{LsmEngine, LsmOptions, TieringOptions, MAX_COLD_TABLES};
+    use crate::env::{Env, SharedEnv};
+    use crate::fault::{FaultInjectionEnv, FaultOp};
+    use crate::ingest::SstBuilder;
+    use crate::memory::MemoryEngine;
+    use crate::storage::{StorageEngine, StoredVersion, WriteBatch};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
     use tempfile::TempDir;
 
+    fn test_options(dir: &TempDir, memtable_max_bytes: usize) -> LsmOptions {
+        let data_dir = dir.path().join("data");
+        std::fs::create_dir_all(&data_dir).expect("data dir");
+        let wal_path = data_dir.join("wal.log");
+        LsmOptions {
+            data_dir: data_dir.to_string_lossy().to_string(),
+            wal_path: wal_path.to_string_lossy().to_string(),
+            memtable_max_bytes,
+            encryption_key: None,
+            wal_enabled: true,
+            tiering: None,
+        }
+    }
+
+    fn fault_options(memtable_max_bytes: usize) -> LsmOptions {
+        LsmOptions {
+            data_dir: "/db".to_string(),
+            wal_path: "/db/wal.log".to_string(),
+            memtable_max_bytes,
+            encryption_key: None,
+            wal_enabled: true,
+            tiering: None,
+        }
+    }
+
+    fn tiered_options(min_level: Option<u32>, min_age_secs: Option<u64>) -> LsmOptions {
+        LsmOptions {
+            tiering: Some(TieringOptions {
+                cold_dir: "/cold".to_string(),
+                min_level,
+                min_age_secs,
+            }),
+            ..fault_options(1 << 20)
+        }
+    }
+
+    async fn sst_files(env: &FaultInjectionEnv, dir: &str) -> Vec<String> {
+        env.list_dir(dir)
+            .await
+            .expect("list")
+            .into_iter()
+            .filter(|name| name.starts_with("sst-"))
+            .collect()
+    }
+
+    async fn open_faulty(env: &FaultInjectionEnv, memtable_max_bytes: usize) -> LsmEngine {
+        let shared: SharedEnv = Arc::new(env.clone());
+        LsmEngine::open_with_env(fault_options(memtable_max_bytes), shared)
+            .await
+            .expect("open")
+    }
+
+    async fn assert_scan_semantics(engine: &dyn StorageEngine) {
+        engine.put(b"t|1", b"a", 1).await.expect("put");
+        engine.put(b"t|2", b"b", 2).await.expect("put");
+        engine.put(b"u|1", b"other", 3).await.expect("put");
+        engine.put(b"t|1", b"a2", 4).await.expect("put");
+        engine.delete(b"t|2", 5).await.expect("delete");
+
+        let rows = engine.scan(b"t|", 3).await.expect("scan");
+        assert_eq!(
+            rows,
+            vec![(b"t|1".to_vec(), b"a".to_vec()), (b"t|2".to_vec(), b"b".to_vec())]
+        );
+        let rows = engine.scan(b"t|", 5).await.expect("scan");
+        assert_eq!(rows, vec![(b"t|1".to_vec(), b"a2".to_vec())]);
+        let rows = engine.scan_range(b"t|1", b"t|3", 3).await.expect("scan range");
+        assert_eq!(rows.len(), 2);
+        let rows = engine.scan_range(b"t|10", b"u|1", 3).await.expect("scan range");
+        assert_eq!(rows, vec![(b"t|2".to_vec(), b"b".to_vec())]);
+        assert_eq!(engine.snapshot().await.expect("snapshot").version, 5);
+    }
+
     #[tokio::test]
     async fn put_get_roundtrip() {
         let dir = TempDir::new().expect("tempdir");
@@ -15,6 +97,7 @@ mod tests {
             memtable_max_bytes: 1024,
             encryption_key: None,
             wal_enabled: true,
+            tiering: None,
         };
         let engine = LsmEngine::open(options).await.expect("open");
         let key = b"user:1";
@@ -36,6 +119,7 @@ mod tests {
             memtable_max_bytes: 1024,
             encryption_key: None,
             wal_enabled: true,
+            tiering: None,
         };
         let engine = LsmEngine::open(options).await.expect("open");
         let key = b"user:2";
@@ -57,6 +141,7 @@ mod tests {
             memtable_max_bytes: 1024,
             encryption_key: None,
             wal_enabled: true,
+            tiering: None,
         };
         let engine = LsmEngine::open(options.clone()).await.expect("open");
         engine.put(b"user:3", b"claire", 1).await.expect("put");
@@ -78,6 +163,7 @@ mod tests {
             memtable_max_bytes: 64,
             encryption_key: None,
             wal_enabled: true,
+            tiering: None,
         };
         let engine = LsmEngine::open(options.clone()).await.expect("open");
         engine.put(b"user:4", b"dana", 1).await.expect("put");
@@ -87,4 +173,697 @@ mod tests {
         let got = restored.get(b"user:4", 1).await.expect("get");
         assert_eq!(got, Some(b"dana".to_vec()));
     }
+
+    #[tokio::test]
+    async fn get_after_flush_returns_newest_version() {
+        let dir = TempDir::new().expect("tempdir");
+        let engine = LsmEngine::open(test_options(&dir, 1 << 20)).await.expect("open");
+        engine.put(b"user:5", b"v1", 1).await.expect("put");
+        engine.put(b"user:5", b"v2", 2).await.expect("put");
+        engine.flush().await.expect("flush");
+        assert_eq!(engine.get(b"user:5", 1).await.expect("get"), Some(b"v1".to_vec()));
+        assert_eq!(engine.get(b"user:5", 2).await.expect("get"), Some(b"v2".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn lsm_scan_merges_memtable_and_sstables() {
+        let dir = TempDir::new().expect("tempdir");
+        let engine = LsmEngine::open(test_options(&dir, 1 << 20)).await.expect("open");
+        assert_scan_semantics(&engine).await;
+        engine.flush().await.expect("flush");
+        engine.put(b"t|3", b"c", 6).await.expect("put");
+        let rows = StorageEngine::scan(&engine, b"t|", 6).await.expect("scan");
+        assert_eq!(
+            rows,
+            vec![(b"t|1".to_vec(), b"a2".to_vec()), (b"t|3".to_vec(), b"c".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_engine_scan_and_versions() {
+        let engine = MemoryEngine::new();
+        assert_scan_semantics(&engine).await;
+        assert_eq!(engine.get(b"t|1", 1).await.expect("get"), Some(b"a".to_vec()));
+        assert_eq!(engine.get(b"t|2", 5).await.expect("get"), None);
+    }
+
+    #[tokio::test]
+    async fn write_batch_applies_at_one_version() {
+        let dir = TempDir::new().expect("tempdir");
+        let options = test_options(&dir, 1 << 20);
+        let engine = LsmEngine::open(options.clone()).await.expect("open");
+        engine.put(b"k|old", b"x", 1).await.expect("put");
+        let mut batch = WriteBatch::new();
+        batch.put(b"k|a", b"1");
+        batch.put(b"k|b", b"2");
+        batch.delete(b"k|old");
+        engine.write_batch(batch, 2).await.expect("batch");
+        drop(engine);
+
+        let restored = LsmEngine::open(options).await.expect("open");
+        assert_eq!(restored.scan(b"k|", 1).await.expect("scan").len(), 1);
+        let rows = restored.scan(b"k|", 2).await.expect("scan");
+        assert_eq!(
+            rows,
+            vec![(b"k|a".to_vec(), b"1".to_vec()), (b"k|b".to_vec(), b"2".to_vec())]
+        );
+        assert_eq!(restored.snapshot().version, 2);
+    }
+
+    async fn assert_delete_range_semantics(engine: &dyn StorageEngine) {
+        engine.put(b"t|1", b"a", 1).await.expect("put");
+        engine.put(b"t|2", b"b", 2).await.expect("put");
+        engine.put(b"t|3", b"c", 3).await.expect("put");
+        engine.put(b"u|1", b"other", 4).await.expect("put");
+        engine.put(b"t|3", b"later", 6).await.expect("put");
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"t|", b"t}");
+        batch.put(b"t|4", b"d");
+        engine.write_batch(batch, 5).await.expect("batch");
+
+        assert_eq!(engine.get(b"t|2", 4).await.expect("get"), Some(b"b".to_vec()));
+        assert_eq!(engine.get(b"t|2", 5).await.expect("get"), None);
+        assert_eq!(
+            engine.scan(b"t|", 5).await.expect("scan"),
+            vec![(b"t|4".to_vec(), b"d".to_vec())]
+        );
+        assert_eq!(
+            engine.scan(b"", 6).await.expect("scan"),
+            vec![
+                (b"t|3".to_vec(), b"later".to_vec()),
+                (b"t|4".to_vec(), b"d".to_vec()),
+                (b"u|1".to_vec(), b"other".to_vec()),
+            ]
+        );
+
+        // The tombstone is stored under the range's start, which the batch may not write.
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"u|", b"u}");
+        batch.put(b"u|", b"clash");
+        assert!(engine.write_batch(batch, 7).await.is_err());
+        assert_eq!(engine.get(b"u|1", 7).await.expect("get"), Some(b"other".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn delete_range_hides_older_keys_in_memory_and_on_disk() {
+        assert_delete_range_semantics(&MemoryEngine::new()).await;
+
+        let dir = TempDir::new().expect("tempdir");
+        let options = test_options(&dir, 1 << 20);
+        let engine = LsmEngine::open(options.clone()).await.expect("open");
+        engine.put(b"t|0", b"flushed", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        assert_delete_range_semantics(&engine).await;
+        drop(engine);
+
+        let restored = LsmEngine::open(options).await.expect("open");
+        assert_eq!(restored.get(b"t|0", 6).await.expect("get"), None);
+        assert_eq!(restored.scan(b"t|", 6).await.expect("scan").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unsynced_wal_record_is_dropped_on_restart() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.put(b"k2", b"b", 2).await.expect("put");
+        env.fail_once(FaultOp::Sync, Some("wal.log"));
+        assert!(engine.put(b"k3", b"c", 3).await.is_err());
+        drop(engine);
+        env.restart();
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        assert_eq!(restored.get(b"k1", 3).await.expect("get"), Some(b"a".to_vec()));
+        assert_eq!(restored.get(b"k2", 3).await.expect("get"), Some(b"b".to_vec()));
+        assert_eq!(restored.get(b"k3", 3).await.expect("get"), None);
+    }
+
+    #[tokio::test]
+    async fn failed_flush_keeps_data_in_wal_and_cleans_temp_files() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        env.fail_once(FaultOp::Rename, Some("sst-"));
+        assert!(engine.flush().await.is_err());
+        drop(engine);
+        env.restart();
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        assert_eq!(restored.get(b"k1", 1).await.expect("get"), Some(b"a".to_vec()));
+        let files = env.list_dir("/db").await.expect("list");
+        assert_eq!(files, vec!["wal.log".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn compaction_replaces_inputs_and_keeps_versions() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.put(b"k1", b"b", 2).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.compact().await.expect("compact");
+        engine.put(b"k1", b"c", 3).await.expect("put");
+        engine.flush().await.expect("flush");
+        drop(engine);
+        env.restart();
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        let tables: Vec<String> = env
+            .list_dir("/db")
+            .await
+            .expect("list")
+            .into_iter()
+            .filter(|name| name.starts_with("sst-"))
+            .collect();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(restored.get(b"k1", 1).await.expect("get"), Some(b"a".to_vec()));
+        assert_eq!(restored.get(b"k1", 2).await.expect("get"), Some(b"b".to_vec()));
+        assert_eq!(restored.get(b"k1", 3).await.expect("get"), Some(b"c".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn compaction_drops_versions_shadowed_below_the_history_horizon() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        for (version, value) in [(1, b"a"), (2, b"b"), (3, b"c"), (4, b"d")] {
+            engine.put(b"k1", value, version).await.expect("put");
+            engine.flush().await.expect("flush");
+        }
+        engine.delete(b"k2", 2).await.expect("delete");
+        engine.flush().await.expect("flush");
+        engine.set_history_horizon(3);
+        engine.compact().await.expect("compact");
+
+        assert_eq!(engine.get(b"k1", 2).await.expect("get"), None);
+        assert_eq!(engine.get(b"k1", 3).await.expect("get"), Some(b"c".to_vec()));
+        assert_eq!(engine.get(b"k1", 4).await.expect("get"), Some(b"d".to_vec()));
+        assert_eq!(engine.get(b"k2", 4).await.expect("get"), None);
+
+        // The horizon only moves forward.
+        engine.set_history_horizon(1);
+        engine.put(b"k1", b"e", 5).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.compact().await.expect("compact");
+        assert_eq!(engine.get(b"k1", 3).await.expect("get"), Some(b"c".to_vec()));
+        assert_eq!(engine.get(b"k1", 5).await.expect("get"), Some(b"e".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn range_tombstones_survive_compaction_restart_and_repair() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"t|1", b"a", 1).await.expect("put");
+        engine.put(b"t|2", b"b", 2).await.expect("put");
+        engine.flush().await.expect("flush");
+        let mut batch = WriteBatch::new();
+        batch.delete_range(b"t|", b"t}");
+        engine.write_batch(batch, 3).await.expect("batch");
+        engine.flush().await.expect("flush");
+        engine.put(b"t|2", b"c", 4).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.set_history_horizon(3);
+        engine.compact().await.expect("compact");
+        drop(engine);
+        env.restart();
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        assert_eq!(restored.get(b"t|1", 4).await.expect("get"), None);
+        assert_eq!(
+            restored.scan(b"t|", 4).await.expect("scan"),
+            vec![(b"t|2".to_vec(), b"c".to_vec())]
+        );
+        // Compaction dropped the versions the tombstone hides but kept the tombstone.
+        let versions = restored.scan_versions(None, usize::MAX).await.expect("versions");
+        assert_eq!(
+            versions,
+            vec![
+                StoredVersion {
+                    key: b"t|".to_vec(),
+                    version: 3,
+                    value: None,
+                    range_end: Some(b"t}".to_vec()),
+                },
+                StoredVersion {
+                    key: b"t|2".to_vec(),
+                    version: 4,
+                    value: Some(b"c".to_vec()),
+                    range_end: None,
+                },
+            ]
+        );
+
+        let replica_env = FaultInjectionEnv::new();
+        let replica = open_faulty(&replica_env, 1 << 20).await;
+        replica.put(b"t|1", b"a", 1).await.expect("put");
+        assert_eq!(replica.restore_versions(versions).await.expect("restore"), 2);
+        assert_eq!(replica.get(b"t|1", 1).await.expect("get"), Some(b"a".to_vec()));
+        assert_eq!(
+            replica.scan(b"t|", 4).await.expect("scan"),
+            vec![(b"t|2".to_vec(), b"c".to_vec())]
+        );
+    }
+
+    /// What a key may legally read as after a crash: its last acknowledged value, or any
+    /// value from a write that was in flight or failed after that acknowledgement.
+    #[derive(Default)]
+    struct KeyModel {
+        acked: Option<Vec<u8>>,
+        unacked: Vec<Option<Vec<u8>>>,
+    }
+
+    #[tokio::test]
+    async fn randomized_crash_recovery_preserves_acknowledged_writes() {
+        const KEYS: u32 = 8;
+        for seed in 0..24u64 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let env = FaultInjectionEnv::new();
+            let memtable_max_bytes = rng.gen_range(64..512);
+            let mut model: BTreeMap<Vec<u8>, KeyModel> = BTreeMap::new();
+            let mut version = 0u64;
+
+            for round in 0..6 {
+                let engine = open_faulty(&env, memtable_max_bytes).await;
+                for key_id in 0..KEYS {
+                    let key = format!("key-{key_id}").into_bytes();
+                    let state = model.entry(key.clone()).or_default();
+                    let got = engine.get(&key, u64::MAX).await.expect("get");
+                    assert!(
+                        got == state.acked || state.unacked.contains(&got),
+                        "seed {seed} round {round}: {} read {got:?}, acked {:?}",
+                        String::from_utf8_lossy(&key),
+                        state.acked,
+                    );
+                    // Whatever recovery produced is now the durable state.
+                    state.acked = got;
+                    state.unacked.clear();
+                }
+
+                env.crash_after(rng.gen_range(0..80));
+                for _ in 0..60 {
+                    let key = format!("key-{}", rng.gen_range(0..KEYS)).into_bytes();
+                    version += 1;
+                    let roll = rng.gen_range(0..100);
+                    let (value, result) = if roll < 65 {
+                        let value = format!("v{version}").into_bytes();
+                        let result = engine.put(&key, &value, version).await;
+                        (Some(Some(value)), result)
+                    } else if roll < 85 {
+                        (Some(None), engine.delete(&key, version).await)
+                    } else if roll < 95 {
+                        (None, engine.flush().await)
+                    } else {
+                        (None, engine.compact().await)
+                    };
+                    if let Some(value) = value {
+                        let state = model.get_mut(&key).expect("modelled key");
+                        if result.is_ok() {
+                            state.acked = value;
+                            state.unacked.clear();
+                        } else {
+                            state.unacked.push(value);
+                        }
+                    }
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                env.crash();
+                drop(engine);
+                if rng.gen_bool(0.5) {
+                    env.restart_torn(&mut rng);
+                } else {
+                    env.restart();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn manifest_keeps_high_water_version_after_wal_reset() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 7).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.put(b"k2", b"b", 8).await.expect("put");
+        drop(engine);
+        env.restart();
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        assert_eq!(restored.snapshot().version, 8);
+        // Rows replayed from the WAL are newer than every table, so reading them leaves
+        // the tables alone.
+        env.fail_always(FaultOp::Read, Some("sst-"));
+        assert_eq!(restored.get(b"k2", 8).await.expect("get"), Some(b"b".to_vec()));
+        assert!(restored.get(b"k1", 8).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn open_derives_high_water_version_for_tables_without_manifest() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 9).await.expect("put");
+        engine.flush().await.expect("flush");
+        drop(engine);
+        env.remove_file("/db/MANIFEST").await.expect("remove");
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        assert_eq!(restored.snapshot().version, 9);
+        assert!(env.file_contents("/db/MANIFEST").is_some());
+    }
+
+    #[tokio::test]
+    async fn ingested_sstable_is_visible_at_its_version_and_survives_restart() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"old", 5).await.expect("put");
+
+        let mut builder =
+            SstBuilder::create_with(Arc::new(env.clone()), "/staging/load.sst", 10, None)
+                .await
+                .expect("create");
+        builder.put(b"k1", b"loaded").await.expect("add");
+        builder.put(b"k2", b"loaded").await.expect("add");
+        assert!(builder.put(b"k0", b"late").await.is_err());
+        assert_eq!(builder.finish().await.expect("build"), 2);
+        assert_eq!(engine.ingest_sstable("/staging/load.sst").await.expect("ingest"), 10);
+
+        assert_eq!(engine.get(b"k1", 5).await.expect("get"), Some(b"old".to_vec()));
+        assert_eq!(engine.get(b"k1", 10).await.expect("get"), Some(b"loaded".to_vec()));
+        assert_eq!(engine.scan(b"k", 10).await.expect("scan").len(), 2);
+        assert_eq!(engine.snapshot().version, 10);
+        drop(engine);
+        env.restart();
+
+        let restored = open_faulty(&env, 1 << 20).await;
+        assert_eq!(restored.get(b"k2", 10).await.expect("get"), Some(b"loaded".to_vec()));
+        assert_eq!(restored.snapshot().version, 10);
+    }
+
+    #[tokio::test]
+    async fn sstable_builder_writes_large_and_encrypted_tables() {
+        let env = FaultInjectionEnv::new();
+        let shared: SharedEnv = Arc::new(env.clone());
+        let key = vec![3u8; 32];
+        let encryptor = crate::encryption::DataEncryptor::new(&key).expect("key");
+        let tables = [("/staging/plain.sst", None), ("/staging/sealed.sst", Some(&encryptor))];
+        for (path, encryptor) in tables {
+            let mut builder = SstBuilder::create_with(shared.clone(), path, 4, encryptor)
+                .await
+                .expect("create");
+            // Enough rows to span several appends to the file.
+            for i in 0..20_000u32 {
+                builder.put(&i.to_be_bytes(), &[7u8; 100]).await.expect("add");
+            }
+            assert_eq!(builder.finish().await.expect("build"), 20_000);
+            assert!(env.file_contents(&format!("{path}.tmp")).is_none());
+            let entries = crate::sstable::SSTable::new(path.to_string())
+                .load_with(&env, encryptor)
+                .await
+                .expect("load");
+            assert_eq!(entries.len(), 20_000);
+        }
+
+        let builder = SstBuilder::create_with(shared, "/staging/empty.sst", 4, None)
+            .await
+            .expect("create");
+        assert!(builder.finish().await.is_err());
+        assert!(env.file_contents("/staging/empty.sst.tmp").is_none());
+    }
+
+    #[tokio::test]
+    async fn compacted_tables_move_to_cold_tier_and_stay_readable() {
+        let env = FaultInjectionEnv::new();
+        let shared: SharedEnv = Arc::new(env.clone());
+        let engine = LsmEngine::open_with_env(tiered_options(Some(1), None), shared.clone())
+            .await
+            .expect("open");
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.put(b"k2", b"b", 2).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.compact().await.expect("compact");
+        assert!(sst_files(&env, "/db").await.is_empty());
+        assert_eq!(sst_files(&env, "/cold").await.len(), 1);
+
+        engine.put(b"k1", b"c", 3).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.compact().await.expect("compact");
+        assert_eq!(sst_files(&env, "/db").await.len(), 1);
+        assert_eq!(engine.get(b"k1", 2).await.expect("get"), Some(b"a".to_vec()));
+        drop(engine);
+        env.restart();
+
+        let restored = LsmEngine::open_with_env(tiered_options(Some(1), None), shared)
+            .await
+            .expect("open");
+        assert_eq!(restored.get(b"k1", 3).await.expect("get"), Some(b"c".to_vec()));
+        assert_eq!(restored.get(b"k2", 3).await.expect("get"), Some(b"b".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn cold_tables_are_compacted_among_themselves() {
+        let env = FaultInjectionEnv::new();
+        let shared: SharedEnv = Arc::new(env.clone());
+        let engine = LsmEngine::open_with_env(tiered_options(Some(1), None), shared)
+            .await
+            .expect("open");
+        for version in 1..=20u64 {
+            engine.put(b"k", format!("v{version}").as_bytes(), version).await.expect("put");
+            engine.flush().await.expect("flush");
+            engine.put(format!("k{version:02}").as_bytes(), b"x", version).await.expect("put");
+            engine.flush().await.expect("flush");
+            engine.set_history_horizon(version);
+            engine.compact().await.expect("compact");
+            let tables = sst_files(&env, "/db").await.len() + sst_files(&env, "/cold").await.len();
+            assert!(tables <= MAX_COLD_TABLES, "{tables} tables after round {version}");
+        }
+        assert_eq!(engine.get(b"k", 20).await.expect("get"), Some(b"v20".to_vec()));
+        assert_eq!(engine.scan(b"k", 20).await.expect("scan").len(), 21);
+        // Merging cold tables drops the versions the history horizon has passed.
+        let versions = engine.scan_versions(None, 1).await.expect("versions");
+        assert!(versions.len() <= MAX_COLD_TABLES, "{} versions of k kept", versions.len());
+    }
+
+    #[tokio::test]
+    async fn old_tables_move_to_cold_tier_by_age() {
+        let env = FaultInjectionEnv::new();
+        let shared: SharedEnv = Arc::new(env.clone());
+        let engine = LsmEngine::open_with_env(tiered_options(None, Some(3600)), shared)
+            .await
+            .expect("open");
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        assert_eq!(engine.tier_cold_tables().await.expect("tier"), 0);
+
+        let name = sst_files(&env, "/db").await.remove(0);
+        let two_hours_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(7200);
+        env.set_modified(&format!("/db/{name}"), two_hours_ago);
+        assert_eq!(engine.tier_cold_tables().await.expect("tier"), 1);
+        assert_eq!(sst_files(&env, "/cold").await, vec![name]);
+        assert_eq!(engine.get(b"k1", 1).await.expect("get"), Some(b"a".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn interrupted_tier_move_keeps_cold_copy_on_open() {
+        let env = FaultInjectionEnv::new();
+        let shared: SharedEnv = Arc::new(env.clone());
+        let engine = LsmEngine::open_with_env(tiered_options(None, Some(0)), shared.clone())
+            .await
+            .expect("open");
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        env.fail_once(FaultOp::Remove, Some("/db/sst-"));
+        assert!(engine.tier_cold_tables().await.is_err());
+        drop(engine);
+        env.restart();
+        assert_eq!(sst_files(&env, "/db").await.len(), 1);
+
+        let restored = LsmEngine::open_with_env(tiered_options(None, None), shared)
+            .await
+            .expect("open");
+        assert!(sst_files(&env, "/db").await.is_empty());
+        assert_eq!(sst_files(&env, "/cold").await.len(), 1);
+        assert_eq!(restored.get(b"k1", 1).await.expect("get"), Some(b"a".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn scrub_reports_corrupt_sstable_and_reads_fail_loudly() {
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.put(b"k2", b"b", 2).await.expect("put");
+        assert!(engine.scrub().await.expect("scrub").is_clean());
+
+        let name = sst_files(&env, "/db").await.remove(0);
+        let path = format!("/db/{name}");
+        env.corrupt_byte(&path, 2);
+        let report = engine.scrub().await.expect("scrub");
+        assert_eq!(report.files_checked, 2);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].path, path);
+        assert!(report.corrupt[0].reason.contains("checksum"));
+        assert!(engine.get(b"k1", 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn scrub_verifies_encrypted_tables_and_wal() {
+        let env = FaultInjectionEnv::new();
+        let shared: SharedEnv = Arc::new(env.clone());
+        let options = LsmOptions {
+            encryption_key: Some(vec![7u8; 32]),
+            ..fault_options(1 << 20)
+        };
+        let engine = LsmEngine::open_with_env(options, shared).await.expect("open");
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        engine.put(b"k2", b"b", 2).await.expect("put");
+        engine.put(b"k3", b"c", 3).await.expect("put");
+        assert!(engine.scrub().await.expect("scrub").is_clean());
+
+        env.corrupt_byte("/db/wal.log", 10);
+        let report = engine.scrub().await.expect("scrub");
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].path, "/db/wal.log");
+        assert!(crate::wal::Wal::replay_with(&env, "/db/wal.log", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn quarantined_table_is_repaired_from_healthy_replica() {
+        let healthy_env = FaultInjectionEnv::new();
+        let damaged_env = FaultInjectionEnv::new();
+        let healthy = open_faulty(&healthy_env, 1 << 20).await;
+        let damaged = open_faulty(&damaged_env, 1 << 20).await;
+        for engine in [&healthy, &damaged] {
+            engine.put(b"k1", b"a", 1).await.expect("put");
+            engine.put(b"k2", b"b", 2).await.expect("put");
+            engine.put(b"k1", b"a2", 3).await.expect("put");
+            engine.delete(b"k2", 4).await.expect("delete");
+            engine.flush().await.expect("flush");
+        }
+        damaged.put(b"stray", b"x", 5).await.expect("put");
+
+        let path = format!("/db/{}", sst_files(&damaged_env, "/db").await.remove(0));
+        damaged_env.corrupt_byte(&path, 0);
+        let report = damaged.scrub().await.expect("scrub");
+        assert_eq!(report.corrupt.len(), 1);
+        damaged.quarantine(&path).await.expect("quarantine");
+        assert!(damaged.scrub().await.expect("scrub").is_clean());
+        assert!(damaged_env.file_contents(&format!("{path}.quarantined")).is_some());
+
+        // One key per chunk: every version of a key travels together.
+        let mut after: Option<Vec<u8>> = None;
+        let mut restored = 0;
+        loop {
+            let chunk = healthy.scan_versions(after.as_deref(), 1).await.expect("versions");
+            let Some(last) = chunk.last() else {
+                break;
+            };
+            assert!(chunk.iter().all(|entry| entry.key == last.key));
+            after = Some(last.key.clone());
+            restored += damaged.restore_versions(chunk).await.expect("restore");
+        }
+        assert_eq!(restored, 4);
+        assert_eq!(damaged.get(b"k1", 1).await.expect("get"), Some(b"a".to_vec()));
+        assert_eq!(damaged.get(b"k2", 3).await.expect("get"), Some(b"b".to_vec()));
+        assert_eq!(
+            damaged.scan(b"", u64::MAX).await.expect("scan"),
+            vec![
+                (b"k1".to_vec(), b"a2".to_vec()),
+                (b"stray".to_vec(), b"x".to_vec())
+            ]
+        );
+        let everything = healthy.scan_versions(None, usize::MAX).await.expect("versions");
+        assert_eq!(everything.len(), 4);
+        assert_eq!(damaged.restore_versions(everything).await.expect("restore"), 0);
+    }
+
+        async fn write_raw(env: &FaultInjectionEnv, path: &str, bytes: &[u8]) {
+        let mut file = env.create(path).await.expect("create");
+        file.append(bytes).await.expect("append");
+        file.sync().await.expect("sync");
+    }
+
+    fn legacy_chunk(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        out.extend_from_slice(bytes);
+    }
+
+    #[tokio::test]
+    async fn legacy_files_are_readable_and_upgraded_in_place() {
+        use crate::engine::{encode_value, encode_versioned_key};
+        use crate::format::{FORMAT_VERSION, LEGACY_FORMAT_VERSION};
+        use crate::sstable::SSTable;
+        use crate::wal::Wal;
+
+        let env = FaultInjectionEnv::new();
+        let mut table = Vec::new();
+        legacy_chunk(&mut table, &encode_versioned_key(b"k1", 1));
+        legacy_chunk(&mut table, &encode_value(Some(b"a")));
+        write_raw(&env, "/db/sst-00000000000000000000.db", &table).await;
+        let mut body = vec![1u8];
+        legacy_chunk(&mut body, &encode_versioned_key(b"k2", 2));
+        legacy_chunk(&mut body, &encode_value(Some(b"b")));
+        let mut wal = (body.len() as u32).to_le_bytes().to_vec();
+        wal.extend_from_slice(&body);
+        write_raw(&env, "/db/wal.log", &wal).await;
+
+        let engine = open_faulty(&env, 1 << 20).await;
+        assert_eq!(engine.get(b"k1", 2).await.expect("get"), Some(b"a".to_vec()));
+        assert_eq!(engine.get(b"k2", 2).await.expect("get"), Some(b"b".to_vec()));
+        let legacy = SSTable::new("/db/sst-00000000000000000000.db".to_string());
+        assert_eq!(
+            legacy.format_version_with(&env).await.expect("version"),
+            LEGACY_FORMAT_VERSION
+        );
+
+        let report = engine.upgrade().await.expect("upgrade");
+        assert_eq!(report.tables_rewritten, 1);
+        assert!(report.wal_rewritten);
+        assert_eq!(legacy.format_version_with(&env).await.expect("version"), FORMAT_VERSION);
+        assert_eq!(
+            Wal::format_version_with(&env, "/db/wal.log").await.expect("version"),
+            FORMAT_VERSION
+        );
+        let manifest = String::from_utf8(env.file_contents("/db/MANIFEST").expect("manifest"))
+            .expect("utf-8");
+        assert!(manifest.contains(&format!("format_version={FORMAT_VERSION}")));
+        assert_eq!(engine.upgrade().await.expect("upgrade").tables_rewritten, 0);
+        drop(engine);
+
+        let engine = open_faulty(&env, 1 << 20).await;
+        assert_eq!(
+            engine.scan(b"", u64::MAX).await.expect("scan"),
+            vec![(b"k1".to_vec(), b"a".to_vec()), (b"k2".to_vec(), b"b".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn files_from_a_newer_format_are_rejected() {
+        use crate::format::FORMAT_VERSION;
+
+        let env = FaultInjectionEnv::new();
+        let engine = open_faulty(&env, 1 << 20).await;
+        engine.put(b"k1", b"a", 1).await.expect("put");
+        engine.flush().await.expect("flush");
+        drop(engine);
+
+        let newer = FORMAT_VERSION + 1;
+        let manifest = format!("format_version={newer}\nlast_version=1\n");
+        write_raw(&env, "/db/MANIFEST", manifest.as_bytes()).await;
+        let shared: SharedEnv = Arc::new(env.clone());
+        let err = LsmEngine::open_with_env(fault_options(1 << 20), shared.clone())
+            .await
+            .expect_err("newer manifest");
+        assert!(err.to_string().contains("this build reads up to"));
+
+        write_raw(&env, "/db/MANIFEST", b"format_version=1\nlast_version=1\n").await;
+        let mut wal = b"DCWL".to_vec();
+        wal.extend_from_slice(&newer.to_le_bytes());
+        write_raw(&env, "/db/wal.log", &wal).await;
+        assert!(LsmEngine::open_with_env(fault_options(1 << 20), shared)
+            .await
+            .is_err());
+    }
 }
@@ -1,57 +1,102 @@
 use crate::encryption::DataEncryptor;
-use anyhow::Result;
-use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use crate::env::{Env, LocalEnv, WritableFile};
+use crate::format::{check_readable, encode_header, split_header};
+use anyhow::{anyhow, Result};
 
 #[derive(Debug, Clone, Copy)]
 pub enum WalOp {
     Put,
     Delete,
+    /// A range tombstone: the key is the range's versioned start and the value its end.
+    DeleteRange,
 }
 
 #[derive(Debug)]
 pub struct Wal {
-    file: File,
+    file: Box<dyn WritableFile>,
     encryptor: Option<DataEncryptor>,
 }
 
 impl Wal {
     pub async fn open(path: &str, encryptor: Option<DataEncryptor>) -> Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .read(true)
-            .open(path)
-            .await?;
+        Self::open_with(&LocalEnv, path, encryptor).await
+    }
+
+    /// Opens the log for appending. A record torn by a crash mid-append is cut off first;
+    /// otherwise new records would land behind it where replay can never reach them. A new
+    /// log starts with a format header; a legacy log keeps its layout until the next reset.
+    pub async fn open_with(
+        env: &dyn Env,
+        path: &str,
+        encryptor: Option<DataEncryptor>,
+    ) -> Result<Self> {
+        let mut file = env.open_append(path).await?;
+        let data = env.read_file(path).await?;
+        if data.is_empty() {
+            file.append(&encode_header(WAL_MAGIC)).await?;
+            file.sync().await?;
+            return Ok(Self { file, encryptor });
+        }
+        let (header_len, records) = split_log(path, &data)?;
+        let (_, valid_len) = parse_records(records).map_err(|err| anyhow!("{path}: {err}"))?;
+        if header_len + valid_len < data.len() {
+            drop(file);
+            let tmp_path = format!("{path}.tmp");
+            let mut tmp = env.create(&tmp_path).await?;
+            tmp.append(&data[..header_len + valid_len]).await?;
+            tmp.sync().await?;
+            drop(tmp);
+            env.rename(&tmp_path, path).await?;
+            file = env.open_append(path).await?;
+        }
         Ok(Self { file, encryptor })
     }
 
+    /// Appends a record and syncs it; the write is durable once this returns.
     pub async fn append(&mut self, op: WalOp, key: &[u8], value: &[u8]) -> Result<()> {
+        self.append_all(&[(op, key, value)]).await
+    }
+
+    /// Appends several records with a single sync.
+    pub async fn append_all(&mut self, records: &[(WalOp, &[u8], &[u8])]) -> Result<()> {
+        let mut buffer = Vec::new();
+        for (op, key, value) in records {
+            self.encode_record(*op, key, value, &mut buffer)?;
+        }
+        self.file.append(&buffer).await?;
+        self.file.sync().await?;
+        Ok(())
+    }
+
+    fn encode_record(&self, op: WalOp, key: &[u8], value: &[u8], out: &mut Vec<u8>) -> Result<()> {
         let op_byte = match op {
             WalOp::Put => 1u8,
             WalOp::Delete => 2u8,
+            WalOp::DeleteRange => 3u8,
         };
         let (key, value) = if let Some(encryptor) = &self.encryptor {
             (encryptor.encrypt(key)?, encryptor.encrypt(value)?)
         } else {
             (key.to_vec(), value.to_vec())
         };
-        let key_len = key.len() as u32;
-        let value_len = value.len() as u32;
-        let record_len = 1 + 4 + key_len + 4 + value_len;
-        self.file.write_u32_le(record_len).await?;
-        self.file.write_u8(op_byte).await?;
-        self.file.write_u32_le(key_len).await?;
-        self.file.write_all(&key).await?;
-        self.file.write_u32_le(value_len).await?;
-        self.file.write_all(&value).await?;
-        self.file.flush().await?;
+        let mut body = Vec::with_capacity(9 + key.len() + value.len());
+        body.push(op_byte);
+        body.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        body.extend_from_slice(&key);
+        body.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        body.extend_from_slice(&value);
+        let record_len = body.len() as u32 + 4;
+        out.extend_from_slice(&record_len.to_le_bytes());
+        out.extend_from_slice(&body);
+        out.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
         Ok(())
     }
 
+    /// Empties the log, leaving only a current-format header.
     pub async fn reset(&mut self) -> Result<()> {
-        self.file.set_len(0).await?;
-        self.file.seek(std::io::SeekFrom::Start(0)).await?;
+        self.file.truncate().await?;
+        self.file.append(&encode_header(WAL_MAGIC)).await?;
+        self.file.sync().await?;
         Ok(())
     }
 
@@ -59,33 +104,123 @@ impl Wal {
         path: &str,
         encryptor: Option<DataEncryptor>,
     ) -> Result<Vec<(WalOp, Vec<u8>, Vec<u8>)>> {
-        let mut file = OpenOptions::new().read(true).open(path).await?;
-        let mut entries = Vec::new();
-        loop {
-            let len = match file.read_u32_le().await {
-                Ok(v) => v,
-                Err(_) => break,
-            };
-            if len == 0 {
-                break;
-            }
-            let op = match file.read_u8().await? {
-                1 => WalOp::Put,
-                2 => WalOp::Delete,
-                _ => WalOp::Put,
-            };
-            let key_len = file.read_u32_le().await? as usize;
-            let mut key = vec![0u8; key_len];
-            file.read_exact(&mut key).await?;
-            let value_len = file.read_u32_le().await? as usize;
-            let mut value = vec![0u8; value_len];
-            file.read_exact(&mut value).await?;
+        Self::replay_with(&LocalEnv, path, encryptor).await
+    }
+
+    /// Reads back every complete record. A record cut short by a crash mid-append was never
+    /// acknowledged, so replay stops there instead of failing; a damaged record with intact
+    /// records after it is corruption and fails replay.
+    pub async fn replay_with(
+        env: &dyn Env,
+        path: &str,
+        encryptor: Option<DataEncryptor>,
+    ) -> Result<Vec<(WalOp, Vec<u8>, Vec<u8>)>> {
+        let data = env.read_file(path).await?;
+        let (_, records) = split_log(path, &data)?;
+        let (records, _) = parse_records(records).map_err(|err| anyhow!("{path}: {err}"))?;
+        let mut entries = Vec::with_capacity(records.len());
+        for (op, mut key, mut value) in records {
             if let Some(enc) = &encryptor {
-                key = enc.decrypt(&key)?;
-                value = enc.decrypt(&value)?;
+                key = enc.decrypt(&key).map_err(|err| anyhow!("{path}: {err}"))?;
+                value = enc.decrypt(&value).map_err(|err| anyhow!("{path}: {err}"))?;
             }
             entries.push((op, key, value));
         }
         Ok(entries)
     }
 }
+
+impl Wal {
+    /// Strictly validates the log for scrubbing: every record must be intact, including the
+    /// last, and decrypt cleanly. Returns the number of bytes checked.
+    pub async fn verify_with(
+        env: &dyn Env,
+        path: &str,
+        encryptor: Option<&DataEncryptor>,
+    ) -> Result<u64> {
+        let data = env.read_file(path).await?;
+        let (header_len, body) = split_log(path, &data)?;
+        let (records, valid_len) = parse_records(body)?;
+        if valid_len != body.len() {
+            return Err(anyhow!("damaged wal record at offset {}", header_len + valid_len));
+        }
+        if let Some(enc) = encryptor {
+            for (_, key, value) in &records {
+                enc.decrypt(key)?;
+                enc.decrypt(value)?;
+            }
+        }
+        Ok(data.len() as u64)
+    }
+
+    /// On-disk format version recorded in the log's header.
+    pub async fn format_version_with(env: &dyn Env, path: &str) -> Result<u16> {
+        let data = env.read_file(path).await?;
+        Ok(split_header(WAL_MAGIC, &data).0)
+    }
+}
+
+/// Leading magic of the log, followed by the format version. Logs written before headers
+/// existed start directly with their first record.
+pub const WAL_MAGIC: &[u8; 4] = b"DCWL";
+
+/// Returns the header length and the record bytes after it.
+fn split_log<'a>(path: &str, data: &'a [u8]) -> Result<(usize, &'a [u8])> {
+    let (version, records) = split_header(WAL_MAGIC, data);
+    check_readable(path, version)?;
+    Ok((data.len() - records.len(), records))
+}
+
+type WalRecord = (WalOp, Vec<u8>, Vec<u8>);
+
+/// Decodes records from the start of `data`, returning them with the length of the valid
+/// prefix. Only the final record may be damaged; that is a torn append, not corruption.
+fn parse_records(data: &[u8]) -> Result<(Vec<WalRecord>, usize)> {
+    let mut records = Vec::new();
+    let mut offset = 0usize;
+    while let Some(len) = read_u32(data, offset) {
+        let len = len as usize;
+        let end = offset + 4 + len;
+        if len == 0 || end > data.len() {
+            break;
+        }
+        match decode_record(&data[offset + 4..end]) {
+            Some(record) => records.push(record),
+            None if end == data.len() => break,
+            None => return Err(anyhow!("corrupt wal record at offset {offset}")),
+        }
+        offset = end;
+    }
+    Ok((records, offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Decodes `op | key_len | key | value_len | value [| crc32]`. Records written before
+/// checksums were added have no trailing crc.
+fn decode_record(record: &[u8]) -> Option<WalRecord> {
+    let op = match *record.first()? {
+        1 => WalOp::Put,
+        2 => WalOp::Delete,
+        3 => WalOp::DeleteRange,
+        _ => WalOp::Put,
+    };
+    let key_len = read_u32(record, 1)? as usize;
+    let key = record.get(5..5 + key_len)?.to_vec();
+    let value_len = read_u32(record, 5 + key_len)? as usize;
+    let value_start = 9 + key_len;
+    let body_len = value_start + value_len;
+    let value = record.get(value_start..body_len)?.to_vec();
+    if record.len() == body_len + 4 {
+        let stored = read_u32(record, body_len)?;
+        if stored != crc32fast::hash(&record[..body_len]) {
+            return None;
+        }
+    } else if record.len() != body_len {
+        return None;
+    }
+    Some((op, key, value))
+}
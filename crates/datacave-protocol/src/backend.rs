@@ -96,8 +96,14 @@ pub async fn write_message<S: AsyncWrite + Unpin>(stream: &mut S, msg: BackendMe
             buf.put_i32((payload.len() + 4) as i32);
             buf.extend_from_slice(&payload);
         }
-        BackendMessage::ErrorResponse { message } => {
+        BackendMessage::ErrorResponse { code, message } => {
             let mut payload = BytesMut::new();
+            payload.put_u8(b'S');
+            put_cstring(&mut payload, "ERROR");
+            payload.put_u8(b'V');
+            put_cstring(&mut payload, "ERROR");
+            payload.put_u8(b'C');
+            put_cstring(&mut payload, &code);
             payload.put_u8(b'M');
             put_cstring(&mut payload, &message);
             payload.put_u8(0);
@@ -105,6 +111,21 @@ pub async fn write_message<S: AsyncWrite + Unpin>(stream: &mut S, msg: BackendMe
             buf.put_i32((payload.len() + 4) as i32);
             buf.extend_from_slice(&payload);
         }
+        BackendMessage::NoticeResponse { code, message } => {
+            let mut payload = BytesMut::new();
+            payload.put_u8(b'S');
+            put_cstring(&mut payload, "WARNING");
+            payload.put_u8(b'V');
+            put_cstring(&mut payload, "WARNING");
+            payload.put_u8(b'C');
+            put_cstring(&mut payload, &code);
+            payload.put_u8(b'M');
+            put_cstring(&mut payload, &message);
+            payload.put_u8(0);
+            buf.put_u8(b'N');
+            buf.put_i32((payload.len() + 4) as i32);
+            buf.extend_from_slice(&payload);
+        }
         BackendMessage::CloseComplete => {
             buf.put_u8(b'3');
             buf.put_i32(4);
@@ -10,4 +10,5 @@ pub use messages::{data_type_to_oid, RowDescriptionField};
 pub use messages::TransactionState;
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
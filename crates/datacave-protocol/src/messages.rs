@@ -1,3 +1,4 @@
+use datacave_core::types::DataType;
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
@@ -84,12 +85,11 @@ pub struct RowDescriptionField {
 }
 
 impl RowDescriptionField {
-    /// Create a field with type OID inferred from data_type string.
-    /// Uses PostgreSQL pg_type OIDs: 23=int4, 20=int8, 25=text, 16=bool, 701=float8, 17=bytea.
-    pub fn with_type(name: impl Into<String>, data_type: &str) -> Self {
+    /// Create a field whose type OID comes from the column's declared type.
+    pub fn with_type(name: impl Into<String>, data_type: &DataType) -> Self {
         Self {
             name: name.into(),
-            type_oid: data_type_to_oid(data_type),
+            type_oid: data_type.pg_oid(),
         }
     }
 }
@@ -97,36 +97,9 @@ impl RowDescriptionField {
 /// Map SQL data type string to PostgreSQL type OID (pg_type.oid).
 /// Returns 25 (text) for unknown types to preserve protocol compatibility.
 pub fn data_type_to_oid(data_type: &str) -> i32 {
-    let upper = data_type.to_uppercase();
-    let s = upper.trim();
-    if s == "INT" || s == "INTEGER" || s == "INT4" {
-        return 23;
-    }
-    if s == "BIGINT" || s == "INT8" {
-        return 20;
-    }
-    if s == "TEXT"
-        || s == "VARCHAR"
-        || s == "CHAR"
-        || s == "CHARACTER"
-        || s.starts_with("CHARACTER VARYING")
-        || s == "VARYING"
-    {
-        return 25;
-    }
-    if s == "BOOLEAN" || s == "BOOL" {
-        return 16;
-    }
-    if s == "FLOAT" || s == "FLOAT4" || s == "REAL" {
-        return 700;
-    }
-    if s == "DOUBLE" || s == "FLOAT8" || s == "DOUBLE PRECISION" {
-        return 701;
-    }
-    if s == "BYTEA" {
-        return 17;
-    }
-    25
+    DataType::parse(data_type)
+        .map(|data_type| data_type.pg_oid())
+        .unwrap_or(25)
 }
 
 #[derive(Debug, Clone)]
@@ -144,6 +117,9 @@ pub enum BackendMessage {
     RowDescription { fields: Vec<RowDescriptionField> },
     DataRow { values: Vec<Option<Vec<u8>>> },
     CommandComplete { tag: String },
-    ErrorResponse { message: String },
+    /// ErrorResponse (E) – `code` is the SQLSTATE.
+    ErrorResponse { code: String, message: String },
+    /// NoticeResponse (N) – a WARNING sent alongside a successful command.
+    NoticeResponse { code: String, message: String },
     CloseComplete,
 }
@@ -301,6 +301,48 @@ mod tests {
         assert_eq!(i32::from_be_bytes(bytes[1..5].try_into().unwrap()), 4);
     }
 
+    #[tokio::test]
+    async fn write_error_response_fields() {
+        let (mut client, mut server) = tokio::io::duplex(128);
+        write_message(
+            &mut server,
+            BackendMessage::ErrorResponse {
+                code: "42804".into(),
+                message: "bad type".into(),
+            },
+        )
+        .await
+        .expect("write");
+        let mut header = [0u8; 5];
+        client.read_exact(&mut header).await.expect("read header");
+        assert_eq!(header[0], b'E');
+        let len = i32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+        let mut payload = vec![0u8; len - 4];
+        client.read_exact(&mut payload).await.expect("read payload");
+        assert_eq!(payload, b"SERROR\0VERROR\0C42804\0Mbad type\0\0");
+    }
+
+    #[tokio::test]
+    async fn write_notice_response_fields() {
+        let (mut client, mut server) = tokio::io::duplex(128);
+        write_message(
+            &mut server,
+            BackendMessage::NoticeResponse {
+                code: "01000".into(),
+                message: "careful".into(),
+            },
+        )
+        .await
+        .expect("write");
+        let mut header = [0u8; 5];
+        client.read_exact(&mut header).await.expect("read header");
+        assert_eq!(header[0], b'N');
+        let len = i32::from_be_bytes(header[1..5].try_into().unwrap()) as usize;
+        let mut payload = vec![0u8; len - 4];
+        client.read_exact(&mut payload).await.expect("read payload");
+        assert_eq!(payload, b"SWARNING\0VWARNING\0C01000\0Mcareful\0\0");
+    }
+
     #[tokio::test]
     async fn write_no_data() {
         let (mut client, mut server) = tokio::io::duplex(32);
@@ -316,9 +358,10 @@ mod tests {
     #[tokio::test]
     async fn write_row_description_with_type_oids() {
         use crate::messages::RowDescriptionField;
+        use datacave_core::types::DataType;
         let fields = vec![
-            RowDescriptionField::with_type("id", "INT"),
-            RowDescriptionField::with_type("name", "TEXT"),
+            RowDescriptionField::with_type("id", &DataType::Int),
+            RowDescriptionField::with_type("name", &DataType::Text),
         ];
         let (mut client, mut server) = tokio::io::duplex(128);
         write_message(&mut server, BackendMessage::RowDescription { fields })
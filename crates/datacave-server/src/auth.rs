@@ -13,6 +13,8 @@ pub struct AuthManager {
 #[derive(Debug, Clone)]
 pub struct UserContext {
     pub username: String,
+    // Kept for auditing; access checks use the permissions resolved from the roles at login.
+    #[allow(dead_code)]
     pub roles: HashSet<String>,
     pub can_read: bool,
     pub can_write: bool,
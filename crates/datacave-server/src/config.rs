@@ -19,13 +19,49 @@ pub struct ServerConfig {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct StorageConfig {
+    #[serde(default)]
+    pub engine: StorageEngineKind,
     pub data_dir: String,
     pub wal_enabled: bool,
     pub memtable_max_bytes: usize,
+    // Accepted for config compatibility; flush and compaction do not size tables by it yet.
+    #[allow(dead_code)]
     pub sstable_target_bytes: usize,
     pub encryption_enabled: bool,
     pub encryption_key_base64: Option<String>,
     pub compaction_interval_secs: Option<u64>,
+    /// Secondary directory for cold SSTables; tiering is off when unset.
+    #[serde(default)]
+    pub cold_data_dir: Option<String>,
+    /// Tables produced by this many rounds of compaction are moved to `cold_data_dir`.
+    #[serde(default)]
+    pub cold_min_level: Option<u32>,
+    /// Tables older than this are moved to `cold_data_dir`.
+    #[serde(default)]
+    pub cold_after_secs: Option<u64>,
+    /// Period of the background integrity scrub; scrubbing is off when unset.
+    #[serde(default)]
+    pub scrub_interval_secs: Option<u64>,
+    /// Take corrupt SSTables out of service when the scrubber finds them.
+    #[serde(default)]
+    pub scrub_quarantine: bool,
+    /// Rebuild a replica from a healthy peer after its corrupt tables are quarantined.
+    #[serde(default)]
+    pub scrub_repair: bool,
+    /// How far back `AS OF SYSTEM TIME` queries can read; older versions are dropped by
+    /// compaction. History is kept forever when unset.
+    #[serde(default)]
+    pub history_retention_secs: Option<u64>,
+}
+
+/// Backing store for each shard replica. `memory` keeps everything in RAM and is meant for
+/// tests and ephemeral databases.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngineKind {
+    #[default]
+    Lsm,
+    Memory,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -97,6 +133,7 @@ impl Config {
         if self.storage.encryption_enabled && self.storage.encryption_key_base64.is_none() {
             return Err(anyhow::anyhow!("storage encryption enabled but key missing"));
         }
+        #[allow(clippy::collapsible_if)]
         if self.security.tls.enabled {
             if self.security.tls.cert_path.is_none() || self.security.tls.key_path.is_none() {
                 return Err(anyhow::anyhow!("tls enabled but cert_path or key_path missing"));
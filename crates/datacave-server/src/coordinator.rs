@@ -27,7 +27,7 @@ impl Coordinator {
                 .collect();
         }
         let shard_id = table_name(stmt)
-            .map(|name| hash_table(&name) % self.shard_count)
+            .map(|name| self.shard_for_table(&name))
             .unwrap_or(0);
         vec![ShardPlan {
             shard_id,
@@ -35,6 +35,11 @@ impl Coordinator {
         }]
     }
 
+    /// Shard that owns every row of `table`.
+    pub fn shard_for_table(&self, table: &str) -> usize {
+        hash_table(table) % self.shard_count
+    }
+
     pub fn aggregate(&self, stmt: &Statement, results: Vec<SqlResult>) -> SqlResult {
         if results.is_empty() {
             return SqlResult {
@@ -79,8 +84,10 @@ fn table_name(stmt: &Statement) -> Option<String> {
                 _ => None,
             }),
         Statement::CreateTable { name, .. } => Some(name.to_string()),
+        Statement::AlterTable { name, .. } => Some(name.to_string()),
+        Statement::Truncate { table_name, .. } => Some(table_name.to_string()),
         Statement::Drop { names, .. } => {
-            names.get(0).map(|name: &ObjectName| name.to_string())
+            names.first().map(|name: &ObjectName| name.to_string())
         }
         Statement::Query(query) => table_from_query(query),
         _ => None,
@@ -91,7 +98,7 @@ fn table_from_query(query: &Query) -> Option<String> {
     match &*query.body {
         SetExpr::Select(select) => select
             .from
-            .get(0)
+            .first()
             .and_then(|table_with_joins| match &table_with_joins.relation {
                 TableFactor::Table { name, .. } => Some(name.to_string()),
                 _ => None,
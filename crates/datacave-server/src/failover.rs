@@ -5,6 +5,8 @@ use std::time::{Duration, Instant};
 #[derive(Debug, Clone)]
 pub struct NodeHealth {
     pub healthy: bool,
+    // Only read by `stale_nodes`, which waits on a heartbeat sender.
+    #[allow(dead_code)]
     pub last_heartbeat: Instant,
 }
 
@@ -46,6 +48,8 @@ impl FailoverManager {
             );
     }
 
+    // Nothing sends heartbeats yet; nodes are marked healthy or unhealthy directly.
+    #[allow(dead_code)]
     pub fn heartbeat(&self, node_id: &str) {
         self.health
             .lock()
@@ -70,6 +74,8 @@ impl FailoverManager {
             .unwrap_or(false)
     }
 
+    // Nothing sends heartbeats yet, so no node goes stale.
+    #[allow(dead_code)]
     pub fn stale_nodes(&self, max_age: Duration) -> Vec<String> {
         let now = Instant::now();
         self.health
@@ -0,0 +1,252 @@
+use crate::config::{Config, StorageEngineKind};
+use crate::coordinator::Coordinator;
+use crate::server::{load_encryption_key, replica_lsm_options};
+use datacave_core::mvcc::Version;
+use datacave_core::catalog::TableSchema;
+use datacave_core::types::DataValue;
+use datacave_lsm::encryption::DataEncryptor;
+use datacave_lsm::engine::LsmEngine;
+use datacave_lsm::ingest::SstBuilder;
+use datacave_sql::executor::IndexEntry;
+use datacave_sql::{
+    catalog_key, decode_row_id, decode_schema, decode_sequence, encode_primary_row_key,
+    encode_row_key, encode_table_row, row_index_entries, sequence_key, table_key_prefix,
+    table_tenant_key, RowValidator,
+};
+use std::io::{BufRead, BufReader};
+use tracing::info;
+use uuid::Uuid;
+
+/// Offline bulk load of a CSV file into one table.
+#[derive(Debug, Clone)]
+pub struct IngestRequest {
+    pub table: String,
+    pub input: String,
+    pub tenant: Option<String>,
+    /// Version the rows are written at; defaults to just above the newest data on disk.
+    pub version: Option<Version>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestSummary {
+    pub shard_id: usize,
+    pub rows: usize,
+    pub version: Version,
+}
+
+/// Builds one SSTable from `request.input` and ingests it into every replica of the shard
+/// owning the table. The server must not be running against the same data directory.
+pub async fn bulk_ingest(config: &Config, request: &IngestRequest) -> anyhow::Result<IngestSummary> {
+    if config.storage.engine != StorageEngineKind::Lsm {
+        return Err(anyhow::anyhow!("bulk ingestion requires the lsm storage engine"));
+    }
+    let shard_id = Coordinator::new(config.sharding.shard_count).shard_for_table(&request.table);
+    let mut replicas = Vec::new();
+    for replica_id in 0..config.cluster.replication_factor {
+        let options = replica_lsm_options(config, shard_id, replica_id);
+        std::fs::create_dir_all(&options.data_dir)?;
+        if let Some(tiering) = &options.tiering {
+            std::fs::create_dir_all(&tiering.cold_dir)?;
+        }
+        replicas.push(LsmEngine::open(options).await?);
+    }
+
+    // Rows follow the table's current layout and live under its storage name, which differs
+    // from the table name after a rename.
+    let mut schema = None;
+    for engine in &replicas {
+        if let Some(stored) = engine.get(&catalog_key(&request.table), Version::MAX).await? {
+            schema = Some(decode_schema(&stored)?);
+            break;
+        }
+    }
+    let schema = schema
+        .ok_or_else(|| anyhow::anyhow!("relation \"{}\" does not exist", request.table))?;
+    // A UNIQUE constraint from before constraints were indexed is only checked by scanning
+    // the table, which an offline load cannot do.
+    if let Some(unique) = schema.unique.iter().find(|unique| {
+        unique.columns != schema.key_columns
+            && !schema.indexes.iter().any(|index| index.unique && index.name == unique.name)
+    }) {
+        return Err(anyhow::anyhow!(
+            "unique constraint {} of {} has no index to check ingested rows against",
+            unique.name,
+            request.table
+        ));
+    }
+    let storage_name = schema.storage_name.as_str();
+    let tenant = request.tenant.as_deref();
+    let prefix = table_key_prefix(storage_name, tenant);
+    let seq_key = sequence_key(storage_name, tenant);
+    let mut next_row_id = 0u64;
+    let mut newest = 0;
+    for engine in &replicas {
+        newest = newest.max(engine.snapshot().version);
+        if let Some(stored) = engine.get(&seq_key, Version::MAX).await? {
+            next_row_id = next_row_id.max(decode_sequence(&stored)?);
+        }
+        let rows = engine.scan(&prefix, Version::MAX).await?;
+        if let Some((key, _)) = rows.iter().rev().find(|(key, _)| key.len() == prefix.len() + 8) {
+            next_row_id = next_row_id.max(decode_row_id(key)? + 1);
+        }
+    }
+    // Rows written at or below the clock could sort behind versions already on disk.
+    let version = match request.version {
+        Some(version) if version <= newest => {
+            return Err(anyhow::anyhow!(
+                "version {version} is not above the newest stored version {newest}"
+            ));
+        }
+        Some(version) => version,
+        None => newest + 1,
+    };
+
+    // Each row is coerced and checked against NOT NULL and CHECK the way INSERT would.
+    let validator = RowValidator::new(&schema)?;
+    let mut rows = Vec::new();
+    let reader = BufReader::new(std::fs::File::open(&request.input)?);
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let values = validator
+            .validate(line.split(',').map(parse_csv_value).collect())
+            .map_err(|err| anyhow::anyhow!("{}:{}: {err}", request.input, number + 1))?;
+        rows.push(values);
+    }
+    if rows.is_empty() {
+        return Err(anyhow::anyhow!("no rows in {}", request.input));
+    }
+    let keyed_table = !schema.key_columns.is_empty();
+    let row_count = rows.len();
+    let mut keyed = Vec::with_capacity(row_count);
+    if keyed_table {
+        // Rows of a table keyed by its primary key go in key order, and the key must be new.
+        let key_indices = schema.key_indices();
+        for values in rows {
+            let key_values: Vec<DataValue> =
+                key_indices.iter().map(|&idx| values[idx].clone()).collect();
+            keyed.push((encode_primary_row_key(storage_name, &key_values, tenant), values));
+        }
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        for pair in keyed.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(anyhow::anyhow!("duplicate primary key in {}", request.input));
+            }
+        }
+        for (key, _) in &keyed {
+            for engine in &replicas {
+                if engine.get(key, Version::MAX).await?.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "{} reuses a primary key already stored in {}",
+                        request.input,
+                        request.table
+                    ));
+                }
+            }
+        }
+    } else {
+        for (row_id, values) in (next_row_id..).zip(rows) {
+            keyed.push((encode_row_key(storage_name, row_id, tenant), values));
+        }
+    }
+
+    // The rows' index entries go in with them. Entries of a unique index share a key for
+    // equal values, so a duplicate shows up as a repeated or already stored key.
+    let mut entries = Vec::new();
+    for (key, values) in &keyed {
+        entries.extend(row_index_entries(&schema, values, key, tenant));
+    }
+    entries.sort_by(|a, b| a.1.cmp(&b.1));
+    for pair in entries.windows(2) {
+        if pair[0].1 == pair[1].1 {
+            return Err(anyhow::anyhow!(
+                "{} repeats a value of unique index {}",
+                request.input,
+                pair[0].0.name
+            ));
+        }
+    }
+    for (index, entry, _) in entries.iter().filter(|(index, _, _)| index.unique) {
+        for engine in &replicas {
+            if engine.get(entry, Version::MAX).await?.is_some() {
+                return Err(anyhow::anyhow!(
+                    "{} reuses a value of unique index {} already stored in {}",
+                    request.input,
+                    index.name,
+                    request.table
+                ));
+            }
+        }
+    }
+
+    let encryptor = load_encryption_key(config)
+        .map(|key| DataEncryptor::new(&key))
+        .transpose()?;
+    std::fs::create_dir_all(&config.storage.data_dir)?;
+    let staging = format!("{}/ingest-{}.sst", config.storage.data_dir, Uuid::new_v4());
+    let mut builder = SstBuilder::create(&staging, version, encryptor.as_ref()).await?;
+    if let Err(err) = add_entries(&mut builder, &schema, &entries, keyed, request, next_row_id).await {
+        builder.abandon().await?;
+        return Err(err);
+    }
+    builder.finish().await?;
+    let result = ingest_into_replicas(&replicas, &staging).await;
+    let _ = std::fs::remove_file(&staging);
+    result?;
+    info!(
+        "ingested {} rows into {} on shard {} at version {}",
+        row_count, request.table, shard_id, version
+    );
+    Ok(IngestSummary {
+        shard_id,
+        rows: row_count,
+        version,
+    })
+}
+
+/// Writes the load in key order: index entries, the sequence and the tenant key sort before
+/// every row key, in that order.
+async fn add_entries(
+    builder: &mut SstBuilder,
+    schema: &TableSchema,
+    entries: &[IndexEntry<'_>],
+    rows: Vec<(Vec<u8>, Vec<DataValue>)>,
+    request: &IngestRequest,
+    next_row_id: u64,
+) -> anyhow::Result<()> {
+    let storage_name = schema.storage_name.as_str();
+    let tenant = request.tenant.as_deref();
+    for (_, entry, row_key) in entries {
+        builder.put(entry, row_key).await?;
+    }
+    if schema.key_columns.is_empty() {
+        let next = next_row_id + rows.len() as u64;
+        builder.put(&sequence_key(storage_name, tenant), &next.to_be_bytes()).await?;
+    }
+    if let Some(tenant) = tenant {
+        builder.put(&table_tenant_key(storage_name, tenant), &[]).await?;
+    }
+    for (key, values) in rows {
+        builder.put(&key, &encode_table_row(schema, values)?).await?;
+    }
+    Ok(())
+}
+
+async fn ingest_into_replicas(replicas: &[LsmEngine], staging: &str) -> anyhow::Result<()> {
+    for engine in replicas {
+        engine.ingest_sstable(staging).await?;
+    }
+    Ok(())
+}
+
+/// Fields stay text until they are coerced to their column's type, as literals are.
+fn parse_csv_value(field: &str) -> DataValue {
+    let field = field.trim();
+    if field.is_empty() || field.eq_ignore_ascii_case("null") {
+        DataValue::Null
+    } else {
+        DataValue::String(field.to_string())
+    }
+}
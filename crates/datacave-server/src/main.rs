@@ -2,8 +2,11 @@ mod config;
 mod coordinator;
 mod auth;
 mod failover;
+mod ingest;
 mod raft;
+mod scrubber;
 mod server;
+mod upgrade;
 
 use clap::{Parser, Subcommand};
 use config::Config;
@@ -33,6 +36,26 @@ enum Command {
         #[arg(long)]
         password: String,
     },
+    /// Bulk-load a CSV file into a table as a prebuilt SSTable, bypassing the WAL.
+    /// Run it while the server is stopped.
+    Ingest {
+        #[arg(long, default_value = "config.example.toml")]
+        config: String,
+        #[arg(long)]
+        table: String,
+        #[arg(long)]
+        input: String,
+        #[arg(long)]
+        tenant: Option<String>,
+        #[arg(long)]
+        version: Option<u64>,
+    },
+    /// Rewrite every data directory in the current on-disk format. Run it while the
+    /// server is stopped.
+    Upgrade {
+        #[arg(long, default_value = "config.example.toml")]
+        config: String,
+    },
 }
 
 #[tokio::main]
@@ -58,6 +81,34 @@ async fn main() -> anyhow::Result<()> {
                 .to_string();
             println!("{hash}");
         }
+        Command::Ingest {
+            config,
+            table,
+            input,
+            tenant,
+            version,
+        } => {
+            let config = Config::from_path(&config)?;
+            let request = ingest::IngestRequest {
+                table,
+                input,
+                tenant,
+                version,
+            };
+            let summary = ingest::bulk_ingest(&config, &request).await?;
+            println!(
+                "ingested {} rows into {} (shard {}) at version {}",
+                summary.rows, request.table, summary.shard_id, summary.version
+            );
+        }
+        Command::Upgrade { config } => {
+            let config = Config::from_path(&config)?;
+            let summary = upgrade::upgrade_data(&config).await?;
+            println!(
+                "upgraded {} replicas: {} tables and {} rows rewritten",
+                summary.replicas, summary.tables_rewritten, summary.rows_rewritten
+            );
+        }
     }
     Ok(())
 }
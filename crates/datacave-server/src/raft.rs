@@ -31,10 +31,6 @@ impl RaftManager {
             .insert(shard_id, replica_id);
     }
 
-    pub fn replication_factor(&self) -> usize {
-        self.config.replication_factor
-    }
-
     pub fn quorum(&self) -> usize {
         (self.config.replication_factor / 2) + 1
     }
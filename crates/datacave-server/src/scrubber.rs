@@ -0,0 +1,136 @@
+use crate::failover::FailoverManager;
+use datacave_sql::SqlExecutor;
+use datacave_lsm::scrub::CorruptFile;
+use datacave_lsm::storage::SharedStorage;
+use metrics::{counter, gauge};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+/// Corrupt files found by the latest scrub of each replica, keyed by node id.
+#[derive(Debug, Default, Clone)]
+pub struct ScrubMonitor {
+    corrupt: Arc<Mutex<BTreeMap<String, Vec<CorruptFile>>>>,
+}
+
+impl ScrubMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, node_id: &str, files: Vec<CorruptFile>) {
+        gauge!("scrub_corrupt_files", "node" => node_id.to_string()).set(files.len() as f64);
+        let mut corrupt = self.corrupt.lock().unwrap();
+        if files.is_empty() {
+            corrupt.remove(node_id);
+        } else {
+            corrupt.insert(node_id.to_string(), files);
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.lock().unwrap().is_empty()
+    }
+
+    /// One line per corrupt file, for the `/health` response body.
+    pub fn describe(&self) -> String {
+        self.corrupt
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|(node_id, files)| {
+                files
+                    .iter()
+                    .map(move |file| format!("{node_id}: {} ({})\n", file.path, file.reason))
+            })
+            .collect()
+    }
+}
+
+/// What the scrubber may do about corruption besides reporting it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScrubPolicy {
+    pub quarantine: bool,
+    pub repair: bool,
+}
+
+/// A replica as seen by the scrubber. Repairs go through its executor, whose clock the
+/// copied versions are claimed from.
+#[derive(Debug, Clone)]
+pub struct ScrubTarget {
+    pub node_id: String,
+    pub storage: SharedStorage,
+    pub executor: Arc<SqlExecutor>,
+}
+
+/// Scrubs every replica of one shard group.
+///
+/// A replica with corrupt files is marked unhealthy so reads and replication skip it. When
+/// the policy allows, its corrupt SSTables are quarantined and the versions it lost are
+/// copied back from a clean replica of the same group, after which it rejoins the group.
+pub async fn scrub_group(
+    replicas: &[ScrubTarget],
+    failover: &FailoverManager,
+    monitor: &ScrubMonitor,
+    policy: ScrubPolicy,
+) {
+    let mut corrupt = Vec::with_capacity(replicas.len());
+    for replica in replicas {
+        let files = match replica.storage.scrub().await {
+            Ok(report) => report.corrupt,
+            Err(err) => {
+                error!("scrub of {} failed: {err}", replica.node_id);
+                continue;
+            }
+        };
+        counter!("scrub_runs_total").increment(1);
+        corrupt.push((replica, files));
+    }
+
+    for (replica, files) in &corrupt {
+        if files.is_empty() {
+            monitor.record(&replica.node_id, Vec::new());
+            continue;
+        }
+        warn!("{} has {} corrupt files", replica.node_id, files.len());
+        failover.mark_unhealthy(&replica.node_id);
+        let remaining = match heal(replica, files, &corrupt, policy).await {
+            Ok(remaining) => remaining,
+            Err(err) => {
+                error!("repair of {} failed: {err}", replica.node_id);
+                files.clone()
+            }
+        };
+        if remaining.is_empty() {
+            info!("{} repaired from a healthy replica", replica.node_id);
+            counter!("scrub_repairs_total").increment(1);
+            failover.mark_healthy(&replica.node_id);
+        }
+        monitor.record(&replica.node_id, remaining);
+    }
+}
+
+/// Quarantines and repairs `replica` as far as `policy` allows; returns the files still corrupt.
+async fn heal(
+    replica: &ScrubTarget,
+    files: &[CorruptFile],
+    group: &[(&ScrubTarget, Vec<CorruptFile>)],
+    policy: ScrubPolicy,
+) -> anyhow::Result<Vec<CorruptFile>> {
+    if !policy.quarantine {
+        return Ok(files.to_vec());
+    }
+    for file in files {
+        replica.storage.quarantine(&file.path).await?;
+    }
+    if !policy.repair {
+        return Ok(files.to_vec());
+    }
+    let source = group
+        .iter()
+        .find(|(peer, peer_files)| peer.node_id != replica.node_id && peer_files.is_empty())
+        .map(|(peer, _)| peer)
+        .ok_or_else(|| anyhow::anyhow!("no healthy replica to repair from"))?;
+    replica.executor.repair_from(source.storage.as_ref()).await?;
+    Ok(replica.storage.scrub().await?.corrupt)
+}
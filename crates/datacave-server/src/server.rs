@@ -1,12 +1,16 @@
 use crate::auth::{AuthManager, UserContext};
-use crate::config::Config;
+use crate::config::{Config, StorageEngineKind};
 use crate::failover::FailoverManager;
 use crate::raft::RaftManager;
+use crate::scrubber::{scrub_group, ScrubMonitor, ScrubPolicy, ScrubTarget};
 use crate::coordinator::{Coordinator, ShardPlan};
 use datacave_core::catalog::Catalog;
+use datacave_core::error::{sqlstate, DatacaveError};
 use datacave_core::mvcc::MvccManager;
 use datacave_core::types::{DataValue, SqlResult};
-use datacave_lsm::engine::{LsmEngine, LsmOptions};
+use datacave_lsm::engine::{LsmEngine, LsmOptions, TieringOptions};
+use datacave_lsm::memory::MemoryEngine;
+use datacave_lsm::storage::{SharedStorage, WriteBatch};
 use datacave_protocol::backend::write_message;
 use datacave_protocol::frontend::{read_message, read_startup};
 use datacave_protocol::messages::{
@@ -14,8 +18,9 @@ use datacave_protocol::messages::{
     TransactionState,
 };
 use datacave_sql::executor::SqlExecutor;
-use datacave_sql::parse_sql;
-use sqlparser::ast::Statement;
+use datacave_sql::planner::plan_isolation;
+use datacave_sql::{parse_sql, IsolationLevel, TxnId};
+use sqlparser::ast::{AlterTableOperation, Statement};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
@@ -42,13 +47,18 @@ pub async fn run(config: Config) -> anyhow::Result<()> {
         .listen_addr
         .parse()
         .unwrap_or(([127, 0, 0, 1], 9898).into());
+    let scrub_monitor = ScrubMonitor::new();
+    let health_monitor = scrub_monitor.clone();
     tokio::spawn(async move {
         let app = axum::Router::new()
             .route(
                 "/metrics",
                 axum::routing::get(|| async move { metrics_handle.render() }),
             )
-            .route("/health", axum::routing::get(|| async { "ok" }))
+            .route(
+                "/health",
+                axum::routing::get(move || async move { health_response(&health_monitor) }),
+            )
             .route("/ready", axum::routing::get(|| async { "ok" }));
         if let Ok(listener) = tokio::net::TcpListener::bind(metrics_addr).await {
             let _ = axum::serve(listener, app).await;
@@ -59,6 +69,9 @@ pub async fn run(config: Config) -> anyhow::Result<()> {
     info!("Datacave listening on {}", config.server.listen_addr);
 
     let router = ShardRouter::new(&config).await?;
+    if let Some(secs) = config.storage.scrub_interval_secs {
+        router.start_scrubber(Duration::from_secs(secs), scrub_policy(&config), scrub_monitor);
+    }
     let connection_limit = Arc::new(Semaphore::new(config.server.max_connections));
     let idle_timeout = config.server.idle_timeout_secs.map(Duration::from_secs);
     let auth = if config.security.auth.enabled {
@@ -82,7 +95,6 @@ pub async fn run(config: Config) -> anyhow::Result<()> {
         };
         let auth = auth.clone();
         let tls_acceptor = tls_acceptor.clone();
-        let idle_timeout = idle_timeout;
         tokio::spawn(async move {
             let _permit = permit;
             if let Some(acceptor) = tls_acceptor {
@@ -102,17 +114,42 @@ pub async fn run(config: Config) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn health_response(monitor: &ScrubMonitor) -> (axum::http::StatusCode, String) {
+    if monitor.is_clean() {
+        (axum::http::StatusCode::OK, "ok".to_string())
+    } else {
+        (
+            axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            format!("corrupt files:\n{}", monitor.describe()),
+        )
+    }
+}
+
+fn scrub_policy(config: &Config) -> ScrubPolicy {
+    ScrubPolicy {
+        quarantine: config.storage.scrub_quarantine,
+        repair: config.storage.scrub_repair,
+    }
+}
+
 /// A single response item for a multi-statement query.
 #[derive(Debug)]
 enum QueryResponseItem {
     Rows(SqlResult),
     CommandCompleteOnly(String),
+    /// A WARNING notice sent ahead of the statement's CommandComplete.
+    Warning(String),
 }
 
 fn is_transaction_control(stmt: &Statement) -> bool {
     matches!(
         stmt,
-        Statement::StartTransaction { .. } | Statement::Commit { .. } | Statement::Rollback { .. }
+        Statement::StartTransaction { .. }
+            | Statement::Commit { .. }
+            | Statement::Rollback { .. }
+            | Statement::SetTransaction { session: false, .. }
+            | Statement::Savepoint { .. }
+            | Statement::ReleaseSavepoint { .. }
     )
 }
 
@@ -121,13 +158,31 @@ fn transaction_command_tag(stmt: &Statement) -> Option<&'static str> {
         Statement::StartTransaction { .. } => Some("BEGIN"),
         Statement::Commit { .. } => Some("COMMIT"),
         Statement::Rollback { .. } => Some("ROLLBACK"),
+        Statement::SetTransaction { .. } => Some("SET"),
+        Statement::Savepoint { .. } => Some("SAVEPOINT"),
+        Statement::ReleaseSavepoint { .. } => Some("RELEASE"),
         _ => None,
     }
 }
 
-/// Returns true if the statement is allowed during a failed transaction (clears the failed state).
+/// Returns true if the statement ends a failed transaction (clears the failed state).
 fn is_rollback_or_commit(stmt: &Statement) -> bool {
-    matches!(stmt, Statement::Commit { .. } | Statement::Rollback { .. })
+    matches!(
+        stmt,
+        Statement::Commit { .. } | Statement::Rollback { savepoint: None, .. }
+    )
+}
+
+/// Savepoint statements, which only make sense inside a transaction block.
+fn savepoint_command(stmt: &Statement) -> Option<&'static str> {
+    match stmt {
+        Statement::Savepoint { .. } => Some("SAVEPOINT"),
+        Statement::ReleaseSavepoint { .. } => Some("RELEASE SAVEPOINT"),
+        Statement::Rollback {
+            savepoint: Some(_), ..
+        } => Some("ROLLBACK TO SAVEPOINT"),
+        _ => None,
+    }
 }
 
 /// Transaction state for ReadyForQuery based on connection state.
@@ -141,30 +196,38 @@ fn connection_ready_state(in_tx: bool, failed_tx: bool) -> TransactionState {
     }
 }
 
-/// Returns true for statements that mutate state and should be buffered during a transaction.
-fn is_mutating_statement(stmt: &Statement) -> bool {
+/// Returns true for statements that read or write rows inside the session's transaction.
+/// Other statements, such as DDL, take effect immediately.
+fn is_transactional_statement(stmt: &Statement) -> bool {
     matches!(
         stmt,
-        Statement::Insert { .. }
+        Statement::Query(_)
+            | Statement::Insert { .. }
             | Statement::Update { .. }
             | Statement::Delete { .. }
-            | Statement::CreateTable { .. }
     )
 }
 
-/// Returns rows_affected for buffered INSERT (best-effort from parsed values).
-fn buffered_insert_rows_affected(stmt: &Statement) -> u64 {
-    match stmt {
-        Statement::Insert { source, .. } => {
-            if let Some(src) = source {
-                if let sqlparser::ast::SetExpr::Values(v) = &*src.body {
-                    return v.rows.len() as u64;
-                }
+/// Tables are placed on shards by name, so a table can only be renamed to a name owned by
+/// the same shard.
+fn check_rename_stays_on_shard(coordinator: &Coordinator, stmt: &Statement) -> anyhow::Result<()> {
+    let Statement::AlterTable { name, operations, .. } = stmt else {
+        return Ok(());
+    };
+    for op in operations {
+        if let AlterTableOperation::RenameTable { table_name } = op {
+            let from = coordinator.shard_for_table(&name.to_string());
+            let to = coordinator.shard_for_table(&table_name.to_string());
+            if from != to {
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::FEATURE_NOT_SUPPORTED,
+                    format!("cannot rename {name} to {table_name}: the new name belongs to another shard"),
+                )
+                .into());
             }
-            0
         }
-        _ => 0,
     }
+    Ok(())
 }
 
 async fn handle_query(
@@ -172,9 +235,8 @@ async fn handle_query(
     sql: &str,
     tenant_id: Option<String>,
     user: Option<&UserContext>,
-    in_tx: &mut bool,
+    session: &mut Option<SessionTransaction>,
     failed_tx: &mut bool,
-    tx_buffer: &mut Vec<Statement>,
 ) -> anyhow::Result<Vec<QueryResponseItem>> {
     let statements = parse_sql(sql)?;
     let coordinator = Coordinator::new(router.shard_count());
@@ -184,23 +246,31 @@ async fn handle_query(
         if *failed_tx {
             if is_rollback_or_commit(&stmt) {
                 let tag = transaction_command_tag(&stmt).unwrap();
-                match &stmt {
-                    Statement::Commit { .. } | Statement::Rollback { .. } => {
-                        tx_buffer.clear();
-                        *in_tx = false;
-                        *failed_tx = false;
-                    }
-                    _ => {}
+                if let Some(txn) = session.take() {
+                    router.rollback(txn).await;
                 }
+                *failed_tx = false;
                 items.push(QueryResponseItem::CommandCompleteOnly(tag.to_string()));
                 continue;
             }
-            return Err(anyhow::anyhow!(
-                "current transaction is aborted, commands ignored until end of transaction block"
-            ));
+            // ROLLBACK TO SAVEPOINT undoes the failed work and resumes the transaction.
+            if let (Statement::Rollback { savepoint: Some(_), .. }, Some(txn)) =
+                (&stmt, session.as_ref())
+            {
+                router.run_on_every_shard(&stmt, txn).await?;
+                *failed_tx = false;
+                items.push(QueryResponseItem::CommandCompleteOnly("ROLLBACK".to_string()));
+                continue;
+            }
+            return Err(DatacaveError::sqlstate(
+                sqlstate::IN_FAILED_SQL_TRANSACTION,
+                "current transaction is aborted, commands ignored until end of transaction block",
+            )
+            .into());
         }
         counter!("sql_statement_total").increment(1);
         authorize_statement(user, &stmt)?;
+        check_rename_stays_on_shard(&coordinator, &stmt)?;
         if router.audit_enabled {
             let user_name = user
                 .map(|ctx| ctx.username.clone())
@@ -214,45 +284,63 @@ async fn handle_query(
         }
         if is_transaction_control(&stmt) {
             let tag = transaction_command_tag(&stmt).unwrap();
+            if let Some(command) = savepoint_command(&stmt) {
+                let Some(txn) = session.as_ref() else {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::NO_ACTIVE_SQL_TRANSACTION,
+                        format!("{command} can only be used in transaction blocks"),
+                    )
+                    .into());
+                };
+                router.run_on_every_shard(&stmt, txn).await?;
+                items.push(QueryResponseItem::CommandCompleteOnly(tag.to_string()));
+                continue;
+            }
             match &stmt {
-                Statement::StartTransaction { .. } => {
-                    *in_tx = true;
-                    tx_buffer.clear();
+                Statement::StartTransaction { modes, .. } if session.is_none() => {
+                    let isolation = plan_isolation(modes).unwrap_or_default();
+                    *session = Some(router.begin(isolation).await?);
+                }
+                Statement::SetTransaction { .. } => {
+                    // Outside a transaction block it has no effect, as in PostgreSQL.
+                    if let Some(txn) = session.as_ref() {
+                        router.run_on_every_shard(&stmt, txn).await?;
+                    }
                 }
                 Statement::Commit { .. } => {
-                    // Execute buffered statements atomically in order (best-effort)
-                    for buffered in tx_buffer.drain(..) {
-                        let routed = coordinator.route_plan(&buffered);
-                        for plan in routed {
-                            router.execute_plan(plan, tenant_id.clone()).await?;
+                    if let Some(txn) = session.take() {
+                        if let Some(warning) = router.commit(txn).await? {
+                            items.push(QueryResponseItem::Warning(warning));
                         }
                     }
-                    tx_buffer.clear();
-                    *in_tx = false;
                 }
                 Statement::Rollback { .. } => {
-                    tx_buffer.clear();
-                    *in_tx = false;
+                    if let Some(txn) = session.take() {
+                        router.rollback(txn).await;
+                    }
                 }
                 _ => {}
             }
             items.push(QueryResponseItem::CommandCompleteOnly(tag.to_string()));
-        } else if *in_tx && is_mutating_statement(&stmt) {
-            tx_buffer.push(stmt.clone());
-            let tag = if matches!(&stmt, Statement::Insert { .. }) {
-                let n = buffered_insert_rows_affected(&stmt);
-                format!("OK {}", n)
-            } else {
-                "OK".to_string()
-            };
-            items.push(QueryResponseItem::CommandCompleteOnly(tag));
+        } else if let Some(txn) = session.as_mut().filter(|_| is_transactional_statement(&stmt)) {
+            let routed = coordinator.route_plan(&stmt);
+            let mut results = Vec::new();
+            for plan in routed {
+                results.push(router.execute_in_transaction(plan, tenant_id.clone(), txn).await?);
+            }
+            items.push(QueryResponseItem::Rows(coordinator.aggregate(&stmt, results)));
         } else {
-            // SELECT or auto-commit mutation: execute immediately.
-            // SELECT during transaction reads committed state only (no uncommitted visibility).
+            // Auto-commit statement, or DDL inside a transaction: execute immediately. DDL
+            // runs in the session's transaction on the leader, so waiting for older
+            // transactions to end does not wait for the session itself.
             let routed = coordinator.route_plan(&stmt);
             let mut results = Vec::new();
             for plan in routed {
-                results.push(router.execute_plan(plan, tenant_id.clone()).await?);
+                let result = match session.as_ref() {
+                    Some(txn) => router.execute_plan_in(plan, tenant_id.clone(), txn).await?,
+                    None => router.execute_plan(plan, tenant_id.clone()).await?,
+                };
+                results.push(result);
             }
             let last = coordinator.aggregate(&stmt, results);
             items.push(QueryResponseItem::Rows(last));
@@ -261,6 +349,21 @@ async fn handle_query(
     Ok(items)
 }
 
+/// ErrorResponse for a failed query, carrying the SQLSTATE of the error behind it.
+fn query_error_response(err: &anyhow::Error) -> BackendMessage {
+    let code = if let Some(err) = err.downcast_ref::<DatacaveError>() {
+        err.code()
+    } else if err.is::<sqlparser::parser::ParserError>() {
+        sqlstate::SYNTAX_ERROR
+    } else {
+        sqlstate::INTERNAL_ERROR
+    };
+    BackendMessage::ErrorResponse {
+        code: code.into(),
+        message: err.to_string(),
+    }
+}
+
 /// max_rows: 0 means no limit (return all rows).
 async fn write_query_response<S: tokio::io::AsyncWrite + Unpin>(
     stream: &mut S,
@@ -305,6 +408,16 @@ async fn write_query_response<S: tokio::io::AsyncWrite + Unpin>(
             QueryResponseItem::CommandCompleteOnly(tag) => {
                 write_message(stream, BackendMessage::CommandComplete { tag: tag.clone() }).await?;
             }
+            QueryResponseItem::Warning(message) => {
+                write_message(
+                    stream,
+                    BackendMessage::NoticeResponse {
+                        code: sqlstate::WARNING.to_string(),
+                        message: message.clone(),
+                    },
+                )
+                .await?;
+            }
         }
     }
     Ok(())
@@ -461,29 +574,22 @@ impl ShardRouter {
         for shard_id in 0..config.sharding.shard_count {
             let mut replicas = Vec::new();
             for replica_id in 0..config.cluster.replication_factor {
-                let data_dir = format!(
-                    "{}/shard-{}-replica-{}",
-                    config.storage.data_dir, shard_id, replica_id
-                );
-                let wal_path = format!("{}/wal.log", data_dir);
-                let options = LsmOptions {
-                    data_dir: data_dir.clone(),
-                    wal_path,
-                    memtable_max_bytes: config.storage.memtable_max_bytes,
-                    encryption_key: load_encryption_key(&config),
-                    wal_enabled: config.storage.wal_enabled,
-                };
+                let options = replica_lsm_options(config, shard_id, replica_id);
                 let (tx, rx) = mpsc::channel(128);
                 let compaction_interval = config.storage.compaction_interval_secs;
-                let shard = Shard::new(options).await?;
+                let retention = config.storage.history_retention_secs.map(Duration::from_secs);
+                let shard = Shard::new(options, config.storage.engine, retention).await?;
+                let storage = shard.storage.clone();
+                let executor = shard.executor.clone();
                 shard.start(rx, compaction_interval);
                 let node_id = format!("shard-{}-replica-{}", shard_id, replica_id);
                 failover.mark_healthy(&node_id);
                 replicas.push(ShardReplica {
-                    shard_id,
                     replica_id,
                     node_id,
                     tx,
+                    storage,
+                    executor,
                 });
             }
             shard_groups.push(ShardGroup { shard_id, replicas });
@@ -507,20 +613,43 @@ impl ShardRouter {
         tenant_id: Option<String>,
     ) -> anyhow::Result<SqlResult> {
         let group = &self.shard_groups[plan.shard_id];
-        let leader_id = self.select_leader(group);
-        let leader = group
-            .replicas
-            .iter()
-            .find(|replica| replica.replica_id == leader_id)
-            .ok_or_else(|| anyhow::anyhow!("leader not found"))?;
+        let leader = self.leader(group)?;
+        let leader_result = leader.execute(&plan.stmt, tenant_id.clone()).await?;
+        self.replicate(plan, tenant_id, leader.replica_id, leader_result)
+            .await
+    }
+
+    /// Runs a non-transactional statement, such as DDL, issued inside `session`: on the
+    /// replica holding the session's transaction on the plan's shard, in that transaction,
+    /// then on the other replicas.
+    async fn execute_plan_in(
+        &self,
+        plan: ShardPlan,
+        tenant_id: Option<String>,
+        session: &SessionTransaction,
+    ) -> anyhow::Result<SqlResult> {
+        let (replica, txn) = session.shards[plan.shard_id];
+        let leader = &self.shard_groups[plan.shard_id].replicas[replica];
+        let leader_result = leader.execute_in(&plan.stmt, tenant_id.clone(), txn).await?;
+        self.replicate(plan, tenant_id, replica, leader_result).await
+    }
 
+    /// Runs `plan`, already executed on replica `leader_id`, on the plan's other healthy
+    /// replicas, and returns the leader's result once a quorum has it.
+    async fn replicate(
+        &self,
+        plan: ShardPlan,
+        tenant_id: Option<String>,
+        leader_id: usize,
+        leader_result: SqlResult,
+    ) -> anyhow::Result<SqlResult> {
+        let group = &self.shard_groups[plan.shard_id];
         let read_only = matches!(&plan.stmt, Statement::Query(_));
-        let leader_result = leader.execute(&plan.stmt, tenant_id.clone()).await?;
 
         if !read_only {
             let mut acked = 1usize;
             for replica in group.replicas.iter() {
-                if replica.replica_id == leader.replica_id {
+                if replica.replica_id == leader_id {
                     continue;
                 }
                 if !self.failover.is_healthy(&replica.node_id) {
@@ -541,6 +670,174 @@ impl ShardRouter {
         Ok(leader_result)
     }
 
+    /// Opens a transaction on the leader of every shard, so reads on each shard see the
+    /// snapshot taken at BEGIN.
+    async fn begin(&self, isolation: IsolationLevel) -> anyhow::Result<SessionTransaction> {
+        let mut shards = Vec::with_capacity(self.shard_groups.len());
+        for group in self.shard_groups.iter() {
+            let leader = self.leader(group)?;
+            match leader.begin(isolation).await {
+                Ok(txn) => shards.push((leader.replica_id, txn)),
+                Err(err) => {
+                    self.rollback(SessionTransaction {
+                        shards,
+                        write_shard: None,
+                    })
+                    .await;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(SessionTransaction {
+            shards,
+            write_shard: None,
+        })
+    }
+
+    /// Runs a statement that changes the transaction itself, such as `SET TRANSACTION` or
+    /// `SAVEPOINT`, in the session's transaction on every shard.
+    async fn run_on_every_shard(
+        &self,
+        stmt: &Statement,
+        session: &SessionTransaction,
+    ) -> anyhow::Result<()> {
+        for (shard_id, (replica, txn)) in session.shards.iter().enumerate() {
+            self.shard_groups[shard_id].replicas[*replica]
+                .execute_in(stmt, None, *txn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn execute_in_transaction(
+        &self,
+        plan: ShardPlan,
+        tenant_id: Option<String>,
+        session: &mut SessionTransaction,
+    ) -> anyhow::Result<SqlResult> {
+        if !matches!(&plan.stmt, Statement::Query(_)) {
+            match session.write_shard {
+                Some(shard_id) if shard_id != plan.shard_id => {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::FEATURE_NOT_SUPPORTED,
+                        "a transaction can only write to tables on one shard",
+                    )
+                    .into());
+                }
+                _ => session.write_shard = Some(plan.shard_id),
+            }
+        }
+        let (replica, txn) = session.shards[plan.shard_id];
+        self.shard_groups[plan.shard_id].replicas[replica]
+            .execute_in(&plan.stmt, tenant_id, txn)
+            .await
+    }
+
+    /// Commits the session's transaction on the leader of every shard, the shard holding
+    /// its writes last, so a serializable transaction's reads are validated on each shard
+    /// it read. The committed batch is then applied on the write shard's healthy replicas;
+    /// if too few of them take it the commit still stands, and the returned warning says so.
+    async fn commit(&self, session: SessionTransaction) -> anyhow::Result<Option<String>> {
+        // Every shard is validated before any commits: a read-only shard's commit records
+        // its reads for later validations, which must not happen if the write shard then
+        // fails and the transaction rolls back.
+        let mut validated = Ok(());
+        for (shard_id, &(replica, txn)) in session.shards.iter().enumerate() {
+            validated = self.shard_groups[shard_id].replicas[replica].validate(txn).await;
+            if validated.is_err() {
+                break;
+            }
+        }
+        if let Err(err) = validated {
+            self.rollback(session).await;
+            return Err(err);
+        }
+        let write_shard = session.write_shard;
+        let mut order: Vec<usize> = (0..session.shards.len()).collect();
+        order.sort_by_key(|shard_id| Some(*shard_id) == write_shard);
+        let mut batch = WriteBatch::new();
+        for (pos, &shard_id) in order.iter().enumerate() {
+            let (replica, txn) = session.shards[shard_id];
+            match self.shard_groups[shard_id].replicas[replica].commit(txn).await {
+                Ok(committed) => batch = committed,
+                Err(err) => {
+                    for &rest in &order[pos + 1..] {
+                        let (replica, txn) = session.shards[rest];
+                        let _ = self.shard_groups[rest].replicas[replica].rollback(txn).await;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let Some(shard_id) = write_shard else {
+            return Ok(None);
+        };
+        if batch.is_empty() {
+            return Ok(None);
+        }
+        let group = &self.shard_groups[shard_id];
+        let leader_id = session.shards[shard_id].0;
+        let mut acked = 1usize;
+        for replica in group.replicas.iter() {
+            if replica.replica_id == leader_id || !self.failover.is_healthy(&replica.node_id) {
+                continue;
+            }
+            if replica.apply(batch.clone()).await.is_ok() {
+                acked += 1;
+            }
+        }
+        if acked < self.raft.quorum() {
+            return Ok(Some(format!(
+                "transaction committed on the leader, but only {acked} of the {} replicas needed for a quorum have it",
+                self.raft.quorum()
+            )));
+        }
+        Ok(None)
+    }
+
+    async fn rollback(&self, session: SessionTransaction) {
+        for (shard_id, (replica, txn)) in session.shards.into_iter().enumerate() {
+            let _ = self.shard_groups[shard_id].replicas[replica].rollback(txn).await;
+        }
+    }
+
+    fn leader<'a>(&self, group: &'a ShardGroup) -> anyhow::Result<&'a ShardReplica> {
+        let leader_id = self.select_leader(group);
+        group
+            .replicas
+            .iter()
+            .find(|replica| replica.replica_id == leader_id)
+            .ok_or_else(|| anyhow::anyhow!("leader not found"))
+    }
+
+    /// Runs [`scrub_group`] over every shard group once per `period`.
+    fn start_scrubber(&self, period: Duration, policy: ScrubPolicy, monitor: ScrubMonitor) {
+        let router = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = interval(period);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                router.scrub_once(policy, &monitor).await;
+            }
+        });
+    }
+
+    async fn scrub_once(&self, policy: ScrubPolicy, monitor: &ScrubMonitor) {
+        for group in self.shard_groups.iter() {
+            let targets: Vec<ScrubTarget> = group
+                .replicas
+                .iter()
+                .map(|replica| ScrubTarget {
+                    node_id: replica.node_id.clone(),
+                    storage: replica.storage.clone(),
+                    executor: replica.executor.clone(),
+                })
+                .collect();
+            scrub_group(&targets, &self.failover, monitor, policy).await;
+        }
+    }
+
     fn select_leader(&self, group: &ShardGroup) -> usize {
         if let Some(leader) = self.raft.leader_for(group.shard_id) {
             if group
@@ -564,10 +861,11 @@ impl ShardRouter {
 
 #[derive(Clone)]
 struct ShardReplica {
-    shard_id: usize,
     replica_id: usize,
     node_id: String,
     tx: mpsc::Sender<ShardRequest>,
+    storage: SharedStorage,
+    executor: Arc<SqlExecutor>,
 }
 
 #[derive(Clone)]
@@ -576,17 +874,81 @@ struct ShardGroup {
     replicas: Vec<ShardReplica>,
 }
 
+/// A client's open transaction: one executor transaction on the leader of every shard.
+/// Commits are atomic within a shard only, so a transaction writes to at most one.
+struct SessionTransaction {
+    /// Replica and transaction of each shard, indexed by shard id.
+    shards: Vec<(usize, TxnId)>,
+    /// Shard holding the transaction's writes, once it has written.
+    write_shard: Option<usize>,
+}
+
 impl ShardReplica {
     async fn execute(
         &self,
         stmt: &sqlparser::ast::Statement,
         tenant_id: Option<String>,
     ) -> anyhow::Result<SqlResult> {
+        let command = ShardCommand::Execute {
+            stmt: Box::new(stmt.clone()),
+            tenant_id,
+            txn: None,
+        };
+        match self.request(command).await? {
+            ShardReply::Result(result) => Ok(result),
+            _ => Err(anyhow::anyhow!("unexpected shard reply")),
+        }
+    }
+
+    async fn execute_in(
+        &self,
+        stmt: &sqlparser::ast::Statement,
+        tenant_id: Option<String>,
+        txn: TxnId,
+    ) -> anyhow::Result<SqlResult> {
+        let command = ShardCommand::Execute {
+            stmt: Box::new(stmt.clone()),
+            tenant_id,
+            txn: Some(txn),
+        };
+        match self.request(command).await? {
+            ShardReply::Result(result) => Ok(result),
+            _ => Err(anyhow::anyhow!("unexpected shard reply")),
+        }
+    }
+
+    async fn begin(&self, isolation: IsolationLevel) -> anyhow::Result<TxnId> {
+        match self.request(ShardCommand::Begin(isolation)).await? {
+            ShardReply::Begun(txn) => Ok(txn),
+            _ => Err(anyhow::anyhow!("unexpected shard reply")),
+        }
+    }
+
+    async fn commit(&self, txn: TxnId) -> anyhow::Result<WriteBatch> {
+        match self.request(ShardCommand::Commit(txn)).await? {
+            ShardReply::Committed(batch) => Ok(batch),
+            _ => Err(anyhow::anyhow!("unexpected shard reply")),
+        }
+    }
+
+    /// Checks that `txn` would commit, without committing it.
+    async fn validate(&self, txn: TxnId) -> anyhow::Result<()> {
+        self.request(ShardCommand::Validate(txn)).await.map(|_| ())
+    }
+
+    async fn rollback(&self, txn: TxnId) -> anyhow::Result<()> {
+        self.request(ShardCommand::Rollback(txn)).await.map(|_| ())
+    }
+
+    async fn apply(&self, batch: WriteBatch) -> anyhow::Result<()> {
+        self.request(ShardCommand::Apply(batch)).await.map(|_| ())
+    }
+
+    async fn request(&self, command: ShardCommand) -> anyhow::Result<ShardReply> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.tx
             .send(ShardRequest {
-                stmt: stmt.clone(),
-                tenant_id,
+                command,
                 response: tx,
             })
             .await?;
@@ -596,16 +958,34 @@ impl ShardReplica {
 
 struct Shard {
     executor: Arc<SqlExecutor>,
-    storage: Arc<LsmEngine>,
+    storage: SharedStorage,
 }
 
 impl Shard {
-    async fn new(options: LsmOptions) -> anyhow::Result<Self> {
-        std::fs::create_dir_all(&options.data_dir)?;
-        let storage = Arc::new(LsmEngine::open(options).await?);
+    async fn new(
+        options: LsmOptions,
+        engine: StorageEngineKind,
+        history_retention: Option<Duration>,
+    ) -> anyhow::Result<Self> {
+        let storage: SharedStorage = match engine {
+            StorageEngineKind::Lsm => {
+                std::fs::create_dir_all(&options.data_dir)?;
+                if let Some(tiering) = &options.tiering {
+                    std::fs::create_dir_all(&tiering.cold_dir)?;
+                }
+                Arc::new(LsmEngine::open(options).await?)
+            }
+            StorageEngineKind::Memory => Arc::new(MemoryEngine::new()),
+        };
         let catalog = Arc::new(Mutex::new(Catalog::new()));
-        let mvcc = Arc::new(MvccManager::new());
-        let executor = Arc::new(SqlExecutor::new(catalog, mvcc, storage.clone()));
+        let recovered = storage.snapshot().await?;
+        let mvcc = Arc::new(MvccManager::resume_after(recovered.version));
+        let mut executor = SqlExecutor::new(catalog, mvcc, storage.clone());
+        if let Some(retention) = history_retention {
+            executor = executor.with_history_retention(retention);
+        }
+        let executor = Arc::new(executor);
+        executor.load_catalog().await?;
         Ok(Self { executor, storage })
     }
 
@@ -613,32 +993,94 @@ impl Shard {
         let executor = self.executor.clone();
         if let Some(secs) = compaction_interval {
             let storage = self.storage.clone();
+            let executor = executor.clone();
             tokio::spawn(async move {
                 let mut ticker = interval(Duration::from_secs(secs));
                 loop {
                     ticker.tick().await;
+                    // Compaction may then drop versions past the time-travel retention.
+                    if let Err(err) = executor.collect_history().await {
+                        error!("history collection error: {err}");
+                    }
                     if let Err(err) = storage.compact().await {
                         error!("compaction error: {err}");
                     }
                 }
             });
         }
+        // Requests run concurrently, so a statement waiting for a row lock does not hold up
+        // the transaction that holds it; each session still sends one request at a time.
         tokio::spawn(async move {
             while let Some(req) = rx.recv().await {
-                let result = executor
-                    .execute(&req.stmt, req.tenant_id.as_deref())
-                    .await
-                    .map_err(|e| anyhow::anyhow!(e));
-                let _ = req.response.send(result);
+                let executor = executor.clone();
+                tokio::spawn(async move {
+                    let result = match req.command {
+                        ShardCommand::Execute {
+                            stmt,
+                            tenant_id,
+                            txn: None,
+                        } => executor
+                            .execute(&stmt, tenant_id.as_deref())
+                            .await
+                            .map(ShardReply::Result),
+                        ShardCommand::Execute {
+                            stmt,
+                            tenant_id,
+                            txn: Some(txn),
+                        } => executor
+                            .execute_in(&stmt, tenant_id.as_deref(), txn)
+                            .await
+                            .map(ShardReply::Result),
+                        ShardCommand::Begin(isolation) => {
+                            Ok(ShardReply::Begun(executor.begin(isolation)))
+                        }
+                        ShardCommand::Validate(txn) => {
+                            executor.validate(txn).map(|_| ShardReply::Done)
+                        }
+                        ShardCommand::Commit(txn) => {
+                            executor.commit(txn).await.map(ShardReply::Committed)
+                        }
+                        ShardCommand::Rollback(txn) => {
+                            executor.rollback(txn);
+                            Ok(ShardReply::Done)
+                        }
+                        ShardCommand::Apply(batch) => executor
+                            .apply_committed(batch)
+                            .await
+                            .map(|_| ShardReply::Done),
+                    };
+                    let _ = req.response.send(result.map_err(|e| anyhow::anyhow!(e)));
+                });
             }
         });
     }
 }
 
 struct ShardRequest {
-    stmt: sqlparser::ast::Statement,
-    tenant_id: Option<String>,
-    response: tokio::sync::oneshot::Sender<anyhow::Result<SqlResult>>,
+    command: ShardCommand,
+    response: tokio::sync::oneshot::Sender<anyhow::Result<ShardReply>>,
+}
+
+enum ShardCommand {
+    /// Runs a statement, in its own transaction unless `txn` is given.
+    Execute {
+        stmt: Box<sqlparser::ast::Statement>,
+        tenant_id: Option<String>,
+        txn: Option<TxnId>,
+    },
+    Begin(IsolationLevel),
+    Validate(TxnId),
+    Commit(TxnId),
+    Rollback(TxnId),
+    /// Applies a batch committed on the shard's leader.
+    Apply(WriteBatch),
+}
+
+enum ShardReply {
+    Result(SqlResult),
+    Begun(TxnId),
+    Committed(WriteBatch),
+    Done,
 }
 
 fn authorize_statement(user: Option<&UserContext>, stmt: &Statement) -> anyhow::Result<()> {
@@ -651,7 +1093,7 @@ fn authorize_statement(user: Option<&UserContext>, stmt: &Statement) -> anyhow::
             if user.can_read {
                 Ok(())
             } else {
-                Err(anyhow::anyhow!("read access denied"))
+                Err(DatacaveError::sqlstate(sqlstate::INSUFFICIENT_PRIVILEGE, "read access denied").into())
             }
         }
         Statement::Insert { .. }
@@ -660,20 +1102,45 @@ fn authorize_statement(user: Option<&UserContext>, stmt: &Statement) -> anyhow::
             if user.can_write {
                 Ok(())
             } else {
-                Err(anyhow::anyhow!("write access denied"))
+                Err(DatacaveError::sqlstate(sqlstate::INSUFFICIENT_PRIVILEGE, "write access denied").into())
             }
         }
         _ => {
             if user.is_admin {
                 Ok(())
             } else {
-                Err(anyhow::anyhow!("admin access denied"))
+                Err(DatacaveError::sqlstate(sqlstate::INSUFFICIENT_PRIVILEGE, "admin access denied").into())
             }
         }
     }
 }
 
-fn load_encryption_key(config: &Config) -> Option<Vec<u8>> {
+pub(crate) fn replica_lsm_options(config: &Config, shard_id: usize, replica_id: usize) -> LsmOptions {
+    let data_dir = format!(
+        "{}/shard-{}-replica-{}",
+        config.storage.data_dir, shard_id, replica_id
+    );
+    let wal_path = format!("{}/wal.log", data_dir);
+    let tiering = config
+        .storage
+        .cold_data_dir
+        .as_ref()
+        .map(|cold_dir| TieringOptions {
+            cold_dir: format!("{}/shard-{}-replica-{}", cold_dir, shard_id, replica_id),
+            min_level: config.storage.cold_min_level,
+            min_age_secs: config.storage.cold_after_secs,
+        });
+    LsmOptions {
+        data_dir,
+        wal_path,
+        memtable_max_bytes: config.storage.memtable_max_bytes,
+        encryption_key: load_encryption_key(config),
+        wal_enabled: config.storage.wal_enabled,
+        tiering,
+    }
+}
+
+pub(crate) fn load_encryption_key(config: &Config) -> Option<Vec<u8>> {
     if !config.storage.encryption_enabled {
         return None;
     }
@@ -713,6 +1180,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                 let _ = write_message(
                     &mut stream,
                     BackendMessage::ErrorResponse {
+                        code: sqlstate::INVALID_AUTHORIZATION_SPECIFICATION.into(),
                         message: "missing user".into(),
                     },
                 )
@@ -727,6 +1195,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                 let _ = write_message(
                     &mut stream,
                     BackendMessage::ErrorResponse {
+                        code: sqlstate::INVALID_PASSWORD.into(),
                         message: "missing password".into(),
                     },
                 )
@@ -740,6 +1209,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                 let _ = write_message(
                     &mut stream,
                     BackendMessage::ErrorResponse {
+                        code: sqlstate::INVALID_PASSWORD.into(),
                         message: err.to_string(),
                     },
                 )
@@ -760,9 +1230,8 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         },
     )
     .await;
-    let mut in_tx = false;
+    let mut session: Option<SessionTransaction> = None;
     let mut failed_tx = false;
-    let mut tx_buffer: Vec<Statement> = Vec::new();
     let mut prepared_statements: HashMap<String, PreparedStatement> = HashMap::new();
     let mut portals: HashMap<String, Portal> = HashMap::new();
     let _ = write_message(
@@ -783,6 +1252,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                     let _ = write_message(
                         &mut stream,
                         BackendMessage::ErrorResponse {
+                            code: sqlstate::IDLE_SESSION_TIMEOUT.into(),
                             message: "idle timeout".into(),
                         },
                     )
@@ -808,9 +1278,8 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                     &sql,
                     tenant_id.clone(),
                     user_ctx.as_ref(),
-                    &mut in_tx,
+                    &mut session,
                     &mut failed_tx,
-                    &mut tx_buffer,
                 )
                 .await;
                 match result {
@@ -820,18 +1289,13 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                     }
                     Err(err) => {
                         counter!("sql_query_error_total").increment(1);
-                        in_tx = false;
-                        failed_tx = true;
-                        let _ = write_message(
-                            &mut stream,
-                            BackendMessage::ErrorResponse {
-                                message: err.to_string(),
-                            },
-                        )
-                        .await;
+                        // An error aborts the open transaction, which stays open until
+                        // ROLLBACK or COMMIT.
+                        failed_tx = session.is_some();
+                        let _ = write_message(&mut stream, query_error_response(&err)).await;
                     }
                 }
-                let state = connection_ready_state(in_tx, failed_tx);
+                let state = connection_ready_state(session.is_some(), failed_tx);
                 let _ = write_message(
                     &mut stream,
                     BackendMessage::ReadyForQuery { state },
@@ -842,11 +1306,12 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                 let _ = write_message(
                     &mut stream,
                     BackendMessage::ErrorResponse {
+                        code: sqlstate::PROTOCOL_VIOLATION.into(),
                         message: format!("unsupported message: {}", code as char),
                     },
                 )
                 .await;
-                let state = connection_ready_state(in_tx, failed_tx);
+                let state = connection_ready_state(session.is_some(), failed_tx);
                 let _ = write_message(
                     &mut stream,
                     BackendMessage::ReadyForQuery { state },
@@ -897,6 +1362,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                         let _ = write_message(
                             &mut stream,
                             BackendMessage::ErrorResponse {
+                                code: sqlstate::INVALID_SQL_STATEMENT_NAME.into(),
                                 message: format!(
                                     "unknown prepared statement '{}'",
                                     statement_name
@@ -940,17 +1406,14 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                         if stmts.len() == 1 {
                             let stmt = &stmts[0];
                             if matches!(stmt, Statement::Query(_)) {
-                                let mut dummy_tx = in_tx;
                                 let mut dummy_failed_tx = failed_tx;
-                                let mut dummy_buf = Vec::new();
                                 if let Ok(items) = handle_query(
                                     &router,
                                     &sql,
                                     tenant_id.clone(),
                                     user_ctx.as_ref(),
-                                    &mut dummy_tx,
+                                    &mut session,
                                     &mut dummy_failed_tx,
-                                    &mut dummy_buf,
                                 )
                                 .await
                                 {
@@ -983,6 +1446,11 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                         let _ = write_message(
                             &mut stream,
                             BackendMessage::ErrorResponse {
+                                code: if matches!(target, DescribeTarget::Statement) {
+                                    sqlstate::INVALID_SQL_STATEMENT_NAME.into()
+                                } else {
+                                    sqlstate::INVALID_CURSOR_NAME.into()
+                                },
                                 message: format!(
                                     "unknown {} '{}'",
                                     if matches!(target, DescribeTarget::Statement) {
@@ -1011,9 +1479,8 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                                     &sql,
                                     tenant_id.clone(),
                                     user_ctx.as_ref(),
-                                    &mut in_tx,
+                                    &mut session,
                                     &mut failed_tx,
-                                    &mut tx_buffer,
                                 )
                                 .await;
                                 match result {
@@ -1023,13 +1490,8 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                                     }
                                     Err(err) => {
                                         counter!("sql_query_error_total").increment(1);
-                                        let _ = write_message(
-                                            &mut stream,
-                                            BackendMessage::ErrorResponse {
-                                                message: err.to_string(),
-                                            },
-                                        )
-                                        .await;
+                                        let _ = write_message(&mut stream, query_error_response(&err))
+                                            .await;
                                     }
                                 }
                             }
@@ -1037,6 +1499,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                                 let _ = write_message(
                                     &mut stream,
                                     BackendMessage::ErrorResponse {
+                                        code: sqlstate::INVALID_PARAMETER_VALUE.into(),
                                         message: format!("bind parameter substitution failed: {}", err),
                                     },
                                 )
@@ -1048,6 +1511,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                         let _ = write_message(
                             &mut stream,
                             BackendMessage::ErrorResponse {
+                                code: sqlstate::INVALID_CURSOR_NAME.into(),
                                 message: format!("unknown portal '{}'", portal_name),
                             },
                         )
@@ -1067,7 +1531,7 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                 let _ = write_message(&mut stream, BackendMessage::CloseComplete).await;
             }
             FrontendMessage::Sync => {
-                let state = connection_ready_state(in_tx, failed_tx);
+                let state = connection_ready_state(session.is_some(), failed_tx);
                 let _ = write_message(&mut stream, BackendMessage::ReadyForQuery { state }).await;
             }
             FrontendMessage::Flush => {
@@ -1076,6 +1540,9 @@ async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
             _ => {}
         }
     }
+    if let Some(txn) = session.take() {
+        router.rollback(txn).await;
+    }
     info!("connection closed {}", connection_id);
 }
 
@@ -1125,6 +1592,7 @@ mod tests {
                 idle_timeout_secs: None,
             },
             storage: StorageConfig {
+                engine: StorageEngineKind::Lsm,
                 data_dir: data_dir.into(),
                 wal_enabled: true,
                 memtable_max_bytes: 1024,
@@ -1132,6 +1600,13 @@ mod tests {
                 encryption_enabled: false,
                 encryption_key_base64: None,
                 compaction_interval_secs: None,
+                cold_data_dir: None,
+                cold_min_level: None,
+                cold_after_secs: None,
+                scrub_interval_secs: None,
+                scrub_quarantine: false,
+                scrub_repair: false,
+                history_retention_secs: None,
             },
             sharding: ShardingConfig { shard_count: 1 },
             cluster: ClusterConfig {
@@ -1193,6 +1668,15 @@ mod tests {
         client.write_all(&msg).await.expect("write frontend msg");
     }
 
+    /// Fields of an ErrorResponse payload, keyed by their type byte.
+    fn error_fields(payload: &[u8]) -> HashMap<u8, String> {
+        payload
+            .split(|&b| b == 0)
+            .filter(|field| !field.is_empty())
+            .map(|field| (field[0], String::from_utf8_lossy(&field[1..]).to_string()))
+            .collect()
+    }
+
     async fn read_until_ready(
         client: &mut (impl AsyncRead + Unpin),
     ) -> (Vec<Vec<Option<Vec<u8>>>>, Option<String>, Option<String>, u8) {
@@ -1212,6 +1696,7 @@ mod tests {
             }
             match typ[0] {
                 b'D' => {
+                    #[allow(clippy::collapsible_match)]
                     if payload.len() >= 2 {
                         let ncols = i16::from_be_bytes([payload[0], payload[1]]) as usize;
                         let mut row = Vec::new();
@@ -1237,14 +1722,7 @@ mod tests {
                     }
                 }
                 b'E' => {
-                    let msg_start = payload.iter().position(|&b| b == b'M').unwrap_or(0) + 1;
-                    let msg_end = payload[msg_start..]
-                        .iter()
-                        .position(|&b| b == 0)
-                        .unwrap_or(payload.len().saturating_sub(msg_start));
-                    error_msg = Some(
-                        String::from_utf8_lossy(&payload[msg_start..msg_start + msg_end]).to_string(),
-                    );
+                    error_msg = error_fields(&payload).remove(&b'M');
                 }
                 b'C' => {
                     let end = payload.iter().position(|&b| b == 0).unwrap_or(payload.len());
@@ -1262,6 +1740,40 @@ mod tests {
         (data_rows, error_msg, cmd_tag, ready_state)
     }
 
+    #[test]
+    fn query_errors_carry_their_sqlstate() {
+        let coerce = datacave_core::types::DataType::Int
+            .coerce("id", DataValue::Bool(true))
+            .unwrap_err();
+        let parse = parse_sql("SELEC 1").unwrap_err();
+        for (err, code) in [
+            (anyhow::anyhow!(coerce), "42804"),
+            (parse, "42601"),
+            (anyhow::anyhow!("replication quorum not reached"), "XX000"),
+        ] {
+            match query_error_response(&err) {
+                BackendMessage::ErrorResponse { code: actual, .. } => assert_eq!(actual, code),
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn renames_are_limited_to_the_owning_shard() {
+        let coordinator = Coordinator::new(4);
+        let (same, other) = {
+            let home = coordinator.shard_for_table("accounts");
+            let mut names = (0..).map(|i| format!("accounts_{i}"));
+            let same = names.by_ref().find(|n| coordinator.shard_for_table(n) == home).unwrap();
+            let other = names.find(|n| coordinator.shard_for_table(n) != home).unwrap();
+            (same, other)
+        };
+        let rename = |to: &str| parse_sql(&format!("ALTER TABLE accounts RENAME TO {to}")).unwrap().remove(0);
+        assert!(check_rename_stays_on_shard(&coordinator, &rename(&same)).is_ok());
+        let err = check_rename_stays_on_shard(&coordinator, &rename(&other)).unwrap_err();
+        assert_eq!(err.downcast_ref::<DatacaveError>().map(|e| e.code()), Some("0A000"));
+    }
+
     #[tokio::test]
     async fn query_roundtrip() {
         let dir = tempfile::TempDir::new().expect("tempdir");
@@ -1356,6 +1868,326 @@ mod tests {
         assert_eq!(ready_state, b'I', "ReadyForQuery should be Idle after COMMIT");
     }
 
+    #[tokio::test]
+    async fn memory_engine_serves_queries_without_data_dir() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let data_dir = dir.path().join("unused");
+        let mut config = test_config(data_dir.to_string_lossy().as_ref());
+        config.storage.engine = StorageEngineKind::Memory;
+        let router = ShardRouter::new(&config).await.expect("router");
+        assert!(!data_dir.exists(), "memory engine should not touch data_dir");
+        let (mut client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            handle_client(server, router, None, None).await;
+        });
+
+        let params = b"user\0test\0\0";
+        let len = (params.len() + 8) as i32;
+        let protocol = 196608i32;
+        let mut startup = Vec::new();
+        startup.extend_from_slice(&len.to_be_bytes());
+        startup.extend_from_slice(&protocol.to_be_bytes());
+        startup.extend_from_slice(params);
+        client.write_all(&startup).await.expect("startup");
+
+        let _ = read_message_type(&mut client).await;
+        let _ = read_message_type(&mut client).await;
+        let _ = read_message_type(&mut client).await;
+
+        send_query(&mut client, "CREATE TABLE mem_t (id INT); INSERT INTO mem_t VALUES (7);").await;
+        let (_rows, err, _, _) = read_until_ready(&mut client).await;
+        assert!(err.is_none(), "setup failed: {:?}", err);
+
+        send_query(&mut client, "SELECT * FROM mem_t;").await;
+        let (rows, err, _, _) = read_until_ready(&mut client).await;
+        assert!(err.is_none(), "SELECT failed: {:?}", err);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0][0].as_ref().map(|v| String::from_utf8_lossy(v).to_string()),
+            Some("7".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn shard_resumes_mvcc_clock_after_restart() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let data_dir = dir.path().join("shard-0").to_string_lossy().to_string();
+        let options = LsmOptions {
+            wal_path: format!("{data_dir}/wal.log"),
+            data_dir,
+            memtable_max_bytes: 1024,
+            encryption_key: None,
+            wal_enabled: true,
+            tiering: None,
+        };
+        let shard = Shard::new(options.clone(), StorageEngineKind::Lsm, None)
+            .await
+            .expect("shard");
+        shard.storage.put(b"old", b"v", 50).await.expect("put");
+        shard.storage.flush().await.expect("flush");
+        drop(shard);
+
+        let shard = Shard::new(options, StorageEngineKind::Lsm, None).await.expect("shard");
+        for stmt in parse_sql("CREATE TABLE t (id INT); INSERT INTO t VALUES (1);").expect("parse") {
+            shard.executor.execute(&stmt, None).await.expect("execute");
+        }
+        let snapshot = shard.storage.snapshot().await.expect("snapshot");
+        assert!(snapshot.version > 50, "new write got version {}", snapshot.version);
+    }
+
+    #[tokio::test]
+    async fn shard_reloads_catalog_after_restart() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let config = test_config(dir.path().to_string_lossy().as_ref());
+        let router = ShardRouter::new(&config).await.expect("router");
+        for stmt in parse_sql("CREATE TABLE people (id INT, name TEXT);").expect("parse") {
+            let plan = Coordinator::new(1).route_plan(&stmt).remove(0);
+            router.execute_plan(plan, None).await.expect("create");
+        }
+        drop(router);
+
+        let shard = Shard::new(replica_lsm_options(&config, 0, 0), StorageEngineKind::Lsm, None)
+            .await
+            .expect("shard");
+        let stmt = &parse_sql("INSERT INTO people VALUES (1, 'alice');").expect("parse")[0];
+        shard.executor.execute(stmt, None).await.expect("insert after restart");
+        let stmt = &parse_sql("CREATE TABLE people (id INT);").expect("parse")[0];
+        assert!(shard.executor.execute(stmt, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shard_reads_rows_written_before_restart() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let options = replica_lsm_options(&test_config(dir.path().to_string_lossy().as_ref()), 0, 0);
+        let shard = Shard::new(options.clone(), StorageEngineKind::Lsm, None).await.expect("shard");
+        for stmt in parse_sql("CREATE TABLE t (id INT); INSERT INTO t VALUES (1), (2);").expect("parse") {
+            shard.executor.execute(&stmt, None).await.expect("execute");
+        }
+        drop(shard);
+
+        let shard = Shard::new(options, StorageEngineKind::Lsm, None).await.expect("shard");
+        let select = &parse_sql("SELECT id FROM t;").expect("parse")[0];
+        let result = shard.executor.execute(select, None).await.expect("select");
+        assert_eq!(result.rows.len(), 2);
+        let insert = &parse_sql("INSERT INTO t VALUES (3);").expect("parse")[0];
+        shard.executor.execute(insert, None).await.expect("insert");
+        let result = shard.executor.execute(select, None).await.expect("select");
+        assert_eq!(result.rows.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn bulk_ingest_loads_rows_into_every_replica() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let shard_id = Coordinator::new(config.sharding.shard_count).shard_for_table("people");
+        {
+            let shard = Shard::new(
+                replica_lsm_options(&config, shard_id, 0),
+                StorageEngineKind::Lsm,
+                None,
+            )
+            .await
+            .expect("shard");
+            let sql = "CREATE TABLE people (id INT NOT NULL, name TEXT, active BOOLEAN, \
+                       CHECK (id > 0));";
+            shard
+                .executor
+                .execute(&parse_sql(sql).unwrap()[0], None)
+                .await
+                .expect(sql);
+        }
+        let input = dir.path().join("rows.csv");
+        std::fs::write(&input, "1,alice,true\n2,bob,\n").expect("write csv");
+        let request = crate::ingest::IngestRequest {
+            table: "people".into(),
+            input: input.to_string_lossy().to_string(),
+            tenant: None,
+            version: None,
+        };
+        let summary = crate::ingest::bulk_ingest(&config, &request).await.expect("ingest");
+        assert_eq!(summary.rows, 2);
+        let again = crate::ingest::bulk_ingest(&config, &request).await.expect("ingest");
+        assert!(again.version > summary.version);
+        let stale = crate::ingest::IngestRequest {
+            version: Some(again.version),
+            ..request.clone()
+        };
+        assert!(crate::ingest::bulk_ingest(&config, &stale).await.is_err());
+        for (rows, reason) in [
+            ("3,carol\n", "missing data for column \"active\""),
+            ("3,carol,true,x\n", "extra data after last expected column"),
+            ("3,carol,maybe\n", "invalid input syntax"),
+            (",carol,true\n", "not-null constraint"),
+            ("-3,carol,true\n", "check constraint"),
+        ] {
+            std::fs::write(&input, rows).expect("write csv");
+            let err = crate::ingest::bulk_ingest(&config, &request).await.expect_err(rows);
+            assert!(err.to_string().contains(reason), "{err}");
+            assert!(err.to_string().contains(":1:"), "{err}");
+        }
+
+        for replica_id in 0..2 {
+            let options = replica_lsm_options(&config, summary.shard_id, replica_id);
+            let shard = Shard::new(options, StorageEngineKind::Lsm, None).await.expect("shard");
+            let rows = shard
+                .storage
+                .scan(&datacave_sql::table_key_prefix("people", None), u64::MAX)
+                .await
+                .expect("scan");
+            assert_eq!(rows.len(), 4);
+            assert_eq!(rows[3].0, datacave_sql::encode_row_key("people", 3, None));
+            let (_, row) = datacave_sql::decode_row(&rows[1].1).expect("decode");
+            assert_eq!(
+                row.values,
+                vec![DataValue::Int64(2), DataValue::String("bob".into()), DataValue::Null]
+            );
+            let (_, row) = datacave_sql::decode_row(&rows[0].1).expect("decode");
+            assert_eq!(row.values[2], DataValue::Bool(true));
+        }
+        assert!(!std::fs::read_dir(dir.path())
+            .expect("list")
+            .flatten()
+            .any(|entry| entry.file_name().to_string_lossy().starts_with("ingest-")));
+    }
+
+    #[tokio::test]
+    async fn bulk_ingest_writes_index_entries() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let config = test_config(dir.path().to_string_lossy().as_ref());
+        let options = replica_lsm_options(&config, 0, 0);
+        {
+            let shard = Shard::new(options.clone(), StorageEngineKind::Lsm, None)
+                .await
+                .expect("shard");
+            for sql in [
+                "CREATE TABLE users (id INT PRIMARY KEY, email TEXT UNIQUE, city TEXT);",
+                "CREATE INDEX users_city ON users (city);",
+                "INSERT INTO users VALUES (1, 'a@x', 'paris');",
+            ] {
+                shard
+                    .executor
+                    .execute(&parse_sql(sql).unwrap()[0], Some("acme"))
+                    .await
+                    .expect(sql);
+            }
+        }
+        let input = dir.path().join("rows.csv");
+        let request = crate::ingest::IngestRequest {
+            table: "users".into(),
+            input: input.to_string_lossy().to_string(),
+            tenant: Some("acme".into()),
+            version: None,
+        };
+        for rows in ["2,b@x,rome\n3,b@x,oslo\n", "2,a@x,rome\n"] {
+            std::fs::write(&input, rows).expect("write csv");
+            let err = crate::ingest::bulk_ingest(&config, &request).await.expect_err(rows);
+            assert!(err.to_string().contains("users_email_key"), "{err}");
+        }
+        std::fs::write(&input, "2,b@x,rome\n3,c@x,paris\n").expect("write csv");
+        crate::ingest::bulk_ingest(&config, &request).await.expect("ingest");
+
+        let shard = Shard::new(options, StorageEngineKind::Lsm, None).await.expect("shard");
+        let select = "SELECT id FROM users WHERE city = 'paris' ORDER BY id;";
+        let result = shard
+            .executor
+            .execute(&parse_sql(select).unwrap()[0], Some("acme"))
+            .await
+            .expect("select");
+        assert_eq!(result.rows.len(), 2);
+        let insert = "INSERT INTO users VALUES (4, 'c@x', 'rome');";
+        let err = shard
+            .executor
+            .execute(&parse_sql(insert).unwrap()[0], Some("acme"))
+            .await
+            .expect_err("duplicate email");
+        assert_eq!(err.code(), "23505");
+    }
+
+    #[tokio::test]
+    async fn scrubber_reports_and_repairs_corrupt_replica() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let group = &router.shard_groups[0];
+        for replica in &group.replicas {
+            replica.storage.put(b"k1", b"old", 1).await.expect("put");
+            replica.storage.put(b"k1", b"a", 2).await.expect("put");
+            replica.storage.put(b"k2", b"b", 3).await.expect("put");
+            replica.storage.flush().await.expect("flush");
+        }
+        // Written to the damaged replica only, after the healthy one's data.
+        group.replicas[1].storage.put(b"k3", b"c", 4).await.expect("put");
+        let damaged = &group.replicas[1];
+        let data_dir = replica_lsm_options(&config, 0, 1).data_dir;
+        let sst = std::fs::read_dir(&data_dir)
+            .expect("list")
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "db"))
+            .expect("sstable");
+        let mut bytes = std::fs::read(&sst).expect("read");
+        bytes[0] ^= 0xff;
+        std::fs::write(&sst, bytes).expect("corrupt");
+
+        let monitor = ScrubMonitor::new();
+        router.scrub_once(ScrubPolicy::default(), &monitor).await;
+        let (status, body) = health_response(&monitor);
+        assert_eq!(status, axum::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains(&damaged.node_id));
+        assert!(!router.failover.is_healthy(&damaged.node_id));
+        assert!(router.failover.is_healthy(&group.replicas[0].node_id));
+
+        let policy = ScrubPolicy {
+            quarantine: true,
+            repair: true,
+        };
+        router.scrub_once(policy, &monitor).await;
+        assert_eq!(health_response(&monitor).0, axum::http::StatusCode::OK);
+        assert!(router.failover.is_healthy(&damaged.node_id));
+        assert_eq!(
+            damaged.storage.scan(b"", u64::MAX).await.expect("scan"),
+            vec![
+                (b"k1".to_vec(), b"a".to_vec()),
+                (b"k2".to_vec(), b"b".to_vec()),
+                (b"k3".to_vec(), b"c".to_vec())
+            ]
+        );
+        assert_eq!(damaged.storage.get(b"k1", 1).await.expect("get"), Some(b"old".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn upgrade_rewrites_legacy_rows_in_every_replica() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let row = datacave_core::types::DataRow {
+            values: vec![DataValue::Int64(1), DataValue::String("alice".into())],
+        };
+        let key = datacave_sql::encode_row_key("people", 0, None);
+        for replica_id in 0..2 {
+            let options = replica_lsm_options(&config, 0, replica_id);
+            let shard = Shard::new(options, StorageEngineKind::Lsm, None).await.expect("shard");
+            let legacy = bincode::serialize(&row).expect("legacy row");
+            shard.storage.put(&key, &legacy, 1).await.expect("put");
+        }
+
+        let summary = crate::upgrade::upgrade_data(&config).await.expect("upgrade");
+        assert_eq!(summary.replicas, 2);
+        assert_eq!(summary.rows_rewritten, 2);
+        let again = crate::upgrade::upgrade_data(&config).await.expect("upgrade");
+        assert_eq!(again.rows_rewritten, 0);
+
+        for replica_id in 0..2 {
+            let options = replica_lsm_options(&config, 0, replica_id);
+            let shard = Shard::new(options, StorageEngineKind::Lsm, None).await.expect("shard");
+            let value = shard.storage.get(&key, u64::MAX).await.expect("get").expect("row");
+            assert_eq!(datacave_sql::row_format_version(&value), datacave_sql::ROW_FORMAT_VERSION);
+            assert_eq!(datacave_sql::decode_row(&value).expect("decode").1.values, row.values);
+        }
+    }
+
     #[tokio::test]
     async fn transaction_state_rollback() {
         let dir = tempfile::TempDir::new().expect("tempdir");
@@ -1427,27 +2259,12 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn transaction_buffering_select_before_commit_does_not_see_row() {
+    async fn transaction_select_before_commit_sees_own_insert() {
         let dir = tempfile::TempDir::new().expect("tempdir");
         let config = test_config(dir.path().to_string_lossy().as_ref());
         let router = ShardRouter::new(&config).await.expect("router");
-        let (mut client, server) = tokio::io::duplex(4096);
-        tokio::spawn(async move {
-            handle_client(server, router, None, None).await;
-        });
-
-        let params = b"user\0test\0\0";
-        let len = (params.len() + 8) as i32;
-        let protocol = 196608i32;
-        let mut startup = Vec::new();
-        startup.extend_from_slice(&len.to_be_bytes());
-        startup.extend_from_slice(&protocol.to_be_bytes());
-        startup.extend_from_slice(params);
-        client.write_all(&startup).await.expect("startup");
-
-        let _ = read_message_type(&mut client).await;
-        let _ = read_message_type(&mut client).await;
-        let _ = read_message_type(&mut client).await;
+        let mut client = connect(&router).await;
+        let mut other = connect(&router).await;
 
         send_query(&mut client, "CREATE TABLE tx_vis (id INT);").await;
         let (_rows, err, _, _) = read_until_ready(&mut client).await;
@@ -1459,16 +2276,26 @@ mod tests {
         assert_eq!(tag.as_deref(), Some("BEGIN"));
         assert_eq!(ready_state, b'T');
 
-        send_query(&mut client, "INSERT INTO tx_vis VALUES (1);").await;
+        send_query(&mut client, "INSERT INTO tx_vis VALUES (1), (2);").await;
         let (_rows, err, tag, _) = read_until_ready(&mut client).await;
         assert!(err.is_none(), "INSERT failed: {:?}", err);
-        assert_eq!(tag.as_deref(), Some("OK 1"));
+        assert_eq!(tag.as_deref(), Some("OK 2"));
+
+        send_query(&mut client, "DELETE FROM tx_vis WHERE id = 2;").await;
+        let (_rows, err, tag, _) = read_until_ready(&mut client).await;
+        assert!(err.is_none(), "DELETE failed: {:?}", err);
+        assert_eq!(tag.as_deref(), Some("OK 1"), "DELETE sees the uncommitted row");
 
         send_query(&mut client, "SELECT * FROM tx_vis;").await;
         let (rows, err, _tag, ready_state) = read_until_ready(&mut client).await;
         assert!(err.is_none(), "SELECT failed: {:?}", err);
         assert_eq!(ready_state, b'T', "still in transaction");
-        assert_eq!(rows.len(), 0, "SELECT during transaction should not see uncommitted row");
+        assert_eq!(rows.len(), 1, "SELECT during transaction should see its own insert");
+
+        send_query(&mut other, "SELECT * FROM tx_vis;").await;
+        let (rows, err, _tag, _) = read_until_ready(&mut other).await;
+        assert!(err.is_none(), "SELECT failed: {:?}", err);
+        assert_eq!(rows.len(), 0, "other sessions do not see uncommitted rows");
 
         send_query(&mut client, "COMMIT;").await;
         let (_rows, err, tag, ready_state) = read_until_ready(&mut client).await;
@@ -1476,8 +2303,8 @@ mod tests {
         assert_eq!(tag.as_deref(), Some("COMMIT"));
         assert_eq!(ready_state, b'I');
 
-        send_query(&mut client, "SELECT * FROM tx_vis;").await;
-        let (rows, err, _tag, _) = read_until_ready(&mut client).await;
+        send_query(&mut other, "SELECT * FROM tx_vis;").await;
+        let (rows, err, _tag, _) = read_until_ready(&mut other).await;
         assert!(err.is_none(), "SELECT failed: {:?}", err);
         assert_eq!(rows.len(), 1, "SELECT after COMMIT should see committed row");
         assert_eq!(
@@ -1597,6 +2424,428 @@ mod tests {
         assert_eq!(ready_state, b'I');
     }
 
+    async fn connect(router: &ShardRouter) -> tokio::io::DuplexStream {
+        let (mut client, server) = tokio::io::duplex(4096);
+        let router = router.clone();
+        tokio::spawn(async move {
+            handle_client(server, router, None, None).await;
+        });
+        let params = b"user\0test\0\0";
+        let mut startup = Vec::new();
+        startup.extend_from_slice(&((params.len() + 8) as i32).to_be_bytes());
+        startup.extend_from_slice(&196608i32.to_be_bytes());
+        startup.extend_from_slice(params);
+        client.write_all(&startup).await.expect("startup");
+        for _ in 0..3 {
+            let _ = read_message_type(&mut client).await;
+        }
+        client
+    }
+
+    #[tokio::test]
+    async fn concurrent_transactions_do_not_lose_updates() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut alice = connect(&router).await;
+        let mut bob = connect(&router).await;
+
+        for sql in [
+            "CREATE TABLE counters (id INT PRIMARY KEY, hits INT);",
+            "INSERT INTO counters VALUES (1, 0);",
+        ] {
+            send_query(&mut alice, sql).await;
+            let (_rows, err, _, _) = read_until_ready(&mut alice).await;
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+        }
+        send_query(&mut alice, "BEGIN; UPDATE counters SET hits = 1 WHERE id = 1;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut alice).await;
+        assert!(err.is_none(), "update failed: {err:?}");
+        assert_eq!(ready_state, b'T');
+
+        // Bob's update waits for the row lock Alice's update holds until she commits.
+        send_query(&mut bob, "BEGIN; UPDATE counters SET hits = 2 WHERE id = 1;").await;
+        send_query(&mut alice, "COMMIT;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut alice).await;
+        assert!(err.is_none(), "first COMMIT failed: {err:?}");
+        assert_eq!(ready_state, b'I');
+
+        let (_rows, err, _, ready_state) = read_until_ready(&mut bob).await;
+        assert!(
+            err.as_deref().is_some_and(|e| e.contains("could not serialize access")),
+            "second update should fail: {err:?}"
+        );
+        assert_eq!(ready_state, b'E');
+        send_query(&mut bob, "ROLLBACK;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut bob).await;
+        assert!(err.is_none(), "ROLLBACK failed: {err:?}");
+        assert_eq!(ready_state, b'I');
+
+        // The committed batch reached the replica as well as the leader.
+        for replica in &router.shard_groups[0].replicas {
+            let result = replica
+                .execute(&parse_sql("SELECT hits FROM counters;").unwrap()[0], None)
+                .await
+                .expect("select");
+            assert_eq!(result.rows[0].values[0], DataValue::Int64(1));
+        }
+    }
+
+    #[tokio::test]
+    async fn serializable_sessions_abort_write_skew() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let config = test_config(dir.path().to_string_lossy().as_ref());
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut alice = connect(&router).await;
+        let mut bob = connect(&router).await;
+        send_query(
+            &mut alice,
+            "CREATE TABLE doctors (name TEXT PRIMARY KEY, on_call BOOLEAN); \
+             INSERT INTO doctors VALUES ('alice', true), ('bob', true);",
+        )
+        .await;
+        let (_rows, err, _, _) = read_until_ready(&mut alice).await;
+        assert!(err.is_none(), "setup failed: {err:?}");
+
+        for (client, begin) in [
+            (&mut alice, "BEGIN ISOLATION LEVEL SERIALIZABLE;"),
+            (&mut bob, "BEGIN; SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;"),
+        ] {
+            send_query(client, begin).await;
+            let (_rows, err, tag, _) = read_until_ready(client).await;
+            assert!(err.is_none(), "{begin} failed: {err:?}");
+            assert!(matches!(tag.as_deref(), Some("BEGIN" | "SET")));
+            send_query(client, "SELECT name FROM doctors WHERE on_call = true;").await;
+            let (rows, _, _, _) = read_until_ready(client).await;
+            assert_eq!(rows.len(), 2);
+        }
+        for (client, name) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+            let sql = format!("UPDATE doctors SET on_call = false WHERE name = '{name}';");
+            send_query(client, &sql).await;
+            let (_rows, err, _, _) = read_until_ready(client).await;
+            assert!(err.is_none(), "update failed: {err:?}");
+        }
+        send_query(&mut alice, "COMMIT;").await;
+        let (_rows, err, _, _) = read_until_ready(&mut alice).await;
+        assert!(err.is_none(), "first COMMIT failed: {err:?}");
+        send_query(&mut bob, "COMMIT;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut bob).await;
+        assert!(
+            err.as_deref().is_some_and(|e| e.contains("read/write dependencies")),
+            "second COMMIT should fail: {err:?}"
+        );
+        assert_eq!(ready_state, b'I');
+    }
+
+    #[tokio::test]
+    async fn serializable_read_only_sessions_are_validated_at_commit() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let config = test_config(dir.path().to_string_lossy().as_ref());
+        let router = ShardRouter::new(&config).await.expect("router");
+        let (alice, bob, carol) = (0, 1, 2);
+        let mut clients = [
+            connect(&router).await,
+            connect(&router).await,
+            connect(&router).await,
+        ];
+        // Bob reads x and writes y; Alice writes x and commits before Bob does. Carol's
+        // snapshot sees Alice's x but not Bob's y, an order no serial schedule gives.
+        for (client, sql) in [
+            (alice, "CREATE TABLE x (v INT); CREATE TABLE y (v INT); \
+                 INSERT INTO x VALUES (0); INSERT INTO y VALUES (0);"),
+            (bob, "BEGIN ISOLATION LEVEL SERIALIZABLE; SELECT v FROM x;"),
+            (bob, "UPDATE y SET v = 1;"),
+            (alice, "BEGIN ISOLATION LEVEL SERIALIZABLE; UPDATE x SET v = 1;"),
+            (alice, "COMMIT;"),
+            (carol, "BEGIN ISOLATION LEVEL SERIALIZABLE; SELECT v FROM x; SELECT v FROM y;"),
+            (bob, "COMMIT;"),
+        ] {
+            let client = &mut clients[client];
+            send_query(client, sql).await;
+            let (_rows, err, _, _) = read_until_ready(client).await;
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+        }
+        send_query(&mut clients[carol], "COMMIT;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut clients[carol]).await;
+        assert!(
+            err.as_deref().is_some_and(|e| e.contains("read/write dependencies")),
+            "read-only COMMIT should fail: {err:?}"
+        );
+        assert_eq!(ready_state, b'I');
+    }
+
+    #[tokio::test]
+    async fn failed_commits_leave_no_reads_on_other_shards() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.sharding.shard_count = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let coordinator = Coordinator::new(2);
+        let mut names = (0..).map(|i| format!("t{i}"));
+        let mut on_shard = |shard| names.find(|name| coordinator.shard_for_table(name) == shard).unwrap();
+        let (x, z, y, w) = (on_shard(0), on_shard(0), on_shard(1), on_shard(1));
+        // Reads go to every shard, so every shard gets every table.
+        for group in router.shard_groups.iter() {
+            for name in [&x, &y, &z, &w] {
+                let create = format!("CREATE TABLE {name} (v INT);");
+                group.replicas[0]
+                    .execute(&parse_sql(&create).unwrap()[0], None)
+                    .await
+                    .expect("create");
+            }
+        }
+        let (alice, bob, carol, dave, erin) = (0, 1, 2, 3, 4);
+        let mut clients = Vec::new();
+        for _ in 0..5 {
+            clients.push(connect(&router).await);
+        }
+        // Bob reads x and w and writes y; Erin's read of y and Dave's write to w make his
+        // commit fail on shard 1. Had his reads been recorded on shard 0 regardless, Carol,
+        // who read Alice's z and overwrote the x Bob read, would be aborted as the pivot.
+        for (client, sql) in [
+            (alice, format!("INSERT INTO {x} VALUES (0); INSERT INTO {y} VALUES (0);")),
+            (carol, "BEGIN ISOLATION LEVEL SERIALIZABLE;".to_string()),
+            (bob, format!("BEGIN ISOLATION LEVEL SERIALIZABLE; SELECT v FROM {x}; SELECT v FROM {w};")),
+            (bob, format!("UPDATE {y} SET v = 1;")),
+            (erin, format!("BEGIN ISOLATION LEVEL SERIALIZABLE; SELECT v FROM {y}; COMMIT;")),
+            (dave, format!("BEGIN ISOLATION LEVEL SERIALIZABLE; INSERT INTO {w} VALUES (1); COMMIT;")),
+            (alice, format!("BEGIN ISOLATION LEVEL SERIALIZABLE; INSERT INTO {z} VALUES (1); COMMIT;")),
+        ] {
+            run_promptly(&mut clients[client], &[&sql]).await;
+        }
+        send_query(&mut clients[bob], "COMMIT;").await;
+        let (_rows, err, _, _) = read_until_ready(&mut clients[bob]).await;
+        assert!(
+            err.as_deref().is_some_and(|e| e.contains("read/write dependencies")),
+            "Bob's COMMIT should fail: {err:?}"
+        );
+        let sql = format!("SELECT v FROM {z}; UPDATE {x} SET v = 5; COMMIT;");
+        run_promptly(&mut clients[carol], &[&sql]).await;
+    }
+
+    #[tokio::test]
+    async fn commit_without_quorum_warns_instead_of_failing() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut client = connect(&router).await;
+        run_promptly(&mut client, &["CREATE TABLE kept (id INT);", "BEGIN; INSERT INTO kept VALUES (1);"]).await;
+        let group = &router.shard_groups[0];
+        let leader = router.select_leader(group);
+        for replica in group.replicas.iter().filter(|replica| replica.replica_id != leader) {
+            router.failover.mark_unhealthy(&replica.node_id);
+        }
+        send_query(&mut client, "COMMIT;").await;
+        let mut types = Vec::new();
+        for _ in 0..3 {
+            types.push(read_message_type(&mut client).await);
+        }
+        assert_eq!(types, [b'N', b'C', b'Z'], "a warning, then COMMIT");
+        let leader = router.leader(group).expect("leader");
+        let result = leader
+            .execute(&parse_sql("SELECT id FROM kept;").unwrap()[0], None)
+            .await
+            .expect("select");
+        assert_eq!(result.rows.len(), 1, "the commit stands on the leader");
+    }
+
+    /// Runs each statement on `client` within a few seconds, so DDL that waits for its own
+    /// session fails the test instead of hanging it.
+    async fn run_promptly(client: &mut tokio::io::DuplexStream, statements: &[&str]) {
+        for sql in statements {
+            let reply = async {
+                send_query(client, sql).await;
+                read_until_ready(client).await
+            };
+            let (_rows, err, _, _) = tokio::time::timeout(Duration::from_secs(5), reply)
+                .await
+                .unwrap_or_else(|_| panic!("{sql} timed out"));
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn truncate_and_drop_table_run_inside_a_transaction() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut client = connect(&router).await;
+        run_promptly(
+            &mut client,
+            &[
+                "CREATE TABLE items (id INT); CREATE TABLE gone (id INT);",
+                "INSERT INTO items VALUES (1), (2); INSERT INTO gone VALUES (1);",
+                "BEGIN; TRUNCATE items; COMMIT;",
+                "BEGIN; SELECT id FROM items; DROP TABLE gone; COMMIT;",
+            ],
+        )
+        .await;
+        for replica in &router.shard_groups[0].replicas {
+            let result = replica
+                .execute(&parse_sql("SELECT id FROM items;").unwrap()[0], None)
+                .await
+                .expect("select");
+            assert!(result.rows.is_empty());
+            assert!(replica
+                .execute(&parse_sql("SELECT id FROM gone;").unwrap()[0], None)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn index_ddl_runs_inside_a_transaction() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.cluster.replication_factor = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut client = connect(&router).await;
+        run_promptly(
+            &mut client,
+            &[
+                "CREATE TABLE items (id INT, tag TEXT); INSERT INTO items VALUES (1, 'a');",
+                "BEGIN; CREATE INDEX items_id ON items (id); COMMIT;",
+                "BEGIN; CREATE INDEX items_tag ON items (tag); DROP INDEX items_id; COMMIT;",
+                "BEGIN; ALTER TABLE items DROP COLUMN tag; COMMIT;",
+            ],
+        )
+        .await;
+        for replica in &router.shard_groups[0].replicas {
+            let result = replica
+                .execute(&parse_sql("SELECT * FROM items;").unwrap()[0], None)
+                .await
+                .expect("select");
+            assert_eq!(result.columns.len(), 1);
+            let entries = replica
+                .storage
+                .scan(datacave_sql::INDEX_KEY_PREFIX, u64::MAX)
+                .await
+                .expect("scan");
+            assert!(entries.is_empty(), "the dropped indexes left entries");
+        }
+    }
+
+    #[tokio::test]
+    async fn transactions_write_to_one_shard() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let mut config = test_config(dir.path().to_string_lossy().as_ref());
+        config.sharding.shard_count = 2;
+        let router = ShardRouter::new(&config).await.expect("router");
+        let coordinator = Coordinator::new(2);
+        let home = coordinator.shard_for_table("t0");
+        let other = (1..)
+            .map(|i| format!("t{i}"))
+            .find(|name| coordinator.shard_for_table(name) != home)
+            .unwrap();
+        let mut client = connect(&router).await;
+        for sql in [
+            "CREATE TABLE t0 (id INT);".to_string(),
+            format!("CREATE TABLE {other} (id INT);"),
+            "BEGIN; INSERT INTO t0 VALUES (1);".to_string(),
+        ] {
+            send_query(&mut client, &sql).await;
+            let (_rows, err, _, _) = read_until_ready(&mut client).await;
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+        }
+        send_query(&mut client, &format!("INSERT INTO {other} VALUES (1);")).await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut client).await;
+        assert!(err.is_some_and(|e| e.contains("one shard")));
+        assert_eq!(ready_state, b'E');
+    }
+
+    #[tokio::test]
+    async fn rollback_to_savepoint_recovers_a_failed_transaction() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let config = test_config(dir.path().to_string_lossy().as_ref());
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut client = connect(&router).await;
+
+        send_query(&mut client, "SAVEPOINT outside;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut client).await;
+        assert!(err.is_some_and(|e| e.contains("transaction blocks")));
+        assert_eq!(ready_state, b'I');
+
+        for (sql, tag) in [
+            ("CREATE TABLE sp (id INT PRIMARY KEY);", "OK"),
+            ("BEGIN;", "BEGIN"),
+            ("INSERT INTO sp VALUES (1);", "OK 1"),
+            ("SAVEPOINT s1;", "SAVEPOINT"),
+            ("INSERT INTO sp VALUES (2);", "OK 1"),
+        ] {
+            send_query(&mut client, sql).await;
+            let (_rows, err, cmd_tag, _) = read_until_ready(&mut client).await;
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+            assert_eq!(cmd_tag.as_deref(), Some(tag));
+        }
+        send_query(&mut client, "INSERT INTO sp VALUES (1);").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut client).await;
+        assert!(err.is_some(), "duplicate key");
+        assert_eq!(ready_state, b'E');
+
+        send_query(&mut client, "ROLLBACK TO SAVEPOINT missing;").await;
+        let (_rows, err, _, ready_state) = read_until_ready(&mut client).await;
+        assert!(err.is_some_and(|e| e.contains("does not exist")));
+        assert_eq!(ready_state, b'E', "still failed");
+
+        send_query(&mut client, "ROLLBACK TO SAVEPOINT s1;").await;
+        let (_rows, err, tag, ready_state) = read_until_ready(&mut client).await;
+        assert!(err.is_none(), "ROLLBACK TO failed: {err:?}");
+        assert_eq!(tag.as_deref(), Some("ROLLBACK"));
+        assert_eq!(ready_state, b'T', "the transaction resumes");
+
+        for sql in ["RELEASE SAVEPOINT s1;", "INSERT INTO sp VALUES (3);", "COMMIT;"] {
+            send_query(&mut client, sql).await;
+            let (_rows, err, _, _) = read_until_ready(&mut client).await;
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+        }
+        send_query(&mut client, "SELECT id FROM sp;").await;
+        let (rows, err, _, ready_state) = read_until_ready(&mut client).await;
+        assert!(err.is_none(), "SELECT failed: {err:?}");
+        assert_eq!(ready_state, b'I');
+        let ids: Vec<_> = rows.iter().map(|row| row[0].clone().unwrap()).collect();
+        assert_eq!(ids, vec![b"1".to_vec(), b"3".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn job_queue_sessions_claim_distinct_rows() {
+        let dir = tempfile::TempDir::new().expect("tempdir");
+        let config = test_config(dir.path().to_string_lossy().as_ref());
+        let router = ShardRouter::new(&config).await.expect("router");
+        let mut workers = [
+            connect(&router).await,
+            connect(&router).await,
+            connect(&router).await,
+        ];
+        for sql in [
+            "CREATE TABLE queue (id INT PRIMARY KEY, state TEXT);",
+            "INSERT INTO queue VALUES (1, 'ready'), (2, 'ready');",
+        ] {
+            send_query(&mut workers[0], sql).await;
+            let (_rows, err, _, _) = read_until_ready(&mut workers[0]).await;
+            assert!(err.is_none(), "{sql} failed: {err:?}");
+        }
+        let claim = "BEGIN; SELECT id FROM queue WHERE state = 'ready' ORDER BY id LIMIT 1 \
+                     FOR UPDATE SKIP LOCKED;";
+        for (worker, id) in workers.iter_mut().zip([b"1", b"2"]) {
+            send_query(worker, claim).await;
+            let (rows, err, _, _) = read_until_ready(worker).await;
+            assert!(err.is_none(), "claim failed: {err:?}");
+            assert_eq!(rows, vec![vec![Some(id.to_vec())]]);
+        }
+        let [first, _, waiter] = &mut workers;
+        send_query(waiter, "BEGIN; SELECT id FROM queue WHERE id = 1 FOR UPDATE;").await;
+        send_query(first, "COMMIT;").await;
+        let (_rows, err, _, _) = read_until_ready(first).await;
+        assert!(err.is_none(), "COMMIT failed: {err:?}");
+        let (rows, err, _, ready_state) = read_until_ready(waiter).await;
+        assert!(err.is_none(), "waiting lock failed: {err:?}");
+        assert_eq!(rows.len(), 1, "granted once the holder commits");
+        assert_eq!(ready_state, b'T');
+    }
+
     #[tokio::test]
     async fn integration_joins_and_aggregates_via_simple_query() {
         let dir = tempfile::TempDir::new().expect("tempdir");
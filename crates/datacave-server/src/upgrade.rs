@@ -0,0 +1,69 @@
+use crate::config::{Config, StorageEngineKind};
+use crate::server::replica_lsm_options;
+use datacave_lsm::engine::LsmEngine;
+use datacave_lsm::storage::WriteBatch;
+use datacave_sql::{
+    decode_row, decode_schema, encode_row, encode_schema, is_system_key, row_format_version,
+    CATALOG_KEY_PREFIX, ROW_FORMAT_VERSION, SCHEMA_FORMAT_VERSION,
+};
+use tracing::info;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpgradeSummary {
+    pub replicas: usize,
+    pub tables_rewritten: usize,
+    pub rows_rewritten: usize,
+}
+
+/// Rewrites every replica's data directory in the current on-disk format: legacy SSTables
+/// and WAL files are re-encoded, then rows and table schemas in an older row format are
+/// written back at a new version. The server must not be running against the same data directory.
+pub async fn upgrade_data(config: &Config) -> anyhow::Result<UpgradeSummary> {
+    if config.storage.engine != StorageEngineKind::Lsm {
+        return Err(anyhow::anyhow!("upgrade requires the lsm storage engine"));
+    }
+    let mut summary = UpgradeSummary::default();
+    for shard_id in 0..config.sharding.shard_count {
+        for replica_id in 0..config.cluster.replication_factor {
+            let options = replica_lsm_options(config, shard_id, replica_id);
+            if !std::path::Path::new(&options.data_dir).exists() {
+                continue;
+            }
+            let data_dir = options.data_dir.clone();
+            let engine = LsmEngine::open(options).await?;
+            let report = engine.upgrade().await?;
+            let rows = upgrade_rows(&engine).await?;
+            if rows > 0 {
+                engine.flush().await?;
+            }
+            info!(
+                "upgraded {}: {} tables, {} rows rewritten",
+                data_dir, report.tables_rewritten, rows
+            );
+            summary.replicas += 1;
+            summary.tables_rewritten += report.tables_rewritten;
+            summary.rows_rewritten += rows;
+        }
+    }
+    Ok(summary)
+}
+
+async fn upgrade_rows(engine: &LsmEngine) -> anyhow::Result<usize> {
+    let version = engine.snapshot().version;
+    let mut batch = WriteBatch::new();
+    for (key, value) in engine.scan(b"", version).await? {
+        if key.starts_with(CATALOG_KEY_PREFIX) {
+            if row_format_version(&value) < SCHEMA_FORMAT_VERSION {
+                batch.put(&key, &encode_schema(&decode_schema(&value)?)?);
+            }
+        } else if !is_system_key(&key) && row_format_version(&value) < ROW_FORMAT_VERSION {
+            let (schema_version, row) = decode_row(&value)?;
+            batch.put(&key, &encode_row(&row, schema_version)?);
+        }
+    }
+    let rows = batch.len();
+    if rows > 0 {
+        engine.write_batch(batch, version + 1).await?;
+    }
+    Ok(rows)
+}
@@ -32,6 +32,7 @@ fn sql_end_to_end_bench(c: &mut Criterion) {
                     memtable_max_bytes: 1024,
                     encryption_key: None,
                     wal_enabled: true,
+                    tiering: None,
                 })
                 .await
                 .expect("open"),
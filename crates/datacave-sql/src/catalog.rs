@@ -1,4 +1,173 @@
-use datacave_core::catalog::Catalog;
+use crate::executor::{split_row_header, ROW_MAGIC};
+use datacave_core::catalog::{
+    Catalog, CheckConstraint, ColumnConstraints, ColumnLayout, TableSchema, UniqueConstraint,
+};
+use datacave_core::error::DatacaveError;
+use datacave_core::types::Column;
+use serde::Deserialize;
 use std::sync::{Arc, Mutex};
 
 pub type SharedCatalog = Arc<Mutex<Catalog>>;
+
+/// Prefix of the storage keys holding table schemas. The leading NUL keeps it clear of row
+/// keys, which start with a tenant or table name.
+pub const CATALOG_KEY_PREFIX: &[u8] = b"\0catalog|";
+
+/// Prefix of the storage keys holding each table's next free row id.
+pub const SEQUENCE_KEY_PREFIX: &[u8] = b"\0seq|";
+
+/// Prefix of the storage keys recording which tenants wrote rows of each table, so a table's
+/// rows can be reached tenant by tenant.
+pub const TENANT_KEY_PREFIX: &[u8] = b"\0tenant|";
+
+/// Prefix of the storage keys holding secondary index entries.
+pub const INDEX_KEY_PREFIX: &[u8] = b"\0index|";
+
+/// Prefix of the storage keys mapping commit times to versions, for `AS OF SYSTEM TIME`.
+pub const HISTORY_KEY_PREFIX: &[u8] = b"\0history|";
+
+/// Storage key of the oldest version time-travel queries may read.
+pub const HISTORY_HORIZON_KEY: &[u8] = b"\0horizon";
+
+/// Whether `key` holds engine metadata (schemas, sequences, index entries, history) rather
+/// than a table row.
+pub fn is_system_key(key: &[u8]) -> bool {
+    key.first() == Some(&0)
+}
+
+/// Storage key of `table`'s schema.
+pub fn catalog_key(table: &str) -> Vec<u8> {
+    let mut out = CATALOG_KEY_PREFIX.to_vec();
+    out.extend_from_slice(table.as_bytes());
+    out
+}
+
+/// Version of the schema encoding written by this build. Schemas share the row header, and
+/// each version keeps a struct below to read it: v1 predates ALTER TABLE, v2 predates
+/// column constraints, v3 predates primary-key-addressed rows, v4 predates secondary indexes.
+pub const SCHEMA_FORMAT_VERSION: u8 = 5;
+
+/// Storage value of a table schema: `ROW_MAGIC || SCHEMA_FORMAT_VERSION || bincode(schema)`.
+pub fn encode_schema(schema: &TableSchema) -> Result<Vec<u8>, DatacaveError> {
+    let mut out = ROW_MAGIC.to_vec();
+    out.push(SCHEMA_FORMAT_VERSION);
+    bincode::serialize_into(&mut out, schema).map_err(|e| DatacaveError::Storage(e.to_string()))?;
+    Ok(out)
+}
+
+pub fn decode_schema(bytes: &[u8]) -> Result<TableSchema, DatacaveError> {
+    let (version, body) = split_row_header(bytes);
+    let decode_error = |e: bincode::Error| DatacaveError::Catalog(e.to_string());
+    match version {
+        1 => {
+            let schema: SchemaV1 = bincode::deserialize(body).map_err(decode_error)?;
+            Ok(TableSchema::new(schema.name, schema.columns, schema.primary_key))
+        }
+        2 => {
+            let schema: SchemaV2 = bincode::deserialize(body).map_err(decode_error)?;
+            Ok(TableSchema {
+                column_constraints: vec![ColumnConstraints::default(); schema.columns.len()],
+                name: schema.name,
+                columns: schema.columns,
+                primary_key: schema.primary_key,
+                storage_name: schema.storage_name,
+                version: schema.version,
+                layout: schema.layout,
+                row_width: schema.row_width,
+                unique: Vec::new(),
+                checks: Vec::new(),
+                key_columns: Vec::new(),
+                indexes: Vec::new(),
+            })
+        }
+        3 => {
+            let schema: SchemaV3 = bincode::deserialize(body).map_err(decode_error)?;
+            Ok(TableSchema {
+                name: schema.name,
+                columns: schema.columns,
+                primary_key: schema.primary_key,
+                storage_name: schema.storage_name,
+                version: schema.version,
+                layout: schema.layout,
+                row_width: schema.row_width,
+                column_constraints: schema.column_constraints,
+                unique: schema.unique,
+                checks: schema.checks,
+                key_columns: Vec::new(),
+                indexes: Vec::new(),
+            })
+        }
+        4 => {
+            let schema: SchemaV4 = bincode::deserialize(body).map_err(decode_error)?;
+            Ok(TableSchema {
+                name: schema.name,
+                columns: schema.columns,
+                primary_key: schema.primary_key,
+                storage_name: schema.storage_name,
+                version: schema.version,
+                layout: schema.layout,
+                row_width: schema.row_width,
+                column_constraints: schema.column_constraints,
+                unique: schema.unique,
+                checks: schema.checks,
+                key_columns: schema.key_columns,
+                indexes: Vec::new(),
+            })
+        }
+        SCHEMA_FORMAT_VERSION => bincode::deserialize(body).map_err(decode_error),
+        _ => Err(DatacaveError::Catalog(format!(
+            "unsupported schema format v{version}"
+        ))),
+    }
+}
+
+/// Schemas written before ALTER TABLE existed: no layout, rows keyed by the table name.
+#[derive(Deserialize)]
+struct SchemaV1 {
+    name: String,
+    columns: Vec<Column>,
+    primary_key: Option<String>,
+}
+
+/// Schemas written before column constraints were stored.
+#[derive(Deserialize)]
+struct SchemaV2 {
+    name: String,
+    columns: Vec<Column>,
+    primary_key: Option<String>,
+    storage_name: String,
+    version: u32,
+    layout: Vec<ColumnLayout>,
+    row_width: usize,
+}
+
+/// Schemas written before primary keys addressed rows; their rows stay keyed by row id.
+#[derive(Deserialize)]
+struct SchemaV3 {
+    name: String,
+    columns: Vec<Column>,
+    primary_key: Option<String>,
+    storage_name: String,
+    version: u32,
+    layout: Vec<ColumnLayout>,
+    row_width: usize,
+    column_constraints: Vec<ColumnConstraints>,
+    unique: Vec<UniqueConstraint>,
+    checks: Vec<CheckConstraint>,
+}
+
+/// Schemas written before secondary indexes existed.
+#[derive(Deserialize)]
+struct SchemaV4 {
+    name: String,
+    columns: Vec<Column>,
+    primary_key: Option<String>,
+    storage_name: String,
+    version: u32,
+    layout: Vec<ColumnLayout>,
+    row_width: usize,
+    column_constraints: Vec<ColumnConstraints>,
+    unique: Vec<UniqueConstraint>,
+    checks: Vec<CheckConstraint>,
+    key_columns: Vec<String>,
+}
@@ -1,49 +1,313 @@
 use crate::planner::{
-    plan_statement, AggregateFunc, HavingCond, HavingOp, HavingOperand, JoinKind, OrderBySpec,
+    build_plan, parse_check, rename_check_column, AsOf, CreateIndexPlan, DropIndexPlan, DropTablePlan, TruncatePlan, SetTransactionPlan, RollbackPlan, SavepointPlan, ReleaseSavepointPlan, AlterTableOp, AlterTablePlan, HavingOp, JoinKind, OrderBySpec,
     OrderBySpecKind, Plan, ProjectionItem, WhereCond, WhereOperand, WherePredicate,
 };
-use datacave_core::catalog::{Catalog, TableSchema};
-use datacave_core::error::DatacaveError;
-use datacave_core::mvcc::MvccManager;
+use datacave_core::catalog::{undefined_table, Catalog, TableIndex, TableSchema, UniqueConstraint};
+use datacave_core::error::{sqlstate, DatacaveError};
+use datacave_core::keys::{decode_key, encode_key, prefix_end};
+use datacave_core::mvcc::{CommitConflict, KeyRange, MvccManager, Snapshot, Version};
 use datacave_core::types::{Column, DataRow, DataValue, SqlResult};
-use datacave_lsm::engine::LsmEngine;
+use datacave_lsm::storage::{SharedStorage, StorageEngine, WriteBatch};
 use sqlparser::ast::Statement;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedMutexGuard};
+use crate::expr::{compare, literal_as, truth, BinaryOp, ScalarExpr};
+use crate::catalog::{
+    catalog_key, decode_schema, encode_schema, CATALOG_KEY_PREFIX,
+    HISTORY_HORIZON_KEY, HISTORY_KEY_PREFIX, INDEX_KEY_PREFIX, SEQUENCE_KEY_PREFIX,
+    TENANT_KEY_PREFIX,
+};
+use crate::history::{decode_version, history_key, now_millis, parse_timestamp};
+use crate::lock::{LockManager, LockMode, LockWait, RowLock};
+use crate::transaction::{IsolationLevel, Transaction, TxnId};
 use crate::vectorized::ColumnBatch;
 
 #[derive(Debug)]
 pub struct SqlExecutor {
     catalog: Arc<Mutex<Catalog>>,
     mvcc: Arc<MvccManager>,
-    storage: Arc<LsmEngine>,
+    storage: SharedStorage,
     table_seq: Arc<Mutex<HashMap<String, u64>>>,
+    transactions: Mutex<HashMap<TxnId, Transaction>>,
+    next_txn: AtomicU64,
+    locks: LockManager,
+    /// Woken whenever a snapshot ends, for schema changes waiting on older transactions.
+    snapshot_ended: Notify,
+    /// Snapshot versions of the transactions running a schema change that waits for older
+    /// transactions, which therefore do not wait for each other.
+    schema_changes: Mutex<Vec<Version>>,
+    /// One lock per table, by storage name, held by schema changes while they read, modify
+    /// and publish its schema.
+    ddl_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// How long `AS OF SYSTEM TIME` can look back; history is kept forever when unset.
+    history_retention: Option<Duration>,
 }
 
 impl SqlExecutor {
-    pub fn new(catalog: Arc<Mutex<Catalog>>, mvcc: Arc<MvccManager>, storage: Arc<LsmEngine>) -> Self {
+    pub fn new(catalog: Arc<Mutex<Catalog>>, mvcc: Arc<MvccManager>, storage: SharedStorage) -> Self {
         Self {
             catalog,
             mvcc,
             storage,
             table_seq: Arc::new(Mutex::new(HashMap::new())),
+            transactions: Mutex::new(HashMap::new()),
+            next_txn: AtomicU64::new(1),
+            locks: LockManager::default(),
+            snapshot_ended: Notify::new(),
+            schema_changes: Mutex::new(Vec::new()),
+            ddl_locks: Mutex::new(HashMap::new()),
+            history_retention: None,
         }
     }
 
+    /// Limits time-travel queries to the last `retention` and lets [`Self::collect_history`]
+    /// discard older versions.
+    pub fn with_history_retention(mut self, retention: Duration) -> Self {
+        self.history_retention = Some(retention);
+        self
+    }
+
+    /// Runs `stmt` in a transaction of its own, committed when it succeeds.
     pub async fn execute(&self, stmt: &Statement, tenant_id: Option<&str>) -> Result<SqlResult, DatacaveError> {
-        let plan = plan_statement(stmt).ok_or_else(|| DatacaveError::Sql("unsupported SQL".into()))?;
+        let plan = build_plan(stmt)?;
+        let id = self.next_txn.fetch_add(1, Ordering::SeqCst);
+        let mut txn = Transaction::new(id, self.mvcc.begin(), IsolationLevel::Snapshot);
+        match self.exec_plan(plan, tenant_id, &mut txn).await {
+            Ok(result) => {
+                self.commit_transaction(txn).await?;
+                Ok(result)
+            }
+            Err(err) => {
+                self.end_transaction(&txn);
+                Err(err)
+            }
+        }
+    }
+
+    /// Opens a transaction reading at the current snapshot. Statements run in it with
+    /// [`Self::execute_in`] until [`Self::commit`] or [`Self::rollback`].
+    pub fn begin(&self, isolation: IsolationLevel) -> TxnId {
+        let id = self.next_txn.fetch_add(1, Ordering::SeqCst);
+        let txn = Transaction::new(id, self.mvcc.begin(), isolation);
+        self.transactions.lock().unwrap().insert(id, txn);
+        id
+    }
+
+    /// Runs `stmt` inside transaction `txn`. Its writes stay private to the transaction;
+    /// DDL is not transactional and takes effect immediately.
+    pub async fn execute_in(
+        &self,
+        stmt: &Statement,
+        tenant_id: Option<&str>,
+        txn: TxnId,
+    ) -> Result<SqlResult, DatacaveError> {
+        let plan = build_plan(stmt)?;
+        let mut transaction = self
+            .transactions
+            .lock()
+            .unwrap()
+            .remove(&txn)
+            .ok_or_else(|| unknown_transaction(txn))?;
+        let result = self.exec_plan(plan, tenant_id, &mut transaction).await;
+        self.transactions.lock().unwrap().insert(txn, transaction);
+        result
+    }
+
+    /// Commits `txn` at a single new version and returns its writes, for replicas to
+    /// apply with [`Self::apply_committed`]. Fails with a serialization failure, rolling
+    /// the transaction back, when another transaction committed a write to one of the same
+    /// keys after `txn`'s snapshot was taken, or when a serializable transaction's reads
+    /// and writes could not have happened in any serial order.
+    pub async fn commit(&self, txn: TxnId) -> Result<WriteBatch, DatacaveError> {
+        let transaction = self
+            .transactions
+            .lock()
+            .unwrap()
+            .remove(&txn)
+            .ok_or_else(|| unknown_transaction(txn))?;
+        self.commit_transaction(transaction).await
+    }
+
+    /// Fails, as [`Self::commit`] would, when `txn` could not commit now, but leaves it open
+    /// and records nothing. A transaction spanning several executors is validated on each
+    /// before it commits on any, so a failure leaves none of them committed.
+    pub fn validate(&self, txn: TxnId) -> Result<(), DatacaveError> {
+        let transactions = self.transactions.lock().unwrap();
+        let transaction = transactions.get(&txn).ok_or_else(|| unknown_transaction(txn))?;
+        let serializable = transaction.isolation == IsolationLevel::Serializable;
+        if transaction.is_read_only() && !serializable {
+            return Ok(());
+        }
+        self.mvcc
+            .validate(
+                transaction.snapshot,
+                transaction.conflict_keys(),
+                serializable.then(|| transaction.reads()),
+            )
+            .map_err(serialization_failure)
+    }
+
+    /// Discards `txn` and its writes and releases its row locks. Unknown ids are ignored.
+    pub fn rollback(&self, txn: TxnId) {
+        if let Some(transaction) = self.transactions.lock().unwrap().remove(&txn) {
+            self.end_transaction(&transaction);
+        }
+    }
+
+    /// Applies a batch committed by another replica's executor at a version of this one.
+    pub async fn apply_committed(&self, batch: WriteBatch) -> Result<(), DatacaveError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        // The batch may advance row-id sequences behind the cached next ids.
+        self.table_seq.lock().unwrap().clear();
+        self.write_now(batch).await?;
+        Ok(())
+    }
+
+    /// Copies into this replica every stored version `source`, a healthy replica of the
+    /// same shard, holds and this one lacks, such as versions lost with a quarantined file.
+    /// The keyspace is read in chunks of [`REPAIR_BATCH_KEYS`] keys, and the copied versions
+    /// are claimed from the clock so later writes sort above them. Returns how many
+    /// versions were copied.
+    pub async fn repair_from(&self, source: &dyn StorageEngine) -> Result<usize, DatacaveError> {
+        let storage_error = |e: anyhow::Error| DatacaveError::Storage(e.to_string());
+        let mut after: Option<Vec<u8>> = None;
+        let mut restored = 0;
+        loop {
+            let chunk = source
+                .scan_versions(after.as_deref(), REPAIR_BATCH_KEYS)
+                .await
+                .map_err(storage_error)?;
+            let Some(last) = chunk.last() else {
+                break;
+            };
+            after = Some(last.key.clone());
+            let newest = chunk.iter().map(|entry| entry.version).max().unwrap_or(0);
+            let claimed = self.mvcc.claim_through(newest);
+            let written = self.storage.restore_versions(chunk).await;
+            if let Some(version) = claimed {
+                self.mvcc.publish(version);
+            }
+            restored += written.map_err(storage_error)?;
+        }
+        // Restored versions may advance row-id sequences and carry schemas.
+        self.table_seq.lock().unwrap().clear();
+        let snapshot = self.mvcc.snapshot();
+        let schemas = self
+            .storage
+            .scan(CATALOG_KEY_PREFIX, snapshot.version)
+            .await
+            .map_err(storage_error)?;
+        let mut catalog = self.catalog.lock().unwrap();
+        for (_, value) in &schemas {
+            let schema = decode_schema(value)?;
+            if catalog.get_table(&schema.name).is_some() {
+                catalog.alter_table(&schema.name.clone(), schema)?;
+            } else {
+                catalog.create_table(schema)?;
+            }
+        }
+        Ok(restored)
+    }
+
+    async fn exec_plan(
+        &self,
+        plan: Plan,
+        tenant_id: Option<&str>,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        if matches!(
+            plan,
+            Plan::Insert(_) | Plan::Select(_) | Plan::Update(_) | Plan::Delete(_)
+        ) {
+            txn.started = true;
+        }
         match plan {
-            Plan::CreateTable(plan) => self.exec_create_table(plan),
-            Plan::Insert(plan) => self.exec_insert(plan, tenant_id).await,
-            Plan::Select(plan) => self.exec_select(plan, tenant_id).await,
-            Plan::Update(plan) => self.exec_update(plan, tenant_id).await,
-            Plan::Delete(plan) => self.exec_delete(plan, tenant_id).await,
+            Plan::CreateTable(plan) => self.exec_create_table(plan).await,
+            Plan::AlterTable(plan) => self.exec_alter_table(plan, txn).await,
+            Plan::DropTable(plan) => self.exec_drop_table(plan, txn).await,
+            Plan::Truncate(plan) => self.exec_truncate(plan, tenant_id, txn).await,
+            Plan::CreateIndex(plan) => self.exec_create_index(plan, txn).await,
+            Plan::DropIndex(plan) => self.exec_drop_index(plan, txn).await,
+            Plan::Insert(plan) => self.exec_insert(plan, tenant_id, txn).await,
+            Plan::Select(plan) => match plan.as_of.clone() {
+                Some(as_of) => self.exec_select_as_of(plan, &as_of, tenant_id, txn).await,
+                None => self.exec_select(plan, tenant_id, txn).await,
+            },
+            Plan::Update(plan) => self.exec_update(plan, tenant_id, txn).await,
+            Plan::Delete(plan) => self.exec_delete(plan, tenant_id, txn).await,
             Plan::Begin(_) => self.exec_begin(),
             Plan::Commit(_) => self.exec_commit(),
-            Plan::Rollback(_) => self.exec_rollback(),
+            Plan::Rollback(plan) => self.exec_rollback(plan, txn),
+            Plan::SetTransaction(plan) => self.exec_set_transaction(plan, txn),
+            Plan::Savepoint(plan) => self.exec_savepoint(plan, txn),
+            Plan::ReleaseSavepoint(plan) => self.exec_release_savepoint(plan, txn),
         }
     }
 
+    async fn commit_transaction(&self, mut txn: Transaction) -> Result<WriteBatch, DatacaveError> {
+        let snapshot = txn.snapshot;
+        let serializable = txn.isolation == IsolationLevel::Serializable;
+        if txn.is_read_only() && !serializable {
+            self.end_transaction(&txn);
+            return Ok(WriteBatch::new());
+        }
+        let reads = txn.take_reads();
+        let committed = if serializable {
+            self.mvcc
+                .commit_serializable(snapshot, txn.conflict_keys(), reads)
+        } else {
+            self.mvcc.commit(Some(snapshot), txn.conflict_keys())
+        };
+        let version = match committed {
+            Ok(version) => version,
+            Err(conflict) => {
+                self.end_transaction(&txn);
+                return Err(serialization_failure(conflict));
+            }
+        };
+        let batch = txn.to_batch();
+        let written = if batch.is_empty() {
+            Ok(())
+        } else {
+            let stamped = with_history_entry(batch.clone(), version);
+            self.storage.write_batch(stamped, version).await
+        };
+        self.mvcc.publish(version);
+        self.end_transaction(&txn);
+        written.map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        Ok(batch)
+    }
+
+    /// Closes `txn`'s snapshot and releases its row locks, once it is committed or discarded.
+    fn end_transaction(&self, txn: &Transaction) {
+        self.end_snapshot(txn.snapshot);
+        self.locks.release_all(txn.id);
+    }
+
+    fn end_snapshot(&self, snapshot: Snapshot) {
+        self.mvcc.end(snapshot);
+        self.snapshot_ended.notify_waiters();
+    }
+
+    /// Writes `batch` outside any transaction, at a version of its own, and returns the
+    /// version.
+    async fn write_now(&self, batch: WriteBatch) -> Result<Version, DatacaveError> {
+        let version = self.mvcc.next_version();
+        let written = self
+            .storage
+            .write_batch(with_history_entry(batch, version), version)
+            .await;
+        self.mvcc.publish(version);
+        written.map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        Ok(version)
+    }
+
     pub async fn execute_vectorized(
         &self,
         stmt: &Statement,
@@ -53,13 +317,394 @@ impl SqlExecutor {
         Ok(ColumnBatch::from_rows(result.columns, result.rows))
     }
 
-    fn exec_create_table(&self, plan: crate::planner::CreateTablePlan) -> Result<SqlResult, DatacaveError> {
-        let schema = TableSchema {
-            name: plan.table.clone(),
-            columns: plan.columns.clone(),
-            primary_key: plan.primary_key.clone(),
+    /// Loads the table schemas persisted by earlier `CREATE TABLE`s into the catalog.
+    pub async fn load_catalog(&self) -> Result<usize, DatacaveError> {
+        let snapshot = self
+            .storage
+            .snapshot()
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let entries = self
+            .storage
+            .scan(CATALOG_KEY_PREFIX, snapshot.version)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let mut catalog = self.catalog.lock().unwrap();
+        for (_, value) in &entries {
+            catalog.create_table(decode_schema(value)?)?;
+        }
+        Ok(entries.len())
+    }
+
+    async fn exec_create_table(&self, plan: crate::planner::CreateTablePlan) -> Result<SqlResult, DatacaveError> {
+        let mut schema = TableSchema::new(
+            plan.table.clone(),
+            plan.columns.clone(),
+            plan.primary_key.clone(),
+        );
+        schema.column_constraints = plan.column_constraints;
+        schema.unique = plan.unique;
+        schema.checks = plan.checks;
+        schema.key_columns = plan.key_columns;
+        // UNIQUE constraints are enforced through a unique index of the same name, whose
+        // shared entries make concurrent duplicates conflict at commit. A primary key the
+        // rows are stored under needs none.
+        let backing: Vec<TableIndex> = schema
+            .unique
+            .iter()
+            .filter(|u| schema.key_columns.is_empty() || u.columns != schema.key_columns)
+            .map(|u| TableIndex {
+                name: u.name.clone(),
+                columns: u.columns.clone(),
+                unique: true,
+                valid_since: Some(0),
+            })
+            .collect();
+        schema.indexes = backing;
+        // The name is claimed in the catalog before the schema is written, so of two
+        // concurrent CREATE TABLEs for it only one gets to write.
+        {
+            let mut catalog = self.catalog.lock().unwrap();
+            for index in &schema.indexes {
+                if catalog.table_of_index(&index.name).is_some()
+                    || catalog.get_table(&index.name).is_some()
+                {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::DUPLICATE_TABLE,
+                        format!("relation \"{}\" already exists", index.name),
+                    ));
+                }
+            }
+            schema.storage_name = catalog.unused_storage_name(&schema.name);
+            catalog.create_table(schema.clone())?;
+        }
+        let written = match encode_schema(&schema) {
+            Ok(encoded) => {
+                let mut batch = WriteBatch::new();
+                batch.put(&catalog_key(&schema.name), &encoded);
+                self.write_now(batch).await.map(|_| ())
+            }
+            Err(err) => Err(err),
         };
-        self.catalog.lock().unwrap().create_table(schema)?;
+        if let Err(err) = written {
+            let _ = self.catalog.lock().unwrap().drop_table(&schema.name);
+            return Err(err);
+        }
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        })
+    }
+
+    /// Applies the operations to a copy of the schema and persists it. Rows are not touched:
+    /// they keep the layout they were written with and are projected when read. Indexes
+    /// dropped with their columns lose their entries.
+    async fn exec_alter_table(
+        &self,
+        plan: AlterTablePlan,
+        txn: &Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        let Some((mut schema, _ddl)) = self.lock_schema(&plan.table).await else {
+            if plan.if_exists {
+                return Ok(SqlResult {
+                    columns: Vec::new(),
+                    rows: Vec::new(),
+                    rows_affected: 0,
+                });
+            }
+            return Err(undefined_table(&plan.table));
+        };
+        let indexes_before: Vec<String> = schema.indexes.iter().map(|i| i.name.clone()).collect();
+        for op in plan.operations {
+            match op {
+                AlterTableOp::AddColumn {
+                    column,
+                    constraints,
+                    if_not_exists,
+                } => {
+                    if if_not_exists && schema.column_index(&column.name).is_some() {
+                        continue;
+                    }
+                    let backfill = constraints.default.clone().unwrap_or(DataValue::Null);
+                    if constraints.not_null
+                        && backfill == DataValue::Null
+                        && self.has_rows(&schema).await?
+                    {
+                        return Err(DatacaveError::sqlstate(
+                            sqlstate::NOT_NULL_VIOLATION,
+                            format!(
+                                "column \"{}\" of relation \"{}\" contains null values",
+                                column.name, schema.name
+                            ),
+                        ));
+                    }
+                    schema.add_column(column, constraints, backfill)?;
+                }
+                AlterTableOp::DropColumn { name, if_exists } => {
+                    if if_exists && schema.column_index(&name).is_none() {
+                        continue;
+                    }
+                    schema.drop_column(&name)?;
+                }
+                AlterTableOp::RenameColumn { from, to } => {
+                    schema.rename_column(&from, &to)?;
+                    for check in schema.checks.iter_mut().filter(|c| c.columns.contains(&to)) {
+                        check.expr = rename_check_column(&check.expr, &from, &to)?;
+                    }
+                }
+                AlterTableOp::RenameTable { to } => {
+                    if self.catalog.lock().unwrap().get_table(&to).is_some() {
+                        return Err(DatacaveError::sqlstate(
+                            sqlstate::DUPLICATE_TABLE,
+                            format!("relation \"{to}\" already exists"),
+                        ));
+                    }
+                    schema.name = to;
+                }
+            }
+        }
+        let mut batch = WriteBatch::new();
+        if schema.name != plan.table {
+            batch.delete(&catalog_key(&plan.table));
+        }
+        batch.put(&catalog_key(&schema.name), &encode_schema(&schema)?);
+        let version = self.write_now(batch).await?;
+        let dropped: Vec<String> = indexes_before
+            .into_iter()
+            .filter(|name| !schema.indexes.iter().any(|i| i.name == *name))
+            .collect();
+        let storage_name = schema.storage_name.clone();
+        self.catalog.lock().unwrap().alter_table(&plan.table, schema)?;
+        self.drop_index_entries(&storage_name, &dropped, version, txn)
+            .await?;
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        })
+    }
+
+    /// Drops tables with their indexes. The schemas go first, so no later statement reaches
+    /// the tables; once every transaction that may still write them has ended, their rows,
+    /// index entries and row-id sequences are deleted for every tenant.
+    async fn exec_drop_table(
+        &self,
+        plan: DropTablePlan,
+        txn: &Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        let done = SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        };
+        // Locked in name order, so two DROPs of the same tables cannot wait for each other.
+        let mut names = plan.names.clone();
+        names.sort();
+        names.dedup();
+        let mut ddl = Vec::new();
+        for name in &names {
+            match self.lock_schema(name).await {
+                Some((_, guard)) => ddl.push(guard),
+                None if plan.if_exists => {}
+                None => return Err(undefined_table(name)),
+            }
+        }
+        let schemas: Vec<TableSchema> = {
+            let mut catalog = self.catalog.lock().unwrap();
+            names
+                .iter()
+                .filter_map(|name| catalog.drop_table(name).ok())
+                .collect()
+        };
+        if schemas.is_empty() {
+            return Ok(done);
+        }
+        let mut batch = WriteBatch::new();
+        for schema in &schemas {
+            batch.delete(&catalog_key(&schema.name));
+        }
+        let version = self.write_now(batch).await?;
+        drop(ddl);
+        self.wait_for_transactions_before(version, txn).await;
+
+        let mut batch = WriteBatch::new();
+        let mut cached = Vec::new();
+        for schema in &schemas {
+            for tenant in self.table_tenants(schema, u64::MAX).await? {
+                let tenant = tenant.as_deref();
+                let (start, end) = table_row_range(&schema.storage_name, tenant);
+                batch.delete_range(&start, &end);
+                batch.delete(&sequence_key(&schema.storage_name, tenant));
+                cached.push(tenant_key(&schema.storage_name, tenant));
+            }
+            delete_prefix(&mut batch, &table_indexes_prefix(&schema.storage_name))?;
+            delete_prefix(&mut batch, &table_tenants_prefix(&schema.storage_name))?;
+        }
+        self.write_now(batch).await?;
+        let mut table_seq = self.table_seq.lock().unwrap();
+        for key in cached {
+            table_seq.remove(&key);
+        }
+        Ok(done)
+    }
+
+    /// Deletes the rows `tenant_id` holds in a table and their index entries, and restarts
+    /// the tenant's row ids. Like other DDL it takes effect at once, outside any transaction.
+    /// Row ids restart only once every transaction that may hold ids reserved before the
+    /// delete has ended, and only if no statement has reserved ids since; one that has keeps
+    /// the old sequence going, so no id is handed out twice.
+    async fn exec_truncate(
+        &self,
+        plan: TruncatePlan,
+        tenant_id: Option<&str>,
+        txn: &Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        let (schema, ddl) = self
+            .lock_schema(&plan.table)
+            .await
+            .ok_or_else(|| undefined_table(&plan.table))?;
+        let cache_key = tenant_key(&schema.storage_name, tenant_id);
+        self.table_seq.lock().unwrap().remove(&cache_key);
+        let mut batch = WriteBatch::new();
+        let (start, end) = table_row_range(&schema.storage_name, tenant_id);
+        batch.delete_range(&start, &end);
+        for index in &schema.indexes {
+            delete_prefix(
+                &mut batch,
+                &index_key_prefix(&schema.storage_name, &index.name, tenant_id),
+            )?;
+        }
+        let version = self.write_now(batch).await?;
+        drop(ddl);
+        self.wait_for_transactions_before(version, txn).await;
+        if !self.table_seq.lock().unwrap().contains_key(&cache_key) {
+            // The next statement derives the sequence from the rows written since.
+            let mut batch = WriteBatch::new();
+            batch.delete(&sequence_key(&schema.storage_name, tenant_id));
+            self.write_now(batch).await?;
+        }
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        })
+    }
+
+    /// Adds an index to a table without blocking its writers. The index is published first
+    /// in a write-only state, in which statements maintain it but do not read it. Once every
+    /// transaction that may have written without it has ended, the rows committed before
+    /// then are backfilled, and the index becomes readable to snapshots that include the
+    /// whole backfill.
+    async fn exec_create_index(
+        &self,
+        plan: CreateIndexPlan,
+        txn: &Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        let done = SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        };
+        let (mut schema, ddl) = self
+            .lock_schema(&plan.table)
+            .await
+            .ok_or_else(|| undefined_table(&plan.table))?;
+        {
+            let catalog = self.catalog.lock().unwrap();
+            if catalog.table_of_index(&plan.name).is_some()
+                || catalog.get_table(&plan.name).is_some()
+            {
+                if plan.if_not_exists {
+                    return Ok(done);
+                }
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::DUPLICATE_TABLE,
+                    format!("relation \"{}\" already exists", plan.name),
+                ));
+            }
+        }
+        for column in &plan.columns {
+            column_position(&schema, column)?;
+        }
+        let index = TableIndex {
+            name: plan.name,
+            columns: plan.columns,
+            unique: plan.unique,
+            valid_since: None,
+        };
+        schema.indexes.push(index.clone());
+        let table = schema.name.clone();
+        let published = self.publish_schema(&table, schema.clone()).await?;
+        // The backfill waits for older transactions, which may be waiting for this lock.
+        drop(ddl);
+        let built = self.build_index(&schema, &index, published, txn).await;
+        // Later statements may have changed the table meanwhile; only the index is updated.
+        let Some((mut schema, _ddl)) = self.lock_schema(&table).await else {
+            return built.map(|_| done);
+        };
+        match built {
+            Ok(valid_since) => {
+                if let Some(built) = schema.indexes.iter_mut().find(|i| i.name == index.name) {
+                    built.valid_since = Some(valid_since);
+                }
+                self.publish_schema(&table, schema).await?;
+                Ok(done)
+            }
+            Err(err) => {
+                schema.indexes.retain(|i| i.name != index.name);
+                let storage_name = schema.storage_name.clone();
+                let version = self.publish_schema(&table, schema).await?;
+                self.drop_index_entries(&storage_name, &[index.name], version, txn)
+                    .await?;
+                Err(err)
+            }
+        }
+    }
+
+    async fn exec_drop_index(
+        &self,
+        plan: DropIndexPlan,
+        txn: &Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        for name in plan.names {
+            let table = self
+                .catalog
+                .lock()
+                .unwrap()
+                .table_of_index(&name)
+                .map(|schema| schema.name.clone());
+            let locked = match table {
+                Some(table) => self.lock_schema(&table).await,
+                None => None,
+            };
+            let Some((mut schema, _ddl)) =
+                locked.filter(|(schema, _)| schema.indexes.iter().any(|i| i.name == name))
+            else {
+                if plan.if_exists {
+                    continue;
+                }
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::UNDEFINED_OBJECT,
+                    format!("index \"{name}\" does not exist"),
+                ));
+            };
+            if schema.unique.iter().any(|u| u.name == name) {
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::DEPENDENT_OBJECTS_STILL_EXIST,
+                    format!(
+                        "cannot drop index {name} because constraint {name} on table {} requires it",
+                        schema.name
+                    ),
+                ));
+            }
+            schema.indexes.retain(|i| i.name != name);
+            let storage_name = schema.storage_name.clone();
+            let table = schema.name.clone();
+            let version = self.publish_schema(&table, schema).await?;
+            self.drop_index_entries(&storage_name, &[name], version, txn)
+                .await?;
+        }
         Ok(SqlResult {
             columns: Vec::new(),
             rows: Vec::new(),
@@ -67,10 +712,223 @@ impl SqlExecutor {
         })
     }
 
+    /// Locks `table` against other schema changes and returns its schema, or `None` if there
+    /// is no such table. Schema changes hold the lock while they read, modify and publish a
+    /// schema, so none of them overwrites a change made since it read.
+    async fn lock_schema(&self, table: &str) -> Option<(TableSchema, OwnedMutexGuard<()>)> {
+        loop {
+            let storage_name = self.catalog.lock().unwrap().get_table(table)?.storage_name.clone();
+            let lock = self
+                .ddl_locks
+                .lock()
+                .unwrap()
+                .entry(storage_name.clone())
+                .or_default()
+                .clone();
+            let guard = lock.lock_owned().await;
+            // The table may have been renamed, dropped or recreated while we waited.
+            let schema = self.catalog.lock().unwrap().get_table(table).cloned()?;
+            if schema.storage_name == storage_name {
+                return Some((schema, guard));
+            }
+        }
+    }
+
+    /// Makes `schema` the schema of `table` and persists it, returning the version it was
+    /// written at. The catalog changes first, so every transaction whose snapshot includes
+    /// that version plans its statements against the new schema.
+    async fn publish_schema(
+        &self,
+        table: &str,
+        schema: TableSchema,
+    ) -> Result<Version, DatacaveError> {
+        let mut batch = WriteBatch::new();
+        batch.put(&catalog_key(table), &encode_schema(&schema)?);
+        self.catalog.lock().unwrap().alter_table(table, schema)?;
+        self.write_now(batch).await
+    }
+
+    /// Waits until every transaction older than `version` has ended, so none planned a
+    /// statement against an earlier schema. `txn`, which runs the schema change, and other
+    /// transactions waiting here do not count, so schema changes never wait for each other.
+    async fn wait_for_transactions_before(&self, version: Version, txn: &Transaction) {
+        self.schema_changes.lock().unwrap().push(txn.snapshot.version);
+        loop {
+            // Registered before the count, so a snapshot ending in between still wakes us.
+            let ended = self.snapshot_ended.notified();
+            let exempt = self
+                .schema_changes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|v| **v < version)
+                .count();
+            if self.mvcc.active_before(version) <= exempt {
+                break;
+            }
+            ended.await;
+        }
+        let mut schema_changes = self.schema_changes.lock().unwrap();
+        if let Some(pos) = schema_changes.iter().position(|v| *v == txn.snapshot.version) {
+            schema_changes.remove(pos);
+        }
+    }
+
+    /// Backfills `index`, published in `schema` at version `published`, and returns the
+    /// version from which it is complete. A unique index is then checked over the whole
+    /// table, since rows written during the backfill were checked against a partial index:
+    /// rows with equal values share an entry, which maps to only one of them.
+    async fn build_index(
+        &self,
+        schema: &TableSchema,
+        index: &TableIndex,
+        published: Version,
+        txn: &Transaction,
+    ) -> Result<Version, DatacaveError> {
+        self.wait_for_transactions_before(published, txn).await;
+        let snapshot = self.mvcc.begin();
+        let backfilled = self.backfill_index(schema, index, snapshot).await;
+        self.end_snapshot(snapshot);
+        let valid_since = backfilled?.max(published);
+        if !index.unique {
+            return Ok(valid_since);
+        }
+        for tenant in self.table_tenants(schema, valid_since).await? {
+            let tenant = tenant.as_deref();
+            let mut after = None;
+            loop {
+                let rows = self
+                    .table_rows_chunk(schema, tenant, after.as_deref(), valid_since)
+                    .await?;
+                for (key, values) in &rows {
+                    let (entry, _) = index_entry(schema, index, values, key, tenant);
+                    let holder = self
+                        .storage
+                        .get(&entry, valid_since)
+                        .await
+                        .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+                    if holder.as_deref() != Some(key.as_slice()) {
+                        return Err(DatacaveError::sqlstate(
+                            sqlstate::UNIQUE_VIOLATION,
+                            format!("could not create unique index \"{}\"", index.name),
+                        ));
+                    }
+                }
+                match rows.into_iter().last() {
+                    Some((key, _)) => after = Some(key),
+                    None => break,
+                }
+            }
+        }
+        Ok(valid_since)
+    }
+
+    /// Writes `index`'s entries for the rows in `snapshot`, in batches committed against
+    /// it, and returns the version of the last batch. An entry a statement wrote or deleted
+    /// after the snapshot conflicts and is left out: that statement maintained the index for
+    /// the row itself.
+    async fn backfill_index(
+        &self,
+        schema: &TableSchema,
+        index: &TableIndex,
+        snapshot: Snapshot,
+    ) -> Result<Version, DatacaveError> {
+        let mut last = snapshot.version;
+        for tenant in self.table_tenants(schema, snapshot.version).await? {
+            let tenant = tenant.as_deref();
+            let mut after = None;
+            loop {
+                let rows = self
+                    .table_rows_chunk(schema, tenant, after.as_deref(), snapshot.version)
+                    .await?;
+                let mut pending: Vec<(Vec<u8>, Vec<u8>)> = rows
+                    .iter()
+                    .map(|(key, values)| index_entry(schema, index, values, key, tenant))
+                    .collect();
+                match rows.into_iter().last() {
+                    Some((key, _)) => after = Some(key),
+                    None => break,
+                }
+                let version = loop {
+                    let keys = pending.iter().map(|(key, _)| key.as_slice());
+                    match self.mvcc.commit(Some(snapshot), keys) {
+                        Ok(version) => break version,
+                        Err(CommitConflict::WriteWrite(key)) => {
+                            pending.retain(|(k, _)| *k != key)
+                        }
+                        Err(conflict) => return Err(serialization_failure(conflict)),
+                    }
+                };
+                let mut batch = WriteBatch::new();
+                for (key, value) in &pending {
+                    batch.put(key, value);
+                }
+                let written = self
+                    .storage
+                    .write_batch(with_history_entry(batch, version), version)
+                    .await;
+                self.mvcc.publish(version);
+                written.map_err(|e| DatacaveError::Storage(e.to_string()))?;
+                last = version;
+            }
+        }
+        Ok(last)
+    }
+
+    /// Deletes the entries of the indexes `names` of the table stored as `storage_name`, once
+    /// they were dropped from its schema at `version` and no transaction that may still
+    /// write them remains.
+    async fn drop_index_entries(
+        &self,
+        storage_name: &str,
+        names: &[String],
+        version: Version,
+        txn: &Transaction,
+    ) -> Result<(), DatacaveError> {
+        if names.is_empty() {
+            return Ok(());
+        }
+        self.wait_for_transactions_before(version, txn).await;
+        let mut batch = WriteBatch::new();
+        for name in names {
+            delete_prefix(&mut batch, &index_prefix(storage_name, name))?;
+        }
+        self.write_now(batch).await?;
+        Ok(())
+    }
+
+    /// Up to [`BACKFILL_BATCH_ENTRIES`] live rows `tenant_id` holds in `schema`'s table at
+    /// `version`, in key order after the row key `after`: each row's storage key and values
+    /// projected onto the current columns.
+    async fn table_rows_chunk(
+        &self,
+        schema: &TableSchema,
+        tenant_id: Option<&str>,
+        after: Option<&[u8]>,
+        version: Version,
+    ) -> Result<Vec<(Vec<u8>, Vec<DataValue>)>, DatacaveError> {
+        let (mut start, end) = table_row_range(&schema.storage_name, tenant_id);
+        let prefix_len = start.len();
+        if let Some(after) = after {
+            start = after.to_vec();
+            start.push(0);
+        }
+        let entries = self
+            .storage
+            .scan_range_limit(&start, &end, version, BACKFILL_BATCH_ENTRIES)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        Ok(project_rows(schema, prefix_len, entries)?
+            .into_iter()
+            .map(|(key, row)| (key, row.values))
+            .collect())
+    }
+
     async fn exec_insert(
         &self,
         plan: crate::planner::InsertPlan,
         tenant_id: Option<&str>,
+        txn: &mut Transaction,
     ) -> Result<SqlResult, DatacaveError> {
         let schema = self
             .catalog
@@ -78,21 +936,70 @@ impl SqlExecutor {
             .unwrap()
             .get_table(&plan.table)
             .cloned()
-            .ok_or_else(|| DatacaveError::Sql(format!("unknown table: {}", plan.table)))?;
+            .ok_or_else(|| undefined_table(&plan.table))?;
 
-        let mut rows_affected = 0;
-        for row in plan.values {
-            let values = align_columns(&schema.columns, &plan.columns, row);
-            let row_id = self.reserve_row_id(&plan.table, tenant_id);
-            let key = encode_row_key(&plan.table, row_id, tenant_id);
-            let value = bincode::serialize(&DataRow { values })
-                .map_err(|e| DatacaveError::Storage(e.to_string()))?;
-            let version = self.mvcc.next_version();
-            self.storage
-                .put(&key, &value, version)
-                .await
-                .map_err(|e| DatacaveError::Storage(e.to_string()))?;
-            rows_affected += 1;
+        let rows = plan
+            .values
+            .into_iter()
+            .map(|row| align_columns(&schema, &plan.columns, row))
+            .collect::<Result<Vec<_>, _>>()?;
+        let checks = compile_checks(&schema)?;
+        for values in &rows {
+            validate_row(&schema, &checks, values)?;
+        }
+        // The primary key of a keyed table is checked by point lookups below, and other
+        // constraints through their unique indexes.
+        let scanned_unique = scanned_unique(&schema);
+        if !scanned_unique.is_empty() {
+            let existing = self.fetch_table_rows(&schema, tenant_id, txn).await?;
+            check_unique(
+                &schema,
+                scanned_unique.into_iter(),
+                existing.iter().map(|row| &row.values).chain(rows.iter()),
+            )?;
+        }
+        let rows_affected = rows.len() as u64;
+        let mut keyed = Vec::with_capacity(rows.len());
+        if schema.key_columns.is_empty() {
+            let first_id = self
+                .reserve_row_ids(&schema, tenant_id, rows_affected)
+                .await?;
+            for (row_id, values) in (first_id..).zip(rows) {
+                keyed.push((encode_row_key(&schema.storage_name, row_id, tenant_id), values));
+            }
+            // The rows and the advanced sequence are written together, so a crash can never
+            // leave stored rows above the persisted sequence.
+            txn.stage_unchecked(
+                &sequence_key(&schema.storage_name, tenant_id),
+                &(first_id + rows_affected).to_be_bytes(),
+            );
+        } else {
+            let mut seen = HashSet::new();
+            for values in rows {
+                let key = primary_row_key(&schema, &values, tenant_id);
+                let stored = self.read_key(&key, txn).await?;
+                if stored.is_some() || !seen.insert(key.clone()) {
+                    return Err(primary_key_violation(&schema));
+                }
+                keyed.push((key, values));
+            }
+        }
+        let entries: Vec<_> = keyed
+            .iter()
+            .flat_map(|(key, values)| row_index_entries(&schema, values, key, tenant_id))
+            .collect();
+        self.check_unique_indexes(&entries, &HashSet::new(), txn)
+            .await?;
+        let mut batch = WriteBatch::new();
+        for (_, key, value) in &entries {
+            batch.put(key, value);
+        }
+        for (key, values) in keyed {
+            batch.put(&key, &encode_table_row(&schema, values)?);
+        }
+        txn.stage(batch);
+        if let Some(tenant) = tenant_id {
+            txn.stage_unchecked(&table_tenant_key(&schema.storage_name, tenant), &[]);
         }
         Ok(SqlResult {
             columns: Vec::new(),
@@ -117,7 +1024,67 @@ impl SqlExecutor {
         })
     }
 
-    fn exec_rollback(&self) -> Result<SqlResult, DatacaveError> {
+    /// A plain ROLLBACK ends the transaction through [`Self::rollback`]; here only
+    /// `ROLLBACK TO SAVEPOINT` has an effect.
+    fn exec_rollback(
+        &self,
+        plan: RollbackPlan,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        if let Some(name) = plan.savepoint {
+            if !txn.rollback_to_savepoint(&name) {
+                return Err(no_such_savepoint(&name));
+            }
+        }
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        })
+    }
+
+    fn exec_set_transaction(
+        &self,
+        plan: SetTransactionPlan,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        if let Some(isolation) = plan.isolation {
+            if txn.started && isolation != txn.isolation {
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::ACTIVE_SQL_TRANSACTION,
+                    "SET TRANSACTION ISOLATION LEVEL must be called before any query",
+                ));
+            }
+            txn.isolation = isolation;
+        }
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        })
+    }
+
+    fn exec_savepoint(
+        &self,
+        plan: SavepointPlan,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        txn.savepoint(plan.name);
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        })
+    }
+
+    fn exec_release_savepoint(
+        &self,
+        plan: ReleaseSavepointPlan,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        if !txn.release_savepoint(&plan.name) {
+            return Err(no_such_savepoint(&plan.name));
+        }
         Ok(SqlResult {
             columns: Vec::new(),
             rows: Vec::new(),
@@ -129,79 +1096,319 @@ impl SqlExecutor {
         &self,
         plan: crate::planner::SelectPlan,
         tenant_id: Option<&str>,
+        txn: &mut Transaction,
     ) -> Result<SqlResult, DatacaveError> {
-        let snapshot = self.mvcc.snapshot();
-
+        let has_aggregates = plan.is_aggregate();
+        if plan.lock.is_some() && (!plan.joins.is_empty() || has_aggregates) {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::FEATURE_NOT_SUPPORTED,
+                "FOR UPDATE and FOR SHARE are only supported on single-table queries without aggregates",
+            ));
+        }
         if !plan.joins.is_empty() {
-            return self.exec_select_join(&plan, tenant_id, snapshot.version).await;
+            return self.exec_select_join(&plan, tenant_id, txn).await;
+        }
+
+        let schema = self
+            .catalog
+            .lock()
+            .unwrap()
+            .get_table(&plan.table)
+            .cloned()
+            .ok_or_else(|| undefined_table(&plan.table))?;
+        let mut scanned = self
+            .scan_table_where(&schema, tenant_id, txn, plan.where_clause.as_ref())
+            .await?;
+        if let Some(cond) = plan.where_clause.as_ref() {
+            let mut matching = Vec::with_capacity(scanned.len());
+            for (key, row) in scanned {
+                if evaluate_where(cond, &row, &schema.columns)? {
+                    matching.push((key, row));
+                }
+            }
+            scanned = matching;
+        }
+        let (keys, rows): (Vec<Vec<u8>>, Vec<DataRow>) = scanned.into_iter().unzip();
+
+        let (columns, result_rows) = if has_aggregates {
+            compute_grouped_aggregates(
+                &plan.projection,
+                &plan.group_by,
+                &schema.columns,
+                &rows,
+                plan.having.as_ref(),
+            )?
+        } else {
+            apply_projection(&plan.projection, &schema.columns, &rows)?
+        };
+        let result_rows = match plan.lock {
+            Some(lock) => {
+                let candidates = keys.into_iter().zip(result_rows).collect();
+                self.lock_rows(&schema.name, &plan, lock, &columns, candidates, txn)
+                    .await?
+            }
+            None => result_rows,
+        };
+
+        let rows = apply_order_limit(
+            &columns,
+            result_rows,
+            &plan.order_by,
+            plan.limit,
+            plan.offset,
+        )?;
+        Ok(SqlResult {
+            columns,
+            rows,
+            rows_affected: 0,
+        })
+    }
+
+    /// Locks the rows of a `FOR UPDATE` / `FOR SHARE` query in result order, stopping once
+    /// `plan`'s OFFSET and LIMIT are covered, and returns the rows it locked. `candidates`
+    /// are the projected rows with their storage keys. A row another transaction changed
+    /// after `txn`'s snapshot cannot be locked as read, and fails the statement.
+    async fn lock_rows(
+        &self,
+        table: &str,
+        plan: &crate::planner::SelectPlan,
+        lock: RowLock,
+        columns: &[Column],
+        candidates: Vec<(Vec<u8>, DataRow)>,
+        txn: &mut Transaction,
+    ) -> Result<Vec<DataRow>, DatacaveError> {
+        let candidates = sort_rows(columns, &plan.order_by, candidates, |(_, row)| row)?;
+        let wanted = match plan.limit {
+            Some(limit) => plan.offset.unwrap_or(0).saturating_add(limit) as usize,
+            None => usize::MAX,
+        };
+        let mut locked = Vec::new();
+        for (key, row) in candidates {
+            if locked.len() >= wanted {
+                break;
+            }
+            if !self.locks.lock(txn.id, table, &key, lock).await? {
+                continue;
+            }
+            if self.mvcc.written_since(txn.snapshot, &key) {
+                return Err(serialization_failure(CommitConflict::WriteWrite(key)));
+            }
+            locked.push(row);
+        }
+        Ok(locked)
+    }
+
+    /// Locks row `key` of `table` exclusively for an UPDATE or DELETE of `txn`, waiting
+    /// for other transactions that lock or write it. Like a locking read, it fails the
+    /// statement when the row changed after `txn`'s snapshot.
+    async fn lock_for_write(
+        &self,
+        table: &str,
+        key: &[u8],
+        txn: &Transaction,
+    ) -> Result<(), DatacaveError> {
+        let lock = RowLock {
+            mode: LockMode::Update,
+            wait: LockWait::Block,
+        };
+        self.locks.lock(txn.id, table, key, lock).await?;
+        if self.mvcc.written_since(txn.snapshot, key) {
+            return Err(serialization_failure(CommitConflict::WriteWrite(key.to_vec())));
+        }
+        Ok(())
+    }
+
+    /// Runs a time-travel query: every table is read at the version `as_of` resolves to,
+    /// without `txn`'s staged writes.
+    async fn exec_select_as_of(
+        &self,
+        plan: crate::planner::SelectPlan,
+        as_of: &AsOf,
+        tenant_id: Option<&str>,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        if plan.lock.is_some() {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::FEATURE_NOT_SUPPORTED,
+                "FOR UPDATE and FOR SHARE cannot be used with AS OF",
+            ));
+        }
+        let version = self.resolve_as_of(as_of).await?;
+        let tables = std::iter::once(&plan.table).chain(plan.joins.iter().map(|join| &join.right_table));
+        for table in tables {
+            self.check_schema_at(table, version).await?;
         }
+        let snapshot = Snapshot { version };
+        let mut historical = Transaction::new(txn.id, snapshot, IsolationLevel::Snapshot);
+        self.exec_select(plan, tenant_id, &mut historical).await
+    }
 
-        let rows = self
-            .fetch_table_rows(&plan.table, tenant_id, snapshot.version)
-            .await?;
-        let schema = self
-            .catalog
-            .lock()
-            .unwrap()
-            .get_table(&plan.table)
-            .cloned()
-            .ok_or_else(|| DatacaveError::Sql(format!("unknown table: {}", plan.table)))?;
+    /// Fails unless `table` already had its current row layout at `version`: old rows are
+    /// read with the current schema, which cannot show columns as they were before an
+    /// ALTER TABLE.
+    async fn check_schema_at(&self, table: &str, version: Version) -> Result<(), DatacaveError> {
+        let Some(current) = self.catalog.lock().unwrap().get_table(table).map(|schema| schema.version) else {
+            return Err(undefined_table(table));
+        };
+        let stored = self
+            .storage
+            .get(&catalog_key(table), version)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let Some(bytes) = stored else {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_TABLE,
+                format!("relation \"{table}\" does not exist at version {version}"),
+            ));
+        };
+        if decode_schema(&bytes)?.version != current {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::FEATURE_NOT_SUPPORTED,
+                format!(
+                    "AS OF cannot read \"{table}\" at version {version}, before its last ALTER TABLE"
+                ),
+            ));
+        }
+        Ok(())
+    }
 
-        let rows = filter_rows_by_where(rows, plan.where_clause.as_ref(), &schema.columns);
+    /// The version an `AS OF` clause reads at. Fails for versions not yet committed and for
+    /// points in time outside the retention window or before history was first recorded.
+    async fn resolve_as_of(&self, as_of: &AsOf) -> Result<Version, DatacaveError> {
+        let out_of_range = |message: String| {
+            DatacaveError::sqlstate(sqlstate::INVALID_PARAMETER_VALUE, message)
+        };
+        let version = match as_of {
+            AsOf::Version(version) => *version,
+            AsOf::Timestamp(text) => {
+                let millis = parse_timestamp(text)?;
+                let now = now_millis();
+                if millis > now {
+                    return Err(out_of_range(format!(
+                        "AS OF SYSTEM TIME '{text}' is in the future"
+                    )));
+                }
+                if let Some(retention) = self.history_retention {
+                    if millis < now.saturating_sub(retention.as_millis() as u64) {
+                        return Err(out_of_range(format!(
+                            "AS OF SYSTEM TIME '{text}' is older than the {}s history retention",
+                            retention.as_secs()
+                        )));
+                    }
+                }
+                self.history_entry_at(millis)
+                    .await?
+                    .map(|(_, version)| version)
+                    .ok_or_else(|| {
+                        out_of_range(format!("no history is recorded at or before '{text}'"))
+                    })?
+            }
+        };
+        let visible = self.mvcc.snapshot().version;
+        if version > visible {
+            return Err(out_of_range(format!(
+                "version {version} is newer than the latest committed version {visible}"
+            )));
+        }
+        let horizon = self.history_horizon(visible).await?;
+        if version < horizon {
+            return Err(out_of_range(format!(
+                "version {version} is older than the retained history, which starts at {horizon}"
+            )));
+        }
+        Ok(version)
+    }
 
-        let has_aggregates = plan
-            .projection
-            .iter()
-            .any(|p| matches!(p, ProjectionItem::Aggregate(_, _, _)));
-        let (columns, result_rows) = if has_aggregates {
-            if plan.group_by.is_empty() {
-                let (cols, agg_row) =
-                    compute_aggregates(&plan.projection, &schema.columns, &rows)?;
-                (cols, vec![agg_row])
-            } else {
-                compute_grouped_aggregates(
-                    &plan.projection,
-                    &plan.group_by,
-                    &schema.columns,
-                    &rows,
-                    plan.having.as_ref(),
-                )?
+    /// The newest history entry at or before `millis`: its key and the version it maps to.
+    /// The search window widens from a second back until an entry turns up.
+    async fn history_entry_at(
+        &self,
+        millis: u64,
+    ) -> Result<Option<(Vec<u8>, Version)>, DatacaveError> {
+        let visible = self.mvcc.snapshot().version;
+        let end = history_key(millis.saturating_add(1));
+        let mut span: u64 = 1000;
+        loop {
+            let from = millis.saturating_sub(span);
+            let entries = self
+                .storage
+                .scan_range(&history_key(from), &end, visible)
+                .await
+                .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+            if let Some((key, value)) = entries.into_iter().last() {
+                return Ok(Some((key, decode_version(&value)?)));
             }
-        } else {
-            apply_projection(&plan.projection, &schema.columns, &rows)
+            if from == 0 {
+                return Ok(None);
+            }
+            span = span.saturating_mul(16);
+        }
+    }
+
+    /// Moves the history horizon up to the oldest version a time-travel query within the
+    /// retention window or an open transaction can read, so compaction may drop older
+    /// versions, and forgets the commit times that mapped below it. Returns the horizon,
+    /// which stays 0 without a retention.
+    pub async fn collect_history(&self) -> Result<Version, DatacaveError> {
+        let Some(retention) = self.history_retention else {
+            return Ok(0);
         };
+        let visible = self.mvcc.snapshot().version;
+        let stored = self.history_horizon(visible).await?;
+        let boundary = now_millis().saturating_sub(retention.as_millis() as u64);
+        let Some((boundary_key, boundary_version)) = self.history_entry_at(boundary).await?
+        else {
+            return Ok(stored);
+        };
+        let oldest_reader = self.mvcc.oldest_active().unwrap_or(boundary_version);
+        let horizon = boundary_version.min(oldest_reader).max(stored);
+        let stale = self
+            .storage
+            .scan_range(HISTORY_KEY_PREFIX, &boundary_key, visible)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let mut batch = WriteBatch::new();
+        for (key, _) in &stale {
+            batch.delete(key);
+        }
+        if horizon > stored {
+            batch.put(HISTORY_HORIZON_KEY, &horizon.to_be_bytes());
+        }
+        if !batch.is_empty() {
+            self.write_now(batch).await?;
+        }
+        self.storage.set_history_horizon(horizon);
+        Ok(horizon)
+    }
 
-        let rows = apply_order_limit(
-            &columns,
-            result_rows,
-            &plan.order_by,
-            plan.limit,
-            plan.offset,
-        )?;
-        Ok(SqlResult {
-            columns,
-            rows,
-            rows_affected: 0,
-        })
+    /// Oldest version time-travel queries may read, as of the snapshot at `visible`.
+    async fn history_horizon(&self, visible: Version) -> Result<Version, DatacaveError> {
+        self.storage
+            .get(HISTORY_HORIZON_KEY, visible)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?
+            .map(|bytes| decode_version(&bytes))
+            .transpose()
+            .map(Option::unwrap_or_default)
     }
 
     async fn exec_select_join(
         &self,
         plan: &crate::planner::SelectPlan,
         tenant_id: Option<&str>,
-        version: u64,
+        txn: &mut Transaction,
     ) -> Result<SqlResult, DatacaveError> {
         let base_schema = {
             let catalog = self.catalog.lock().unwrap();
             catalog
                 .get_table(&plan.table)
                 .cloned()
-                .ok_or_else(|| DatacaveError::Sql(format!("unknown table: {}", plan.table)))?
+                .ok_or_else(|| undefined_table(&plan.table))?
         };
 
         let mut columns = qualify_columns(&base_schema.columns, &plan.table);
         let mut joined_rows = self
-            .fetch_table_rows(&plan.table, tenant_id, version)
+            .fetch_table_rows(&base_schema, tenant_id, txn)
             .await?;
 
         for join in &plan.joins {
@@ -210,11 +1417,11 @@ impl SqlExecutor {
                 catalog
                     .get_table(&join.right_table)
                     .cloned()
-                    .ok_or_else(|| DatacaveError::Sql(format!("unknown table: {}", join.right_table)))?
+                    .ok_or_else(|| undefined_table(&join.right_table))?
             };
 
             let right_rows = self
-                .fetch_table_rows(&join.right_table, tenant_id, version)
+                .fetch_table_rows(&right_schema, tenant_id, txn)
                 .await?;
 
             let left_col_idx = resolve_column_index(&columns, &join.left_column)
@@ -264,28 +1471,18 @@ impl SqlExecutor {
             joined_rows = new_rows;
         }
 
-        let joined_rows = filter_rows_by_where(joined_rows, plan.where_clause.as_ref(), &columns);
+        let joined_rows = filter_rows_by_where(joined_rows, plan.where_clause.as_ref(), &columns)?;
 
-        let has_aggregates = plan
-            .projection
-            .iter()
-            .any(|p| matches!(p, ProjectionItem::Aggregate(_, _, _)));
-        let (out_columns, result_rows) = if has_aggregates {
-            if plan.group_by.is_empty() {
-                let (cols, agg_row) =
-                    compute_aggregates(&plan.projection, &columns, &joined_rows)?;
-                (cols, vec![agg_row])
-            } else {
-                compute_grouped_aggregates(
-                    &plan.projection,
-                    &plan.group_by,
-                    &columns,
-                    &joined_rows,
-                    plan.having.as_ref(),
-                )?
-            }
+        let (out_columns, result_rows) = if plan.is_aggregate() {
+            compute_grouped_aggregates(
+                &plan.projection,
+                &plan.group_by,
+                &columns,
+                &joined_rows,
+                plan.having.as_ref(),
+            )?
         } else {
-            apply_projection(&plan.projection, &columns, &joined_rows)
+            apply_projection(&plan.projection, &columns, &joined_rows)?
         };
 
         let rows = apply_order_limit(
@@ -304,32 +1501,132 @@ impl SqlExecutor {
 
     async fn fetch_table_rows(
         &self,
-        table: &str,
+        schema: &TableSchema,
         tenant_id: Option<&str>,
-        version: u64,
+        txn: &mut Transaction,
     ) -> Result<Vec<DataRow>, DatacaveError> {
+        Ok(self
+            .scan_table(schema, tenant_id, txn)
+            .await?
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect())
+    }
+
+    /// Live rows of `schema`'s table in `txn`'s snapshot with their storage keys, in key
+    /// order and projected onto the current columns.
+    async fn scan_table(
+        &self,
+        schema: &TableSchema,
+        tenant_id: Option<&str>,
+        txn: &mut Transaction,
+    ) -> Result<Vec<(Vec<u8>, DataRow)>, DatacaveError> {
+        let prefix = table_key_prefix(&schema.storage_name, tenant_id);
+        let entries = self.read_range(KeyRange::prefix(&prefix), txn).await?;
+        project_rows(schema, prefix.len(), entries)
+    }
+
+    /// Like [`Self::scan_table`], but only reads the rows `cond` can match on the primary
+    /// key of a table keyed by it, or else on one of its indexes. Callers still filter by
+    /// `cond`.
+    async fn scan_table_where(
+        &self,
+        schema: &TableSchema,
+        tenant_id: Option<&str>,
+        txn: &mut Transaction,
+        cond: Option<&WhereCond>,
+    ) -> Result<Vec<(Vec<u8>, DataRow)>, DatacaveError> {
+        let prefix = table_key_prefix(&schema.storage_name, tenant_id);
+        let entries = match plan_key_access(schema, cond) {
+            KeyAccess::Full => {
+                return match plan_index_access(schema, cond, txn.snapshot.version) {
+                    Some((index, access)) => {
+                        self.scan_index(schema, index, access, tenant_id, txn).await
+                    }
+                    None => self.scan_table(schema, tenant_id, txn).await,
+                };
+            }
+            KeyAccess::Point(key) => {
+                let mut full_key = prefix.clone();
+                encode_key(&key, &mut full_key);
+                self.read_key(&full_key, txn)
+                    .await?
+                    .map(|value| (full_key, value))
+                    .into_iter()
+                    .collect()
+            }
+            access => match access_range(&prefix, access)? {
+                Some(range) => self.read_range(range, txn).await?,
+                None => return Ok(Vec::new()),
+            },
+        };
+        project_rows(schema, prefix.len(), entries)
+    }
+
+    /// Rows of `schema`'s table whose entries in `index` fall in `access`, in index order.
+    async fn scan_index(
+        &self,
+        schema: &TableSchema,
+        index: &TableIndex,
+        access: KeyAccess,
+        tenant_id: Option<&str>,
+        txn: &mut Transaction,
+    ) -> Result<Vec<(Vec<u8>, DataRow)>, DatacaveError> {
+        let base = index_key_prefix(&schema.storage_name, &index.name, tenant_id);
+        let Some(range) = access_range(&base, access)? else {
+            return Ok(Vec::new());
+        };
         let mut rows = Vec::new();
-        let max_row_id = self.current_row_count(table, tenant_id);
-        for row_id in 0..max_row_id {
-            let key = encode_row_key(table, row_id, tenant_id);
-            if let Some(bytes) = self
-                .storage
-                .get(&key, version)
-                .await
-                .map_err(|e| DatacaveError::Storage(e.to_string()))?
-            {
-                let row: DataRow =
-                    bincode::deserialize(&bytes).map_err(|e| DatacaveError::Storage(e.to_string()))?;
-                rows.push(row);
+        for (_, row_key) in self.read_range(range, txn).await? {
+            if let Some(value) = self.read_key(&row_key, txn).await? {
+                rows.push((row_key, value));
             }
         }
-        Ok(rows)
+        let prefix = table_key_prefix(&schema.storage_name, tenant_id);
+        project_rows(schema, prefix.len(), rows)
+    }
+
+    /// Value of `key` in `txn`: its own staged write if it has one, otherwise the value
+    /// in its snapshot.
+    async fn read_key(
+        &self,
+        key: &[u8],
+        txn: &mut Transaction,
+    ) -> Result<Option<Vec<u8>>, DatacaveError> {
+        txn.record_read(KeyRange::point(key));
+        if let Some(staged) = txn.staged(key) {
+            return Ok(staged.map(<[u8]>::to_vec));
+        }
+        self.storage
+            .get(key, txn.snapshot.version)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))
+    }
+
+    /// Entries of `range` in `txn`'s snapshot with its staged writes applied, in key order.
+    async fn read_range(
+        &self,
+        range: KeyRange,
+        txn: &mut Transaction,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, DatacaveError> {
+        let version = txn.snapshot.version;
+        // A range without an end only comes from a prefix of 0xFF bytes, where the prefix
+        // scan covers the same keys.
+        let entries = match &range.end {
+            Some(end) => self.storage.scan_range(&range.start, end, version).await,
+            None => self.storage.scan(&range.start, version).await,
+        }
+        .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let entries = txn.overlay(entries, &range);
+        txn.record_read(range);
+        Ok(entries)
     }
 
     async fn exec_update(
         &self,
         plan: crate::planner::UpdatePlan,
         tenant_id: Option<&str>,
+        txn: &mut Transaction,
     ) -> Result<SqlResult, DatacaveError> {
         let schema = self
             .catalog
@@ -337,42 +1634,169 @@ impl SqlExecutor {
             .unwrap()
             .get_table(&plan.table)
             .cloned()
-            .ok_or_else(|| DatacaveError::Sql(format!("unknown table: {}", plan.table)))?;
-        let mut rows_affected = 0;
-        let snapshot = self.mvcc.snapshot();
-        let max_row_id = self.current_row_count(&plan.table, tenant_id);
-        for row_id in 0..max_row_id {
-            let key = encode_row_key(&plan.table, row_id, tenant_id);
-            if let Some(bytes) = self
-                .storage
-                .get(&key, snapshot.version)
-                .await
-                .map_err(|e| DatacaveError::Storage(e.to_string()))?
-            {
-                let mut row: DataRow =
-                    bincode::deserialize(&bytes).map_err(|e| DatacaveError::Storage(e.to_string()))?;
-                if let Some(ref cond) = plan.where_clause {
-                    if !evaluate_where(cond, &row, &schema.columns) {
-                        continue;
-                    }
+            .ok_or_else(|| undefined_table(&plan.table))?;
+        // Values that read no column are computed and coerced once, so they are checked
+        // even when no row matches.
+        let assignments = plan
+            .assignments
+            .iter()
+            .map(|(col, expr)| {
+                let idx = column_position(&schema, col)?;
+                expr.check_columns(&schema.columns)?;
+                let mut read = Vec::new();
+                expr.collect_columns(&mut read);
+                let constant = if read.is_empty() {
+                    let column = &schema.columns[idx];
+                    Some(column.data_type.coerce(&column.name, expr.eval(&schema.columns, &[])?)?)
+                } else {
+                    None
+                };
+                Ok((idx, expr, constant))
+            })
+            .collect::<Result<Vec<_>, DatacaveError>>()?;
+        let checks = compile_checks(&schema)?;
+        let mut rows = self
+            .scan_table_where(&schema, tenant_id, txn, plan.where_clause.as_ref())
+            .await?;
+        // Every updated row is validated, and uniqueness checked over the resulting table,
+        // before anything is written, so a violation leaves the table untouched.
+        let mut updated = Vec::new();
+        let mut previous = Vec::new();
+        for (pos, (_, row)) in rows.iter_mut().enumerate() {
+            if let Some(ref cond) = plan.where_clause {
+                if !evaluate_where(cond, row, &schema.columns)? {
+                    continue;
                 }
-                for (col, val) in &plan.assignments {
-                    if let Some(idx) = schema.columns.iter().position(|c| c.name == *col) {
-                        if idx < row.values.len() {
-                            row.values[idx] = val.clone();
-                        }
+            }
+            // Every SET expression reads the row as it was before the update.
+            let values = assignments
+                .iter()
+                .map(|(idx, expr, constant)| match constant {
+                    Some(value) => Ok((*idx, value.clone())),
+                    None => {
+                        let column = &schema.columns[*idx];
+                        let value = expr.eval(&schema.columns, &row.values)?;
+                        Ok((*idx, column.data_type.coerce(&column.name, value)?))
                     }
+                })
+                .collect::<Result<Vec<_>, DatacaveError>>()?;
+            previous.push(row.values.clone());
+            for (idx, value) in values {
+                if idx < row.values.len() {
+                    row.values[idx] = value;
+                }
+            }
+            validate_row(&schema, &checks, &row.values)?;
+            updated.push(pos);
+        }
+        let scanned_unique = scanned_unique(&schema);
+        if !updated.is_empty() && !scanned_unique.is_empty() {
+            let mut table = self.scan_table(&schema, tenant_id, txn).await?;
+            let changed: HashMap<&[u8], &DataRow> = updated
+                .iter()
+                .map(|&pos| (rows[pos].0.as_slice(), &rows[pos].1))
+                .collect();
+            for (key, row) in &mut table {
+                if let Some(new_row) = changed.get(key.as_slice()) {
+                    *row = (*new_row).clone();
+                }
+            }
+            check_unique(
+                &schema,
+                scanned_unique.into_iter(),
+                table.iter().map(|(_, row)| &row.values),
+            )?;
+        }
+        let leaving: HashSet<Vec<u8>> = updated.iter().map(|&pos| rows[pos].0.clone()).collect();
+        for &pos in &updated {
+            self.lock_for_write(&schema.name, &rows[pos].0, txn).await?;
+        }
+        // Rows whose primary key changed move to their new key. Deletes go first so a row
+        // may take over a key, or an index entry, another row of this statement leaves.
+        let mut batch = WriteBatch::new();
+        let mut puts = Vec::with_capacity(updated.len());
+        let mut old_entries = Vec::new();
+        let mut new_entries = Vec::new();
+        let mut claimed = HashSet::new();
+        for (&pos, old_values) in updated.iter().zip(&previous) {
+            let (key, row) = &rows[pos];
+            let new_key = if schema.key_columns.is_empty() {
+                key.clone()
+            } else {
+                primary_row_key(&schema, &row.values, tenant_id)
+            };
+            if !claimed.insert(new_key.clone()) {
+                return Err(primary_key_violation(&schema));
+            }
+            if new_key != *key {
+                if !leaving.contains(&new_key) && self.read_key(&new_key, txn).await?.is_some() {
+                    return Err(primary_key_violation(&schema));
+                }
+                batch.delete(key);
+            }
+            old_entries.extend(row_index_entries(&schema, old_values, key, tenant_id));
+            new_entries.extend(row_index_entries(&schema, &row.values, &new_key, tenant_id));
+            puts.push((new_key, encode_table_row(&schema, row.values.clone())?));
+        }
+        self.check_unique_indexes(&new_entries, &leaving, txn)
+            .await?;
+        // Entries a row keeps are left alone.
+        let (old_set, new_set) = (entry_set(&old_entries), entry_set(&new_entries));
+        for (_, key, value) in &old_entries {
+            if !new_set.contains(&(key.as_slice(), value.as_slice())) {
+                batch.delete(key);
+            }
+        }
+        for (_, key, value) in &new_entries {
+            if !old_set.contains(&(key.as_slice(), value.as_slice())) {
+                puts.push((key.clone(), value.clone()));
+            }
+        }
+        for (key, value) in puts {
+            batch.put(&key, &value);
+        }
+        let rows_affected = updated.len() as u64;
+        txn.stage(batch);
+        Ok(SqlResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected,
+        })
+    }
+
+    async fn exec_delete(
+        &self,
+        plan: crate::planner::DeletePlan,
+        tenant_id: Option<&str>,
+        txn: &mut Transaction,
+    ) -> Result<SqlResult, DatacaveError> {
+        let mut rows_affected = 0;
+        let schema = self
+            .catalog
+            .lock()
+            .unwrap()
+            .get_table(&plan.table)
+            .cloned()
+            .ok_or_else(|| undefined_table(&plan.table))?;
+
+        let rows = self
+            .scan_table_where(&schema, tenant_id, txn, plan.where_clause.as_ref())
+            .await?;
+        let mut batch = WriteBatch::new();
+        for (key, row) in rows {
+            if let Some(ref cond) = plan.where_clause {
+                if !evaluate_where(cond, &row, &schema.columns)? {
+                    continue;
                 }
-                let updated = bincode::serialize(&row)
-                    .map_err(|e| DatacaveError::Storage(e.to_string()))?;
-                let version = self.mvcc.next_version();
-                self.storage
-                    .put(&key, &updated, version)
-                    .await
-                    .map_err(|e| DatacaveError::Storage(e.to_string()))?;
-                rows_affected += 1;
             }
+            self.lock_for_write(&schema.name, &key, txn).await?;
+            for (_, entry, _) in row_index_entries(&schema, &row.values, &key, tenant_id) {
+                batch.delete(&entry);
+            }
+            batch.delete(&key);
+            rows_affected += 1;
         }
+        txn.stage(batch);
         Ok(SqlResult {
             columns: Vec::new(),
             rows: Vec::new(),
@@ -380,68 +1804,584 @@ impl SqlExecutor {
         })
     }
 
-    async fn exec_delete(
-        &self,
-        plan: crate::planner::DeletePlan,
-        tenant_id: Option<&str>,
-    ) -> Result<SqlResult, DatacaveError> {
-        let mut rows_affected = 0;
-        let snapshot = self.mvcc.snapshot();
-        let max_row_id = self.current_row_count(&plan.table, tenant_id);
-        let schema = self
-            .catalog
-            .lock()
-            .unwrap()
-            .get_table(&plan.table)
-            .cloned()
-            .ok_or_else(|| DatacaveError::Sql(format!("unknown table: {}", plan.table)))?;
+    /// Fails if one of `entries`, the index entries of rows a statement writes, takes the
+    /// key of a unique index that a row other than its own holds: another row of `entries`,
+    /// or a stored row not among `leaving`, the rows the statement rewrites.
+    async fn check_unique_indexes(
+        &self,
+        entries: &[IndexEntry<'_>],
+        leaving: &HashSet<Vec<u8>>,
+        txn: &mut Transaction,
+    ) -> Result<(), DatacaveError> {
+        let mut seen = HashSet::new();
+        for (index, key, row_key) in entries.iter().filter(|(index, _, _)| index.unique) {
+            if !seen.insert(key.as_slice()) {
+                return Err(unique_violation(&index.name));
+            }
+            if let Some(holder) = self.read_key(key, txn).await? {
+                if holder != *row_key && !leaving.contains(&holder) {
+                    return Err(unique_violation(&index.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `schema`'s table holds a live row for any tenant.
+    async fn has_rows(&self, schema: &TableSchema) -> Result<bool, DatacaveError> {
+        for tenant in self.table_tenants(schema, u64::MAX).await? {
+            let (start, end) = table_row_range(&schema.storage_name, tenant.as_deref());
+            let first = self
+                .storage
+                .scan_range_limit(&start, &end, u64::MAX, 1)
+                .await
+                .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+            if !first.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Tenants that may hold rows of `schema`'s table at `version`, starting with `None` for
+    /// rows stored without a tenant. Tables written before tenants were recorded are covered
+    /// through their row-id sequences.
+    async fn table_tenants(
+        &self,
+        schema: &TableSchema,
+        version: Version,
+    ) -> Result<Vec<Option<String>>, DatacaveError> {
+        let prefix = table_tenants_prefix(&schema.storage_name);
+        let recorded = self
+            .storage
+            .scan(&prefix, version)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let sequences = self
+            .storage
+            .scan(SEQUENCE_KEY_PREFIX, version)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let mut tenants = vec![None];
+        for (key, _) in &recorded {
+            let tenant = std::str::from_utf8(&key[prefix.len()..])
+                .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+            tenants.push(Some(tenant.to_string()));
+        }
+        for (key, _) in &sequences {
+            if let Some(Some(tenant)) = sequence_tenant(&schema.storage_name, key) {
+                if !tenants.iter().flatten().any(|t| t == tenant) {
+                    tenants.push(Some(tenant.to_string()));
+                }
+            }
+        }
+        Ok(tenants)
+    }
+
+    /// Allocates `count` consecutive row ids and returns the first. The next free id is
+    /// cached per table; on first use it is read from the persisted sequence, or derived from
+    /// the highest stored row key for tables written before sequences were persisted.
+    async fn reserve_row_ids(
+        &self,
+        schema: &TableSchema,
+        tenant_id: Option<&str>,
+        count: u64,
+    ) -> Result<u64, DatacaveError> {
+        let cache_key = tenant_key(&schema.storage_name, tenant_id);
+        let cached = self.table_seq.lock().unwrap().contains_key(&cache_key);
+        let loaded = if cached {
+            0
+        } else {
+            self.load_sequence(schema, tenant_id).await?
+        };
+        // A concurrent statement may have filled the cache while the sequence loaded.
+        let mut table_seq = self.table_seq.lock().unwrap();
+        let next = table_seq.entry(cache_key).or_insert(loaded);
+        let first = *next;
+        *next = first.saturating_add(count);
+        Ok(first)
+    }
+
+    async fn load_sequence(
+        &self,
+        schema: &TableSchema,
+        tenant_id: Option<&str>,
+    ) -> Result<u64, DatacaveError> {
+        let stored = self
+            .storage
+            .get(&sequence_key(&schema.storage_name, tenant_id), u64::MAX)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?
+            .map(|bytes| decode_sequence(&bytes))
+            .transpose()?
+            .unwrap_or(0);
+        let prefix = table_key_prefix(&schema.storage_name, tenant_id);
+        let entries = self
+            .storage
+            .scan(&prefix, u64::MAX)
+            .await
+            .map_err(|e| DatacaveError::Storage(e.to_string()))?;
+        let highest = project_rows(schema, prefix.len(), entries)?
+            .last()
+            .map(|(key, _)| decode_row_id(key))
+            .transpose()?
+            .map_or(0, |row_id| row_id + 1);
+        Ok(stored.max(highest))
+    }
+}
+
+/// `batch` with the history entry recording that `version` was committed now.
+fn with_history_entry(mut batch: WriteBatch, version: Version) -> WriteBatch {
+    batch.put(&history_key(now_millis()), &version.to_be_bytes());
+    batch
+}
+
+fn no_such_savepoint(name: &str) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::INVALID_SAVEPOINT_SPECIFICATION,
+        format!("savepoint \"{name}\" does not exist"),
+    )
+}
+
+fn serialization_failure(conflict: CommitConflict) -> DatacaveError {
+    let message = match conflict {
+        CommitConflict::WriteWrite(_) => "could not serialize access due to concurrent update",
+        CommitConflict::ReadWrite => {
+            "could not serialize access due to read/write dependencies among transactions"
+        }
+    };
+    DatacaveError::sqlstate(sqlstate::SERIALIZATION_FAILURE, message)
+}
+
+fn unknown_transaction(txn: TxnId) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::INVALID_TRANSACTION_STATE,
+        format!("transaction {txn} is not open"),
+    )
+}
+
+/// Storage key of a table row: `[tenant|]table|row_id_be`.
+pub fn encode_row_key(table: &str, row_id: u64, tenant_id: Option<&str>) -> Vec<u8> {
+    let mut out = table_key_prefix(table, tenant_id);
+    out.extend_from_slice(&row_id.to_be_bytes());
+    out
+}
+
+/// Storage key of a row of a table keyed by its primary key: `[tenant|]table|key`, with the
+/// key values in the memcomparable encoding of [`encode_key`].
+pub fn encode_primary_row_key(table: &str, key: &[DataValue], tenant_id: Option<&str>) -> Vec<u8> {
+    let mut out = table_key_prefix(table, tenant_id);
+    encode_key(key, &mut out);
+    out
+}
+
+fn primary_row_key(schema: &TableSchema, values: &[DataValue], tenant_id: Option<&str>) -> Vec<u8> {
+    let key: Vec<DataValue> = schema
+        .key_indices()
+        .into_iter()
+        .map(|idx| values[idx].clone())
+        .collect();
+    encode_primary_row_key(&schema.storage_name, &key, tenant_id)
+}
+
+/// Whether `row`, a storage key with the table prefix removed, addresses a row of `schema`'s
+/// table. `t|` is also the prefix of tenant `t`'s tables, whose keys fail this check.
+fn is_row_key_of(schema: &TableSchema, row: &[u8]) -> bool {
+    if schema.key_columns.is_empty() {
+        row.len() == 8
+    } else {
+        decode_key(row, schema.key_columns.len()).is_ok()
+    }
+}
+
+/// Decodes the rows of `entries`, read under a table prefix of `prefix_len` bytes, and
+/// projects them onto the current columns.
+fn project_rows(
+    schema: &TableSchema,
+    prefix_len: usize,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+) -> Result<Vec<(Vec<u8>, DataRow)>, DatacaveError> {
+    let mut rows = Vec::with_capacity(entries.len());
+    for (key, bytes) in entries {
+        if !is_row_key_of(schema, &key[prefix_len..]) {
+            continue;
+        }
+        let (schema_version, row) = decode_row(&bytes)?;
+        let values = schema.project_row(schema_version, &row.values);
+        rows.push((key, DataRow { values }));
+    }
+    Ok(rows)
+}
+
+/// Tenant whose row-id sequence of the table stored as `table` is kept at `key`.
+fn sequence_tenant<'a>(table: &str, key: &'a [u8]) -> Option<Option<&'a str>> {
+    let tenant = key
+        .strip_prefix(SEQUENCE_KEY_PREFIX)?
+        .strip_suffix(table_key_prefix(table, None).as_slice())?;
+    if tenant.is_empty() {
+        return Some(None);
+    }
+    let tenant = tenant.strip_suffix(b"|")?;
+    if tenant.contains(&b'|') {
+        return None;
+    }
+    std::str::from_utf8(tenant).ok().map(Some)
+}
+
+/// Rows an index backfill reads, and index entries it writes, per batch.
+const BACKFILL_BATCH_ENTRIES: usize = 1024;
+
+/// Keys whose versions [`SqlExecutor::repair_from`] copies per batch.
+pub const REPAIR_BATCH_KEYS: usize = 1024;
+
+/// An index entry of a row: the index, the entry's storage key and the row's storage key.
+pub type IndexEntry<'a> = (&'a TableIndex, Vec<u8>, Vec<u8>);
+
+/// Prefix of every index entry of the table stored as `table`.
+fn table_indexes_prefix(table: &str) -> Vec<u8> {
+    let mut out = INDEX_KEY_PREFIX.to_vec();
+    out.extend_from_slice(table.as_bytes());
+    out.push(b'|');
+    out
+}
+
+/// Prefix of every entry of index `index` on the table stored as `table`, for all tenants.
+fn index_prefix(table: &str, index: &str) -> Vec<u8> {
+    let mut out = table_indexes_prefix(table);
+    out.extend_from_slice(index.as_bytes());
+    out.push(b'|');
+    out
+}
+
+/// Adds the deletion of every key under `prefix` to `batch`, as one range the storage
+/// engine resolves itself.
+fn delete_prefix(batch: &mut WriteBatch, prefix: &[u8]) -> Result<(), DatacaveError> {
+    let end =
+        prefix_end(prefix).ok_or_else(|| DatacaveError::Storage("unbounded key range".into()))?;
+    batch.delete_range(prefix, &end);
+    Ok(())
+}
+
+/// Prefix of the entries of index `index` on the table stored as `table` for rows of
+/// `tenant_id`.
+pub fn index_key_prefix(table: &str, index: &str, tenant_id: Option<&str>) -> Vec<u8> {
+    let mut out = index_prefix(table, index);
+    let tenant = tenant_id.map_or(DataValue::Null, |t| DataValue::String(t.to_string()));
+    encode_key(&[tenant], &mut out);
+    out
+}
+
+/// Storage key and value of `index`'s entry for the row stored at `row_key` with `values`:
+/// `\0index|table|index|tenant || indexed values [|| row]`, mapping to the row key. The row
+/// part, the row key without its table prefix, keeps entries of equal values apart. Unique
+/// indexes leave it out unless a value is NULL, so rows with equal values write the same
+/// key and concurrent inserts of a duplicate conflict.
+fn index_entry(
+    schema: &TableSchema,
+    index: &TableIndex,
+    values: &[DataValue],
+    row_key: &[u8],
+    tenant_id: Option<&str>,
+) -> (Vec<u8>, Vec<u8>) {
+    let indexed: Vec<DataValue> = index
+        .columns
+        .iter()
+        .map(|name| {
+            schema
+                .column_index(name)
+                .map_or(DataValue::Null, |idx| values[idx].clone())
+        })
+        .collect();
+    let mut key = index_key_prefix(&schema.storage_name, &index.name, tenant_id);
+    encode_key(&indexed, &mut key);
+    if !index.unique || indexed.contains(&DataValue::Null) {
+        let table_prefix = table_key_prefix(&schema.storage_name, tenant_id).len();
+        key.extend_from_slice(&row_key[table_prefix..]);
+    }
+    (key, row_key.to_vec())
+}
+
+/// Entries in every index of `schema`'s table for the row stored at `row_key` with `values`.
+pub fn row_index_entries<'a>(
+    schema: &'a TableSchema,
+    values: &[DataValue],
+    row_key: &[u8],
+    tenant_id: Option<&str>,
+) -> Vec<IndexEntry<'a>> {
+    schema
+        .indexes
+        .iter()
+        .map(|index| {
+            let (key, row) = index_entry(schema, index, values, row_key, tenant_id);
+            (index, key, row)
+        })
+        .collect()
+}
+
+fn entry_set<'a>(entries: &'a [IndexEntry<'_>]) -> HashSet<(&'a [u8], &'a [u8])> {
+    entries
+        .iter()
+        .map(|(_, key, row)| (key.as_slice(), row.as_slice()))
+        .collect()
+}
+
+/// Key range of the entries under `base` that `access` selects, or `None` when it selects
+/// nothing. A point selects every entry starting with its values.
+fn access_range(base: &[u8], access: KeyAccess) -> Result<Option<KeyRange>, DatacaveError> {
+    let (key_prefix, lower, upper) = match access {
+        KeyAccess::Full => (Vec::new(), Bound::Unbounded, Bound::Unbounded),
+        KeyAccess::Point(values) => (values, Bound::Unbounded, Bound::Unbounded),
+        KeyAccess::Range {
+            prefix,
+            lower,
+            upper,
+        } => (prefix, lower, upper),
+    };
+    let mut base = base.to_vec();
+    encode_key(&key_prefix, &mut base);
+    let bound = |value: &DataValue| {
+        let mut out = base.clone();
+        encode_key(std::slice::from_ref(value), &mut out);
+        out
+    };
+    let after = |bytes: Vec<u8>| {
+        prefix_end(&bytes).ok_or_else(|| DatacaveError::Storage("unbounded key range".into()))
+    };
+    let start = match &lower {
+        Bound::Unbounded => base.clone(),
+        Bound::Included(value) => bound(value),
+        Bound::Excluded(value) => after(bound(value))?,
+    };
+    let end = match &upper {
+        Bound::Unbounded => after(base.clone())?,
+        Bound::Included(value) => after(bound(value))?,
+        Bound::Excluded(value) => bound(value),
+    };
+    if start >= end {
+        return Ok(None);
+    }
+    Ok(Some(KeyRange {
+        start,
+        end: Some(end),
+    }))
+}
+
+/// How a statement reaches the rows of a table keyed by its primary key, or the entries of
+/// an index.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum KeyAccess {
+    /// Every row of the table.
+    Full,
+    /// The single row with this key.
+    Point(Vec<DataValue>),
+    /// Rows whose leading key columns equal `prefix` and whose next key column lies between
+    /// `lower` and `upper`.
+    Range {
+        prefix: Vec<DataValue>,
+        lower: Bound<DataValue>,
+        upper: Bound<DataValue>,
+    },
+}
+
+/// Picks the key access for a WHERE clause on a table keyed by its primary key.
+pub(crate) fn plan_key_access(schema: &TableSchema, cond: Option<&WhereCond>) -> KeyAccess {
+    match cond {
+        Some(cond) if !schema.key_columns.is_empty() => {
+            column_access(schema, &schema.key_columns, cond)
+        }
+        _ => KeyAccess::Full,
+    }
+}
+
+/// Picks the index of `schema`'s table that narrows a WHERE clause the most, among those a
+/// snapshot at `version` can read: a unique index matched on all its columns first, then
+/// the one with the most columns matched by equality, preferring a match on all of them.
+pub(crate) fn plan_index_access<'a>(
+    schema: &'a TableSchema,
+    cond: Option<&WhereCond>,
+    version: Version,
+) -> Option<(&'a TableIndex, KeyAccess)> {
+    let cond = cond?;
+    schema
+        .indexes
+        .iter()
+        .filter(|index| index.valid_since.is_some_and(|since| since <= version))
+        .filter_map(|index| match column_access(schema, &index.columns, cond) {
+            KeyAccess::Full => None,
+            access => Some((index, access)),
+        })
+        .max_by_key(|(index, access)| match access {
+            KeyAccess::Point(values) => (index.unique, values.len(), true),
+            KeyAccess::Range { prefix, .. } => (false, prefix.len(), false),
+            KeyAccess::Full => (false, 0, false),
+        })
+}
 
-        for row_id in 0..max_row_id {
-            let key = encode_row_key(&plan.table, row_id, tenant_id);
-            if let Some(bytes) = self
-                .storage
-                .get(&key, snapshot.version)
-                .await
-                .map_err(|e| DatacaveError::Storage(e.to_string()))?
-            {
-                if let Some(ref cond) = plan.where_clause {
-                    let row: DataRow =
-                        bincode::deserialize(&bytes).map_err(|e| DatacaveError::Storage(e.to_string()))?;
-                    if !evaluate_where(cond, &row, &schema.columns) {
-                        continue;
-                    }
-                }
-                let version = self.mvcc.next_version();
-                self.storage
-                    .delete(&key, version)
-                    .await
-                    .map_err(|e| DatacaveError::Storage(e.to_string()))?;
-                rows_affected += 1;
+/// Picks the access to rows or entries keyed by `columns` for a WHERE clause from the
+/// `column op literal` predicates ANDed at its top level: equalities on a leading run of the
+/// columns, then bounds on the next one.
+fn column_access(schema: &TableSchema, columns: &[String], cond: &WhereCond) -> KeyAccess {
+    let mut predicates = Vec::new();
+    key_predicates(schema, columns, cond, &mut predicates);
+    let mut prefix = Vec::new();
+    let mut lower = Bound::Unbounded;
+    let mut upper = Bound::Unbounded;
+    for column in columns {
+        let on_column = || predicates.iter().filter(|(c, _, _)| c == column);
+        if let Some((_, _, value)) = on_column().find(|(_, op, _)| *op == HavingOp::Eq) {
+            prefix.push(value.clone());
+            continue;
+        }
+        for (_, op, value) in on_column() {
+            match op {
+                HavingOp::Gt => lower = tighter(lower, Bound::Excluded(value.clone()), true),
+                HavingOp::Gte => lower = tighter(lower, Bound::Included(value.clone()), true),
+                HavingOp::Lt => upper = tighter(upper, Bound::Excluded(value.clone()), false),
+                HavingOp::Lte => upper = tighter(upper, Bound::Included(value.clone()), false),
+                HavingOp::Eq | HavingOp::NotEq => {}
             }
         }
-        Ok(SqlResult {
-            columns: Vec::new(),
-            rows: Vec::new(),
-            rows_affected,
-        })
+        break;
     }
+    if prefix.len() == columns.len() {
+        KeyAccess::Point(prefix)
+    } else if prefix.is_empty() && lower == Bound::Unbounded && upper == Bound::Unbounded {
+        KeyAccess::Full
+    } else {
+        KeyAccess::Range {
+            prefix,
+            lower,
+            upper,
+        }
+    }
+}
 
-    fn reserve_row_id(&self, table: &str, tenant_id: Option<&str>) -> u64 {
-        let mut seq = self.table_seq.lock().unwrap();
-        let key = tenant_key(table, tenant_id);
-        let entry = seq.entry(key).or_insert(0);
-        let current = *entry;
-        *entry = current.saturating_add(1);
-        current
+/// Collects `(column, op, value)` for the top-level ANDed predicates comparing one of
+/// `columns` with a literal, the literal coerced to the column type. Numeric literals that
+/// do not convert exactly are skipped, since a rounded bound could exclude matching rows;
+/// string literals are read as the column type, as [`evaluate_where`] reads them.
+fn key_predicates(
+    schema: &TableSchema,
+    columns: &[String],
+    cond: &WhereCond,
+    out: &mut Vec<(String, HavingOp, DataValue)>,
+) {
+    let predicate = match cond {
+        WhereCond::And(left, right) => {
+            key_predicates(schema, columns, left, out);
+            key_predicates(schema, columns, right, out);
+            return;
+        }
+        WhereCond::Or(..) | WhereCond::Expr(_) => return,
+        WhereCond::Predicate(p) => p,
+    };
+    let (name, op, literal) = match (&predicate.left, &predicate.right) {
+        (WhereOperand::Column(name), WhereOperand::Literal(value)) => (name, predicate.op, value),
+        (WhereOperand::Literal(value), WhereOperand::Column(name)) => {
+            let flipped = match predicate.op {
+                HavingOp::Gt => HavingOp::Lt,
+                HavingOp::Gte => HavingOp::Lte,
+                HavingOp::Lt => HavingOp::Gt,
+                HavingOp::Lte => HavingOp::Gte,
+                op => op,
+            };
+            (name, flipped, value)
+        }
+        _ => return,
+    };
+    let name = name
+        .strip_prefix(schema.name.as_str())
+        .and_then(|rest| rest.strip_prefix('.'))
+        .unwrap_or(name);
+    if !columns.iter().any(|c| c == name) {
+        return;
+    }
+    let Some(column) = schema.column_index(name).map(|idx| &schema.columns[idx]) else {
+        return;
+    };
+    if let DataValue::String(_) = literal {
+        if let Ok(value) = literal_as(literal, column.data_type) {
+            out.push((name.to_string(), op, value));
+        }
+        return;
+    }
+    match column.data_type.coerce(&column.name, literal.clone()) {
+        Ok(value)
+            if value != DataValue::Null
+                && cmp_data_value(&value, literal) == std::cmp::Ordering::Equal =>
+        {
+            out.push((name.to_string(), op, value));
+        }
+        _ => {}
     }
+}
 
-    fn current_row_count(&self, table: &str, tenant_id: Option<&str>) -> u64 {
-        let seq = self.table_seq.lock().unwrap();
-        seq.get(&tenant_key(table, tenant_id)).cloned().unwrap_or(0)
+/// The narrower of two bounds on the same side of a range.
+fn tighter(current: Bound<DataValue>, new: Bound<DataValue>, lower: bool) -> Bound<DataValue> {
+    let (current_value, new_value) = match (&current, &new) {
+        (Bound::Unbounded, _) => return new,
+        (_, Bound::Unbounded) => return current,
+        (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => {
+            (a, b)
+        }
+    };
+    match cmp_data_value(new_value, current_value) {
+        std::cmp::Ordering::Greater if lower => new,
+        std::cmp::Ordering::Less if !lower => new,
+        std::cmp::Ordering::Equal if matches!(new, Bound::Excluded(_)) => new,
+        _ => current,
     }
 }
 
-fn encode_row_key(table: &str, row_id: u64, tenant_id: Option<&str>) -> Vec<u8> {
+/// Row id encoded in the last eight bytes of a row key.
+pub fn decode_row_id(key: &[u8]) -> Result<u64, DatacaveError> {
+    key.len()
+        .checked_sub(8)
+        .and_then(|start| key[start..].try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| DatacaveError::Storage("malformed row key".into()))
+}
+
+/// Storage key of the next free row id of `table`.
+pub fn sequence_key(table: &str, tenant_id: Option<&str>) -> Vec<u8> {
+    let mut out = SEQUENCE_KEY_PREFIX.to_vec();
+    out.extend_from_slice(&table_key_prefix(table, tenant_id));
+    out
+}
+
+pub fn decode_sequence(bytes: &[u8]) -> Result<u64, DatacaveError> {
+    bytes
+        .try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|_| DatacaveError::Storage("malformed row-id sequence".into()))
+}
+
+/// Key range of the rows `tenant_id` stores in the table stored as `table`. Row ids below
+/// 2^60 and primary-key encodings start with a byte below 0x10, while the rows of a tenant
+/// named like the table continue with a table name, so none of those fall inside.
+pub fn table_row_range(table: &str, tenant_id: Option<&str>) -> (Vec<u8>, Vec<u8>) {
+    let start = table_key_prefix(table, tenant_id);
+    let mut end = start.clone();
+    end.push(0x10);
+    (start, end)
+}
+
+fn table_tenants_prefix(table: &str) -> Vec<u8> {
+    let mut out = TENANT_KEY_PREFIX.to_vec();
+    out.extend_from_slice(table.as_bytes());
+    out.push(b'|');
+    out
+}
+
+/// Storage key recording that `tenant` wrote rows of the table stored as `table`.
+pub fn table_tenant_key(table: &str, tenant: &str) -> Vec<u8> {
+    let mut out = table_tenants_prefix(table);
+    out.extend_from_slice(tenant.as_bytes());
+    out
+}
+
+/// Prefix shared by every row key of `table`.
+pub fn table_key_prefix(table: &str, tenant_id: Option<&str>) -> Vec<u8> {
     let mut out = Vec::new();
     if let Some(tenant) = tenant_id {
         out.extend_from_slice(tenant.as_bytes());
@@ -449,15 +2389,75 @@ fn encode_row_key(table: &str, row_id: u64, tenant_id: Option<&str>) -> Vec<u8>
     }
     out.extend_from_slice(table.as_bytes());
     out.push(b'|');
-    out.extend_from_slice(&row_id.to_be_bytes());
     out
 }
 
+/// Leading magic of an encoded row, followed by a one-byte [`ROW_FORMAT_VERSION`].
+pub const ROW_MAGIC: &[u8; 3] = b"DCR";
+
+/// Version of the row encoding written by this build. Bump it whenever `DataRow` or
+/// `DataValue` change shape, keep [`decode_row`] able to read the previous layout, and let
+/// the `upgrade` command rewrite old rows. v2 added the schema version.
+pub const ROW_FORMAT_VERSION: u8 = 2;
+
+/// Storage value of a table row:
+/// `ROW_MAGIC || ROW_FORMAT_VERSION || schema_version_be || bincode(row)`, where `row`
+/// follows the layout of [`TableSchema::version`] `schema_version`.
+pub fn encode_row(row: &DataRow, schema_version: u32) -> Result<Vec<u8>, DatacaveError> {
+    let mut out = ROW_MAGIC.to_vec();
+    out.push(ROW_FORMAT_VERSION);
+    out.extend_from_slice(&schema_version.to_be_bytes());
+    bincode::serialize_into(&mut out, row).map_err(|e| DatacaveError::Storage(e.to_string()))?;
+    Ok(out)
+}
+
+/// Stored form of `values`, given in the order of `schema`'s current columns.
+pub fn encode_table_row(schema: &TableSchema, values: Vec<DataValue>) -> Result<Vec<u8>, DatacaveError> {
+    let row = DataRow {
+        values: schema.store_row(values),
+    };
+    encode_row(&row, schema.version)
+}
+
+/// Decodes a stored row and the schema version it was written under. Rows from before
+/// format v2 predate ALTER TABLE and follow schema version 0.
+pub fn decode_row(bytes: &[u8]) -> Result<(u32, DataRow), DatacaveError> {
+    let (version, body) = split_row_header(bytes);
+    if version > ROW_FORMAT_VERSION {
+        return Err(DatacaveError::Storage(format!(
+            "row uses format v{version}, but this build reads up to v{ROW_FORMAT_VERSION}"
+        )));
+    }
+    let (schema_version, body) = match version {
+        0 | 1 => (0, body),
+        _ => {
+            let (schema_version, body) = body
+                .split_first_chunk::<4>()
+                .ok_or_else(|| DatacaveError::Storage("truncated row header".into()))?;
+            (u32::from_be_bytes(*schema_version), body)
+        }
+    };
+    let row = bincode::deserialize(body).map_err(|e| DatacaveError::Storage(e.to_string()))?;
+    Ok((schema_version, row))
+}
+
+/// Format version of an encoded row; rows written before the header existed are version 0.
+pub fn row_format_version(bytes: &[u8]) -> u8 {
+    split_row_header(bytes).0
+}
+
+pub(crate) fn split_row_header(bytes: &[u8]) -> (u8, &[u8]) {
+    match bytes.strip_prefix(ROW_MAGIC.as_slice()) {
+        Some([version, body @ ..]) => (*version, body),
+        _ => (0, bytes),
+    }
+}
+
 fn qualify_columns(cols: &[Column], table: &str) -> Vec<Column> {
     cols.iter()
         .map(|c| Column {
             name: format!("{}.{}", table, c.name),
-            data_type: c.data_type.clone(),
+            data_type: c.data_type,
         })
         .collect()
 }
@@ -477,299 +2477,429 @@ fn tenant_key(table: &str, tenant_id: Option<&str>) -> String {
     }
 }
 
-fn align_columns(schema: &[Column], insert_cols: &[String], values: Vec<DataValue>) -> Vec<DataValue> {
-    if insert_cols.is_empty() {
-        return values;
+/// Checks rows that reach a table without going through SQL, such as bulk-ingested CSV
+/// lines, the way INSERT checks a row that lists every column.
+pub struct RowValidator<'a> {
+    schema: &'a TableSchema,
+    checks: Vec<(&'a str, WhereCond)>,
+}
+
+impl<'a> RowValidator<'a> {
+    pub fn new(schema: &'a TableSchema) -> Result<Self, DatacaveError> {
+        Ok(Self {
+            schema,
+            checks: compile_checks(schema)?,
+        })
+    }
+
+    /// Coerces `values` to the column types and enforces NOT NULL and CHECK. The row must
+    /// carry exactly one value per column.
+    pub fn validate(&self, values: Vec<DataValue>) -> Result<Vec<DataValue>, DatacaveError> {
+        if let Some(column) = self.schema.columns.get(values.len()) {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::BAD_COPY_FILE_FORMAT,
+                format!("missing data for column \"{}\"", column.name),
+            ));
+        }
+        if values.len() > self.schema.columns.len() {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::BAD_COPY_FILE_FORMAT,
+                "extra data after last expected column",
+            ));
+        }
+        let values = align_columns(self.schema, &[], values)?;
+        validate_row(self.schema, &self.checks, &values)?;
+        Ok(values)
+    }
+}
+
+/// Places the values of one INSERT row at their schema positions, coerced to the column
+/// types. Columns left out of an explicit column list take their DEFAULT; without a list,
+/// every column needs a value.
+fn align_columns(
+    schema: &TableSchema,
+    insert_cols: &[String],
+    values: Vec<DataValue>,
+) -> Result<Vec<DataValue>, DatacaveError> {
+    let targets = if insert_cols.is_empty() {
+        (0..schema.columns.len()).collect::<Vec<_>>()
+    } else {
+        insert_cols
+            .iter()
+            .map(|col| column_position(schema, col))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    if values.len() > targets.len() {
+        return Err(DatacaveError::sqlstate(
+            sqlstate::SYNTAX_ERROR,
+            "INSERT has more expressions than target columns",
+        ));
+    }
+    if values.len() < targets.len() {
+        return Err(DatacaveError::sqlstate(
+            sqlstate::SYNTAX_ERROR,
+            "INSERT has more target columns than expressions",
+        ));
+    }
+    let mut aligned: Vec<DataValue> = (0..schema.columns.len())
+        .map(|idx| schema.default_value(idx))
+        .collect();
+    for (idx, value) in targets.into_iter().zip(values) {
+        let column = &schema.columns[idx];
+        aligned[idx] = column.data_type.coerce(&column.name, value)?;
+    }
+    Ok(aligned)
+}
+
+/// The table's CHECK constraints, parsed once per statement.
+fn compile_checks(schema: &TableSchema) -> Result<Vec<(&str, WhereCond)>, DatacaveError> {
+    schema
+        .checks
+        .iter()
+        .map(|check| Ok((check.name.as_str(), parse_check(&check.expr)?)))
+        .collect()
+}
+
+/// Enforces NOT NULL and CHECK on a row about to be written.
+fn validate_row(
+    schema: &TableSchema,
+    checks: &[(&str, WhereCond)],
+    values: &[DataValue],
+) -> Result<(), DatacaveError> {
+    for ((column, constraints), value) in schema
+        .columns
+        .iter()
+        .zip(&schema.column_constraints)
+        .zip(values)
+    {
+        if constraints.not_null && *value == DataValue::Null {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::NOT_NULL_VIOLATION,
+                format!(
+                    "null value in column \"{}\" of relation \"{}\" violates not-null constraint",
+                    column.name, schema.name
+                ),
+            ));
+        }
+    }
+    let row = DataRow {
+        values: values.to_vec(),
+    };
+    for (name, cond) in checks {
+        if evaluate_check(cond, &row, &schema.columns)? == Some(false) {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::CHECK_VIOLATION,
+                format!(
+                    "new row for relation \"{}\" violates check constraint \"{name}\"",
+                    schema.name
+                ),
+            ));
+        }
     }
-    let mut aligned = vec![DataValue::Null; schema.len()];
-    for (idx, col) in insert_cols.iter().enumerate() {
-        if let Some(schema_idx) = schema.iter().position(|c| c.name == *col) {
-            if idx < values.len() {
-                aligned[schema_idx] = values[idx].clone();
+    Ok(())
+}
+
+/// SQL three-valued evaluation: `None` when a NULL makes the result unknown, which a CHECK
+/// constraint lets through.
+fn evaluate_check(cond: &WhereCond, row: &DataRow, schema: &[Column]) -> Result<Option<bool>, DatacaveError> {
+    Ok(match cond {
+        WhereCond::Predicate(p) => {
+            let left = where_operand_value(&p.left, &p.right, row, schema)?;
+            let right = where_operand_value(&p.right, &p.left, row, schema)?;
+            if left == DataValue::Null || right == DataValue::Null {
+                return Ok(None);
+            }
+            Some(evaluate_where_predicate(p, row, schema)?)
+        }
+        WhereCond::And(left, right) => {
+            match (evaluate_check(left, row, schema)?, evaluate_check(right, row, schema)?) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            }
+        }
+        WhereCond::Or(left, right) => {
+            match (evaluate_check(left, row, schema)?, evaluate_check(right, row, schema)?) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            }
+        }
+        WhereCond::Expr(expr) => truth(expr.eval(schema, &row.values)?, "CHECK")?,
+    })
+}
+
+/// UNIQUE constraints of `schema` checked against a scan of the table: those neither keying
+/// its rows nor backed by a unique index, as on tables created before UNIQUE was indexed.
+fn scanned_unique(schema: &TableSchema) -> Vec<&UniqueConstraint> {
+    schema
+        .unique
+        .iter()
+        .filter(|u| schema.key_columns.is_empty() || u.columns != schema.key_columns)
+        .filter(|u| !schema.indexes.iter().any(|i| i.unique && i.name == u.name))
+        .collect()
+}
+
+/// Fails if two of `rows` agree on all columns of one of `constraints`. Keys containing a
+/// NULL never conflict.
+fn check_unique<'a>(
+    schema: &TableSchema,
+    constraints: impl Iterator<Item = &'a UniqueConstraint>,
+    rows: impl Iterator<Item = &'a Vec<DataValue>> + Clone,
+) -> Result<(), DatacaveError> {
+    for constraint in constraints {
+        let indices = constraint
+            .columns
+            .iter()
+            .map(|name| column_position(schema, name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut seen = HashSet::new();
+        for values in rows.clone() {
+            let key: Vec<&DataValue> = indices.iter().map(|&i| &values[i]).collect();
+            if key.iter().any(|v| **v == DataValue::Null) {
+                continue;
+            }
+            let key = bincode::serialize(&key)
+                .map_err(|e| DatacaveError::Sql(format!("serialize unique key: {e}")))?;
+            if !seen.insert(key) {
+                return Err(unique_violation(&constraint.name));
             }
         }
     }
-    aligned
+    Ok(())
+}
+
+fn primary_key_violation(schema: &TableSchema) -> DatacaveError {
+    let name = schema
+        .unique
+        .iter()
+        .find(|u| u.columns == schema.key_columns)
+        .map_or_else(|| format!("{}_pkey", schema.name), |u| u.name.clone());
+    unique_violation(&name)
+}
+
+fn unique_violation(constraint: &str) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::UNIQUE_VIOLATION,
+        format!("duplicate key value violates unique constraint \"{constraint}\""),
+    )
 }
 
+fn column_position(schema: &TableSchema, name: &str) -> Result<usize, DatacaveError> {
+    schema
+        .columns
+        .iter()
+        .position(|c| c.name == name)
+        .ok_or_else(|| {
+            DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_COLUMN,
+                format!("column \"{name}\" of relation \"{}\" does not exist", schema.name),
+            )
+        })
+}
+
+/// Where an output column takes its values from: a position in the input row, or in the
+/// group key for grouped queries, or an expression.
+enum OutputSource<'a> {
+    Input(usize),
+    Expr(&'a ScalarExpr),
+}
+
+/// One output row per group of `rows` with equal GROUP BY values, or a single row over all
+/// of `rows` without GROUP BY, filtered by `having`. Other columns may only be read through
+/// aggregates; a plain selected column outside GROUP BY is left out.
 fn compute_grouped_aggregates(
     projection: &[ProjectionItem],
-    group_by: &[String],
+    group_by: &[ScalarExpr],
     schema: &[Column],
     rows: &[DataRow],
-    having: Option<&HavingCond>,
+    having: Option<&ScalarExpr>,
 ) -> Result<(Vec<Column>, Vec<DataRow>), DatacaveError> {
-    let group_indices: Vec<usize> = group_by
-        .iter()
-        .map(|name| {
-            schema_resolve_column_index(schema, name)
-                .ok_or_else(|| DatacaveError::Sql(format!("GROUP BY column not found: {}", name)))
+    let same_column = |a: &str, b: &str| {
+        a.eq_ignore_ascii_case(b)
+            || matches!(
+                (schema_resolve_column_index(schema, a), schema_resolve_column_index(schema, b)),
+                (Some(x), Some(y)) if x == y
+            )
+    };
+    let group_position = |expr: &ScalarExpr| {
+        group_by.iter().position(|g| match (g, expr) {
+            (ScalarExpr::Column(a), ScalarExpr::Column(b)) => same_column(a, b),
+            _ => g == expr,
         })
-        .collect::<Result<Vec<_>, _>>()?;
+    };
+    for expr in group_by {
+        expr.check_columns(schema)?;
+    }
 
-    let mut groups: HashMap<Vec<u8>, Vec<DataRow>> = HashMap::new();
-    for row in rows {
-        let key_values: Vec<DataValue> = group_indices
-            .iter()
-            .filter_map(|&i| row.values.get(i).cloned())
-            .collect();
-        let key = bincode::serialize(&key_values)
-            .map_err(|e| DatacaveError::Sql(format!("serialize group key: {}", e)))?;
-        groups.entry(key).or_default().push(row.clone());
+    let mut groups: Vec<(Vec<DataValue>, Vec<DataRow>)> = Vec::new();
+    if group_by.is_empty() {
+        // Without GROUP BY, aggregates give one row, even over no rows at all.
+        groups.push((Vec::new(), rows.to_vec()));
+    } else {
+        let mut positions: HashMap<Vec<u8>, usize> = HashMap::new();
+        for row in rows {
+            let key_values = group_by
+                .iter()
+                .map(|g| g.eval(schema, &row.values))
+                .collect::<Result<Vec<_>, _>>()?;
+            let key = bincode::serialize(&key_values)
+                .map_err(|e| DatacaveError::Sql(format!("serialize group key: {}", e)))?;
+            let pos = *positions.entry(key).or_insert_with(|| {
+                groups.push((key_values, Vec::new()));
+                groups.len() - 1
+            });
+            groups[pos].1.push(row.clone());
+        }
     }
 
     let mut out_columns = Vec::new();
-    let mut col_order: Vec<(bool, usize)> = Vec::new();
-    let mut output_names: Vec<String> = Vec::new();
-
+    let mut sources = Vec::new();
     for item in projection {
         match item {
             ProjectionItem::Column(name, alias) => {
-                let in_group = group_by.iter().any(|g| g.eq_ignore_ascii_case(name))
-                    || schema_resolve_column_index(schema, name)
-                        .map(|idx| group_indices.contains(&idx))
-                        .unwrap_or(false);
-                if in_group {
-                    let pos = group_by.iter().position(|g| g.eq_ignore_ascii_case(name))
-                        .or_else(|| {
-                            schema_resolve_column_index(schema, name)
-                                .and_then(|idx| group_indices.iter().position(|&i| i == idx))
-                        })
-                        .unwrap_or(0);
-                    let out_name = alias.clone().unwrap_or_else(|| name.clone());
+                if let Some(pos) = group_position(&ScalarExpr::Column(name.clone())) {
                     out_columns.push(Column {
-                        name: out_name.clone(),
-                        data_type: schema_resolve_column_index(schema, name)
-                            .and_then(|i| schema.get(i))
-                            .map(|c| c.data_type.clone())
-                            .unwrap_or_else(|| "TEXT".to_string()),
+                        name: alias.clone().unwrap_or_else(|| name.clone()),
+                        data_type: group_by[pos].data_type(schema),
                     });
-                    output_names.push(out_name);
-                    col_order.push((true, pos));
-                } else {
-                    col_order.push((false, 0));
+                    sources.push(OutputSource::Input(pos));
                 }
             }
-            ProjectionItem::Aggregate(func, _, alias) => {
-                let agg_name = alias.clone().unwrap_or_else(|| {
-                    match func {
-                        AggregateFunc::Count => "count",
-                        AggregateFunc::Sum => "sum",
-                        AggregateFunc::Avg => "avg",
-                        AggregateFunc::Min => "min",
-                        AggregateFunc::Max => "max",
-                    }
-                    .to_string()
-                });
+            ProjectionItem::Expr(expr, name) => {
+                expr.check_columns(schema)?;
+                if let Some(column) = expr.ungrouped_column(&|e| group_position(e).is_some()) {
+                    return Err(grouping_error(column));
+                }
                 out_columns.push(Column {
-                    name: agg_name.clone(),
-                    data_type: "BIGINT".to_string(),
+                    name: name.clone(),
+                    data_type: expr.data_type(schema),
                 });
-                output_names.push(agg_name);
-                col_order.push((false, 0));
+                sources.push(OutputSource::Expr(expr));
             }
             ProjectionItem::AllColumns => {}
         }
     }
 
-    let mut out_rows = Vec::new();
-    for (key_bytes, group_rows) in groups {
-        let group_values: Vec<DataValue> = bincode::deserialize(&key_bytes)
-            .map_err(|e| DatacaveError::Sql(format!("deserialize group key: {}", e)))?;
-
-        let mut out_values = Vec::new();
-        for (i, item) in projection.iter().enumerate() {
-            let (is_group, idx) = col_order.get(i).copied().unwrap_or((false, 0));
-            match item {
-                ProjectionItem::Column(_, _) => {
-                    if is_group {
-                        if let Some(v) = group_values.get(idx) {
-                            out_values.push(v.clone());
-                        }
-                    }
-                }
-                ProjectionItem::Aggregate(func, col, _) => {
-                    let col_idx = col.as_ref().and_then(|c| schema_resolve_column_index(schema, c));
-                    let values: Vec<DataValue> = match col_idx {
-                        Some(idx) => group_rows
-                            .iter()
-                            .filter_map(|r| {
-                                r.values.get(idx).cloned()
-                                    .filter(|v| !matches!(v, DataValue::Null))
-                            })
-                            .collect(),
-                        None => group_rows.iter().map(|_| DataValue::Int64(1)).collect(),
-                    };
-                    let result = match func {
-                        AggregateFunc::Count => DataValue::Int64(values.len() as i64),
-                        AggregateFunc::Sum => {
-                            let sum: f64 = values.iter().filter_map(try_numeric).sum();
-                            DataValue::Float64(sum)
-                        }
-                        AggregateFunc::Avg => {
-                            let sum: f64 = values.iter().filter_map(try_numeric).sum();
-                            let count = values.len() as f64;
-                            DataValue::Float64(if count > 0.0 { sum / count } else { 0.0 })
-                        }
-                        AggregateFunc::Min => values
-                            .iter()
-                            .filter_map(try_numeric)
-                            .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                            .map(DataValue::Float64)
-                            .unwrap_or(DataValue::Null),
-                        AggregateFunc::Max => values
-                            .iter()
-                            .filter_map(try_numeric)
-                            .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                            .map(DataValue::Float64)
-                            .unwrap_or(DataValue::Null),
-                    };
-                    out_values.push(result);
-                }
-                ProjectionItem::AllColumns => {}
-            }
+    // HAVING may also name output columns, which follow the input columns in the rows it
+    // is evaluated over.
+    let having_columns: Vec<Column> = schema.iter().chain(&out_columns).cloned().collect();
+    if let Some(cond) = having {
+        cond.check_columns(&having_columns)?;
+        let grouped = |e: &ScalarExpr| {
+            group_position(e).is_some()
+                || matches!(e, ScalarExpr::Column(name) if schema_resolve_column_index(schema, name).is_none())
+        };
+        if let Some(column) = cond.ungrouped_column(&grouped) {
+            return Err(grouping_error(column));
         }
-        let row = DataRow { values: out_values };
+    }
+
+    let mut out_rows = Vec::new();
+    for (group_values, group_rows) in &groups {
+        let first = group_rows.first().map(|r| r.values.as_slice()).unwrap_or(&[]);
+        let values = sources
+            .iter()
+            .map(|source| match source {
+                OutputSource::Input(pos) => Ok(group_values[*pos].clone()),
+                OutputSource::Expr(expr) => expr.eval_group(schema, first, group_rows),
+            })
+            .collect::<Result<Vec<_>, DatacaveError>>()?;
         if let Some(cond) = having {
-            if evaluate_having(cond, &output_names, &row, &group_rows, schema) {
-                out_rows.push(row);
+            let mut row: Vec<DataValue> = (0..schema.len())
+                .map(|i| first.get(i).cloned().unwrap_or(DataValue::Null))
+                .collect();
+            row.extend(values.iter().cloned());
+            if truth(cond.eval_group(&having_columns, &row, group_rows)?, "HAVING")? != Some(true) {
+                continue;
             }
-        } else {
-            out_rows.push(row);
         }
+        out_rows.push(DataRow { values });
     }
 
     Ok((out_columns, out_rows))
 }
 
-fn evaluate_having(
-    cond: &HavingCond,
-    col_names: &[String],
-    row: &DataRow,
-    group_rows: &[DataRow],
-    schema: &[Column],
-) -> bool {
-    match cond {
-        HavingCond::Predicate(p) => {
-            let left_val = operand_value(&p.left, col_names, row, group_rows, schema);
-            let right_val = operand_value(&p.right, col_names, row, group_rows, schema);
-            match p.op {
-                HavingOp::Eq => left_val == right_val,
-                HavingOp::NotEq => left_val != right_val,
-                HavingOp::Gt => cmp_data_value(&left_val, &right_val) == std::cmp::Ordering::Greater,
-                HavingOp::Gte => {
-                    let o = cmp_data_value(&left_val, &right_val);
-                    o == std::cmp::Ordering::Greater || o == std::cmp::Ordering::Equal
-                }
-                HavingOp::Lt => cmp_data_value(&left_val, &right_val) == std::cmp::Ordering::Less,
-                HavingOp::Lte => {
-                    let o = cmp_data_value(&left_val, &right_val);
-                    o == std::cmp::Ordering::Less || o == std::cmp::Ordering::Equal
-                }
-            }
-        }
-        HavingCond::And(left, right) => {
-            evaluate_having(left, col_names, row, group_rows, schema)
-                && evaluate_having(right, col_names, row, group_rows, schema)
-        }
+fn grouping_error(column: &str) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::GROUPING_ERROR,
+        format!("column \"{column}\" must appear in the GROUP BY clause or be used in an aggregate function"),
+    )
+}
+
+/// Sorts the result rows `items` carry under `order_by`. Names and positions matching no
+/// result column are skipped; other expressions are evaluated over the result columns.
+fn sort_rows<T>(
+    columns: &[Column],
+    order_by: &[OrderBySpec],
+    items: Vec<T>,
+    row: impl Fn(&T) -> &DataRow,
+) -> Result<Vec<T>, DatacaveError> {
+    if order_by.is_empty() {
+        return Ok(items);
     }
+    let mut keyed = items
+        .into_iter()
+        .map(|item| Ok((sort_key(columns, order_by, row(&item))?, item)))
+        .collect::<Result<Vec<_>, DatacaveError>>()?;
+    keyed.sort_by(|(a, _), (b, _)| {
+        a.iter()
+            .zip(b)
+            .map(|((x, asc), (y, _))| {
+                let o = cmp_data_value(x, y);
+                if *asc { o } else { o.reverse() }
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    Ok(keyed.into_iter().map(|(_, item)| item).collect())
 }
 
-fn operand_value(
-    op: &HavingOperand,
-    col_names: &[String],
+/// Values a result row sorts by, each with whether it sorts ascending.
+fn sort_key(
+    columns: &[Column],
+    order_by: &[OrderBySpec],
     row: &DataRow,
-    group_rows: &[DataRow],
-    schema: &[Column],
-) -> DataValue {
-    match op {
-        HavingOperand::Literal(v) => v.clone(),
-        HavingOperand::ColumnOrAlias(name) => {
-            col_names
-                .iter()
-                .position(|c| c == name)
-                .and_then(|i| row.values.get(i).cloned())
-                .unwrap_or(DataValue::Null)
-        }
-        HavingOperand::Aggregate(func, col) => {
-            let col_idx = col.as_ref().and_then(|c| schema_resolve_column_index(schema, c));
-            let values: Vec<DataValue> = match col_idx {
-                Some(idx) => group_rows
-                    .iter()
-                    .filter_map(|r| {
-                        r.values
-                            .get(idx)
-                            .cloned()
-                            .filter(|v| !matches!(v, DataValue::Null))
-                    })
-                    .collect(),
-                None => group_rows.iter().map(|_| DataValue::Int64(1)).collect(),
-            };
-            match func {
-                AggregateFunc::Count => DataValue::Int64(values.len() as i64),
-                AggregateFunc::Sum => {
-                    let sum: f64 = values.iter().filter_map(try_numeric).sum();
-                    DataValue::Float64(sum)
+) -> Result<Vec<(DataValue, bool)>, DatacaveError> {
+    let mut key = Vec::with_capacity(order_by.len());
+    for spec in order_by {
+        let idx = match &spec.spec {
+            OrderBySpecKind::ColumnOrAlias(name) => match schema_resolve_column_index(columns, name) {
+                Some(i) => i,
+                None => continue,
+            },
+            OrderBySpecKind::Position(pos) => {
+                let i = pos.saturating_sub(1);
+                if i < columns.len() {
+                    i
+                } else {
+                    continue;
                 }
-                AggregateFunc::Avg => {
-                    let sum: f64 = values.iter().filter_map(try_numeric).sum();
-                    let count = values.len() as f64;
-                    DataValue::Float64(if count > 0.0 { sum / count } else { 0.0 })
+            }
+            OrderBySpecKind::Expr(expr) => {
+                if expr.has_aggregate() {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::FEATURE_NOT_SUPPORTED,
+                        "aggregates in ORDER BY must be selected and referenced by alias or position",
+                    ));
                 }
-                AggregateFunc::Min => values
-                    .iter()
-                    .filter_map(try_numeric)
-                    .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                    .map(DataValue::Float64)
-                    .unwrap_or(DataValue::Null),
-                AggregateFunc::Max => values
-                    .iter()
-                    .filter_map(try_numeric)
-                    .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                    .map(DataValue::Float64)
-                    .unwrap_or(DataValue::Null),
+                key.push((expr.eval(columns, &row.values)?, spec.asc));
+                continue;
             }
-        }
+        };
+        key.push((row.values.get(idx).cloned().unwrap_or(DataValue::Null), spec.asc));
     }
+    Ok(key)
 }
 
 fn apply_order_limit(
     columns: &[Column],
-    mut rows: Vec<DataRow>,
+    rows: Vec<DataRow>,
     order_by: &[OrderBySpec],
     limit: Option<u64>,
     offset: Option<u64>,
 ) -> Result<Vec<DataRow>, DatacaveError> {
-    if !order_by.is_empty() {
-        rows.sort_by(|a, b| {
-            for spec in order_by {
-                let (idx, asc) = match &spec.spec {
-                    OrderBySpecKind::ColumnOrAlias(name) => {
-                        let i = schema_resolve_column_index(columns, name);
-                        match i {
-                            Some(i) => (i, spec.asc),
-                            None => continue,
-                        }
-                    }
-                    OrderBySpecKind::Position(pos) => {
-                        let i = pos.saturating_sub(1);
-                        if i < columns.len() {
-                            (i, spec.asc)
-                        } else {
-                            continue;
-                        }
-                    }
-                };
-                let a_val = a.values.get(idx).unwrap_or(&DataValue::Null);
-                let b_val = b.values.get(idx).unwrap_or(&DataValue::Null);
-                let o = cmp_data_value(a_val, b_val);
-                if o != std::cmp::Ordering::Equal {
-                    return if asc { o } else { o.reverse() };
-                }
-            }
-            std::cmp::Ordering::Equal
-        });
-    }
+    let rows = sort_rows(columns, order_by, rows, |row| row)?;
 
     let skip = offset.unwrap_or(0) as usize;
     let take = limit.map(|n| n as usize).unwrap_or(rows.len());
@@ -781,53 +2911,76 @@ fn apply_order_limit(
     Ok(rows)
 }
 
-fn evaluate_where(cond: &WhereCond, row: &DataRow, schema: &[Column]) -> bool {
-    match cond {
-        WhereCond::Predicate(p) => evaluate_where_predicate(p, row, schema),
+fn evaluate_where(cond: &WhereCond, row: &DataRow, schema: &[Column]) -> Result<bool, DatacaveError> {
+    Ok(match cond {
+        WhereCond::Predicate(p) => evaluate_where_predicate(p, row, schema)?,
         WhereCond::And(left, right) => {
-            evaluate_where(left, row, schema) && evaluate_where(right, row, schema)
+            evaluate_where(left, row, schema)? && evaluate_where(right, row, schema)?
         }
         WhereCond::Or(left, right) => {
-            evaluate_where(left, row, schema) || evaluate_where(right, row, schema)
+            evaluate_where(left, row, schema)? || evaluate_where(right, row, schema)?
         }
-    }
+        WhereCond::Expr(expr) => truth(expr.eval(schema, &row.values)?, "WHERE")? == Some(true),
+    })
 }
 
-fn evaluate_where_predicate(p: &WherePredicate, row: &DataRow, schema: &[Column]) -> bool {
-    let left_val = where_operand_value(&p.left, row, schema);
-    let right_val = where_operand_value(&p.right, row, schema);
-    match p.op {
-        HavingOp::Eq => left_val == right_val,
-        HavingOp::NotEq => left_val != right_val,
-        HavingOp::Gt => cmp_data_value(&left_val, &right_val) == std::cmp::Ordering::Greater,
-        HavingOp::Gte => {
-            let o = cmp_data_value(&left_val, &right_val);
-            o == std::cmp::Ordering::Greater || o == std::cmp::Ordering::Equal
-        }
-        HavingOp::Lt => cmp_data_value(&left_val, &right_val) == std::cmp::Ordering::Less,
-        HavingOp::Lte => {
-            let o = cmp_data_value(&left_val, &right_val);
-            o == std::cmp::Ordering::Less || o == std::cmp::Ordering::Equal
-        }
-    }
+/// Compares like the expression path does, so a predicate means the same whichever of the
+/// two evaluates it: NULL matches nothing, and values of unrelated types are an error.
+fn evaluate_where_predicate(
+    p: &WherePredicate,
+    row: &DataRow,
+    schema: &[Column],
+) -> Result<bool, DatacaveError> {
+    let left_val = where_operand_value(&p.left, &p.right, row, schema)?;
+    let right_val = where_operand_value(&p.right, &p.left, row, schema)?;
+    let op = match p.op {
+        HavingOp::Eq => BinaryOp::Eq,
+        HavingOp::NotEq => BinaryOp::NotEq,
+        HavingOp::Gt => BinaryOp::Gt,
+        HavingOp::Gte => BinaryOp::GtEq,
+        HavingOp::Lt => BinaryOp::Lt,
+        HavingOp::Lte => BinaryOp::LtEq,
+    };
+    Ok(compare(op, &left_val, &right_val)? == DataValue::Bool(true))
 }
 
-fn where_operand_value(op: &WhereOperand, row: &DataRow, schema: &[Column]) -> DataValue {
-    match op {
-        WhereOperand::Literal(v) => v.clone(),
-        WhereOperand::Column(name) => schema_resolve_column_index(schema, name)
+/// Value of `op` in `row`. A string literal compared with `other`, a column, is read as
+/// that column's type, as the expression path does.
+fn where_operand_value(
+    op: &WhereOperand,
+    other: &WhereOperand,
+    row: &DataRow,
+    schema: &[Column],
+) -> Result<DataValue, DatacaveError> {
+    Ok(match (op, other) {
+        (WhereOperand::Literal(v @ DataValue::String(_)), WhereOperand::Column(name)) => {
+            match schema_resolve_column_index(schema, name) {
+                Some(i) => literal_as(v, schema[i].data_type)?,
+                None => v.clone(),
+            }
+        }
+        (WhereOperand::Literal(v), _) => v.clone(),
+        (WhereOperand::Column(name), _) => schema_resolve_column_index(schema, name)
             .and_then(|i| row.values.get(i).cloned())
             .unwrap_or(DataValue::Null),
-    }
+    })
 }
 
-fn filter_rows_by_where(rows: Vec<DataRow>, cond: Option<&WhereCond>, schema: &[Column]) -> Vec<DataRow> {
+fn filter_rows_by_where(
+    rows: Vec<DataRow>,
+    cond: Option<&WhereCond>,
+    schema: &[Column],
+) -> Result<Vec<DataRow>, DatacaveError> {
     let Some(cond) = cond else {
-        return rows;
+        return Ok(rows);
     };
-    rows.into_iter()
-        .filter(|row| evaluate_where(cond, row, schema))
-        .collect()
+    let mut kept = Vec::with_capacity(rows.len());
+    for row in rows {
+        if evaluate_where(cond, &row, schema)? {
+            kept.push(row);
+        }
+    }
+    Ok(kept)
 }
 
 fn cmp_data_value(a: &DataValue, b: &DataValue) -> std::cmp::Ordering {
@@ -852,113 +3005,59 @@ fn cmp_data_value(a: &DataValue, b: &DataValue) -> std::cmp::Ordering {
 }
 
 
-fn compute_aggregates(
-    projection: &[ProjectionItem],
-    schema: &[Column],
-    rows: &[DataRow],
-) -> Result<(Vec<Column>, DataRow), DatacaveError> {
-    let mut out_columns = Vec::new();
-    let mut out_values = Vec::new();
-    for item in projection {
-        match item {
-                ProjectionItem::Aggregate(func, col, _alias) => {
-                    let col_idx = col.as_ref().and_then(|c| schema_resolve_column_index(schema, c));
-                let values: Vec<DataValue> = match col_idx {
-                    Some(idx) => rows
-                        .iter()
-                        .filter_map(|r| {
-                            r.values.get(idx).cloned()
-                                .filter(|v| !matches!(v, DataValue::Null))
-                        })
-                        .collect(),
-                    None => rows.iter().map(|_| DataValue::Int64(1)).collect(),
-                };
-                let result = match func {
-                    AggregateFunc::Count => DataValue::Int64(values.len() as i64),
-                    AggregateFunc::Sum => {
-                        let sum: f64 = values.iter().filter_map(try_numeric).sum();
-                        DataValue::Float64(sum)
-                    }
-                    AggregateFunc::Avg => {
-                        let sum: f64 = values.iter().filter_map(try_numeric).sum();
-                        let count = values.len() as f64;
-                        DataValue::Float64(if count > 0.0 { sum / count } else { 0.0 })
-                    }
-                    AggregateFunc::Min => values
-                        .iter()
-                        .filter_map(try_numeric)
-                        .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                        .map(DataValue::Float64)
-                        .unwrap_or(DataValue::Null),
-                    AggregateFunc::Max => values
-                        .iter()
-                        .filter_map(try_numeric)
-                        .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                        .map(DataValue::Float64)
-                        .unwrap_or(DataValue::Null),
-                };
-                let agg_name = match func {
-                    AggregateFunc::Count => "count",
-                    AggregateFunc::Sum => "sum",
-                    AggregateFunc::Avg => "avg",
-                    AggregateFunc::Min => "min",
-                    AggregateFunc::Max => "max",
-                };
-                out_columns.push(Column {
-                    name: agg_name.to_string(),
-                    data_type: "BIGINT".to_string(),
-                });
-                out_values.push(result);
-            }
-            _ => {}
-        }
-    }
-    Ok((out_columns, DataRow { values: out_values }))
-}
-
-fn try_numeric(v: &DataValue) -> Option<f64> {
-    match v {
-        DataValue::Int64(n) => Some(*n as f64),
-        DataValue::Float64(n) => Some(*n),
-        _ => None,
-    }
-}
-
 fn apply_projection(
     projection: &[ProjectionItem],
     schema: &[Column],
     rows: &[DataRow],
-) -> (Vec<Column>, Vec<DataRow>) {
+) -> Result<(Vec<Column>, Vec<DataRow>), DatacaveError> {
     let use_all = projection.is_empty()
         || projection
             .iter()
             .any(|p| matches!(p, ProjectionItem::AllColumns));
     if use_all {
-        return (
+        return Ok((
             schema.to_vec(),
-            rows.iter().cloned().collect(),
-        );
+            rows.to_vec(),
+        ));
     }
-    let mut col_indices = Vec::new();
     let mut columns = Vec::new();
+    let mut sources = Vec::new();
     for item in projection {
-        if let ProjectionItem::Column(name, _) = item {
-            if let Some((idx, col)) = projection_resolve_column(schema, name) {
-                col_indices.push(idx);
-                columns.push(col.clone());
+        match item {
+            ProjectionItem::Column(name, alias) => {
+                if let Some((idx, col)) = projection_resolve_column(schema, name) {
+                    columns.push(Column {
+                        name: alias.clone().unwrap_or_else(|| col.name.clone()),
+                        data_type: col.data_type,
+                    });
+                    sources.push(OutputSource::Input(idx));
+                }
             }
+            ProjectionItem::Expr(expr, name) => {
+                expr.check_columns(schema)?;
+                columns.push(Column {
+                    name: name.clone(),
+                    data_type: expr.data_type(schema),
+                });
+                sources.push(OutputSource::Expr(expr));
+            }
+            ProjectionItem::AllColumns => {}
         }
     }
-    let projected_rows: Vec<DataRow> = rows
+    let projected_rows = rows
         .iter()
-        .map(|r| DataRow {
-            values: col_indices
+        .map(|r| {
+            let values = sources
                 .iter()
-                .filter_map(|&i| r.values.get(i).cloned())
-                .collect(),
+                .map(|source| match source {
+                    OutputSource::Input(i) => Ok(r.values.get(*i).cloned().unwrap_or(DataValue::Null)),
+                    OutputSource::Expr(expr) => expr.eval(schema, &r.values),
+                })
+                .collect::<Result<Vec<_>, DatacaveError>>()?;
+            Ok(DataRow { values })
         })
-        .collect();
-    (columns, projected_rows)
+        .collect::<Result<Vec<_>, DatacaveError>>()?;
+    Ok((columns, projected_rows))
 }
 
 /// Resolve column by name: exact match first, then qualified (table.col) matches col, then unqualified first match.
@@ -967,7 +3066,7 @@ fn projection_resolve_column<'a>(schema: &'a [Column], name: &str) -> Option<(us
 }
 
 /// Resolve column index: exact match (case-insensitive), then qualified table.col match, then unqualified first match (deterministic).
-fn schema_resolve_column_index(schema: &[Column], name: &str) -> Option<usize> {
+pub(crate) fn schema_resolve_column_index(schema: &[Column], name: &str) -> Option<usize> {
     if let Some((i, _)) = schema.iter().enumerate().find(|(_, c)| c.name.eq_ignore_ascii_case(name)) {
         return Some(i);
     }
@@ -0,0 +1,822 @@
+//! Scalar expressions: the typed tree projections, WHERE, GROUP BY, HAVING and ORDER BY
+//! are planned into, and its evaluation.
+
+use crate::executor::schema_resolve_column_index;
+use crate::planner::{expr_to_value, AggregateFunc};
+use datacave_core::error::{sqlstate, DatacaveError};
+use datacave_core::types::{Column, DataRow, DataType, DataValue};
+use sqlparser::ast::{BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, UnaryOperator, Value};
+use std::cmp::Ordering;
+
+/// A scalar SQL expression. Columns are bound by name when it is evaluated, so one plan
+/// serves table rows, joined rows and result rows alike.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScalarExpr {
+    Literal(DataValue),
+    Column(String),
+    Unary {
+        op: UnaryOp,
+        expr: Box<ScalarExpr>,
+    },
+    Binary {
+        left: Box<ScalarExpr>,
+        op: BinaryOp,
+        right: Box<ScalarExpr>,
+    },
+    IsNull {
+        expr: Box<ScalarExpr>,
+        negated: bool,
+    },
+    /// `CASE [operand] WHEN .. THEN .. [ELSE ..] END`. With an operand, each WHEN value is
+    /// compared to it; without one, each WHEN is a condition.
+    Case {
+        operand: Option<Box<ScalarExpr>>,
+        branches: Vec<(ScalarExpr, ScalarExpr)>,
+        otherwise: Option<Box<ScalarExpr>>,
+    },
+    Function {
+        func: ScalarFunc,
+        args: Vec<ScalarExpr>,
+    },
+    /// Aggregate over the rows of a group; no argument is `COUNT(*)`.
+    Aggregate {
+        func: AggregateFunc,
+        arg: Option<Box<ScalarExpr>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Plus,
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+/// Built-in scalar functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarFunc {
+    Abs,
+    Round,
+    Floor,
+    Ceil,
+    Lower,
+    Upper,
+    Length,
+    Coalesce,
+    NullIf,
+}
+
+impl UnaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "NOT",
+        }
+    }
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Concat => "||",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+
+    fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq
+        )
+    }
+}
+
+impl ScalarFunc {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "ABS" => ScalarFunc::Abs,
+            "ROUND" => ScalarFunc::Round,
+            "FLOOR" => ScalarFunc::Floor,
+            "CEIL" | "CEILING" => ScalarFunc::Ceil,
+            "LOWER" => ScalarFunc::Lower,
+            "UPPER" => ScalarFunc::Upper,
+            "LENGTH" | "CHAR_LENGTH" | "CHARACTER_LENGTH" => ScalarFunc::Length,
+            "COALESCE" => ScalarFunc::Coalesce,
+            "NULLIF" => ScalarFunc::NullIf,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScalarFunc::Abs => "abs",
+            ScalarFunc::Round => "round",
+            ScalarFunc::Floor => "floor",
+            ScalarFunc::Ceil => "ceil",
+            ScalarFunc::Lower => "lower",
+            ScalarFunc::Upper => "upper",
+            ScalarFunc::Length => "length",
+            ScalarFunc::Coalesce => "coalesce",
+            ScalarFunc::NullIf => "nullif",
+        }
+    }
+
+    fn takes(&self, args: usize) -> bool {
+        match self {
+            ScalarFunc::Coalesce => args >= 1,
+            ScalarFunc::NullIf => args == 2,
+            ScalarFunc::Round => args == 1 || args == 2,
+            _ => args == 1,
+        }
+    }
+}
+
+impl ScalarExpr {
+    /// Plans `expr`, failing on syntax the evaluator does not implement.
+    pub fn plan(expr: &Expr) -> Result<Self, DatacaveError> {
+        Ok(match expr {
+            Expr::Nested(inner) => Self::plan(inner)?,
+            Expr::Identifier(ident) => ScalarExpr::Column(ident.value.clone()),
+            Expr::CompoundIdentifier(parts) => ScalarExpr::Column(
+                parts.iter().map(|p| p.value.as_str()).collect::<Vec<_>>().join("."),
+            ),
+            Expr::Value(
+                Value::Number(..) | Value::SingleQuotedString(_) | Value::Boolean(_) | Value::Null,
+            ) => ScalarExpr::Literal(expr_to_value(expr)),
+            Expr::UnaryOp {
+                op: UnaryOperator::Minus,
+                expr: inner,
+            } if matches!(**inner, Expr::Value(Value::Number(..))) => {
+                ScalarExpr::Literal(expr_to_value(expr))
+            }
+            Expr::UnaryOp { op, expr: inner } => {
+                let op = match op {
+                    UnaryOperator::Plus => UnaryOp::Plus,
+                    UnaryOperator::Minus => UnaryOp::Neg,
+                    UnaryOperator::Not => UnaryOp::Not,
+                    _ => return Err(unsupported(expr)),
+                };
+                ScalarExpr::Unary {
+                    op,
+                    expr: Box::new(Self::plan(inner)?),
+                }
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let op = match op {
+                    BinaryOperator::Plus => BinaryOp::Add,
+                    BinaryOperator::Minus => BinaryOp::Sub,
+                    BinaryOperator::Multiply => BinaryOp::Mul,
+                    BinaryOperator::Divide => BinaryOp::Div,
+                    BinaryOperator::Modulo => BinaryOp::Mod,
+                    BinaryOperator::StringConcat => BinaryOp::Concat,
+                    BinaryOperator::Eq => BinaryOp::Eq,
+                    BinaryOperator::NotEq => BinaryOp::NotEq,
+                    BinaryOperator::Lt => BinaryOp::Lt,
+                    BinaryOperator::LtEq => BinaryOp::LtEq,
+                    BinaryOperator::Gt => BinaryOp::Gt,
+                    BinaryOperator::GtEq => BinaryOp::GtEq,
+                    BinaryOperator::And => BinaryOp::And,
+                    BinaryOperator::Or => BinaryOp::Or,
+                    _ => return Err(unsupported(expr)),
+                };
+                ScalarExpr::Binary {
+                    left: Box::new(Self::plan(left)?),
+                    op,
+                    right: Box::new(Self::plan(right)?),
+                }
+            }
+            Expr::IsNull(inner) => ScalarExpr::IsNull {
+                expr: Box::new(Self::plan(inner)?),
+                negated: false,
+            },
+            Expr::IsNotNull(inner) => ScalarExpr::IsNull {
+                expr: Box::new(Self::plan(inner)?),
+                negated: true,
+            },
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => ScalarExpr::Case {
+                operand: operand.as_deref().map(Self::plan).transpose()?.map(Box::new),
+                branches: conditions
+                    .iter()
+                    .zip(results)
+                    .map(|(when, then)| Ok((Self::plan(when)?, Self::plan(then)?)))
+                    .collect::<Result<_, DatacaveError>>()?,
+                otherwise: else_result.as_deref().map(Self::plan).transpose()?.map(Box::new),
+            },
+            Expr::Function(func) => Self::plan_function(func)?,
+            _ => return Err(unsupported(expr)),
+        })
+    }
+
+    fn plan_function(func: &Function) -> Result<Self, DatacaveError> {
+        if func.over.is_some() {
+            return Err(DatacaveError::NotSupported(format!("window function {func}")));
+        }
+        let name = func
+            .name
+            .0
+            .last()
+            .map(|ident| ident.value.to_uppercase())
+            .unwrap_or_default();
+        let mut args = Vec::with_capacity(func.args.len());
+        let mut star = false;
+        for arg in &func.args {
+            match arg {
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => args.push(Self::plan(expr)?),
+                FunctionArg::Unnamed(
+                    FunctionArgExpr::Wildcard | FunctionArgExpr::QualifiedWildcard(_),
+                ) => star = true,
+                _ => return Err(DatacaveError::NotSupported(format!("named arguments in {func}"))),
+            }
+        }
+        let undefined = || {
+            DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_FUNCTION,
+                format!("function {} does not exist", name.to_lowercase()),
+            )
+        };
+        if let Some(agg) = AggregateFunc::parse(&name) {
+            let arg = match (star, args.pop(), args.is_empty()) {
+                (true, None, _) if agg == AggregateFunc::Count => None,
+                (false, Some(arg), true) => Some(Box::new(arg)),
+                _ => return Err(undefined()),
+            };
+            if arg.as_deref().is_some_and(ScalarExpr::has_aggregate) {
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::GROUPING_ERROR,
+                    "aggregate function calls cannot be nested",
+                ));
+            }
+            return Ok(ScalarExpr::Aggregate { func: agg, arg });
+        }
+        match ScalarFunc::parse(&name) {
+            Some(func) if !star && func.takes(args.len()) => Ok(ScalarExpr::Function { func, args }),
+            _ => Err(undefined()),
+        }
+    }
+
+    /// Whether the expression calls an aggregate function.
+    pub fn has_aggregate(&self) -> bool {
+        matches!(self, ScalarExpr::Aggregate { .. })
+            || self.children().into_iter().any(ScalarExpr::has_aggregate)
+    }
+
+    /// Adds the columns the expression reads to `out`, each once.
+    pub(crate) fn collect_columns(&self, out: &mut Vec<String>) {
+        match self {
+            ScalarExpr::Column(name) => {
+                if !out.contains(name) {
+                    out.push(name.clone());
+                }
+            }
+            _ => {
+                for child in self.children() {
+                    child.collect_columns(out);
+                }
+            }
+        }
+    }
+
+    /// Fails if the expression reads a column missing from `columns`, before any row is
+    /// evaluated.
+    pub(crate) fn check_columns(&self, columns: &[Column]) -> Result<(), DatacaveError> {
+        let mut names = Vec::new();
+        self.collect_columns(&mut names);
+        match names
+            .into_iter()
+            .find(|name| schema_resolve_column_index(columns, name).is_none())
+        {
+            Some(name) => Err(undefined_column(&name)),
+            None => Ok(()),
+        }
+    }
+
+    /// A column the expression reads outside an aggregate and outside every subexpression
+    /// `grouped` accepts, which a grouped query cannot give a single value per group.
+    pub(crate) fn ungrouped_column(&self, grouped: &dyn Fn(&ScalarExpr) -> bool) -> Option<&str> {
+        if grouped(self) {
+            return None;
+        }
+        match self {
+            ScalarExpr::Column(name) => Some(name.as_str()),
+            ScalarExpr::Aggregate { .. } => None,
+            _ => self
+                .children()
+                .into_iter()
+                .find_map(|child| child.ungrouped_column(grouped)),
+        }
+    }
+
+    fn children(&self) -> Vec<&ScalarExpr> {
+        match self {
+            ScalarExpr::Literal(_) | ScalarExpr::Column(_) => Vec::new(),
+            ScalarExpr::Unary { expr, .. } | ScalarExpr::IsNull { expr, .. } => vec![&**expr],
+            ScalarExpr::Binary { left, right, .. } => vec![&**left, &**right],
+            ScalarExpr::Case {
+                operand,
+                branches,
+                otherwise,
+            } => operand
+                .as_deref()
+                .into_iter()
+                .chain(branches.iter().flat_map(|(when, then)| [when, then]))
+                .chain(otherwise.as_deref())
+                .collect(),
+            ScalarExpr::Function { args, .. } => args.iter().collect(),
+            ScalarExpr::Aggregate { arg, .. } => arg.as_deref().into_iter().collect(),
+        }
+    }
+
+    /// Type of the values the expression yields over rows following `columns`. Integer
+    /// arithmetic is reported as `bigint`, the width it is computed in.
+    pub fn data_type(&self, columns: &[Column]) -> DataType {
+        let widened = |t: DataType| if t.is_integer() { DataType::BigInt } else { t };
+        match self {
+            ScalarExpr::Literal(value) => DataType::for_value(value),
+            ScalarExpr::Column(name) => schema_resolve_column_index(columns, name)
+                .map(|i| columns[i].data_type)
+                .unwrap_or(DataType::Text),
+            ScalarExpr::Unary {
+                op: UnaryOp::Not, ..
+            }
+            | ScalarExpr::IsNull { .. } => DataType::Boolean,
+            ScalarExpr::Unary { expr, .. } => widened(expr.data_type(columns)),
+            ScalarExpr::Binary { left, op, right } => match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+                    let (left, right) = (left.data_type(columns), right.data_type(columns));
+                    let exact = |t: DataType| t.is_integer() || matches!(t, DataType::Numeric { .. });
+                    if left.is_integer() && right.is_integer() {
+                        DataType::BigInt
+                    } else if exact(left) && exact(right) {
+                        DataType::Numeric {
+                            precision: None,
+                            scale: None,
+                        }
+                    } else {
+                        DataType::Double
+                    }
+                }
+                BinaryOp::Concat => DataType::Text,
+                _ => DataType::Boolean,
+            },
+            ScalarExpr::Case {
+                branches,
+                otherwise,
+                ..
+            } => first_typed(
+                branches.iter().map(|(_, then)| then).chain(otherwise.as_deref()),
+                columns,
+            ),
+            ScalarExpr::Function { func, args } => match func {
+                ScalarFunc::Lower | ScalarFunc::Upper => DataType::Text,
+                ScalarFunc::Length => DataType::Int,
+                ScalarFunc::Coalesce | ScalarFunc::NullIf => first_typed(args.iter(), columns),
+                ScalarFunc::Abs | ScalarFunc::Round | ScalarFunc::Floor | ScalarFunc::Ceil => {
+                    widened(args[0].data_type(columns))
+                }
+            },
+            ScalarExpr::Aggregate { func, .. } => func.result_type(),
+        }
+    }
+
+    /// Value of the expression for `row`, whose values follow `columns`.
+    pub fn eval(&self, columns: &[Column], row: &[DataValue]) -> Result<DataValue, DatacaveError> {
+        self.evaluate(columns, row, None)
+    }
+
+    /// Value of the expression for a group of rows following `columns`: aggregates run over
+    /// `group`, and everything else reads `row`, a row holding the group's values.
+    pub fn eval_group(
+        &self,
+        columns: &[Column],
+        row: &[DataValue],
+        group: &[DataRow],
+    ) -> Result<DataValue, DatacaveError> {
+        self.evaluate(columns, row, Some(group))
+    }
+
+    fn evaluate(
+        &self,
+        columns: &[Column],
+        row: &[DataValue],
+        group: Option<&[DataRow]>,
+    ) -> Result<DataValue, DatacaveError> {
+        let eval = |expr: &ScalarExpr| expr.evaluate(columns, row, group);
+        match self {
+            ScalarExpr::Literal(value) => Ok(value.clone()),
+            ScalarExpr::Column(name) => {
+                let idx = schema_resolve_column_index(columns, name)
+                    .ok_or_else(|| undefined_column(name))?;
+                Ok(row.get(idx).cloned().unwrap_or(DataValue::Null))
+            }
+            ScalarExpr::Unary { op, expr } => unary(*op, eval(expr)?),
+            // AND and OR skip their right side once the left decides, so it can be guarded.
+            ScalarExpr::Binary {
+                left,
+                op: BinaryOp::And,
+                right,
+            } => {
+                let left = truth(eval(left)?, "AND")?;
+                if left == Some(false) {
+                    return Ok(DataValue::Bool(false));
+                }
+                Ok(match (left, truth(eval(right)?, "AND")?) {
+                    (_, Some(false)) => DataValue::Bool(false),
+                    (Some(true), Some(true)) => DataValue::Bool(true),
+                    _ => DataValue::Null,
+                })
+            }
+            ScalarExpr::Binary {
+                left,
+                op: BinaryOp::Or,
+                right,
+            } => {
+                let left = truth(eval(left)?, "OR")?;
+                if left == Some(true) {
+                    return Ok(DataValue::Bool(true));
+                }
+                Ok(match (left, truth(eval(right)?, "OR")?) {
+                    (_, Some(true)) => DataValue::Bool(true),
+                    (Some(false), Some(false)) => DataValue::Bool(false),
+                    _ => DataValue::Null,
+                })
+            }
+            ScalarExpr::Binary { left, op, right } if op.is_comparison() => {
+                let left_value = untyped_literal(left)
+                    .map(|text| literal_as(text, right.data_type(columns)))
+                    .unwrap_or_else(|| eval(left))?;
+                let right_value = untyped_literal(right)
+                    .map(|text| literal_as(text, left.data_type(columns)))
+                    .unwrap_or_else(|| eval(right))?;
+                compare(*op, &left_value, &right_value)
+            }
+            ScalarExpr::Binary { left, op, right } => binary(*op, eval(left)?, eval(right)?),
+            ScalarExpr::IsNull { expr, negated } => {
+                Ok(DataValue::Bool((eval(expr)? == DataValue::Null) != *negated))
+            }
+            ScalarExpr::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                let operand = operand.as_deref().map(eval).transpose()?;
+                for (when, then) in branches {
+                    let when = eval(when)?;
+                    let matched = match &operand {
+                        Some(value) => compare(BinaryOp::Eq, value, &when)? == DataValue::Bool(true),
+                        None => truth(when, "CASE/WHEN")? == Some(true),
+                    };
+                    if matched {
+                        return eval(then);
+                    }
+                }
+                otherwise.as_deref().map_or(Ok(DataValue::Null), eval)
+            }
+            ScalarExpr::Function {
+                func: ScalarFunc::Coalesce,
+                args,
+            } => {
+                for arg in args {
+                    let value = eval(arg)?;
+                    if value != DataValue::Null {
+                        return Ok(value);
+                    }
+                }
+                Ok(DataValue::Null)
+            }
+            ScalarExpr::Function { func, args } => {
+                let args = args.iter().map(eval).collect::<Result<Vec<_>, _>>()?;
+                call(*func, &args)
+            }
+            ScalarExpr::Aggregate { func, arg } => {
+                let Some(group) = group else {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::GROUPING_ERROR,
+                        "aggregate functions are not allowed here",
+                    ));
+                };
+                let mut values = Vec::with_capacity(group.len());
+                for member in group {
+                    let value = match arg {
+                        Some(arg) => arg.evaluate(columns, &member.values, None)?,
+                        None => DataValue::Int64(1),
+                    };
+                    if value != DataValue::Null {
+                        values.push(value);
+                    }
+                }
+                Ok(aggregate(*func, &values))
+            }
+        }
+    }
+}
+
+/// The value of `expr` if it is a quoted string literal, whose type follows from context.
+fn untyped_literal(expr: &ScalarExpr) -> Option<&DataValue> {
+    match expr {
+        ScalarExpr::Literal(value @ DataValue::String(_)) => Some(value),
+        _ => None,
+    }
+}
+
+/// A string literal compared with a value of type `other`, read as that type as in
+/// PostgreSQL: `int_col = '5'` compares with 5, and `int_col = 'five'` is an error.
+pub(crate) fn literal_as(literal: &DataValue, other: DataType) -> Result<DataValue, DatacaveError> {
+    if other.is_text() {
+        return Ok(literal.clone());
+    }
+    other.coerce("", literal.clone())
+}
+
+/// Type of the first of `exprs` that is not a bare NULL, which decides the type of CASE
+/// and COALESCE.
+fn first_typed<'a>(mut exprs: impl Iterator<Item = &'a ScalarExpr>, columns: &[Column]) -> DataType {
+    exprs
+        .find(|e| **e != ScalarExpr::Literal(DataValue::Null))
+        .map(|e| e.data_type(columns))
+        .unwrap_or(DataType::Text)
+}
+
+/// Truth value of a condition in `context`: `None` for NULL, and an error for a value that
+/// is not a boolean.
+pub(crate) fn truth(value: DataValue, context: &str) -> Result<Option<bool>, DatacaveError> {
+    match value {
+        DataValue::Null => Ok(None),
+        DataValue::Bool(b) => Ok(Some(b)),
+        other => Err(DatacaveError::sqlstate(
+            sqlstate::DATATYPE_MISMATCH,
+            format!(
+                "argument of {context} must be type boolean, not type {}",
+                type_name(&other)
+            ),
+        )),
+    }
+}
+
+fn unary(op: UnaryOp, value: DataValue) -> Result<DataValue, DatacaveError> {
+    Ok(match (op, value) {
+        (_, DataValue::Null) => DataValue::Null,
+        (UnaryOp::Plus, value @ (DataValue::Int64(_) | DataValue::Float64(_))) => value,
+        (UnaryOp::Neg, DataValue::Int64(v)) => {
+            DataValue::Int64(v.checked_neg().ok_or_else(bigint_out_of_range)?)
+        }
+        (UnaryOp::Neg, DataValue::Float64(v)) => DataValue::Float64(-v),
+        (UnaryOp::Not, DataValue::Bool(b)) => DataValue::Bool(!b),
+        (UnaryOp::Not, other) => return truth(other, "NOT").map(|_| DataValue::Null),
+        (op, other) => {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_FUNCTION,
+                format!("operator does not exist: {} {}", op.symbol(), type_name(&other)),
+            ))
+        }
+    })
+}
+
+fn binary(op: BinaryOp, left: DataValue, right: DataValue) -> Result<DataValue, DatacaveError> {
+    if left == DataValue::Null || right == DataValue::Null {
+        return Ok(DataValue::Null);
+    }
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+            arithmetic(op, &left, &right)
+        }
+        BinaryOp::Concat => match (text(&left), text(&right)) {
+            (Some(l), Some(r))
+                if matches!(left, DataValue::String(_)) || matches!(right, DataValue::String(_)) =>
+            {
+                Ok(DataValue::String(l + &r))
+            }
+            _ => Err(no_operator(op, &left, &right)),
+        },
+        _ => compare(op, &left, &right),
+    }
+}
+
+/// Integers stay integers, with overflow an error; any float operand makes the operation
+/// floating point.
+fn arithmetic(op: BinaryOp, left: &DataValue, right: &DataValue) -> Result<DataValue, DatacaveError> {
+    if let (DataValue::Int64(a), DataValue::Int64(b)) = (left, right) {
+        let (a, b) = (*a, *b);
+        if b == 0 && matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+            return Err(division_by_zero());
+        }
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Sub => a.checked_sub(b),
+            BinaryOp::Mul => a.checked_mul(b),
+            BinaryOp::Div => a.checked_div(b),
+            BinaryOp::Mod => Some(a.wrapping_rem(b)),
+            _ => return Err(no_operator(op, left, right)),
+        };
+        return result.map(DataValue::Int64).ok_or_else(bigint_out_of_range);
+    }
+    let (Some(a), Some(b)) = (numeric(left), numeric(right)) else {
+        return Err(no_operator(op, left, right));
+    };
+    if b == 0.0 && matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+        return Err(division_by_zero());
+    }
+    let result = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        BinaryOp::Mod => a % b,
+        _ => return Err(no_operator(op, left, right)),
+    };
+    if result.is_infinite() && a.is_finite() && b.is_finite() {
+        return Err(DatacaveError::sqlstate(
+            sqlstate::NUMERIC_VALUE_OUT_OF_RANGE,
+            "value out of range: overflow",
+        ));
+    }
+    Ok(DataValue::Float64(result))
+}
+
+/// Comparison of two values of comparable types; numbers compare across integer and float.
+pub(crate) fn compare(op: BinaryOp, left: &DataValue, right: &DataValue) -> Result<DataValue, DatacaveError> {
+    let float = |a: f64, b: f64| a.partial_cmp(&b).unwrap_or_else(|| a.total_cmp(&b));
+    let ordering = match (left, right) {
+        (DataValue::Null, _) | (_, DataValue::Null) => return Ok(DataValue::Null),
+        (DataValue::Int64(a), DataValue::Int64(b)) => a.cmp(b),
+        (DataValue::Int64(a), DataValue::Float64(b)) => float(*a as f64, *b),
+        (DataValue::Float64(a), DataValue::Int64(b)) => float(*a, *b as f64),
+        (DataValue::Float64(a), DataValue::Float64(b)) => float(*a, *b),
+        (DataValue::String(a), DataValue::String(b)) => a.cmp(b),
+        (DataValue::Bool(a), DataValue::Bool(b)) => a.cmp(b),
+        (DataValue::Bytes(a), DataValue::Bytes(b)) => a.cmp(b),
+        _ => return Err(no_operator(op, left, right)),
+    };
+    Ok(DataValue::Bool(match op {
+        BinaryOp::Eq => ordering == Ordering::Equal,
+        BinaryOp::NotEq => ordering != Ordering::Equal,
+        BinaryOp::Lt => ordering == Ordering::Less,
+        BinaryOp::LtEq => ordering != Ordering::Greater,
+        BinaryOp::Gt => ordering == Ordering::Greater,
+        BinaryOp::GtEq => ordering != Ordering::Less,
+        _ => return Err(no_operator(op, left, right)),
+    }))
+}
+
+/// Calls a function other than COALESCE. All of them return NULL for a NULL argument,
+/// except NULLIF, whose second argument may be NULL.
+fn call(func: ScalarFunc, args: &[DataValue]) -> Result<DataValue, DatacaveError> {
+    Ok(match (func, args) {
+        (ScalarFunc::NullIf, [left, right]) => {
+            if compare(BinaryOp::Eq, left, right)? == DataValue::Bool(true) {
+                DataValue::Null
+            } else {
+                left.clone()
+            }
+        }
+        _ if args.contains(&DataValue::Null) => DataValue::Null,
+        (ScalarFunc::Abs, [DataValue::Int64(v)]) => {
+            DataValue::Int64(v.checked_abs().ok_or_else(bigint_out_of_range)?)
+        }
+        (ScalarFunc::Abs, [DataValue::Float64(v)]) => DataValue::Float64(v.abs()),
+        (ScalarFunc::Round | ScalarFunc::Floor | ScalarFunc::Ceil, [DataValue::Int64(v)]) => {
+            DataValue::Int64(*v)
+        }
+        (ScalarFunc::Round, [DataValue::Float64(v)]) => DataValue::Float64(v.round()),
+        (ScalarFunc::Round, [DataValue::Int64(v), DataValue::Int64(digits)]) => {
+            if *digits >= 0 {
+                DataValue::Int64(*v)
+            } else {
+                let scale = 10f64.powi(digits.saturating_neg().min(300) as i32);
+                DataValue::Int64(((*v as f64 / scale).round() * scale) as i64)
+            }
+        }
+        (ScalarFunc::Round, [DataValue::Float64(v), DataValue::Int64(digits)]) => {
+            let scale = 10f64.powi((*digits).clamp(-300, 300) as i32);
+            DataValue::Float64((v * scale).round() / scale)
+        }
+        (ScalarFunc::Floor, [DataValue::Float64(v)]) => DataValue::Float64(v.floor()),
+        (ScalarFunc::Ceil, [DataValue::Float64(v)]) => DataValue::Float64(v.ceil()),
+        (ScalarFunc::Lower, [DataValue::String(s)]) => DataValue::String(s.to_lowercase()),
+        (ScalarFunc::Upper, [DataValue::String(s)]) => DataValue::String(s.to_uppercase()),
+        (ScalarFunc::Length, [DataValue::String(s)]) => DataValue::Int64(s.chars().count() as i64),
+        (ScalarFunc::Length, [DataValue::Bytes(b)]) => DataValue::Int64(b.len() as i64),
+        _ => {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_FUNCTION,
+                format!(
+                    "function {}({}) does not exist",
+                    func.name(),
+                    args.iter().map(type_name).collect::<Vec<_>>().join(", ")
+                ),
+            ))
+        }
+    })
+}
+
+/// Aggregate of the non-NULL values of a group. SUM, AVG, MIN and MAX are computed in
+/// floating point over the numeric values.
+fn aggregate(func: AggregateFunc, values: &[DataValue]) -> DataValue {
+    let numbers = || values.iter().filter_map(numeric);
+    match func {
+        AggregateFunc::Count => DataValue::Int64(values.len() as i64),
+        AggregateFunc::Sum => DataValue::Float64(numbers().sum()),
+        AggregateFunc::Avg => DataValue::Float64(if values.is_empty() {
+            0.0
+        } else {
+            numbers().sum::<f64>() / values.len() as f64
+        }),
+        AggregateFunc::Min => numbers()
+            .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .map(DataValue::Float64)
+            .unwrap_or(DataValue::Null),
+        AggregateFunc::Max => numbers()
+            .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .map(DataValue::Float64)
+            .unwrap_or(DataValue::Null),
+    }
+}
+
+fn numeric(value: &DataValue) -> Option<f64> {
+    match value {
+        DataValue::Int64(v) => Some(*v as f64),
+        DataValue::Float64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+/// Text form of a value in string concatenation.
+fn text(value: &DataValue) -> Option<String> {
+    match value {
+        DataValue::String(s) => Some(s.clone()),
+        DataValue::Int64(v) => Some(v.to_string()),
+        DataValue::Float64(v) => Some(v.to_string()),
+        DataValue::Bool(b) => Some(b.to_string()),
+        DataValue::Null | DataValue::Bytes(_) => None,
+    }
+}
+
+fn type_name(value: &DataValue) -> &'static str {
+    DataType::for_value(value).pg_name()
+}
+
+fn no_operator(op: BinaryOp, left: &DataValue, right: &DataValue) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::UNDEFINED_FUNCTION,
+        format!(
+            "operator does not exist: {} {} {}",
+            type_name(left),
+            op.symbol(),
+            type_name(right)
+        ),
+    )
+}
+
+fn undefined_column(name: &str) -> DatacaveError {
+    DatacaveError::sqlstate(
+        sqlstate::UNDEFINED_COLUMN,
+        format!("column \"{name}\" does not exist"),
+    )
+}
+
+fn bigint_out_of_range() -> DatacaveError {
+    DatacaveError::sqlstate(sqlstate::NUMERIC_VALUE_OUT_OF_RANGE, "bigint out of range")
+}
+
+fn division_by_zero() -> DatacaveError {
+    DatacaveError::sqlstate(sqlstate::DIVISION_BY_ZERO, "division by zero")
+}
+
+fn unsupported(expr: &Expr) -> DatacaveError {
+    DatacaveError::NotSupported(format!("expression {expr}"))
+}
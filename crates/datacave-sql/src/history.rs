@@ -0,0 +1,112 @@
+use crate::catalog::HISTORY_KEY_PREFIX;
+use datacave_core::error::{sqlstate, DatacaveError};
+use datacave_core::mvcc::Version;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Storage key of the history entry for commits at `millis` since the Unix epoch. The
+/// value is the newest version committed in that millisecond, big-endian.
+pub fn history_key(millis: u64) -> Vec<u8> {
+    let mut out = HISTORY_KEY_PREFIX.to_vec();
+    out.extend_from_slice(&millis.to_be_bytes());
+    out
+}
+
+/// Version stored in a history entry or as the history horizon.
+pub(crate) fn decode_version(bytes: &[u8]) -> Result<Version, DatacaveError> {
+    bytes
+        .try_into()
+        .map(Version::from_be_bytes)
+        .map_err(|_| DatacaveError::Storage("malformed history entry".into()))
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Milliseconds since the Unix epoch of a timestamp such as `2024-05-01 12:30:00`,
+/// `2024-05-01T12:30:00.250Z` or `2024-05-01 14:30:00+02:00`. The time defaults to
+/// midnight and the zone to UTC.
+pub fn parse_timestamp(text: &str) -> Result<u64, DatacaveError> {
+    let invalid = || {
+        DatacaveError::sqlstate(
+            sqlstate::INVALID_DATETIME_FORMAT,
+            format!("invalid input syntax for type timestamp: \"{text}\""),
+        )
+    };
+    let trimmed = text.trim();
+    let (date, time) = match trimmed.find([' ', 'T']) {
+        Some(split) => (&trimmed[..split], trimmed[split + 1..].trim()),
+        None => (trimmed, ""),
+    };
+    let mut fields = date.splitn(3, '-');
+    let mut field = || fields.next().and_then(|f| f.parse::<u32>().ok()).ok_or_else(invalid);
+    let (year, month, day) = (field()?, field()?, field()?);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    let (time, offset_secs) = split_zone(time).ok_or_else(invalid)?;
+    let (clock, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut clock_fields = clock.split(':');
+    let mut clock_field = |max: u32| match clock_fields.next() {
+        None | Some("") => Some(0),
+        Some(f) => f.parse::<u32>().ok().filter(|v| *v <= max),
+    };
+    let (hour, minute, second) = (
+        clock_field(23).ok_or_else(invalid)?,
+        clock_field(59).ok_or_else(invalid)?,
+        clock_field(59).ok_or_else(invalid)?,
+    );
+    if clock_fields.next().is_some() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let millis: u64 = format!("{fraction:0<3}")[..3].parse().map_err(|_| invalid())?;
+    let seconds = days_from_civil(year.into(), month, day) * 86_400
+        + i64::from(hour * 3600 + minute * 60 + second)
+        - offset_secs;
+    let seconds = u64::try_from(seconds).map_err(|_| invalid())?;
+    Ok(seconds * 1000 + millis)
+}
+
+/// Splits a trailing `Z` or `+HH[:MM]` / `-HH[:MM]` zone off a time of day, returning the
+/// zone's offset from UTC in seconds.
+fn split_zone(time: &str) -> Option<(&str, i64)> {
+    if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        return Some((time, 0));
+    }
+    let Some(sign_at) = time.rfind(['+', '-']) else {
+        return Some((time, 0));
+    };
+    let (time, zone) = time.split_at(sign_at);
+    let sign = if zone.starts_with('-') { -1 } else { 1 };
+    let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+    if !matches!(digits.len(), 2 | 4) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = if digits.len() == 4 { digits[2..].parse().ok()? } else { 0 };
+    Some((time.trim_end(), sign * (hours * 3600 + minutes * 60)))
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+        + i64::from(day)
+        - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
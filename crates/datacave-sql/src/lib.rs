@@ -1,11 +1,30 @@
 pub mod catalog;
 pub mod executor;
+pub mod expr;
+pub mod history;
+pub mod lock;
 pub mod parser;
 pub mod planner;
+pub mod transaction;
 pub mod vectorized;
 
-pub use executor::SqlExecutor;
+pub use catalog::{
+    catalog_key, decode_schema, encode_schema, is_system_key, CATALOG_KEY_PREFIX,
+    HISTORY_HORIZON_KEY, HISTORY_KEY_PREFIX, INDEX_KEY_PREFIX, SCHEMA_FORMAT_VERSION, SEQUENCE_KEY_PREFIX,
+    TENANT_KEY_PREFIX,
+};
+pub use executor::{
+    decode_row, decode_row_id, decode_sequence, encode_primary_row_key, encode_row, encode_row_key,
+    index_key_prefix, row_index_entries,
+    encode_table_row,
+    row_format_version,
+    sequence_key, table_key_prefix, table_row_range, table_tenant_key, RowValidator, SqlExecutor,
+    ROW_FORMAT_VERSION,
+};
+pub use history::{history_key, parse_timestamp};
 pub use parser::parse_sql;
+pub use transaction::{IsolationLevel, TxnId};
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests;
@@ -0,0 +1,170 @@
+use crate::transaction::TxnId;
+use datacave_core::error::{sqlstate, DatacaveError};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Strength of a row lock taken by a locking read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// `FOR SHARE`: other transactions may share the lock but not update-lock the row.
+    Share,
+    /// `FOR UPDATE`: excludes every other lock on the row.
+    Update,
+}
+
+/// What a locking read does when a row is locked by another transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockWait {
+    /// Wait until the holders end.
+    #[default]
+    Block,
+    /// `NOWAIT`: fail with `55P03`.
+    NoWait,
+    /// `SKIP LOCKED`: leave the row out of the result.
+    SkipLocked,
+}
+
+/// Locking clause of a `SELECT ... FOR UPDATE` / `FOR SHARE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowLock {
+    pub mode: LockMode,
+    pub wait: LockWait,
+}
+
+type LockKey = (String, Vec<u8>);
+
+/// Row locks held by transactions, keyed by table and row key. Locks are held until the
+/// transaction that took them ends. A transaction that would wait on a chain of waiting
+/// transactions leading back to itself fails with `40P01` instead, breaking the deadlock.
+#[derive(Debug, Default)]
+pub(crate) struct LockManager {
+    state: Mutex<LockTable>,
+    /// Woken whenever a transaction releases its locks.
+    released: Notify,
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    /// Holders of each locked row, with the mode each holds it in.
+    held: HashMap<LockKey, Vec<(TxnId, LockMode)>>,
+    /// Rows locked by each transaction.
+    owned: HashMap<TxnId, Vec<LockKey>>,
+    /// Transactions each waiting transaction waits for: the wait-for graph.
+    waits_for: HashMap<TxnId, Vec<TxnId>>,
+}
+
+impl LockTable {
+    /// Other transactions holding `key` in a mode that conflicts with `mode`.
+    fn blockers(&self, owner: TxnId, key: &LockKey, mode: LockMode) -> Vec<TxnId> {
+        self.held
+            .get(key)
+            .map(|holders| {
+                holders
+                    .iter()
+                    .filter(|(holder, held)| {
+                        *holder != owner && (mode == LockMode::Update || *held == LockMode::Update)
+                    })
+                    .map(|(holder, _)| *holder)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn grant(&mut self, owner: TxnId, key: LockKey, mode: LockMode) {
+        let holders = self.held.entry(key.clone()).or_default();
+        match holders.iter_mut().find(|(holder, _)| *holder == owner) {
+            Some(entry) => {
+                if mode == LockMode::Update {
+                    entry.1 = mode;
+                }
+            }
+            None => {
+                holders.push((owner, mode));
+                self.owned.entry(owner).or_default().push(key);
+            }
+        }
+    }
+
+    /// Whether `owner` waiting for `blockers` would close a cycle in the wait-for graph.
+    fn closes_cycle(&self, owner: TxnId, blockers: &[TxnId]) -> bool {
+        let mut stack = blockers.to_vec();
+        let mut seen = HashSet::new();
+        while let Some(txn) = stack.pop() {
+            if txn == owner {
+                return true;
+            }
+            if seen.insert(txn) {
+                if let Some(next) = self.waits_for.get(&txn) {
+                    stack.extend(next);
+                }
+            }
+        }
+        false
+    }
+}
+
+impl LockManager {
+    /// Locks row `key` of `table` for `owner`. Returns false, without locking, when another
+    /// transaction holds a conflicting lock and `lock.wait` is [`LockWait::SkipLocked`].
+    pub(crate) async fn lock(
+        &self,
+        owner: TxnId,
+        table: &str,
+        key: &[u8],
+        lock: RowLock,
+    ) -> Result<bool, DatacaveError> {
+        let key = (table.to_string(), key.to_vec());
+        loop {
+            // Registered before the table is checked, so a release in between still wakes us.
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                state.waits_for.remove(&owner);
+                let blockers = state.blockers(owner, &key, lock.mode);
+                if blockers.is_empty() {
+                    state.grant(owner, key, lock.mode);
+                    return Ok(true);
+                }
+                match lock.wait {
+                    LockWait::SkipLocked => return Ok(false),
+                    LockWait::NoWait => {
+                        return Err(DatacaveError::sqlstate(
+                            sqlstate::LOCK_NOT_AVAILABLE,
+                            format!("could not obtain lock on row in relation \"{table}\""),
+                        ));
+                    }
+                    LockWait::Block => {
+                        if state.closes_cycle(owner, &blockers) {
+                            return Err(DatacaveError::sqlstate(
+                                sqlstate::DEADLOCK_DETECTED,
+                                "deadlock detected",
+                            ));
+                        }
+                        state.waits_for.insert(owner, blockers);
+                    }
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Releases every lock `owner` holds, waking the transactions waiting for them.
+    pub(crate) fn release_all(&self, owner: TxnId) {
+        let mut state = self.state.lock().unwrap();
+        state.waits_for.remove(&owner);
+        let Some(keys) = state.owned.remove(&owner) else {
+            return;
+        };
+        for key in keys {
+            if let Some(holders) = state.held.get_mut(&key) {
+                holders.retain(|(holder, _)| *holder != owner);
+                if holders.is_empty() {
+                    state.held.remove(&key);
+                }
+            }
+        }
+        drop(state);
+        self.released.notify_waiters();
+    }
+}
@@ -1,10 +1,103 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use sqlparser::dialect::PostgreSqlDialect;
 use sqlparser::parser::Parser;
-use sqlparser::ast::Statement;
+use sqlparser::ast::{Expr, SetExpr, Statement, TableFactor, TableVersion, Value};
+use sqlparser::tokenizer::{Token, Tokenizer};
 
 pub fn parse_sql(sql: &str) -> Result<Vec<Statement>> {
     let dialect = PostgreSqlDialect {};
-    let statements = Parser::parse_sql(&dialect, sql)?;
+    let tokens = Tokenizer::new(&dialect, sql).tokenize()?;
+    let words: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)))
+        .collect();
+    if !words.windows(2).any(|pair| is_word(pair[0], "AS") && is_word(pair[1], "OF")) {
+        let statements = Parser::parse_sql(&dialect, sql)?;
+        return Ok(statements);
+    }
+    // The PostgreSQL dialect has no time-travel syntax: each statement's `AS OF` clause is
+    // cut from its tokens and attached to the parsed query as a table version.
+    let mut statements = Vec::new();
+    for tokens in tokens.split(|token| *token == Token::SemiColon) {
+        let (tokens, as_of) = strip_as_of(tokens)?;
+        if tokens.iter().all(|token| matches!(token, Token::Whitespace(_))) {
+            continue;
+        }
+        for mut stmt in Parser::new(&dialect).with_tokens(tokens).parse_statements()? {
+            if let Some(as_of) = as_of.clone() {
+                attach_as_of(&mut stmt, as_of)?;
+            }
+            statements.push(stmt);
+        }
+    }
     Ok(statements)
 }
+
+fn is_word(token: &Token, word: &str) -> bool {
+    matches!(token, Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case(word))
+}
+
+/// Removes an `AS OF SYSTEM TIME '<timestamp>'` or `AS OF VERSION <n>` clause from
+/// `tokens`, returning the timestamp as a string literal or the version as a number.
+fn strip_as_of(tokens: &[Token]) -> Result<(Vec<Token>, Option<Expr>)> {
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut as_of = None;
+    let mut idx = 0;
+    while idx < tokens.len() {
+        let mut words = tokens[idx..]
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| !matches!(token, Token::Whitespace(_)));
+        let mut next = || words.next();
+        let clause = match (next(), next(), next()) {
+            (Some((_, a)), Some((_, o)), Some((_, kind))) if is_word(a, "AS") && is_word(o, "OF") => {
+                if is_word(kind, "SYSTEM") {
+                    match (next(), next()) {
+                        (Some((_, t)), Some((end, Token::SingleQuotedString(ts)))) if is_word(t, "TIME") => {
+                            Some((end, Expr::Value(Value::SingleQuotedString(ts.clone()))))
+                        }
+                        _ => return Err(anyhow!("AS OF SYSTEM TIME expects a timestamp string")),
+                    }
+                } else if is_word(kind, "VERSION") {
+                    match next() {
+                        Some((end, Token::Number(n, long))) => {
+                            Some((end, Expr::Value(Value::Number(n.clone(), *long))))
+                        }
+                        _ => return Err(anyhow!("AS OF VERSION expects a version number")),
+                    }
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        match clause {
+            Some((end, expr)) => {
+                if as_of.replace(expr).is_some() {
+                    return Err(anyhow!("a statement can only have one AS OF clause"));
+                }
+                idx += end + 1;
+            }
+            None => {
+                out.push(tokens[idx].clone());
+                idx += 1;
+            }
+        }
+    }
+    Ok((out, as_of))
+}
+
+/// Records `as_of` as the version of the first table a query reads.
+fn attach_as_of(stmt: &mut Statement, as_of: Expr) -> Result<()> {
+    if let Statement::Query(query) = stmt {
+        if let SetExpr::Select(select) = query.body.as_mut() {
+            if let Some(TableFactor::Table { version, .. }) =
+                select.from.first_mut().map(|from| &mut from.relation)
+            {
+                *version = Some(TableVersion::ForSystemTimeAsOf(as_of));
+                return Ok(());
+            }
+        }
+    }
+    Err(anyhow!("AS OF is only supported in SELECT queries that read a table"))
+}
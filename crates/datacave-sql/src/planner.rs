@@ -1,13 +1,25 @@
-use datacave_core::types::{Column, DataValue};
+use datacave_core::catalog::{CheckConstraint, ColumnConstraints, UniqueConstraint};
+use datacave_core::error::{sqlstate, DatacaveError};
+use datacave_core::types::{Column, DataType, DataValue};
 use sqlparser::ast::{
-    BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, GroupByExpr, JoinConstraint,
-    JoinOperator, ObjectName, Statement, TableConstraint, TableFactor, Value, FromTable,
-    TableWithJoins, OrderByExpr,
+    AlterTableOperation, BinaryOperator, ColumnDef, ColumnOption, Expr, FunctionArg, FunctionArgExpr, GroupByExpr, JoinConstraint,
+    JoinOperator, ObjectName, ObjectType, Query, Statement, TableConstraint, TableFactor, Value, FromTable,
+    TableWithJoins, OrderByExpr, UnaryOperator, TransactionIsolationLevel, TransactionMode, LockClause, LockType, NonBlock, TableVersion,
 };
+use crate::expr::ScalarExpr;
+use crate::lock::{LockMode, LockWait, RowLock};
+use crate::transaction::IsolationLevel;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
 
 #[derive(Debug, Clone)]
 pub enum Plan {
     CreateTable(CreateTablePlan),
+    AlterTable(AlterTablePlan),
+    DropTable(DropTablePlan),
+    Truncate(TruncatePlan),
+    CreateIndex(CreateIndexPlan),
+    DropIndex(DropIndexPlan),
     Insert(InsertPlan),
     Select(SelectPlan),
     Update(UpdatePlan),
@@ -15,6 +27,9 @@ pub enum Plan {
     Begin(BeginPlan),
     Commit(CommitPlan),
     Rollback(RollbackPlan),
+    SetTransaction(SetTransactionPlan),
+    Savepoint(SavepointPlan),
+    ReleaseSavepoint(ReleaseSavepointPlan),
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +37,71 @@ pub struct CreateTablePlan {
     pub table: String,
     pub columns: Vec<Column>,
     pub primary_key: Option<String>,
+    /// All primary key columns, in key order.
+    pub key_columns: Vec<String>,
+    /// NOT NULL and DEFAULT of each of `columns`, index for index.
+    pub column_constraints: Vec<ColumnConstraints>,
+    /// UNIQUE constraints, the primary key first if there is one.
+    pub unique: Vec<UniqueConstraint>,
+    pub checks: Vec<CheckConstraint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlterTablePlan {
+    pub table: String,
+    pub if_exists: bool,
+    /// Applied in order; the statement fails as a whole if any of them fails.
+    pub operations: Vec<AlterTableOp>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AlterTableOp {
+    /// Existing rows read the column's DEFAULT, or NULL without one.
+    AddColumn {
+        column: Column,
+        constraints: ColumnConstraints,
+        if_not_exists: bool,
+    },
+    DropColumn {
+        name: String,
+        if_exists: bool,
+    },
+    RenameColumn {
+        from: String,
+        to: String,
+    },
+    RenameTable {
+        to: String,
+    },
+}
+
+/// `DROP TABLE`. Indexes, the only objects that depend on a table, always go with it, so
+/// `CASCADE` and `RESTRICT` behave alike.
+#[derive(Debug, Clone)]
+pub struct DropTablePlan {
+    pub names: Vec<String>,
+    pub if_exists: bool,
+}
+
+/// `TRUNCATE`: removes the rows the statement's tenant sees.
+#[derive(Debug, Clone)]
+pub struct TruncatePlan {
+    pub table: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateIndexPlan {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+    pub if_not_exists: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DropIndexPlan {
+    pub names: Vec<String>,
+    pub if_exists: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -34,7 +114,7 @@ pub struct InsertPlan {
 #[derive(Debug, Clone)]
 pub enum ProjectionItem {
     Column(String, Option<String>), // (name, output_alias)
-    Aggregate(AggregateFunc, Option<String>, Option<String>), // (func, arg_col, alias)
+    Expr(ScalarExpr, String),       // (expression, output name)
     AllColumns,
 }
 
@@ -49,14 +129,8 @@ pub struct OrderBySpec {
 pub enum OrderBySpecKind {
     ColumnOrAlias(String),
     Position(usize),
-}
-
-/// HAVING condition operand: column/alias, literal, or aggregate expression.
-#[derive(Debug, Clone)]
-pub enum HavingOperand {
-    ColumnOrAlias(String),
-    Literal(DataValue),
-    Aggregate(AggregateFunc, Option<String>),
+    /// Any other expression, evaluated over the result columns.
+    Expr(ScalarExpr),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -69,21 +143,6 @@ pub enum HavingOp {
     Lte,
 }
 
-/// Single HAVING predicate: left op right (column/alias, literal, or aggregate on either side).
-#[derive(Debug, Clone)]
-pub struct HavingPredicate {
-    pub left: HavingOperand,
-    pub op: HavingOp,
-    pub right: HavingOperand,
-}
-
-/// HAVING condition: simple predicate or AND of conditions.
-#[derive(Debug, Clone)]
-pub enum HavingCond {
-    Predicate(HavingPredicate),
-    And(Box<HavingCond>, Box<HavingCond>),
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AggregateFunc {
     Count,
@@ -93,6 +152,37 @@ pub enum AggregateFunc {
     Max,
 }
 
+impl AggregateFunc {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_uppercase().as_str() {
+            "COUNT" => AggregateFunc::Count,
+            "SUM" => AggregateFunc::Sum,
+            "AVG" => AggregateFunc::Avg,
+            "MIN" => AggregateFunc::Min,
+            "MAX" => AggregateFunc::Max,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggregateFunc::Count => "count",
+            AggregateFunc::Sum => "sum",
+            AggregateFunc::Avg => "avg",
+            AggregateFunc::Min => "min",
+            AggregateFunc::Max => "max",
+        }
+    }
+
+    /// COUNT is an integer; the other aggregates are computed in floating point.
+    pub fn result_type(&self) -> DataType {
+        match self {
+            AggregateFunc::Count => DataType::BigInt,
+            _ => DataType::Double,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JoinKind {
     Inner,
@@ -123,11 +213,14 @@ pub struct WherePredicate {
 }
 
 /// WHERE condition: simple predicate, AND/OR of conditions, or parenthesized expressions.
+/// Conditions other than `column op literal` are kept as expressions, which key access
+/// planning cannot use.
 #[derive(Debug, Clone)]
 pub enum WhereCond {
     Predicate(WherePredicate),
     And(Box<WhereCond>, Box<WhereCond>),
     Or(Box<WhereCond>, Box<WhereCond>),
+    Expr(ScalarExpr),
 }
 
 #[derive(Debug, Clone)]
@@ -137,19 +230,44 @@ pub struct SelectPlan {
     pub projection: Vec<ProjectionItem>,
     /// WHERE clause (optional)
     pub where_clause: Option<WhereCond>,
-    /// Column names for GROUP BY (empty when no GROUP BY)
-    pub group_by: Vec<String>,
-    /// HAVING condition (only when GROUP BY present)
-    pub having: Option<HavingCond>,
+    /// GROUP BY expressions (empty when no GROUP BY)
+    pub group_by: Vec<ScalarExpr>,
+    /// HAVING condition; output column aliases may be used in it
+    pub having: Option<ScalarExpr>,
     pub order_by: Vec<OrderBySpec>,
     pub limit: Option<u64>,
     pub offset: Option<u64>,
+    /// `FOR UPDATE` / `FOR SHARE` clause, locking the rows returned.
+    pub lock: Option<RowLock>,
+    /// `AS OF SYSTEM TIME` / `AS OF VERSION` clause, reading every table as it was then.
+    pub as_of: Option<AsOf>,
+}
+
+impl SelectPlan {
+    /// Whether the query returns one row per group rather than one per input row.
+    pub fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty()
+            || self.having.is_some()
+            || self.projection.iter().any(|item| match item {
+                ProjectionItem::Expr(expr, _) => expr.has_aggregate(),
+                _ => false,
+            })
+    }
+}
+
+/// Point in history a time-travel query reads at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsOf {
+    /// A commit time, resolved to the newest version committed at or before it.
+    Timestamp(String),
+    Version(u64),
 }
 
 #[derive(Debug, Clone)]
 pub struct UpdatePlan {
     pub table: String,
-    pub assignments: Vec<(String, DataValue)>,
+    /// `SET column = expression`, evaluated against each row's values before the update
+    pub assignments: Vec<(String, ScalarExpr)>,
     /// WHERE clause (optional)
     pub where_clause: Option<WhereCond>,
 }
@@ -162,13 +280,409 @@ pub struct DeletePlan {
 }
 
 #[derive(Debug, Clone)]
-pub struct BeginPlan {}
+pub struct BeginPlan {
+    /// Isolation level given with `BEGIN ISOLATION LEVEL ...`.
+    pub isolation: Option<IsolationLevel>,
+}
 
 #[derive(Debug, Clone)]
 pub struct CommitPlan {}
 
 #[derive(Debug, Clone)]
-pub struct RollbackPlan {}
+pub struct RollbackPlan {
+    /// Savepoint given with `ROLLBACK TO SAVEPOINT`, which keeps the transaction open.
+    pub savepoint: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetTransactionPlan {
+    pub isolation: Option<IsolationLevel>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SavepointPlan {
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReleaseSavepointPlan {
+    pub name: String,
+}
+
+/// Isolation level set by the last `ISOLATION LEVEL` among `modes`.
+pub fn plan_isolation(modes: &[TransactionMode]) -> Option<IsolationLevel> {
+    modes.iter().rev().find_map(|mode| match mode {
+        TransactionMode::IsolationLevel(TransactionIsolationLevel::Serializable) => {
+            Some(IsolationLevel::Serializable)
+        }
+        TransactionMode::IsolationLevel(_) => Some(IsolationLevel::Snapshot),
+        TransactionMode::AccessMode(_) => None,
+    })
+}
+
+/// Plans `stmt`, reporting why it cannot be planned (for example an unsupported column type).
+pub fn build_plan(stmt: &Statement) -> Result<Plan, DatacaveError> {
+    if let Statement::CreateTable {
+        name,
+        columns,
+        constraints,
+        ..
+    } = stmt
+    {
+        return plan_create_table(name, columns, constraints).map(Plan::CreateTable);
+    }
+    if let Statement::AlterTable {
+        name,
+        if_exists,
+        operations,
+        ..
+    } = stmt
+    {
+        return plan_alter_table(name, *if_exists, operations).map(Plan::AlterTable);
+    }
+    if let Statement::CreateIndex { .. } = stmt {
+        return plan_create_index(stmt).map(Plan::CreateIndex);
+    }
+    if let Statement::Query(query) = stmt {
+        return plan_query(query);
+    }
+    if let Statement::Update { .. } = stmt {
+        return plan_update(stmt);
+    }
+    plan_statement(stmt).ok_or_else(|| DatacaveError::Sql("unsupported SQL".into()))
+}
+
+fn plan_create_table(
+    name: &ObjectName,
+    columns: &[ColumnDef],
+    constraints: &[TableConstraint],
+) -> Result<CreateTablePlan, DatacaveError> {
+    let table = object_name(name);
+    let mut plan = CreateTablePlan {
+        table: table.clone(),
+        columns: Vec::new(),
+        primary_key: None,
+        key_columns: Vec::new(),
+        column_constraints: Vec::new(),
+        unique: Vec::new(),
+        checks: Vec::new(),
+    };
+    let mut primary_key: Option<(Option<String>, Vec<String>)> = None;
+    let mut set_primary_key = |name: Option<String>, columns: Vec<String>| {
+        if primary_key.replace((name, columns)).is_some() {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::INVALID_TABLE_DEFINITION,
+                format!("multiple primary keys for table \"{table}\" are not allowed"),
+            ));
+        }
+        Ok(())
+    };
+    for def in columns {
+        let (column, column_constraints) = plan_column(def)?;
+        for option in &def.options {
+            let name = option.name.as_ref().map(|n| n.value.clone());
+            match &option.option {
+                ColumnOption::Unique {
+                    is_primary: true, ..
+                } => set_primary_key(name, vec![column.name.clone()])?,
+                ColumnOption::Unique { .. } => {
+                    let name = name.unwrap_or_else(|| {
+                        constraint_name(&plan, &format!("{}_{}_key", plan.table, column.name))
+                    });
+                    plan.unique.push(UniqueConstraint {
+                        name,
+                        columns: vec![column.name.clone()],
+                    });
+                }
+                ColumnOption::Check(expr) => {
+                    let name = name.unwrap_or_else(|| {
+                        constraint_name(&plan, &format!("{}_{}_check", plan.table, column.name))
+                    });
+                    plan.checks.push(plan_check(name, expr)?);
+                }
+                _ => {}
+            }
+        }
+        plan.columns.push(column);
+        plan.column_constraints.push(column_constraints);
+    }
+    for constraint in constraints {
+        match constraint {
+            TableConstraint::PrimaryKey { name, columns, .. } => set_primary_key(
+                name.as_ref().map(|n| n.value.clone()),
+                columns.iter().map(|c| c.value.clone()).collect(),
+            )?,
+            TableConstraint::Unique { name, columns, .. } => {
+                let columns: Vec<String> = columns.iter().map(|c| c.value.clone()).collect();
+                let name = match name {
+                    Some(name) => name.value.clone(),
+                    None => constraint_name(&plan, &format!("{}_{}_key", plan.table, columns.join("_"))),
+                };
+                plan.unique.push(UniqueConstraint { name, columns });
+            }
+            TableConstraint::Check { name, expr } => {
+                let name = match name {
+                    Some(name) => name.value.clone(),
+                    None => constraint_name(&plan, &format!("{}_check", plan.table)),
+                };
+                plan.checks.push(plan_check(name, expr)?);
+            }
+            // FOREIGN KEY and other constraints are accepted but not enforced.
+            _ => {}
+        }
+    }
+    if let Some((name, columns)) = primary_key {
+        for column in &columns {
+            if let Some(idx) = plan.columns.iter().position(|c| c.name == *column) {
+                plan.column_constraints[idx].not_null = true;
+            }
+        }
+        plan.primary_key = columns.first().cloned();
+        plan.key_columns = columns.clone();
+        let name = name.unwrap_or_else(|| format!("{}_pkey", plan.table));
+        plan.unique.insert(0, UniqueConstraint { name, columns });
+    }
+    let constrained = plan
+        .unique
+        .iter()
+        .flat_map(|u| &u.columns)
+        .chain(plan.checks.iter().flat_map(|c| &c.columns));
+    for column in constrained {
+        if !plan.columns.iter().any(|c| c.name == *column) {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::UNDEFINED_COLUMN,
+                format!("column \"{column}\" named in constraint does not exist"),
+            ));
+        }
+    }
+    Ok(plan)
+}
+
+/// A column's type, NOT NULL and DEFAULT. The DEFAULT must be a constant and is stored
+/// coerced to the column type.
+fn plan_column(def: &ColumnDef) -> Result<(Column, ColumnConstraints), DatacaveError> {
+    let column = Column {
+        name: def.name.value.clone(),
+        data_type: DataType::parse(&def.data_type.to_string())?,
+    };
+    let mut constraints = ColumnConstraints::default();
+    for option in &def.options {
+        match &option.option {
+            ColumnOption::Null => constraints.not_null = false,
+            ColumnOption::NotNull => constraints.not_null = true,
+            ColumnOption::Default(expr) => {
+                let value = column.data_type.coerce(&column.name, constant_value(expr)?)?;
+                constraints.default = Some(value);
+            }
+            // REFERENCES and other options are accepted but not enforced.
+            _ => {}
+        }
+    }
+    Ok((column, constraints))
+}
+
+/// `base`, or `base` with the lowest numeric suffix no other constraint of the table uses.
+fn constraint_name(plan: &CreateTablePlan, base: &str) -> String {
+    let taken = |name: &str| {
+        plan.unique.iter().any(|u| u.name == name) || plan.checks.iter().any(|c| c.name == name)
+    };
+    (0..)
+        .map(|n| if n == 0 { base.to_string() } else { format!("{base}{n}") })
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+fn plan_check(name: String, expr: &Expr) -> Result<CheckConstraint, DatacaveError> {
+    let cond = parse_where_expr(expr)?;
+    let mut columns = Vec::new();
+    where_columns(&cond, &mut columns);
+    Ok(CheckConstraint {
+        name,
+        expr: expr.to_string(),
+        columns,
+    })
+}
+
+/// Condition of a stored CHECK constraint.
+pub(crate) fn parse_check(expr: &str) -> Result<WhereCond, DatacaveError> {
+    let expr = Parser::new(&PostgreSqlDialect {})
+        .try_with_sql(expr)
+        .and_then(|mut parser| parser.parse_expr())
+        .map_err(|e| DatacaveError::Catalog(format!("invalid CHECK expression {expr}: {e}")))?;
+    parse_where_expr(&expr)
+}
+
+/// `expr` with every reference to column `from` renamed to `to`.
+pub(crate) fn rename_check_column(expr: &str, from: &str, to: &str) -> Result<String, DatacaveError> {
+    fn rename(expr: &mut Expr, from: &str, to: &str) {
+        match expr {
+            Expr::Identifier(ident) if ident.value == from => ident.value = to.to_string(),
+            Expr::CompoundIdentifier(parts) => {
+                if let Some(last) = parts.last_mut().filter(|p| p.value == from) {
+                    last.value = to.to_string();
+                }
+            }
+            Expr::Nested(inner)
+            | Expr::UnaryOp { expr: inner, .. }
+            | Expr::IsNull(inner)
+            | Expr::IsNotNull(inner) => rename(inner, from, to),
+            Expr::BinaryOp { left, right, .. } => {
+                rename(left, from, to);
+                rename(right, from, to);
+            }
+            Expr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                for expr in operand
+                    .iter_mut()
+                    .chain(else_result.iter_mut())
+                    .map(|e| &mut **e)
+                    .chain(conditions.iter_mut())
+                    .chain(results.iter_mut())
+                {
+                    rename(expr, from, to);
+                }
+            }
+            Expr::Function(func) => {
+                for arg in &mut func.args {
+                    if let FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) = arg {
+                        rename(expr, from, to);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut parsed = Parser::new(&PostgreSqlDialect {})
+        .try_with_sql(expr)
+        .and_then(|mut parser| parser.parse_expr())
+        .map_err(|e| DatacaveError::Catalog(format!("invalid CHECK expression {expr}: {e}")))?;
+    rename(&mut parsed, from, to);
+    Ok(parsed.to_string())
+}
+
+fn where_columns(cond: &WhereCond, out: &mut Vec<String>) {
+    match cond {
+        WhereCond::Predicate(p) => {
+            for operand in [&p.left, &p.right] {
+                if let WhereOperand::Column(name) = operand {
+                    if !out.contains(name) {
+                        out.push(name.clone());
+                    }
+                }
+            }
+        }
+        WhereCond::And(left, right) | WhereCond::Or(left, right) => {
+            where_columns(left, out);
+            where_columns(right, out);
+        }
+        WhereCond::Expr(expr) => expr.collect_columns(out),
+    }
+}
+
+fn plan_alter_table(
+    name: &ObjectName,
+    if_exists: bool,
+    operations: &[AlterTableOperation],
+) -> Result<AlterTablePlan, DatacaveError> {
+    let operations = operations
+        .iter()
+        .map(|op| match op {
+            AlterTableOperation::AddColumn {
+                if_not_exists,
+                column_def,
+                column_position: None,
+                ..
+            } => {
+                if column_def.options.iter().any(|o| {
+                    matches!(o.option, ColumnOption::Unique { .. } | ColumnOption::Check(_))
+                }) {
+                    return Err(DatacaveError::NotSupported(
+                        "UNIQUE, PRIMARY KEY or CHECK in ADD COLUMN".into(),
+                    ));
+                }
+                let (column, constraints) = plan_column(column_def)?;
+                Ok(AlterTableOp::AddColumn {
+                    column,
+                    constraints,
+                    if_not_exists: *if_not_exists,
+                })
+            }
+            AlterTableOperation::DropColumn {
+                column_name,
+                if_exists,
+                ..
+            } => Ok(AlterTableOp::DropColumn {
+                name: column_name.value.clone(),
+                if_exists: *if_exists,
+            }),
+            AlterTableOperation::RenameColumn {
+                old_column_name,
+                new_column_name,
+            } => Ok(AlterTableOp::RenameColumn {
+                from: old_column_name.value.clone(),
+                to: new_column_name.value.clone(),
+            }),
+            AlterTableOperation::RenameTable { table_name } => Ok(AlterTableOp::RenameTable {
+                to: object_name(table_name),
+            }),
+            other => Err(DatacaveError::NotSupported(format!("ALTER TABLE {other}"))),
+        })
+        .collect::<Result<Vec<_>, DatacaveError>>()?;
+    Ok(AlterTablePlan {
+        table: object_name(name),
+        if_exists,
+        operations,
+    })
+}
+
+fn plan_create_index(stmt: &Statement) -> Result<CreateIndexPlan, DatacaveError> {
+    let Statement::CreateIndex {
+        name,
+        table_name,
+        columns,
+        unique,
+        if_not_exists,
+        predicate,
+        ..
+    } = stmt
+    else {
+        return Err(DatacaveError::Sql("not a CREATE INDEX".into()));
+    };
+    if predicate.is_some() {
+        return Err(DatacaveError::NotSupported("partial indexes".into()));
+    }
+    let table = object_name(table_name);
+    let columns = columns
+        .iter()
+        .map(|column| match (&column.expr, column.asc, column.nulls_first) {
+            (Expr::Identifier(ident), None | Some(true), None) => Ok(ident.value.clone()),
+            _ => Err(DatacaveError::NotSupported(format!("index key {column}"))),
+        })
+        .collect::<Result<Vec<_>, DatacaveError>>()?;
+    let name = match name {
+        Some(name) => object_name(name),
+        None => format!("{table}_{}_idx", columns.join("_")),
+    };
+    Ok(CreateIndexPlan {
+        name,
+        table,
+        columns,
+        unique: *unique,
+        if_not_exists: *if_not_exists,
+    })
+}
+
+/// Value of a constant expression such as a DEFAULT.
+fn constant_value(expr: &Expr) -> Result<DataValue, DatacaveError> {
+    match expr {
+        Expr::Value(_) | Expr::UnaryOp { op: UnaryOperator::Minus, .. } => Ok(expr_to_value(expr)),
+        _ => Err(DatacaveError::NotSupported(format!("non-constant expression {expr}"))),
+    }
+}
 
 pub fn plan_statement(stmt: &Statement) -> Option<Plan> {
     match stmt {
@@ -177,26 +691,39 @@ pub fn plan_statement(stmt: &Statement) -> Option<Plan> {
             columns,
             constraints,
             ..
-        } => {
-            let cols = columns
-                .iter()
-                .map(|c| Column {
-                    name: c.name.value.clone(),
-                    data_type: c.data_type.to_string(),
-                })
-                .collect();
-            let primary_key = constraints.iter().find_map(|c| match c {
-                TableConstraint::PrimaryKey { columns, .. } => {
-                    columns.first().map(|c| c.value.clone())
-                }
-                _ => None,
-            });
-            Some(Plan::CreateTable(CreateTablePlan {
-                table: object_name(name),
-                columns: cols,
-                primary_key,
-            }))
-        }
+        } => plan_create_table(name, columns, constraints)
+            .ok()
+            .map(Plan::CreateTable),
+        Statement::AlterTable {
+            name,
+            if_exists,
+            operations,
+            ..
+        } => plan_alter_table(name, *if_exists, operations)
+            .ok()
+            .map(Plan::AlterTable),
+        Statement::CreateIndex { .. } => plan_create_index(stmt).ok().map(Plan::CreateIndex),
+        Statement::Drop {
+            object_type: ObjectType::Table,
+            if_exists,
+            names,
+            ..
+        } => Some(Plan::DropTable(DropTablePlan {
+            names: names.iter().map(object_name).collect(),
+            if_exists: *if_exists,
+        })),
+        Statement::Truncate { table_name, .. } => Some(Plan::Truncate(TruncatePlan {
+            table: object_name(table_name),
+        })),
+        Statement::Drop {
+            object_type: ObjectType::Index,
+            if_exists,
+            names,
+            ..
+        } => Some(Plan::DropIndex(DropIndexPlan {
+            names: names.iter().map(object_name).collect(),
+            if_exists: *if_exists,
+        })),
         Statement::Insert { table_name, columns, source, .. } => {
             let table = object_name(table_name);
             let cols = columns.iter().map(|c| c.value.clone()).collect();
@@ -218,149 +745,176 @@ pub fn plan_statement(stmt: &Statement) -> Option<Plan> {
                 values,
             }))
         }
-        Statement::Query(query) => {
-            if let sqlparser::ast::SetExpr::Select(select) = &*query.body {
-                let first_rel = select.from.first()?;
-                let table = match &first_rel.relation {
-                    TableFactor::Table { name, .. } => object_name(name),
-                    _ => return None,
-                };
-                let projection = plan_projection(&select.projection)?;
-                let joins = plan_joins(first_rel)?;
-                let where_clause = plan_where(select.selection.as_ref())?;
-                let group_by = plan_group_by(&select.group_by)?;
-                let having = plan_having(select.having.as_ref())?;
-                let order_by = plan_order_by(&query.order_by)?;
-                let limit = plan_limit(query.limit.as_ref())?;
-                let offset = plan_offset(query.offset.as_ref())?;
-                return Some(Plan::Select(SelectPlan {
-                    table,
-                    joins,
-                    projection,
-                    where_clause,
-                    group_by,
-                    having,
-                    order_by,
-                    limit,
-                    offset,
-                }));
-            }
-            None
-        }
-        Statement::StartTransaction { .. } => Some(Plan::Begin(BeginPlan {})),
+        Statement::Query(query) => plan_query(query).ok(),
+        Statement::StartTransaction { modes, .. } => Some(Plan::Begin(BeginPlan {
+            isolation: plan_isolation(modes),
+        })),
+        Statement::SetTransaction {
+            modes,
+            session: false,
+            snapshot: None,
+        } => Some(Plan::SetTransaction(SetTransactionPlan {
+            isolation: plan_isolation(modes),
+        })),
         Statement::Commit { .. } => Some(Plan::Commit(CommitPlan {})),
-        Statement::Rollback { .. } => Some(Plan::Rollback(RollbackPlan {})),
-        Statement::Update { table, assignments, selection, .. } => {
-            let table = match &table.relation {
-                TableFactor::Table { name, .. } => object_name(name),
-                _ => return None,
-            };
-            let assigns = assignments
-                .iter()
-                .filter_map(|a| a.id.first().map(|ident| (ident.value.clone(), expr_to_value(&a.value))))
-                .collect();
-            let where_clause = plan_where(selection.as_ref())?;
-            Some(Plan::Update(UpdatePlan {
-                table,
-                assignments: assigns,
-                where_clause,
+        Statement::Rollback { savepoint, .. } => Some(Plan::Rollback(RollbackPlan {
+            savepoint: savepoint.as_ref().map(|name| name.value.clone()),
+        })),
+        Statement::Savepoint { name } => Some(Plan::Savepoint(SavepointPlan {
+            name: name.value.clone(),
+        })),
+        Statement::ReleaseSavepoint { name } => {
+            Some(Plan::ReleaseSavepoint(ReleaseSavepointPlan {
+                name: name.value.clone(),
             }))
         }
+        Statement::Update { .. } => plan_update(stmt).ok(),
         Statement::Delete { from, selection, .. } => {
             let table = first_from_table(from)
                 .and_then(|relation| match &relation.relation {
                     TableFactor::Table { name, .. } => Some(object_name(name)),
                     _ => None,
                 })?;
-            let where_clause = plan_where(selection.as_ref())?;
+            let where_clause = plan_where(selection.as_ref()).ok()?;
             Some(Plan::Delete(DeletePlan { table, where_clause }))
         }
         _ => None,
     }
 }
 
+/// Plans a `SELECT`, reporting why an expression in it cannot be planned.
+fn plan_query(query: &Query) -> Result<Plan, DatacaveError> {
+    let unsupported = || DatacaveError::Sql("unsupported SQL".into());
+    let sqlparser::ast::SetExpr::Select(select) = &*query.body else {
+        return Err(unsupported());
+    };
+    let first_rel = select.from.first().ok_or_else(unsupported)?;
+    let table = match &first_rel.relation {
+        TableFactor::Table { name, .. } => object_name(name),
+        _ => return Err(unsupported()),
+    };
+    Ok(Plan::Select(SelectPlan {
+        table,
+        joins: plan_joins(first_rel).ok_or_else(unsupported)?,
+        projection: plan_projection(&select.projection)?,
+        where_clause: plan_where(select.selection.as_ref())?,
+        group_by: plan_group_by(&select.group_by)?,
+        having: select.having.as_ref().map(ScalarExpr::plan).transpose()?,
+        order_by: plan_order_by(&query.order_by)?,
+        limit: plan_limit(query.limit.as_ref()).ok_or_else(unsupported)?,
+        offset: plan_offset(query.offset.as_ref()).ok_or_else(unsupported)?,
+        lock: plan_lock(&query.locks).ok_or_else(unsupported)?,
+        as_of: plan_as_of(&first_rel.relation).ok_or_else(unsupported)?,
+    }))
+}
+
+/// Plans an `UPDATE`, reporting why a SET or WHERE expression cannot be planned.
+fn plan_update(stmt: &Statement) -> Result<Plan, DatacaveError> {
+    let Statement::Update {
+        table,
+        assignments,
+        selection,
+        ..
+    } = stmt
+    else {
+        return Err(DatacaveError::Sql("unsupported SQL".into()));
+    };
+    let (table, alias) = match &table.relation {
+        TableFactor::Table { name, alias, .. } => (object_name(name), alias.as_ref()),
+        _ => return Err(DatacaveError::Sql("unsupported SQL".into())),
+    };
+    // `SET t.col = ...` may qualify the column with the table, or its alias when it has one.
+    let matches_target = |qualifier: &str| match alias {
+        Some(alias) => qualifier == alias.name.value,
+        None => qualifier == table || table.rsplit('.').next() == Some(qualifier),
+    };
+    let mut assigns = Vec::with_capacity(assignments.len());
+    for assignment in assignments {
+        let Some((ident, qualifier)) = assignment.id.split_last() else {
+            continue;
+        };
+        if !qualifier.is_empty() {
+            let qualifier = qualifier
+                .iter()
+                .map(|part| part.value.as_str())
+                .collect::<Vec<_>>()
+                .join(".");
+            if !matches_target(&qualifier) {
+                return Err(DatacaveError::sqlstate(
+                    sqlstate::UNDEFINED_COLUMN,
+                    format!("column \"{qualifier}\" of relation \"{table}\" does not exist"),
+                ));
+            }
+        }
+        let value = ScalarExpr::plan(&assignment.value)?;
+        if value.has_aggregate() {
+            return Err(DatacaveError::sqlstate(
+                sqlstate::GROUPING_ERROR,
+                "aggregate functions are not allowed in UPDATE",
+            ));
+        }
+        assigns.push((ident.value.clone(), value));
+    }
+    Ok(Plan::Update(UpdatePlan {
+        table,
+        assignments: assigns,
+        where_clause: plan_where(selection.as_ref())?,
+    }))
+}
+
 fn plan_projection(
     items: &[sqlparser::ast::SelectItem],
-) -> Option<Vec<ProjectionItem>> {
-    let mut out = Vec::new();
-    for item in items {
-        let proj = match item {
-            sqlparser::ast::SelectItem::UnnamedExpr(expr) => match expr {
-                Expr::Function(func) => {
-                    let agg = parse_aggregate_func(&func)?;
-                    Some(ProjectionItem::Aggregate(agg.0, agg.1, None))
-                }
-                Expr::Identifier(ident) => {
-                    Some(ProjectionItem::Column(ident.value.clone(), None))
-                }
-                Expr::CompoundIdentifier(parts) => {
-                    let name = parts
-                        .last()
-                        .map(|p| p.value.clone())
-                        .unwrap_or_else(|| parts.iter().map(|p| p.value.clone()).collect::<Vec<_>>().join("."));
-                    Some(ProjectionItem::Column(name, None))
-                }
-                Expr::Wildcard => Some(ProjectionItem::AllColumns),
-                _ => Some(ProjectionItem::Column(expr.to_string(), None)),
-            },
+) -> Result<Vec<ProjectionItem>, DatacaveError> {
+    items
+        .iter()
+        .map(|item| match item {
+            sqlparser::ast::SelectItem::UnnamedExpr(expr) => plan_projection_expr(expr, None),
             sqlparser::ast::SelectItem::ExprWithAlias { expr, alias } => {
-                let alias_val = alias.value.clone();
-                match &*expr {
-                    Expr::Function(func) => {
-                        let agg = parse_aggregate_func(&func)?;
-                        Some(ProjectionItem::Aggregate(agg.0, agg.1, Some(alias_val)))
-                    }
-                    Expr::Identifier(ident) => {
-                        Some(ProjectionItem::Column(ident.value.clone(), Some(alias_val)))
-                    }
-                    _ => Some(ProjectionItem::Column(expr.to_string(), None)),
-                }
+                plan_projection_expr(expr, Some(alias.value.clone()))
             }
-            sqlparser::ast::SelectItem::Wildcard(_) => Some(ProjectionItem::AllColumns),
-            sqlparser::ast::SelectItem::QualifiedWildcard(_, _) => Some(ProjectionItem::AllColumns),
-        };
-        if let Some(p) = proj {
-            out.push(p);
-        }
-    }
-    Some(out)
+            sqlparser::ast::SelectItem::Wildcard(_) => Ok(ProjectionItem::AllColumns),
+            sqlparser::ast::SelectItem::QualifiedWildcard(_, _) => Ok(ProjectionItem::AllColumns),
+        })
+        .collect()
 }
 
-fn parse_aggregate_func(func: &Function) -> Option<(AggregateFunc, Option<String>)> {
-    let name = func.name.0.first()?.value.to_uppercase();
-    let arg = func.args.first().and_then(|a| match a {
-        FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident))) => {
-            Some(ident.value.clone())
+/// Plain columns stay columns; everything else is computed, named after its alias or, like
+/// PostgreSQL, after the function it calls.
+fn plan_projection_expr(expr: &Expr, alias: Option<String>) -> Result<ProjectionItem, DatacaveError> {
+    match expr {
+        Expr::Nested(inner) => plan_projection_expr(inner, alias),
+        Expr::Identifier(ident) => Ok(ProjectionItem::Column(ident.value.clone(), alias)),
+        Expr::CompoundIdentifier(parts) => {
+            let name = parts
+                .last()
+                .map(|p| p.value.clone())
+                .unwrap_or_default();
+            Ok(ProjectionItem::Column(name, alias))
         }
-        FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::CompoundIdentifier(parts))) => {
-            Some(parts.iter().map(|p| p.value.clone()).collect::<Vec<_>>().join("."))
+        Expr::Wildcard => Ok(ProjectionItem::AllColumns),
+        _ => {
+            let name = alias.unwrap_or_else(|| match expr {
+                Expr::Function(func) => func
+                    .name
+                    .0
+                    .last()
+                    .map(|ident| ident.value.to_lowercase())
+                    .unwrap_or_default(),
+                Expr::Case { .. } => "case".to_string(),
+                _ => "?column?".to_string(),
+            });
+            Ok(ProjectionItem::Expr(ScalarExpr::plan(expr)?, name))
         }
-        FunctionArg::Unnamed(FunctionArgExpr::Wildcard) => None,
-        FunctionArg::Unnamed(FunctionArgExpr::QualifiedWildcard(_)) => None,
-        _ => None,
-    });
-    let agg = match name.as_str() {
-        "COUNT" => AggregateFunc::Count,
-        "SUM" => AggregateFunc::Sum,
-        "AVG" => AggregateFunc::Avg,
-        "MIN" => AggregateFunc::Min,
-        "MAX" => AggregateFunc::Max,
-        _ => return None,
-    };
-    Some((agg, arg))
+    }
 }
 
-fn plan_where(selection: Option<&Expr>) -> Option<Option<WhereCond>> {
-    match selection {
-        None => Some(None),
-        Some(expr) => {
-            let cond = parse_where_expr(expr)?;
-            Some(Some(cond))
-        }
-    }
+fn plan_where(selection: Option<&Expr>) -> Result<Option<WhereCond>, DatacaveError> {
+    selection.map(parse_where_expr).transpose()
 }
 
-fn parse_where_expr(expr: &Expr) -> Option<WhereCond> {
+/// `column op literal` comparisons, the conditions key access can use, become predicates
+/// joined by AND and OR; any other condition is planned as an expression.
+fn parse_where_expr(expr: &Expr) -> Result<WhereCond, DatacaveError> {
     // Unwrap parenthesized expressions so (a OR b) parses correctly
     if let Expr::Nested(inner) = expr {
         return parse_where_expr(inner);
@@ -369,37 +923,47 @@ fn parse_where_expr(expr: &Expr) -> Option<WhereCond> {
         if *op == BinaryOperator::And {
             let left_cond = parse_where_expr(left)?;
             let right_cond = parse_where_expr(right)?;
-            return Some(WhereCond::And(Box::new(left_cond), Box::new(right_cond)));
+            return Ok(WhereCond::And(Box::new(left_cond), Box::new(right_cond)));
         }
         if *op == BinaryOperator::Or {
             let left_cond = parse_where_expr(left)?;
             let right_cond = parse_where_expr(right)?;
-            return Some(WhereCond::Or(Box::new(left_cond), Box::new(right_cond)));
+            return Ok(WhereCond::Or(Box::new(left_cond), Box::new(right_cond)));
         }
         let op_enum = match op {
-            BinaryOperator::Eq => HavingOp::Eq,
-            BinaryOperator::NotEq => HavingOp::NotEq,
-            BinaryOperator::Gt => HavingOp::Gt,
-            BinaryOperator::GtEq => HavingOp::Gte,
-            BinaryOperator::Lt => HavingOp::Lt,
-            BinaryOperator::LtEq => HavingOp::Lte,
-            _ => return None,
+            BinaryOperator::Eq => Some(HavingOp::Eq),
+            BinaryOperator::NotEq => Some(HavingOp::NotEq),
+            BinaryOperator::Gt => Some(HavingOp::Gt),
+            BinaryOperator::GtEq => Some(HavingOp::Gte),
+            BinaryOperator::Lt => Some(HavingOp::Lt),
+            BinaryOperator::LtEq => Some(HavingOp::Lte),
+            _ => None,
         };
-        let left_op = expr_to_where_operand(left)?;
-        let right_op = expr_to_where_operand(right)?;
-        // One must be Column, one must be Literal
-        match (&left_op, &right_op) {
-            (WhereOperand::Column(_), WhereOperand::Literal(_)) | (WhereOperand::Literal(_), WhereOperand::Column(_)) => {}
-            _ => return None,
+        if let (Some(op), Some(left_op), Some(right_op)) =
+            (op_enum, expr_to_where_operand(left), expr_to_where_operand(right))
+        {
+            // One must be Column, one must be Literal
+            if matches!(
+                (&left_op, &right_op),
+                (WhereOperand::Column(_), WhereOperand::Literal(_))
+                    | (WhereOperand::Literal(_), WhereOperand::Column(_))
+            ) {
+                return Ok(WhereCond::Predicate(WherePredicate {
+                    left: left_op,
+                    op,
+                    right: right_op,
+                }));
+            }
         }
-        Some(WhereCond::Predicate(WherePredicate {
-            left: left_op,
-            op: op_enum,
-            right: right_op,
-        }))
-    } else {
-        None
     }
+    let cond = ScalarExpr::plan(expr)?;
+    if cond.has_aggregate() {
+        return Err(DatacaveError::sqlstate(
+            sqlstate::GROUPING_ERROR,
+            "aggregate functions are not allowed in WHERE",
+        ));
+    }
+    Ok(WhereCond::Expr(cond))
 }
 
 fn expr_to_where_operand(expr: &Expr) -> Option<WhereOperand> {
@@ -409,83 +973,36 @@ fn expr_to_where_operand(expr: &Expr) -> Option<WhereOperand> {
             Some(WhereOperand::Column(parts.iter().map(|p| p.value.clone()).collect::<Vec<_>>().join(".")))
         }
         Expr::Value(_) => Some(WhereOperand::Literal(expr_to_value(expr))),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr: inner,
+        } if matches!(**inner, Expr::Value(Value::Number(..))) => {
+            Some(WhereOperand::Literal(expr_to_value(expr)))
+        }
         _ => None,
     }
 }
 
-fn plan_group_by(group_by: &GroupByExpr) -> Option<Vec<String>> {
+fn plan_group_by(group_by: &GroupByExpr) -> Result<Vec<ScalarExpr>, DatacaveError> {
     match group_by {
-        GroupByExpr::All => None, // GROUP BY ALL not supported
-        GroupByExpr::Expressions(exprs) => {
-            let mut cols = Vec::new();
-            for expr in exprs {
-                let col = match expr {
-                    Expr::Identifier(ident) => ident.value.clone(),
-                    Expr::CompoundIdentifier(parts) => {
-                        parts.iter().map(|p| p.value.clone()).collect::<Vec<_>>().join(".")
-                    }
-                    _ => return None,
-                };
-                cols.push(col);
-            }
-            Some(cols)
-        }
-    }
-}
-
-fn plan_having(having: Option<&Expr>) -> Option<Option<HavingCond>> {
-    let expr = match having {
-        None => return Some(None),
-        Some(e) => e,
-    };
-    let cond = parse_having_expr(expr)?;
-    Some(Some(cond))
-}
-
-fn parse_having_expr(expr: &Expr) -> Option<HavingCond> {
-    if let Expr::BinaryOp { left, op, right } = expr {
-        if *op == BinaryOperator::And {
-            let left_cond = parse_having_expr(left)?;
-            let right_cond = parse_having_expr(right)?;
-            return Some(HavingCond::And(Box::new(left_cond), Box::new(right_cond)));
-        }
-        let op_enum = match op {
-            BinaryOperator::Eq => HavingOp::Eq,
-            BinaryOperator::NotEq => HavingOp::NotEq,
-            BinaryOperator::Gt => HavingOp::Gt,
-            BinaryOperator::GtEq => HavingOp::Gte,
-            BinaryOperator::Lt => HavingOp::Lt,
-            BinaryOperator::LtEq => HavingOp::Lte,
-            _ => return None,
-        };
-        let left_op = expr_to_having_operand(left)?;
-        let right_op = expr_to_having_operand(right)?;
-        Some(HavingCond::Predicate(HavingPredicate {
-            left: left_op,
-            op: op_enum,
-            right: right_op,
-        }))
-    } else {
-        None
-    }
-}
-
-fn expr_to_having_operand(expr: &Expr) -> Option<HavingOperand> {
-    match expr {
-        Expr::Identifier(ident) => Some(HavingOperand::ColumnOrAlias(ident.value.clone())),
-        Expr::CompoundIdentifier(parts) => {
-            parts.last().map(|p| HavingOperand::ColumnOrAlias(p.value.clone()))
-        }
-        Expr::Value(_v) => Some(HavingOperand::Literal(expr_to_value(expr))),
-        Expr::Function(func) => {
-            let (agg, arg) = parse_aggregate_func(func)?;
-            Some(HavingOperand::Aggregate(agg, arg))
-        }
-        _ => None,
+        GroupByExpr::All => Err(DatacaveError::NotSupported("GROUP BY ALL".into())),
+        GroupByExpr::Expressions(exprs) => exprs
+            .iter()
+            .map(|expr| {
+                let expr = ScalarExpr::plan(expr)?;
+                if expr.has_aggregate() {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::GROUPING_ERROR,
+                        "aggregate functions are not allowed in GROUP BY",
+                    ));
+                }
+                Ok(expr)
+            })
+            .collect(),
     }
 }
 
-fn plan_order_by(order_by: &[OrderByExpr]) -> Option<Vec<OrderBySpec>> {
+fn plan_order_by(order_by: &[OrderByExpr]) -> Result<Vec<OrderBySpec>, DatacaveError> {
     let mut out = Vec::new();
     for oe in order_by {
         let spec_kind = match &oe.expr {
@@ -493,20 +1010,21 @@ fn plan_order_by(order_by: &[OrderByExpr]) -> Option<Vec<OrderBySpec>> {
             Expr::CompoundIdentifier(parts) => {
                 OrderBySpecKind::ColumnOrAlias(parts.iter().map(|p| p.value.clone()).collect::<Vec<_>>().join("."))
             }
-            Expr::Value(Value::Number(n, _)) => {
-                let v: i64 = n.parse().ok()?;
-                if v >= 1 {
-                    OrderBySpecKind::Position(v as usize)
-                } else {
-                    return None;
+            Expr::Value(Value::Number(n, _)) => match n.parse::<usize>() {
+                Ok(v) if v >= 1 => OrderBySpecKind::Position(v),
+                _ => {
+                    return Err(DatacaveError::sqlstate(
+                        sqlstate::INVALID_COLUMN_REFERENCE,
+                        format!("ORDER BY position {n} is not in select list"),
+                    ))
                 }
-            }
-            _ => return None,
+            },
+            expr => OrderBySpecKind::Expr(ScalarExpr::plan(expr)?),
         };
         let asc = oe.asc.unwrap_or(true);
         out.push(OrderBySpec { spec: spec_kind, asc });
     }
-    Some(out)
+    Ok(out)
 }
 
 fn plan_limit(limit: Option<&Expr>) -> Option<Option<u64>> {
@@ -521,6 +1039,42 @@ fn plan_limit(limit: Option<&Expr>) -> Option<Option<u64>> {
     }
 }
 
+/// A single locking clause; its `OF` list can only name the one table locked.
+fn plan_lock(locks: &[LockClause]) -> Option<Option<RowLock>> {
+    match locks {
+        [] => Some(None),
+        [clause] => Some(Some(RowLock {
+            mode: match clause.lock_type {
+                LockType::Share => LockMode::Share,
+                LockType::Update => LockMode::Update,
+            },
+            wait: match clause.nonblock {
+                None => LockWait::Block,
+                Some(NonBlock::Nowait) => LockWait::NoWait,
+                Some(NonBlock::SkipLocked) => LockWait::SkipLocked,
+            },
+        })),
+        _ => None,
+    }
+}
+
+/// The version [`crate::parse_sql`] attached to the first table for an `AS OF` clause: a
+/// timestamp string or a version number.
+fn plan_as_of(relation: &TableFactor) -> Option<Option<AsOf>> {
+    match relation {
+        TableFactor::Table { version: None, .. } => Some(None),
+        TableFactor::Table {
+            version: Some(TableVersion::ForSystemTimeAsOf(expr)),
+            ..
+        } => match expr {
+            Expr::Value(Value::SingleQuotedString(ts)) => Some(Some(AsOf::Timestamp(ts.clone()))),
+            Expr::Value(Value::Number(n, _)) => n.parse().ok().map(|v| Some(AsOf::Version(v))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn plan_offset(offset: Option<&sqlparser::ast::Offset>) -> Option<Option<u64>> {
     let off = match offset {
         None => return Some(None),
@@ -586,9 +1140,9 @@ fn extract_equality_columns(
     {
         let (l_col, l_tbl) = expr_to_column_and_table(left)?;
         let (r_col, r_tbl) = expr_to_column_and_table(right)?;
-        let right_tbl_simple = right_table.split('.').last().unwrap_or(right_table);
+        let right_tbl_simple = right_table.split('.').next_back().unwrap_or(right_table);
         let left_in_tables = |t: &str| {
-            left_tables.iter().any(|lt| lt.split('.').last().unwrap_or(lt) == t)
+            left_tables.iter().any(|lt| lt.split('.').next_back().unwrap_or(lt) == t)
         };
         let right_in_right = r_tbl == right_tbl_simple || r_tbl.is_empty();
         let left_in_left = l_tbl.is_empty() || left_in_tables(&l_tbl);
@@ -653,7 +1207,7 @@ fn first_from_table(from: &FromTable) -> Option<&TableWithJoins> {
     }
 }
 
-fn expr_to_value(expr: &Expr) -> DataValue {
+pub(crate) fn expr_to_value(expr: &Expr) -> DataValue {
     match expr {
         Expr::Value(Value::Number(n, _)) => n
             .parse::<i64>()
@@ -662,6 +1216,14 @@ fn expr_to_value(expr: &Expr) -> DataValue {
         Expr::Value(Value::SingleQuotedString(s)) => DataValue::String(s.clone()),
         Expr::Value(Value::Boolean(b)) => DataValue::Bool(*b),
         Expr::Value(Value::Null) => DataValue::Null,
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr_to_value(expr) {
+            DataValue::Int64(v) => DataValue::Int64(-v),
+            DataValue::Float64(v) => DataValue::Float64(-v),
+            _ => DataValue::Null,
+        },
         _ => DataValue::Null,
     }
 }
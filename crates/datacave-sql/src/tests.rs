@@ -1,12 +1,15 @@
 #[cfg(test)]
 mod tests {
     use crate::executor::SqlExecutor;
+    use crate::transaction::IsolationLevel;
     use crate::parser::parse_sql;
     use crate::planner::plan_statement;
     use datacave_core::catalog::Catalog;
     use datacave_core::mvcc::MvccManager;
     use datacave_core::types::DataValue;
     use datacave_lsm::engine::{LsmEngine, LsmOptions};
+    use datacave_lsm::memory::MemoryEngine;
+    use datacave_lsm::storage::StorageEngine;
     use std::sync::{Arc, Mutex};
     use tempfile::TempDir;
 
@@ -22,6 +25,7 @@ mod tests {
                 memtable_max_bytes: 1024,
                 encryption_key: None,
                 wal_enabled: true,
+                tiering: None,
             })
             .await
             .expect("open"),
@@ -32,9 +36,15 @@ mod tests {
         (executor, dir)
     }
 
+    fn setup_memory_executor() -> SqlExecutor {
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let mvcc = Arc::new(MvccManager::new());
+        SqlExecutor::new(catalog, mvcc, Arc::new(MemoryEngine::new()))
+    }
+
     #[tokio::test]
     async fn create_insert_select_flow() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE users (id INT, name TEXT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -49,7 +59,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_aggregates() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE t (id INT, val INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -78,7 +88,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_inner_join() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE orders (id INT, user_id INT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -101,7 +111,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_left_join() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE orders (id INT, user_id INT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -141,7 +151,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_three_table_inner_join() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE orders (id INT, user_id INT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -187,7 +197,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_three_table_left_join_chain() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE orders (id INT, user_id INT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -230,7 +240,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_group_by_aggregates() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE sales (region TEXT, product TEXT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -278,7 +288,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_group_by_having() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE sales (region TEXT, product TEXT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -353,7 +363,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_join_group_by_having() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE orders (id INT, user_id INT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -379,7 +389,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_order_by_limit() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE t (id INT, val INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -412,7 +422,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_order_by_limit_with_aggregates() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE sales (region TEXT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -435,7 +445,7 @@ mod tests {
 
     #[tokio::test]
     async fn select_join_order_by_limit() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE orders (id INT, user_id INT, amount INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -458,9 +468,152 @@ mod tests {
         assert_eq!(result.rows[1].values[2], DataValue::Int64(150));
     }
 
+    #[tokio::test]
+    async fn expressions_in_projections_where_group_by_having_and_order_by() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE items (id INT, name TEXT, price INT, qty INT);",
+            "INSERT INTO items VALUES (1, 'Apple', 3, 10), (2, 'pear', 5, NULL), (3, 'Plum', 7, 2), (4, 'kiwi', 2, 8);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+
+        let result = executor
+            .execute(
+                &run("SELECT id, price * qty AS total, -price, upper(name), length(name), coalesce(qty, 0) + 1, \
+                      CASE WHEN qty > 5 THEN 'many' WHEN qty IS NULL THEN 'unknown' ELSE 'few' END AS stock \
+                      FROM items ORDER BY id;"),
+                None,
+            )
+            .await
+            .expect("select");
+        let names: Vec<&str> = result.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["id", "total", "?column?", "upper", "length", "?column?", "stock"]);
+        assert_eq!(
+            result.rows[0].values,
+            vec![
+                DataValue::Int64(1),
+                DataValue::Int64(30),
+                DataValue::Int64(-3),
+                DataValue::String("APPLE".into()),
+                DataValue::Int64(5),
+                DataValue::Int64(11),
+                DataValue::String("many".into()),
+            ]
+        );
+        assert_eq!(result.rows[1].values[1], DataValue::Null);
+        assert_eq!(result.rows[1].values[5], DataValue::Int64(1));
+        assert_eq!(result.rows[1].values[6], DataValue::String("unknown".into()));
+        assert_eq!(result.rows[2].values[6], DataValue::String("few".into()));
+
+        let ids = |result: &datacave_core::types::SqlResult| -> Vec<DataValue> {
+            result.rows.iter().map(|r| r.values[0].clone()).collect()
+        };
+        for (sql, expected) in [
+            ("SELECT id FROM items WHERE price * 2 > qty ORDER BY id;", vec![3]),
+            ("SELECT id FROM items WHERE qty > price AND NOT (name = 'kiwi') ORDER BY id;", vec![1]),
+            ("SELECT id FROM items WHERE (price < 3 OR qty IS NULL) AND id <> 1 ORDER BY id;", vec![2, 4]),
+            ("SELECT id FROM items WHERE lower(name) = 'apple';", vec![1]),
+            ("SELECT id, price FROM items ORDER BY price * -1 LIMIT 2;", vec![3, 2]),
+        ] {
+            let result = executor.execute(&run(sql), None).await.expect(sql);
+            let expected: Vec<DataValue> = expected.into_iter().map(DataValue::Int64).collect();
+            assert_eq!(ids(&result), expected, "{sql}");
+        }
+
+        let result = executor
+            .execute(
+                &run("SELECT price % 2 AS parity, COUNT(*), SUM(price) FROM items GROUP BY price % 2 ORDER BY parity;"),
+                None,
+            )
+            .await
+            .expect("group by expression");
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(
+            result.rows[0].values,
+            vec![DataValue::Int64(0), DataValue::Int64(1), DataValue::Float64(2.0)]
+        );
+        assert_eq!(
+            result.rows[1].values,
+            vec![DataValue::Int64(1), DataValue::Int64(3), DataValue::Float64(15.0)]
+        );
+
+        // HAVING may compute over aggregates and output aliases.
+        let result = executor
+            .execute(
+                &run("SELECT price % 2 AS parity, SUM(price) AS total FROM items GROUP BY price % 2 \
+                      HAVING total / COUNT(*) > 3;"),
+                None,
+            )
+            .await
+            .expect("having expression");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values, vec![DataValue::Int64(1), DataValue::Float64(15.0)]);
+
+        for (sql, code) in [
+            ("SELECT name + 1 FROM items;", "42883"),
+            ("SELECT price / 0 FROM items;", "22012"),
+            ("SELECT id FROM items WHERE price;", "42804"),
+            ("SELECT price + missing FROM items;", "42703"),
+            ("SELECT nosuch(id) FROM items;", "42883"),
+            ("SELECT upper(name), COUNT(*) FROM items;", "42803"),
+            ("SELECT id FROM items WHERE COUNT(*) > 1;", "42803"),
+            ("SELECT id FROM items ORDER BY 0;", "42P10"),
+        ] {
+            let err = executor.execute(&run(sql), None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn string_literals_compare_as_the_other_operand_type() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE items (id INT PRIMARY KEY, price FLOAT, ok BOOLEAN, name TEXT);",
+            "CREATE INDEX items_price ON items (price);",
+            "INSERT INTO items VALUES (1, 2.5, true, '1'), (2, 5, false, 'two'), (3, 7.5, true, '3');",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let ids = |result: &datacave_core::types::SqlResult| -> Vec<DataValue> {
+            result.rows.iter().map(|r| r.values[0].clone()).collect()
+        };
+        // Each condition is asked both as a plain comparison and through an expression.
+        for (sql, expected) in [
+            ("SELECT id FROM items WHERE id = '2';", vec![2]),
+            ("SELECT id FROM items WHERE id + 0 = '2';", vec![2]),
+            ("SELECT id FROM items WHERE '2' < id ORDER BY id;", vec![3]),
+            ("SELECT id FROM items WHERE '2' < id * 1 ORDER BY id;", vec![3]),
+            ("SELECT id FROM items WHERE price >= '5' ORDER BY id;", vec![2, 3]),
+            ("SELECT id FROM items WHERE price * 1 >= '5' ORDER BY id;", vec![2, 3]),
+            ("SELECT id FROM items WHERE price = 5;", vec![2]),
+            ("SELECT id FROM items WHERE price + 0 = 5;", vec![2]),
+            ("SELECT id FROM items WHERE ok = 'f';", vec![2]),
+            ("SELECT id FROM items WHERE ok = 'f' AND id > 0;", vec![2]),
+            ("SELECT id FROM items WHERE name = '1';", vec![1]),
+            ("SELECT id FROM items WHERE upper(name) = '3';", vec![3]),
+        ] {
+            let result = executor.execute(&run(sql), None).await.expect(sql);
+            let expected: Vec<DataValue> = expected.into_iter().map(DataValue::Int64).collect();
+            assert_eq!(ids(&result), expected, "{sql}");
+        }
+        for (sql, code) in [
+            ("SELECT id FROM items WHERE id = 'two';", "22P02"),
+            ("SELECT id FROM items WHERE id + 0 = 'two';", "22P02"),
+            ("SELECT id FROM items WHERE ok = 'maybe';", "22P02"),
+            ("SELECT id FROM items WHERE NOT ok = 'maybe';", "22P02"),
+            ("SELECT id FROM items WHERE name = 1;", "42883"),
+        ] {
+            let err = executor.execute(&run(sql), None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+        }
+    }
+
     #[tokio::test]
     async fn select_where_filtering() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE t (id INT, val INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -531,7 +684,7 @@ mod tests {
 
     #[tokio::test]
     async fn update_with_where() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE t (id INT, val INT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -566,7 +719,7 @@ mod tests {
 
     #[tokio::test]
     async fn delete_with_where() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         let stmts = parse_sql("CREATE TABLE t (id INT, name TEXT);").expect("parse");
         executor.execute(&stmts[0], Some("t1")).await.expect("create");
@@ -610,7 +763,7 @@ mod tests {
 
     #[tokio::test]
     async fn begin_commit_rollback_plan_and_execute() {
-        let (executor, _) = setup_executor().await;
+        let (executor, _dir) = setup_executor().await;
 
         for sql in ["BEGIN", "COMMIT", "ROLLBACK", "START TRANSACTION"] {
             let stmts = parse_sql(sql).expect("parse");
@@ -621,4 +774,1570 @@ mod tests {
             assert!(result.is_ok(), "{} should execute without error", sql);
         }
     }
+
+    #[tokio::test]
+    async fn executor_runs_on_memory_engine() {
+        let executor = setup_memory_executor();
+
+        let stmts = parse_sql("CREATE TABLE users (id INT, name TEXT);").expect("parse");
+        executor.execute(&stmts[0], Some("t1")).await.expect("create");
+
+        let stmts = parse_sql("INSERT INTO users (id, name) VALUES (1, 'alice'), (2, 'bob');")
+            .expect("parse");
+        executor.execute(&stmts[0], Some("t1")).await.expect("insert");
+
+        let stmts = parse_sql("DELETE FROM users WHERE id = 1;").expect("parse");
+        executor.execute(&stmts[0], Some("t1")).await.expect("delete");
+
+        let stmts = parse_sql("SELECT name FROM users;").expect("parse");
+        let result = executor.execute(&stmts[0], Some("t1")).await.expect("select");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values[0], DataValue::String("bob".into()));
+    }
+
+    #[test]
+    fn row_encoding_is_versioned_and_reads_legacy_rows() {
+        use crate::executor::{decode_row, encode_row, row_format_version, ROW_FORMAT_VERSION};
+        use datacave_core::types::DataRow;
+
+        let row = DataRow {
+            values: vec![DataValue::Int64(1), DataValue::String("alice".into())],
+        };
+        let encoded = encode_row(&row, 7).expect("encode");
+        assert!(encoded.starts_with(b"DCR"));
+        assert_eq!(row_format_version(&encoded), ROW_FORMAT_VERSION);
+        let (schema_version, decoded) = decode_row(&encoded).expect("decode");
+        assert_eq!((schema_version, decoded.values), (7, row.values.clone()));
+
+        let legacy = bincode::serialize(&row).expect("legacy");
+        assert_eq!(row_format_version(&legacy), 0);
+        let (schema_version, decoded) = decode_row(&legacy).expect("decode legacy");
+        assert_eq!((schema_version, decoded.values), (0, row.values.clone()));
+
+        let mut v1 = b"DCR\x01".to_vec();
+        v1.extend_from_slice(&legacy);
+        assert_eq!(decode_row(&v1).expect("decode v1").1.values, row.values);
+
+        let mut newer = encoded.clone();
+        newer[3] = ROW_FORMAT_VERSION + 1;
+        assert!(decode_row(&newer).is_err());
+    }
+
+    #[tokio::test]
+    async fn catalog_is_persisted_in_storage() {
+        let storage = Arc::new(MemoryEngine::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            Arc::new(MvccManager::new()),
+            storage.clone(),
+        );
+        let stmts = parse_sql("CREATE TABLE users (id INT, name TEXT);").expect("parse");
+        executor.execute(&stmts[0], None).await.expect("create");
+        assert!(executor.execute(&stmts[0], None).await.is_err());
+
+        // Of two concurrent CREATE TABLEs for one name, exactly one is stored.
+        let narrow = parse_sql("CREATE TABLE pets (id INT);").expect("parse").remove(0);
+        let wide = parse_sql("CREATE TABLE pets (id INT, name TEXT, age INT);").expect("parse").remove(0);
+        let (first, second) = tokio::join!(executor.execute(&narrow, None), executor.execute(&wide, None));
+        assert!(first.is_ok() != second.is_ok(), "{first:?} {second:?}");
+        let width = if first.is_ok() { 1 } else { 3 };
+
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let reopened = SqlExecutor::new(catalog.clone(), Arc::new(MvccManager::new()), storage);
+        assert_eq!(reopened.load_catalog().await.expect("load"), 2);
+        let schema = catalog.lock().unwrap().get_table("users").cloned().expect("schema");
+        assert_eq!(schema.columns.len(), 2);
+        let schema = catalog.lock().unwrap().get_table("pets").cloned().expect("schema");
+        assert_eq!(schema.columns.len(), width);
+    }
+
+    #[tokio::test]
+    async fn rows_and_row_ids_survive_executor_restart() {
+        let storage = Arc::new(MemoryEngine::new());
+        let mvcc = Arc::new(MvccManager::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            mvcc.clone(),
+            storage.clone(),
+        );
+        for sql in [
+            "CREATE TABLE users (id INT, name TEXT);",
+            "INSERT INTO users VALUES (1, 'alice'), (2, 'bob'), (3, 'carol');",
+            "DELETE FROM users WHERE id = 3;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect("execute");
+        }
+
+        let reopened = SqlExecutor::new(Arc::new(Mutex::new(Catalog::new())), mvcc, storage.clone());
+        reopened.load_catalog().await.expect("load");
+        let stmts = parse_sql("SELECT name FROM users;").expect("parse");
+        let result = reopened.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(result.rows.len(), 2);
+
+        let stmts = parse_sql("INSERT INTO users VALUES (4, 'dave');").expect("parse");
+        reopened.execute(&stmts[0], None).await.expect("insert");
+        let rows = storage
+            .scan(&crate::executor::table_key_prefix("users", None), u64::MAX)
+            .await
+            .expect("scan");
+        let ids: Vec<u64> = rows
+            .iter()
+            .map(|(key, _)| crate::executor::decode_row_id(key).expect("row id"))
+            .collect();
+        assert_eq!(ids, vec![0, 1, 3], "deleted row id 2 must not be reused");
+    }
+
+    #[tokio::test]
+    async fn table_scan_ignores_tenant_sharing_the_table_prefix() {
+        let executor = setup_memory_executor();
+        for (sql, tenant) in [
+            ("CREATE TABLE t (id INT);", None),
+            ("CREATE TABLE x (id INT);", None),
+            ("INSERT INTO t VALUES (1);", None),
+            ("INSERT INTO x VALUES (2);", Some("t")),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], tenant).await.expect("execute");
+        }
+        let stmts = parse_sql("SELECT id FROM t;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values[0], DataValue::Int64(1));
+    }
+
+    #[tokio::test]
+    async fn create_table_types_are_checked_at_plan_time() {
+        use datacave_core::error::DatacaveError;
+        use datacave_core::types::DataType;
+
+        let executor = setup_memory_executor();
+        let stmts = parse_sql("CREATE TABLE bad (id INT, born GEOMETRY);").expect("parse");
+        let err = executor.execute(&stmts[0], None).await.expect_err("unsupported type");
+        assert!(matches!(err, DatacaveError::NotSupported(_)), "{err}");
+
+        let stmts = parse_sql(
+            "CREATE TABLE items (id BIGINT, name VARCHAR(20), price NUMERIC(10, 2));",
+        )
+        .expect("parse");
+        executor.execute(&stmts[0], None).await.expect("create");
+        let stmts = parse_sql("INSERT INTO items VALUES (1, 'pen', 2.5);").expect("parse");
+        executor.execute(&stmts[0], None).await.expect("insert");
+        let stmts = parse_sql("SELECT id, name, price FROM items;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                DataType::BigInt,
+                DataType::Varchar(Some(20)),
+                DataType::Numeric {
+                    precision: Some(10),
+                    scale: Some(2)
+                }
+            ]
+        );
+        let stmts = parse_sql("SELECT COUNT(*), AVG(price) FROM items;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("aggregate");
+        let types: Vec<DataType> = result.columns.iter().map(|c| c.data_type).collect();
+        assert_eq!(types, vec![DataType::BigInt, DataType::Double]);
+
+        // Foreign keys are accepted without being enforced.
+        let stmts = parse_sql(
+            "CREATE TABLE orders (id INT PRIMARY KEY, item BIGINT REFERENCES items (id), placed DATE, \
+             FOREIGN KEY (item) REFERENCES items (id));",
+        )
+        .expect("parse");
+        executor.execute(&stmts[0], None).await.expect("create with foreign keys");
+    }
+
+    #[tokio::test]
+    async fn insert_and_update_values_are_coerced_to_column_types() {
+        let executor = setup_memory_executor();
+        for sql in [
+            "CREATE TABLE items (id INT, price DOUBLE PRECISION, name VARCHAR(4), active BOOLEAN);",
+            "INSERT INTO items VALUES (1, 2, 'pen', 'yes');",
+            "INSERT INTO items (name, id) VALUES ('ink', '2');",
+            "UPDATE items SET price = 3 WHERE id = 2;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+        let stmts = parse_sql("SELECT id, price, name, active FROM items ORDER BY id;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(
+            result.rows[0].values,
+            vec![
+                DataValue::Int64(1),
+                DataValue::Float64(2.0),
+                DataValue::String("pen".into()),
+                DataValue::Bool(true)
+            ]
+        );
+        assert_eq!(
+            result.rows[1].values,
+            vec![
+                DataValue::Int64(2),
+                DataValue::Float64(3.0),
+                DataValue::String("ink".into()),
+                DataValue::Null
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn insert_and_update_reject_values_that_do_not_fit() {
+        let executor = setup_memory_executor();
+        let stmts = parse_sql("CREATE TABLE items (id INT, name VARCHAR(3));").expect("parse");
+        executor.execute(&stmts[0], None).await.expect("create");
+        for (sql, code, message) in [
+            (
+                "INSERT INTO items VALUES ('one', 'a');",
+                "22P02",
+                "invalid input syntax for type integer: \"one\"",
+            ),
+            (
+                "INSERT INTO items VALUES (true, 'a');",
+                "42804",
+                "column \"id\" is of type integer but expression is of type boolean",
+            ),
+            ("INSERT INTO items VALUES (3000000000, 'a');", "22003", "integer out of range"),
+            (
+                "INSERT INTO items VALUES (1, 'abcd');",
+                "22001",
+                "value too long for type character varying(3)",
+            ),
+            ("INSERT INTO items VALUES (1);", "42601", "INSERT has more target columns than expressions"),
+            ("INSERT INTO items (id) VALUES (1, 'a');", "42601", "INSERT has more expressions than target columns"),
+            (
+                "INSERT INTO items (id, price) VALUES (1, 2);",
+                "42703",
+                "column \"price\" of relation \"items\" does not exist",
+            ),
+            ("UPDATE items SET id = 'x';", "22P02", "invalid input syntax for type integer: \"x\""),
+            (
+                "UPDATE items SET price = 1;",
+                "42703",
+                "column \"price\" of relation \"items\" does not exist",
+            ),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            let err = executor.execute(&stmts[0], None).await.expect_err(sql);
+            assert_eq!((err.code(), err.to_string().as_str()), (code, message), "{sql}");
+        }
+        let stmts = parse_sql("SELECT * FROM items;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        assert!(result.rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_set_expressions_read_the_current_row() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE accounts (id INT PRIMARY KEY, owner TEXT, balance INT, active BOOLEAN);",
+            "INSERT INTO accounts VALUES (1, 'ann', 100, true), (2, 'bob', 5, true), (3, 'cy', NULL, false);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let result = executor
+            .execute(&run("UPDATE accounts SET balance = balance - 10 WHERE active = true;"), None)
+            .await
+            .expect("update");
+        assert_eq!(result.rows_affected, 2);
+        // Every SET expression sees the row as it was before the statement.
+        for sql in [
+            "UPDATE accounts SET balance = id * 1000 + coalesce(balance, 0), owner = upper(owner) || ':' || id, \
+             active = balance IS NULL WHERE id = 3;",
+            "UPDATE accounts SET balance = CASE WHEN balance < 0 THEN 0 ELSE balance END;",
+            // Qualified targets name the table, or its alias.
+            "UPDATE accounts SET accounts.balance = balance + 1 WHERE id = 1;",
+            "UPDATE accounts AS a SET a.balance = balance - 1 WHERE id = 1;",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let expected = vec![
+            vec![DataValue::String("ann".into()), DataValue::Int64(90), DataValue::Bool(true)],
+            vec![DataValue::String("bob".into()), DataValue::Int64(0), DataValue::Bool(true)],
+            vec![DataValue::String("CY:3".into()), DataValue::Int64(3000), DataValue::Bool(true)],
+        ];
+        let select = run("SELECT owner, balance, active FROM accounts ORDER BY id;");
+        let result = executor.execute(&select, None).await.expect("select");
+        let rows: Vec<Vec<DataValue>> = result.rows.into_iter().map(|r| r.values).collect();
+        assert_eq!(rows, expected);
+
+        // A statement failing on any row writes nothing.
+        for (sql, code) in [
+            ("UPDATE accounts SET balance = owner || 'x';", "22P02"),
+            ("UPDATE accounts SET balance = owner + 1;", "42883"),
+            ("UPDATE accounts SET balance = balance * 1000000;", "22003"),
+            ("UPDATE accounts SET balance = balance / (id - 2);", "22012"),
+            ("UPDATE accounts SET active = balance;", "42804"),
+            ("UPDATE accounts SET balance = missing + 1;", "42703"),
+            ("UPDATE accounts SET balance = SUM(balance);", "42803"),
+            ("UPDATE accounts SET other.balance = 1;", "42703"),
+            ("UPDATE accounts AS a SET accounts.balance = 1;", "42703"),
+        ] {
+            let err = executor.execute(&run(sql), None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+        }
+        let result = executor.execute(&select, None).await.expect("select");
+        let rows: Vec<Vec<DataValue>> = result.rows.into_iter().map(|r| r.values).collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[tokio::test]
+    async fn alter_table_evolves_the_schema_without_rewriting_rows() {
+        let storage = Arc::new(MemoryEngine::new());
+        let mvcc = Arc::new(MvccManager::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            mvcc.clone(),
+            storage.clone(),
+        );
+        for sql in [
+            "CREATE TABLE users (id INT, name TEXT, age INT);",
+            "INSERT INTO users VALUES (1, 'alice', 30);",
+            "ALTER TABLE users ADD COLUMN active BOOLEAN DEFAULT true;",
+            "ALTER TABLE users DROP COLUMN age;",
+            "ALTER TABLE users RENAME COLUMN name TO full_name;",
+            "INSERT INTO users (id, full_name, active) VALUES (2, 'bob', false);",
+            "ALTER TABLE users RENAME TO members;",
+            "CREATE TABLE users (id INT);",
+            "INSERT INTO users VALUES (3);",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+        let first_row = storage
+            .get(&crate::executor::encode_row_key("users", 0, None), u64::MAX)
+            .await
+            .expect("get")
+            .expect("row");
+        assert_eq!(crate::executor::decode_row(&first_row).expect("decode").0, 0);
+
+        let reopened = SqlExecutor::new(Arc::new(Mutex::new(Catalog::new())), mvcc, storage);
+        reopened.load_catalog().await.expect("load");
+        let stmts = parse_sql("SELECT * FROM members ORDER BY id;").expect("parse");
+        let result = reopened.execute(&stmts[0], None).await.expect("select");
+        let names: Vec<&str> = result.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["id", "full_name", "active"]);
+        let rows: Vec<Vec<DataValue>> = result.rows.into_iter().map(|r| r.values).collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![DataValue::Int64(1), DataValue::String("alice".into()), DataValue::Bool(true)],
+                vec![DataValue::Int64(2), DataValue::String("bob".into()), DataValue::Bool(false)],
+            ]
+        );
+        let stmts = parse_sql("SELECT id FROM users;").expect("parse");
+        let result = reopened.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values, vec![DataValue::Int64(3)]);
+
+        for (sql, code) in [
+            ("ALTER TABLE members ADD COLUMN id INT;", "42701"),
+            ("ALTER TABLE members DROP COLUMN age;", "42703"),
+            ("ALTER TABLE members RENAME TO users;", "42P07"),
+            ("ALTER TABLE missing ADD COLUMN x INT;", "42P01"),
+            ("ALTER TABLE members ADD COLUMN n INT DEFAULT 'x';", "22P02"),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            let err = reopened.execute(&stmts[0], None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+        }
+        for sql in [
+            "ALTER TABLE IF EXISTS missing ADD COLUMN x INT;",
+            "ALTER TABLE members ADD COLUMN IF NOT EXISTS id INT;",
+            "ALTER TABLE members DROP COLUMN IF EXISTS age;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            reopened.execute(&stmts[0], None).await.expect(sql);
+        }
+    }
+
+    #[test]
+    fn schemas_written_before_alter_table_still_decode() {
+        use datacave_core::types::{Column, DataType};
+
+        #[derive(serde::Serialize)]
+        struct SchemaV1 {
+            name: String,
+            columns: Vec<Column>,
+            primary_key: Option<String>,
+        }
+        let mut bytes = b"DCR\x01".to_vec();
+        bincode::serialize_into(
+            &mut bytes,
+            &SchemaV1 {
+                name: "users".into(),
+                columns: vec![Column {
+                    name: "id".into(),
+                    data_type: DataType::Int,
+                }],
+                primary_key: None,
+            },
+        )
+        .expect("encode");
+        let schema = crate::catalog::decode_schema(&bytes).expect("decode");
+        assert_eq!((schema.storage_name.as_str(), schema.version, schema.row_width), ("users", 0, 1));
+    }
+
+    #[tokio::test]
+    async fn constraints_are_enforced_on_insert_and_update() {
+        let storage = Arc::new(MemoryEngine::new());
+        let mvcc = Arc::new(MvccManager::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            mvcc.clone(),
+            storage.clone(),
+        );
+        for sql in [
+            "CREATE TABLE items (id INT PRIMARY KEY, sku TEXT UNIQUE, qty INT NOT NULL DEFAULT 0, \
+             price INT CHECK (price > 0), CONSTRAINT qty_cap CHECK (qty <= 100));",
+            "INSERT INTO items (id, sku, price) VALUES (1, 'a', 5);",
+            "INSERT INTO items (id, sku, qty) VALUES (2, NULL, 3), (3, NULL, 4);",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+
+        let reopened = SqlExecutor::new(Arc::new(Mutex::new(Catalog::new())), mvcc, storage);
+        reopened.load_catalog().await.expect("load");
+        let stmts = parse_sql("SELECT qty FROM items WHERE id = 1;").expect("parse");
+        let result = reopened.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(result.rows[0].values, vec![DataValue::Int64(0)]);
+
+        for (sql, code, message) in [
+            (
+                "INSERT INTO items (id, sku, qty) VALUES (4, 'b', NULL);",
+                "23502",
+                "null value in column \"qty\" of relation \"items\" violates not-null constraint",
+            ),
+            (
+                "INSERT INTO items (sku) VALUES ('c');",
+                "23502",
+                "null value in column \"id\" of relation \"items\" violates not-null constraint",
+            ),
+            (
+                "INSERT INTO items (id, sku) VALUES (1, 'd');",
+                "23505",
+                "duplicate key value violates unique constraint \"items_pkey\"",
+            ),
+            (
+                "INSERT INTO items (id, sku) VALUES (5, 'e'), (6, 'e');",
+                "23505",
+                "duplicate key value violates unique constraint \"items_sku_key\"",
+            ),
+            (
+                "INSERT INTO items (id, price) VALUES (7, -1);",
+                "23514",
+                "new row for relation \"items\" violates check constraint \"items_price_check\"",
+            ),
+            (
+                "UPDATE items SET qty = 101 WHERE id = 2;",
+                "23514",
+                "new row for relation \"items\" violates check constraint \"qty_cap\"",
+            ),
+            (
+                "UPDATE items SET sku = 'a';",
+                "23505",
+                "duplicate key value violates unique constraint \"items_sku_key\"",
+            ),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            let err = reopened.execute(&stmts[0], None).await.expect_err(sql);
+            assert_eq!((err.code(), err.to_string().as_str()), (code, message), "{sql}");
+        }
+        // Failed statements wrote nothing, and a NULL price passes the CHECK.
+        let stmts = parse_sql("SELECT id, sku FROM items ORDER BY id;").expect("parse");
+        let result = reopened.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(result.rows.len(), 3);
+        assert_eq!(result.rows[1].values[1], DataValue::Null);
+
+        for (sql, code) in [
+            ("CREATE TABLE bad (a INT PRIMARY KEY, b INT PRIMARY KEY);", "42P16"),
+            ("CREATE TABLE bad (a INT, UNIQUE (b));", "42703"),
+            ("UPDATE items SET id = 1 WHERE id = 2;", "23505"),
+            ("DROP INDEX items_sku_key;", "2BP01"),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            let err = reopened.execute(&stmts[0], None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+        }
+
+        // UNIQUE is enforced through index entries, so concurrent duplicates conflict.
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        let first = reopened.begin(IsolationLevel::Snapshot);
+        let second = reopened.begin(IsolationLevel::Snapshot);
+        for (txn, id) in [(first, 8), (second, 9)] {
+            let sql = format!("INSERT INTO items (id, sku) VALUES ({id}, 'z');");
+            reopened.execute_in(&run(&sql), None, txn).await.expect(&sql);
+        }
+        reopened.commit(first).await.expect("first commit");
+        let err = reopened.commit(second).await.expect_err("duplicate sku");
+        assert_eq!(err.code(), "40001");
+    }
+
+    #[tokio::test]
+    async fn alter_table_keeps_constraints_consistent() {
+        let executor = setup_memory_executor();
+        for sql in [
+            "CREATE TABLE t (a INT CHECK (a > 0), b INT);",
+            "ALTER TABLE t ADD COLUMN c INT NOT NULL DEFAULT 1;",
+            "INSERT INTO t (a, b) VALUES (1, 2);",
+            "ALTER TABLE t RENAME COLUMN a TO x;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+        for (sql, code) in [
+            ("ALTER TABLE t ADD COLUMN d INT NOT NULL;", "23502"),
+            ("INSERT INTO t (x, b) VALUES (0, 2);", "23514"),
+            ("INSERT INTO t (x, c) VALUES (1, NULL);", "23502"),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            let err = executor.execute(&stmts[0], None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+        }
+        // NOT NULL without a default is fine while no tenant has rows.
+        for sql in [
+            "CREATE TABLE empty (a INT);",
+            "ALTER TABLE empty ADD COLUMN b INT NOT NULL;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+        // Concurrent ALTERs of one table each keep the other's change.
+        let add_c = parse_sql("ALTER TABLE empty ADD COLUMN c INT NOT NULL;").expect("parse");
+        let add_d = parse_sql("ALTER TABLE empty ADD COLUMN d INT NOT NULL;").expect("parse");
+        let (c, d) = tokio::join!(executor.execute(&add_c[0], None), executor.execute(&add_d[0], None));
+        c.expect("add c");
+        d.expect("add d");
+        let stmts = parse_sql("SELECT * FROM empty;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        assert_eq!(result.columns.len(), 4);
+
+        // Rows held only by a tenant count too.
+        for (sql, tenant) in [
+            ("CREATE TABLE tenanted (a INT PRIMARY KEY);", None),
+            ("INSERT INTO tenanted VALUES (1);", Some("acme")),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], tenant).await.expect(sql);
+        }
+        let stmts = parse_sql("ALTER TABLE tenanted ADD COLUMN b INT NOT NULL;").expect("parse");
+        let err = executor.execute(&stmts[0], None).await.expect_err("tenant rows");
+        assert_eq!(err.code(), "23502");
+    }
+
+    #[tokio::test]
+    async fn rows_are_stored_under_their_primary_key() {
+        let storage = Arc::new(MemoryEngine::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            Arc::new(MvccManager::new()),
+            storage.clone(),
+        );
+        for (sql, tenant) in [
+            ("CREATE TABLE events (region TEXT, seq INT, note TEXT, PRIMARY KEY (region, seq));", None),
+            ("INSERT INTO events VALUES ('eu', 10, 'b'), ('us', -5, 'c'), ('eu', 2, 'a');", None),
+            ("CREATE TABLE x (id INT PRIMARY KEY);", None),
+            ("INSERT INTO x VALUES (1);", Some("events")),
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], tenant).await.expect(sql);
+        }
+        let key = crate::executor::encode_primary_row_key(
+            "events",
+            &[DataValue::String("eu".into()), DataValue::Int64(2)],
+            None,
+        );
+        assert!(storage.get(&key, u64::MAX).await.expect("get").is_some());
+
+        let stmts = parse_sql("SELECT note FROM events;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        let notes: Vec<DataValue> = result.rows.into_iter().map(|r| r.values[0].clone()).collect();
+        let expected: Vec<DataValue> = ["a", "b", "c"].iter().map(|n| DataValue::String((*n).into())).collect();
+        assert_eq!(notes, expected);
+
+        for sql in [
+            "INSERT INTO events VALUES ('eu', 2, 'dup');",
+            "INSERT INTO events VALUES ('ap', 1, 'x'), ('ap', 1, 'y');",
+            "UPDATE events SET seq = 10 WHERE region = 'eu' AND seq = 2;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            let err = executor.execute(&stmts[0], None).await.expect_err(sql);
+            assert_eq!(err.code(), "23505", "{sql}: {err}");
+            assert!(err.to_string().contains("events_pkey"), "{sql}: {err}");
+        }
+        // Changing the key moves the row.
+        for sql in [
+            "UPDATE events SET seq = 3 WHERE region = 'eu' AND seq = 10;",
+            "UPDATE events SET region = 'ap' WHERE region = 'us';",
+            "DELETE FROM events WHERE region = 'eu' AND seq = 3;",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+        let stmts = parse_sql("SELECT region, seq FROM events;").expect("parse");
+        let result = executor.execute(&stmts[0], None).await.expect("select");
+        let rows: Vec<Vec<DataValue>> = result.rows.into_iter().map(|r| r.values).collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![DataValue::String("ap".into()), DataValue::Int64(-5)],
+                vec![DataValue::String("eu".into()), DataValue::Int64(2)],
+            ]
+        );
+        let stmts = parse_sql("ALTER TABLE events DROP COLUMN seq;").expect("parse");
+        let err = executor.execute(&stmts[0], None).await.expect_err("drop key column");
+        assert_eq!(err.code(), "0A000");
+    }
+
+    #[tokio::test]
+    async fn key_predicates_plan_point_lookups_and_range_scans() {
+        use crate::executor::{plan_key_access, KeyAccess};
+        use crate::planner::{build_plan, Plan};
+        use std::ops::Bound;
+
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let executor = SqlExecutor::new(
+            catalog.clone(),
+            Arc::new(MvccManager::new()),
+            Arc::new(MemoryEngine::new()),
+        );
+        for sql in [
+            "CREATE TABLE t (a INT, b INT, c TEXT, PRIMARY KEY (a, b));",
+            "INSERT INTO t VALUES (1, 1, 'x'), (1, 2, 'y'), (1, 3, 'z'), (2, 1, 'w'), (-1, 7, 'v');",
+        ] {
+            let stmts = parse_sql(sql).expect("parse");
+            executor.execute(&stmts[0], None).await.expect(sql);
+        }
+        let schema = catalog.lock().unwrap().get_table("t").cloned().expect("schema");
+        let int = DataValue::Int64;
+        for (filter, access) in [
+            ("a = 1 AND b = 2", KeyAccess::Point(vec![int(1), int(2)])),
+            ("2 = b AND t.a = 1 AND c = 'y'", KeyAccess::Point(vec![int(1), int(2)])),
+            (
+                "a = 1 AND b > 1 AND b <= 3 AND b < 9",
+                KeyAccess::Range {
+                    prefix: vec![int(1)],
+                    lower: Bound::Excluded(int(1)),
+                    upper: Bound::Included(int(3)),
+                },
+            ),
+            (
+                "a >= 0",
+                KeyAccess::Range {
+                    prefix: vec![],
+                    lower: Bound::Included(int(0)),
+                    upper: Bound::Unbounded,
+                },
+            ),
+            ("b = 1", KeyAccess::Full),
+            ("a = 1 OR a = 2", KeyAccess::Full),
+            ("a > 0.5", KeyAccess::Full),
+        ] {
+            let stmts = parse_sql(&format!("SELECT c FROM t WHERE {filter};")).expect("parse");
+            let Plan::Select(plan) = build_plan(&stmts[0]).expect("plan") else {
+                panic!("not a select");
+            };
+            assert_eq!(plan_key_access(&schema, plan.where_clause.as_ref()), access, "{filter}");
+        }
+
+        for (filter, expected) in [
+            ("a = 1 AND b = 2", vec!["y"]),
+            ("a = 1 AND b > 1", vec!["y", "z"]),
+            ("a = 1 AND b < 3", vec!["x", "y"]),
+            ("a > 1", vec!["w"]),
+            ("a < 1", vec!["v"]),
+            ("a >= 1 AND a <= 1 AND b >= 3", vec!["z"]),
+            ("a > 0.5", vec!["x", "y", "z", "w"]),
+            ("a = 3", vec![]),
+        ] {
+            let stmts = parse_sql(&format!("SELECT c FROM t WHERE {filter};")).expect("parse");
+            let result = executor.execute(&stmts[0], None).await.expect(filter);
+            let got: Vec<DataValue> = result.rows.into_iter().map(|r| r.values[0].clone()).collect();
+            let expected: Vec<DataValue> =
+                expected.iter().map(|c| DataValue::String((*c).into())).collect();
+            assert_eq!(got, expected, "{filter}");
+        }
+    }
+
+    #[tokio::test]
+    async fn transactions_read_their_snapshot_and_first_committer_wins() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE accounts (id INT PRIMARY KEY, balance INT);",
+            "INSERT INTO accounts VALUES (1, 100), (2, 0);",
+            "CREATE TABLE log (msg TEXT);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let balance = |rows: &[datacave_core::types::DataRow]| rows[0].values[0].clone();
+        let select = run("SELECT balance FROM accounts WHERE id = 1;");
+
+        let first = executor.begin(IsolationLevel::Snapshot);
+        let second = executor.begin(IsolationLevel::Snapshot);
+        executor
+            .execute_in(&run("UPDATE accounts SET balance = 90 WHERE id = 1;"), None, first)
+            .await
+            .expect("first update");
+        let outside = executor.execute(&select, None).await.expect("select");
+        assert_eq!(balance(&outside.rows), DataValue::Int64(100), "writes stay private");
+        let inside = executor.execute_in(&select, None, first).await.expect("select");
+        assert_eq!(balance(&inside.rows), DataValue::Int64(90), "its own write");
+
+        // The second waits for the first's row lock, then finds the row changed.
+        let update = run("UPDATE accounts SET balance = 80 WHERE id = 1;");
+        let (lost, committed) = tokio::join!(
+            executor.execute_in(&update, None, second),
+            executor.commit(first),
+        );
+        assert_eq!(committed.expect("first commit").len(), 1);
+        assert_eq!(lost.expect_err("lost update").code(), "40001");
+        let outside = executor.execute(&select, None).await.expect("select");
+        assert_eq!(balance(&outside.rows), DataValue::Int64(90));
+        executor.rollback(second);
+        assert_eq!(executor.commit(second).await.unwrap_err().code(), "25000");
+
+        // Writes to different rows, and inserts into a table keyed by row id, do not conflict.
+        let first = executor.begin(IsolationLevel::Snapshot);
+        let second = executor.begin(IsolationLevel::Snapshot);
+        for (txn, sql) in [
+            (first, "UPDATE accounts SET balance = 1 WHERE id = 1;"),
+            (second, "UPDATE accounts SET balance = 2 WHERE id = 2;"),
+            (first, "INSERT INTO log VALUES ('first');"),
+            (second, "INSERT INTO log VALUES ('second');"),
+        ] {
+            executor.execute_in(&run(sql), None, txn).await.expect(sql);
+        }
+        executor.commit(second).await.expect("second commit");
+        executor.commit(first).await.expect("first commit");
+        let log = executor
+            .execute(&run("SELECT msg FROM log;"), None)
+            .await
+            .expect("select log");
+        assert_eq!(log.rows.len(), 2);
+
+        let rolled_back = executor.begin(IsolationLevel::Snapshot);
+        executor
+            .execute_in(&run("DELETE FROM accounts;"), None, rolled_back)
+            .await
+            .expect("delete");
+        executor.rollback(rolled_back);
+        let all = executor
+            .execute(&run("SELECT id FROM accounts;"), None)
+            .await
+            .expect("select");
+        assert_eq!(all.rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn serializable_transactions_abort_write_skew() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE doctors (name TEXT PRIMARY KEY, on_call BOOLEAN);",
+            "INSERT INTO doctors VALUES ('alice', true), ('bob', true);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let count = run("SELECT COUNT(*) FROM doctors WHERE on_call = true;");
+        // Each doctor goes off call after checking someone else still is.
+        let (executor, count) = (&executor, &count);
+        let skew = |isolation| async move {
+            let alice = executor.begin(IsolationLevel::Snapshot);
+            let bob = executor.begin(IsolationLevel::Snapshot);
+            for txn in [alice, bob] {
+                let set = format!("SET TRANSACTION ISOLATION LEVEL {isolation};");
+                executor.execute_in(&run(&set), None, txn).await.expect("set");
+                let on_call = executor.execute_in(count, None, txn).await.expect("count");
+                assert_eq!(on_call.rows[0].values[0], DataValue::Int64(2));
+            }
+            for (txn, name) in [(alice, "alice"), (bob, "bob")] {
+                let sql = format!("UPDATE doctors SET on_call = false WHERE name = '{name}';");
+                executor.execute_in(&run(&sql), None, txn).await.expect("update");
+            }
+            executor.commit(alice).await.expect("first commit");
+            executor.commit(bob).await
+        };
+
+        skew("REPEATABLE READ").await.expect("snapshot isolation allows write skew");
+        executor
+            .execute(&run("UPDATE doctors SET on_call = true;"), None)
+            .await
+            .expect("reset");
+        let err = skew("SERIALIZABLE").await.expect_err("write skew");
+        assert_eq!(err.code(), "40001");
+        assert!(err.to_string().contains("read/write dependencies"));
+        let on_call = executor.execute(count, None).await.expect("count");
+        assert_eq!(on_call.rows[0].values[0], DataValue::Int64(1));
+
+        let txn = executor.begin(IsolationLevel::Snapshot);
+        executor.execute_in(count, None, txn).await.expect("count");
+        let err = executor
+            .execute_in(&run("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;"), None, txn)
+            .await
+            .expect_err("set after a query");
+        assert_eq!(err.code(), "25001");
+    }
+
+    #[tokio::test]
+    async fn transactions_read_their_own_writes() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE items (id INT PRIMARY KEY, qty INT);",
+            "INSERT INTO items VALUES (1, 10);",
+            "CREATE TABLE notes (body TEXT);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let txn = executor.begin(IsolationLevel::Snapshot);
+        for sql in [
+            "INSERT INTO items VALUES (2, 20), (3, 30);",
+            "UPDATE items SET qty = 21 WHERE id = 2;",
+            "DELETE FROM items WHERE id = 1;",
+            "INSERT INTO notes VALUES ('a'), ('b');",
+            "DELETE FROM notes WHERE body = 'a';",
+        ] {
+            executor.execute_in(&run(sql), None, txn).await.expect(sql);
+        }
+        let err = executor
+            .execute_in(&run("INSERT INTO items VALUES (3, 31);"), None, txn)
+            .await
+            .expect_err("duplicate of an uncommitted row");
+        assert_eq!(err.code(), "23505");
+
+        let select = run("SELECT id, qty FROM items;");
+        let inside = executor.execute_in(&select, None, txn).await.expect("select");
+        let rows: Vec<_> = inside.rows.iter().map(|row| row.values.clone()).collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![DataValue::Int64(2), DataValue::Int64(21)],
+                vec![DataValue::Int64(3), DataValue::Int64(30)],
+            ]
+        );
+        let point = run("SELECT qty FROM items WHERE id = 2;");
+        let inside = executor.execute_in(&point, None, txn).await.expect("point lookup");
+        assert_eq!(inside.rows[0].values[0], DataValue::Int64(21));
+        let notes = run("SELECT body FROM notes;");
+        let inside = executor.execute_in(&notes, None, txn).await.expect("select notes");
+        assert_eq!(inside.rows.len(), 1);
+
+        let outside = executor.execute(&select, None).await.expect("select");
+        assert_eq!(outside.rows.len(), 1, "uncommitted writes stay private");
+        executor.commit(txn).await.expect("commit");
+        let outside = executor.execute(&select, None).await.expect("select");
+        assert_eq!(outside.rows.len(), 2);
+        let outside = executor.execute(&notes, None).await.expect("select notes");
+        assert_eq!(outside.rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn savepoints_undo_the_writes_made_after_them() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        executor
+            .execute(&run("CREATE TABLE steps (id INT PRIMARY KEY, name TEXT);"), None)
+            .await
+            .expect("create");
+        let txn = executor.begin(IsolationLevel::Snapshot);
+        for sql in [
+            "INSERT INTO steps VALUES (1, 'one');",
+            "SAVEPOINT a;",
+            "INSERT INTO steps VALUES (2, 'two');",
+            "SAVEPOINT b;",
+            "UPDATE steps SET name = 'uno' WHERE id = 1;",
+            "INSERT INTO steps VALUES (3, 'three');",
+            "ROLLBACK TO SAVEPOINT b;",
+            "INSERT INTO steps VALUES (3, 'drei');",
+            "SAVEPOINT c;",
+            "RELEASE SAVEPOINT b;",
+        ] {
+            executor.execute_in(&run(sql), None, txn).await.expect(sql);
+        }
+        for sql in ["ROLLBACK TO SAVEPOINT c;", "RELEASE b;"] {
+            let err = executor
+                .execute_in(&run(sql), None, txn)
+                .await
+                .expect_err("released with b");
+            assert_eq!(err.code(), "3B001");
+        }
+        // Savepoints outlive ROLLBACK TO, so a transaction can return to one repeatedly.
+        for _ in 0..2 {
+            executor
+                .execute_in(&run("INSERT INTO steps VALUES (4, 'four');"), None, txn)
+                .await
+                .expect("insert");
+            executor
+                .execute_in(&run("ROLLBACK TO a;"), None, txn)
+                .await
+                .expect("rollback to a");
+        }
+        let select = run("SELECT id, name FROM steps;");
+        let inside = executor.execute_in(&select, None, txn).await.expect("select");
+        assert_eq!(inside.rows.len(), 1);
+        executor
+            .execute_in(&run("INSERT INTO steps VALUES (2, 'zwei');"), None, txn)
+            .await
+            .expect("insert after rollback to a");
+        executor.commit(txn).await.expect("commit");
+        let rows: Vec<_> = executor
+            .execute(&select, None)
+            .await
+            .expect("select")
+            .rows
+            .into_iter()
+            .map(|row| row.values)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![DataValue::Int64(1), DataValue::String("one".into())],
+                vec![DataValue::Int64(2), DataValue::String("zwei".into())],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn locking_reads_skip_wait_for_or_deadlock_on_locked_rows() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE jobs (id INT PRIMARY KEY, state TEXT);",
+            "INSERT INTO jobs VALUES (1, 'pending'), (2, 'pending'), (3, 'pending');",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let next_job = run(
+            "SELECT id FROM jobs WHERE state = 'pending' ORDER BY id LIMIT 1 FOR UPDATE SKIP LOCKED;",
+        );
+        let first = executor.begin(IsolationLevel::Snapshot);
+        let second = executor.begin(IsolationLevel::Snapshot);
+        let third = executor.begin(IsolationLevel::Snapshot);
+        for (txn, id) in [(first, 1), (second, 2)] {
+            let job = executor.execute_in(&next_job, None, txn).await.expect("claim");
+            assert_eq!(job.rows.len(), 1);
+            assert_eq!(job.rows[0].values[0], DataValue::Int64(id));
+        }
+        let err = executor
+            .execute_in(&run("SELECT * FROM jobs WHERE id = 1 FOR UPDATE NOWAIT;"), None, third)
+            .await
+            .expect_err("locked by the first");
+        assert_eq!(err.code(), "55P03");
+        let share = run("SELECT * FROM jobs WHERE id = 3 FOR SHARE;");
+        for txn in [second, third] {
+            executor.execute_in(&share, None, txn).await.expect("shared lock");
+        }
+        let err = executor
+            .execute_in(&run("SELECT * FROM jobs WHERE id = 3 FOR UPDATE NOWAIT;"), None, first)
+            .await
+            .expect_err("share-locked by the others");
+        assert_eq!(err.code(), "55P03");
+        executor.rollback(third);
+
+        // The first waits for the second's row while the second asks for the first's.
+        let (lock_second, lock_first) = (
+            run("SELECT * FROM jobs WHERE id = 2 FOR UPDATE;"),
+            run("SELECT * FROM jobs WHERE id = 1 FOR UPDATE;"),
+        );
+        let (waited, deadlocked) = tokio::join!(
+            executor.execute_in(&lock_second, None, first),
+            async {
+                let result = executor.execute_in(&lock_first, None, second).await;
+                executor.rollback(second);
+                result
+            }
+        );
+        assert_eq!(deadlocked.expect_err("deadlock").code(), "40P01");
+        assert_eq!(waited.expect("granted once the second ends").rows.len(), 1);
+        executor
+            .execute_in(&run("UPDATE jobs SET state = 'done' WHERE id = 1;"), None, first)
+            .await
+            .expect("update");
+        executor.commit(first).await.expect("commit");
+
+        // A row changed since the snapshot cannot be locked as it was read.
+        let stale = executor.begin(IsolationLevel::Snapshot);
+        executor.execute_in(&next_job, None, stale).await.expect("claim");
+        executor
+            .execute(&run("UPDATE jobs SET state = 'done' WHERE id = 3;"), None)
+            .await
+            .expect("update");
+        let err = executor
+            .execute_in(&run("SELECT * FROM jobs WHERE id = 3 FOR UPDATE;"), None, stale)
+            .await
+            .expect_err("changed after the snapshot");
+        assert_eq!(err.code(), "40001");
+        executor.rollback(stale);
+
+        // UPDATE and DELETE hold the rows they write locked, as FOR UPDATE does.
+        let share = run("SELECT * FROM jobs WHERE id = 3 FOR SHARE NOWAIT;");
+        for write in [
+            "UPDATE jobs SET state = 'pending' WHERE id = 3;",
+            "DELETE FROM jobs WHERE id = 3;",
+        ] {
+            let writer = executor.begin(IsolationLevel::Snapshot);
+            let reader = executor.begin(IsolationLevel::Snapshot);
+            executor.execute_in(&run(write), None, writer).await.expect(write);
+            let err = executor
+                .execute_in(&share, None, reader)
+                .await
+                .expect_err("locked by the writer");
+            assert_eq!(err.code(), "55P03");
+            executor.rollback(writer);
+            executor.execute_in(&share, None, reader).await.expect("released");
+            executor.rollback(reader);
+        }
+        let err = executor
+            .execute(&run("SELECT COUNT(*) FROM jobs FOR UPDATE;"), None)
+            .await
+            .expect_err("aggregate");
+        assert_eq!(err.code(), "0A000");
+    }
+
+    /// `millis` since the Unix epoch as a UTC timestamp literal.
+    fn timestamp_literal(millis: u64) -> String {
+        let days = (millis / 86_400_000) as i64 + 719_468;
+        let (era, day_of_era) = (days.div_euclid(146_097), days.rem_euclid(146_097));
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        let of_day = millis % 86_400_000;
+        format!(
+            "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}.{:03}",
+            of_day / 3_600_000,
+            of_day / 60_000 % 60,
+            of_day / 1000 % 60,
+            of_day % 1000
+        )
+    }
+
+    fn unix_millis() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("clock")
+            .as_millis() as u64
+    }
+
+    #[test]
+    fn parses_timestamps_in_utc_or_with_an_offset() {
+        use crate::history::parse_timestamp;
+        assert_eq!(parse_timestamp("1970-01-01").expect("date"), 0);
+        assert_eq!(
+            parse_timestamp("2024-02-29 12:30:05.25").expect("timestamp"),
+            1_709_209_805_250
+        );
+        assert_eq!(
+            parse_timestamp("2024-02-29T14:30:05.250+02:00").expect("offset"),
+            1_709_209_805_250
+        );
+        assert_eq!(parse_timestamp("2024-02-29T12:30:05.250Z").expect("zulu"), 1_709_209_805_250);
+        assert_eq!(timestamp_literal(1_709_209_805_250), "2024-02-29 12:30:05.250");
+        for bad in ["2023-02-29", "2024-13-01", "2024-01-01 25:00", "yesterday", "1969-12-31"] {
+            assert_eq!(parse_timestamp(bad).expect_err(bad).code(), "22007");
+        }
+    }
+
+    #[tokio::test]
+    async fn as_of_queries_read_tables_as_they_were() {
+        let mvcc = Arc::new(MvccManager::new());
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let executor = SqlExecutor::new(catalog, mvcc.clone(), Arc::new(MemoryEngine::new()));
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE accounts (id INT PRIMARY KEY, balance INT);",
+            "INSERT INTO accounts VALUES (1, 100), (2, 50);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let before = mvcc.snapshot().version;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let then = timestamp_literal(unix_millis());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        for sql in [
+            "UPDATE accounts SET balance = 0 WHERE id = 1;",
+            "DELETE FROM accounts WHERE id = 2;",
+            "INSERT INTO accounts VALUES (3, 10);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let rows = |result: datacave_core::types::SqlResult| -> Vec<Vec<DataValue>> {
+            result.rows.into_iter().map(|row| row.values).collect()
+        };
+        let original = vec![
+            vec![DataValue::Int64(1), DataValue::Int64(100)],
+            vec![DataValue::Int64(2), DataValue::Int64(50)],
+        ];
+        for sql in [
+            format!("SELECT id, balance FROM accounts AS OF SYSTEM TIME '{then}' ORDER BY id;"),
+            format!("SELECT id, balance FROM accounts AS OF VERSION {before} WHERE id < 3;"),
+        ] {
+            let result = executor.execute(&run(&sql), None).await.expect(&sql);
+            assert_eq!(rows(result), original, "{sql}");
+        }
+        let current = executor
+            .execute(&run("SELECT id, balance FROM accounts ORDER BY id;"), None)
+            .await
+            .expect("select");
+        assert_eq!(
+            rows(current),
+            vec![
+                vec![DataValue::Int64(1), DataValue::Int64(0)],
+                vec![DataValue::Int64(3), DataValue::Int64(10)],
+            ]
+        );
+
+        // Inside a transaction, a time-travel query does not see the staged writes either.
+        let txn = executor.begin(IsolationLevel::Snapshot);
+        executor
+            .execute_in(&run("DELETE FROM accounts WHERE id = 1;"), None, txn)
+            .await
+            .expect("delete");
+        let as_of = run(&format!("SELECT COUNT(*) FROM accounts AS OF VERSION {before};"));
+        let result = executor.execute_in(&as_of, None, txn).await.expect("as of");
+        assert_eq!(result.rows[0].values[0], DataValue::Int64(2));
+        executor.rollback(txn);
+
+        for (sql, code) in [
+            ("SELECT * FROM accounts AS OF SYSTEM TIME '9999-01-01';", "22023"),
+            ("SELECT * FROM accounts AS OF SYSTEM TIME '2000-01-01';", "22023"),
+            ("SELECT * FROM accounts AS OF SYSTEM TIME 'noon';", "22007"),
+            ("SELECT * FROM accounts AS OF VERSION 1000000;", "22023"),
+            ("SELECT * FROM accounts AS OF VERSION 1 FOR UPDATE;", "0A000"),
+        ] {
+            let err = executor.execute(&run(sql), None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}");
+        }
+        assert!(parse_sql("DELETE FROM accounts AS OF VERSION 1;").is_err());
+    }
+
+    #[tokio::test]
+    async fn as_of_rejects_versions_before_a_schema_change() {
+        let mvcc = Arc::new(MvccManager::new());
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let executor = SqlExecutor::new(catalog, mvcc.clone(), Arc::new(MemoryEngine::new()));
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        executor
+            .execute(&run("CREATE TABLE notes (id INT PRIMARY KEY);"), None)
+            .await
+            .expect("create");
+        let created = mvcc.snapshot().version;
+        executor
+            .execute(&run("INSERT INTO notes VALUES (1);"), None)
+            .await
+            .expect("insert");
+        let before = mvcc.snapshot().version;
+        for sql in [
+            "ALTER TABLE notes ADD COLUMN body TEXT;",
+            "INSERT INTO notes VALUES (2, 'two');",
+            "CREATE TABLE later (id INT);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let altered = mvcc.snapshot().version;
+
+        for (sql, code) in [
+            (format!("SELECT * FROM notes AS OF VERSION {before};"), "0A000"),
+            (format!("SELECT * FROM notes AS OF VERSION {created};"), "0A000"),
+            (format!("SELECT * FROM later AS OF VERSION {before};"), "42P01"),
+            (
+                format!("SELECT * FROM later JOIN notes ON later.id = notes.id AS OF VERSION {before};"),
+                "42P01",
+            ),
+        ] {
+            let err = executor.execute(&run(&sql), None).await.expect_err(&sql);
+            assert_eq!(err.code(), code, "{sql}");
+        }
+        let result = executor
+            .execute(&run(&format!("SELECT * FROM notes AS OF VERSION {altered};")), None)
+            .await
+            .expect("after the change");
+        assert_eq!(result.columns.len(), 2);
+        assert_eq!(result.rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn history_outside_the_retention_window_is_collected() {
+        let mvcc = Arc::new(MvccManager::new());
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let executor = SqlExecutor::new(catalog, mvcc.clone(), Arc::new(MemoryEngine::new()))
+            .with_history_retention(std::time::Duration::ZERO);
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE events (id INT PRIMARY KEY);",
+            "INSERT INTO events VALUES (1);",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let old = mvcc.snapshot().version;
+        executor
+            .execute(&run("INSERT INTO events VALUES (2);"), None)
+            .await
+            .expect("insert");
+        let open = executor.begin(IsolationLevel::Snapshot);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let horizon = executor.collect_history().await.expect("collect");
+        assert!(horizon > old, "horizon {horizon} passed version {old}");
+        executor.rollback(open);
+
+        let err = executor
+            .execute(&run(&format!("SELECT * FROM events AS OF VERSION {old};")), None)
+            .await
+            .expect_err("collected");
+        assert_eq!(err.code(), "22023");
+        let latest = executor
+            .execute(&run(&format!("SELECT * FROM events AS OF VERSION {horizon};")), None)
+            .await
+            .expect("at the horizon");
+        assert_eq!(latest.rows.len(), 2);
+        assert!(executor.collect_history().await.expect("collect") >= horizon);
+    }
+
+    #[tokio::test]
+    async fn indexes_are_backfilled_maintained_and_used_for_lookups() {
+        use crate::catalog::INDEX_KEY_PREFIX;
+        use crate::executor::{plan_index_access, KeyAccess};
+        use crate::planner::{build_plan, Plan};
+        use std::ops::Bound;
+
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let storage = Arc::new(MemoryEngine::new());
+        let executor =
+            SqlExecutor::new(catalog.clone(), Arc::new(MvccManager::new()), storage.clone());
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for (sql, tenant) in [
+            ("CREATE TABLE people (id INT PRIMARY KEY, city TEXT, age INT);", None),
+            ("INSERT INTO people VALUES (1, 'oslo', 30), (2, 'rome', 41), (3, 'oslo', 25);", Some("t1")),
+            ("INSERT INTO people VALUES (1, 'lima', 50);", Some("t2")),
+            ("CREATE INDEX people_city_age ON people (city, age);", None),
+            ("INSERT INTO people VALUES (4, 'oslo', 35);", Some("t1")),
+            ("UPDATE people SET city = 'oslo' WHERE id = 2;", Some("t1")),
+            ("DELETE FROM people WHERE id = 3;", Some("t1")),
+        ] {
+            executor.execute(&run(sql), tenant).await.expect(sql);
+        }
+
+        let schema = catalog.lock().unwrap().get_table("people").cloned().expect("schema");
+        let where_clause = |sql: &str| {
+            let Plan::Select(plan) = build_plan(&run(sql)).expect("plan") else {
+                panic!("not a select");
+            };
+            plan.where_clause
+        };
+        let cond = where_clause("SELECT id FROM people WHERE age > 30 AND city = 'oslo';");
+        let (index, access) =
+            plan_index_access(&schema, cond.as_ref(), u64::MAX).expect("index access");
+        assert_eq!(index.name, "people_city_age");
+        assert_eq!(
+            access,
+            KeyAccess::Range {
+                prefix: vec![DataValue::String("oslo".into())],
+                lower: Bound::Excluded(DataValue::Int64(30)),
+                upper: Bound::Unbounded,
+            }
+        );
+        let cond = where_clause("SELECT id FROM people WHERE age = 30;");
+        assert!(plan_index_access(&schema, cond.as_ref(), u64::MAX).is_none());
+        let cond = where_clause("SELECT id FROM people WHERE city = 'oslo';");
+        assert!(plan_index_access(&schema, cond.as_ref(), 0).is_none(), "before the backfill");
+
+        for (sql, tenant, expected) in [
+            ("SELECT id FROM people WHERE city = 'oslo' ORDER BY id;", "t1", vec![1, 2, 4]),
+            ("SELECT id FROM people WHERE city = 'oslo' AND age > 30 ORDER BY id;", "t1", vec![2, 4]),
+            ("SELECT id FROM people WHERE city = 'oslo' AND age = 25;", "t1", vec![]),
+            ("SELECT id FROM people WHERE city = 'rome';", "t1", vec![]),
+            ("SELECT id FROM people WHERE city = 'lima';", "t1", vec![]),
+            ("SELECT id FROM people WHERE city = 'lima';", "t2", vec![1]),
+        ] {
+            let result = executor.execute(&run(sql), Some(tenant)).await.expect(sql);
+            let ids: Vec<DataValue> = result.rows.into_iter().map(|r| r.values[0].clone()).collect();
+            let expected: Vec<DataValue> = expected.into_iter().map(DataValue::Int64).collect();
+            assert_eq!(ids, expected, "{sql} as {tenant}");
+        }
+        // One entry per live row: the update and delete removed the entries they replaced.
+        let entries = storage.scan(INDEX_KEY_PREFIX, u64::MAX).await.expect("scan");
+        assert_eq!(entries.len(), 4);
+
+        executor
+            .execute(&run("ALTER TABLE people DROP COLUMN age;"), None)
+            .await
+            .expect("drop indexed column");
+        assert!(catalog.lock().unwrap().get_table("people").expect("schema").indexes.is_empty());
+        let entries = storage.scan(INDEX_KEY_PREFIX, u64::MAX).await.expect("scan");
+        assert!(entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_table_removes_schema_rows_indexes_and_sequences_for_every_tenant() {
+        use crate::catalog::is_system_key;
+
+        let catalog = Arc::new(Mutex::new(Catalog::new()));
+        let storage = Arc::new(MemoryEngine::new());
+        let executor =
+            SqlExecutor::new(catalog.clone(), Arc::new(MvccManager::new()), storage.clone());
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for (sql, tenant) in [
+            ("CREATE TABLE items (id INT, name TEXT);", None),
+            ("CREATE TABLE keyed (id INT PRIMARY KEY);", None),
+            ("CREATE TABLE kept (id INT);", None),
+            ("CREATE INDEX items_name ON items (name);", None),
+            ("INSERT INTO items VALUES (1, 'a'), (2, 'b');", Some("t1")),
+            ("INSERT INTO items VALUES (3, 'c');", Some("t2")),
+            ("INSERT INTO items VALUES (4, 'd');", None),
+            ("INSERT INTO keyed VALUES (1);", Some("t2")),
+            ("INSERT INTO kept VALUES (7);", Some("t1")),
+            ("DROP TABLE items, keyed CASCADE;", None),
+        ] {
+            executor.execute(&run(sql), tenant).await.expect(sql);
+        }
+        assert!(catalog.lock().unwrap().get_table("items").is_none());
+        let err = executor
+            .execute(&run("SELECT * FROM items;"), Some("t1"))
+            .await
+            .expect_err("dropped");
+        assert_eq!(err.code(), "42P01");
+        for sql in [
+            "INSERT INTO items VALUES (1);",
+            "UPDATE items SET id = 2;",
+            "DELETE FROM items;",
+            "SELECT * FROM kept JOIN items ON kept.id = items.id;",
+        ] {
+            let err = executor.execute(&run(sql), Some("t1")).await.expect_err(sql);
+            assert_eq!(err.code(), "42P01", "{sql}: {err}");
+        }
+        let entries = storage.scan(b"", u64::MAX).await.expect("scan");
+        let rows: Vec<&[u8]> = entries
+            .iter()
+            .map(|(key, _)| key.as_slice())
+            .filter(|key| !is_system_key(key))
+            .collect();
+        assert_eq!(rows.len(), 1, "only the row of kept survives");
+        assert!(entries.iter().all(|(key, _)| {
+            !key.windows(b"items|".len()).any(|w| w == b"items|")
+                && !key.windows(b"keyed|".len()).any(|w| w == b"keyed|")
+        }));
+
+        for sql in [
+            "DROP TABLE IF EXISTS items, missing;",
+            "CREATE TABLE items (id INT, name TEXT);",
+            "INSERT INTO items VALUES (5, 'e');",
+        ] {
+            executor.execute(&run(sql), Some("t1")).await.expect(sql);
+        }
+        let result = executor
+            .execute(&run("SELECT id FROM items;"), Some("t1"))
+            .await
+            .expect("select");
+        assert_eq!(result.rows.len(), 1, "the new table starts empty");
+        let err = executor
+            .execute(&run("DROP TABLE kept, missing;"), None)
+            .await
+            .expect_err("missing table");
+        assert_eq!(err.code(), "42P01");
+        assert!(catalog.lock().unwrap().get_table("kept").is_some());
+    }
+
+    #[tokio::test]
+    async fn truncate_empties_the_tenants_rows_and_restarts_row_ids() {
+        use crate::executor::{decode_row_id, table_key_prefix};
+
+        let storage = Arc::new(MemoryEngine::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            Arc::new(MvccManager::new()),
+            storage.clone(),
+        );
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for (sql, tenant) in [
+            ("CREATE TABLE items (id INT, name TEXT);", None),
+            ("CREATE INDEX items_name ON items (name);", None),
+            ("INSERT INTO items VALUES (1, 'a'), (2, 'b');", Some("t1")),
+            ("INSERT INTO items VALUES (3, 'a');", Some("t2")),
+            ("TRUNCATE items;", Some("t1")),
+            ("INSERT INTO items VALUES (4, 'a');", Some("t1")),
+        ] {
+            executor.execute(&run(sql), tenant).await.expect(sql);
+        }
+        for (tenant, expected) in [("t1", 4), ("t2", 3)] {
+            let result = executor
+                .execute(&run("SELECT id FROM items WHERE name = 'a';"), Some(tenant))
+                .await
+                .expect("select");
+            let ids: Vec<DataValue> = result.rows.into_iter().map(|r| r.values[0].clone()).collect();
+            assert_eq!(ids, vec![DataValue::Int64(expected)], "{tenant}");
+        }
+        let rows = storage
+            .scan(&table_key_prefix("items", Some("t1")), u64::MAX)
+            .await
+            .expect("scan");
+        assert_eq!(rows.len(), 1);
+        assert_eq!(decode_row_id(&rows[0].0).expect("row id"), 0, "row ids restart");
+
+        let err = executor
+            .execute(&run("TRUNCATE missing;"), Some("t1"))
+            .await
+            .expect_err("missing table");
+        assert_eq!(err.code(), "42P01");
+    }
+
+    #[tokio::test]
+    async fn truncate_waits_for_transactions_holding_row_ids() {
+        use crate::executor::{decode_row_id, table_key_prefix};
+
+        let storage = Arc::new(MemoryEngine::new());
+        let executor = SqlExecutor::new(
+            Arc::new(Mutex::new(Catalog::new())),
+            Arc::new(MvccManager::new()),
+            storage.clone(),
+        );
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in ["CREATE TABLE items (id INT);", "INSERT INTO items VALUES (1), (2);"] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let txn = executor.begin(IsolationLevel::Snapshot);
+        executor
+            .execute_in(&run("INSERT INTO items VALUES (3);"), None, txn)
+            .await
+            .expect("insert");
+        let truncate = run("TRUNCATE items;");
+        let (truncated, committed) =
+            tokio::join!(executor.execute(&truncate, None), executor.commit(txn));
+        truncated.expect("truncate");
+        committed.expect("commit");
+        executor
+            .execute(&run("INSERT INTO items VALUES (4), (5);"), None)
+            .await
+            .expect("insert");
+
+        // The row committed after the delete survives, and no new row reuses its id.
+        let result = executor
+            .execute(&run("SELECT id FROM items ORDER BY id;"), None)
+            .await
+            .expect("select");
+        let ids: Vec<DataValue> = result.rows.into_iter().map(|r| r.values[0].clone()).collect();
+        assert_eq!(ids, vec![DataValue::Int64(3), DataValue::Int64(4), DataValue::Int64(5)]);
+        let rows = storage
+            .scan(&table_key_prefix("items", None), u64::MAX)
+            .await
+            .expect("scan");
+        let row_ids: Vec<u64> = rows
+            .iter()
+            .map(|(key, _)| decode_row_id(key).expect("row id"))
+            .collect();
+        assert_eq!(row_ids, vec![2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn unique_indexes_reject_duplicate_keys() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE users (id INT PRIMARY KEY, email TEXT, org INT, handle TEXT);",
+            "INSERT INTO users VALUES (1, 'a@x', 1, 'ann'), (2, 'b@x', 1, 'bob'), (3, NULL, 1, 'cy'), (4, NULL, 2, 'ann');",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let err = executor
+            .execute(&run("CREATE UNIQUE INDEX users_handle ON users (handle);"), None)
+            .await
+            .expect_err("existing duplicates");
+        assert_eq!(err.code(), "23505");
+        for sql in [
+            // The failed index left nothing behind.
+            "CREATE INDEX users_handle ON users (handle);",
+            "DROP INDEX users_handle;",
+            "CREATE UNIQUE INDEX users_email ON users (email);",
+            "CREATE UNIQUE INDEX ON users (org, handle);",
+            "CREATE INDEX IF NOT EXISTS users_email ON users (id);",
+            "INSERT INTO users VALUES (5, NULL, 3, 'dan');",
+            "UPDATE users SET email = 'z@x' WHERE id = 1;",
+            "INSERT INTO users VALUES (6, 'a@x', 3, 'eve');",
+            "UPDATE users SET email = 'b@x' WHERE id = 2;",
+            "DROP INDEX IF EXISTS users_handle;",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        for (sql, code, constraint) in [
+            ("INSERT INTO users VALUES (7, 'a@x', 3, 'fay');", "23505", "users_email"),
+            ("INSERT INTO users VALUES (7, 'c@x', 3, 'fay'), (8, 'c@x', 3, 'gus');", "23505", "users_email"),
+            ("INSERT INTO users VALUES (7, 'c@x', 1, 'bob');", "23505", "users_org_handle_idx"),
+            ("UPDATE users SET email = 'a@x' WHERE id = 2;", "23505", "users_email"),
+            ("UPDATE users SET email = 'q@x' WHERE org = 1;", "23505", "users_email"),
+            ("CREATE INDEX users_email ON users (id);", "42P07", "users_email"),
+            ("DROP INDEX users_handle;", "42704", "users_handle"),
+        ] {
+            let err = executor.execute(&run(sql), None).await.expect_err(sql);
+            assert_eq!(err.code(), code, "{sql}: {err}");
+            assert!(err.to_string().contains(constraint), "{sql}: {err}");
+        }
+        for sql in [
+            "DROP INDEX users_email;",
+            "INSERT INTO users VALUES (7, 'a@x', 3, 'fay');",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+
+        // The backfill and its check reach every tenant's rows; values may repeat across
+        // tenants but not within one.
+        for (sql, tenant) in [
+            ("CREATE TABLE members (id INT PRIMARY KEY, email TEXT);", None),
+            ("INSERT INTO members VALUES (1, 'a@x');", None),
+            ("INSERT INTO members VALUES (1, 'a@x');", Some("acme")),
+            ("CREATE UNIQUE INDEX members_email ON members (email);", None),
+        ] {
+            executor.execute(&run(sql), tenant).await.expect(sql);
+        }
+        let lookup = run("SELECT id FROM members WHERE email = 'a@x';");
+        let result = executor.execute(&lookup, Some("acme")).await.expect("lookup");
+        assert_eq!(result.rows.len(), 1);
+        for (sql, tenant) in [
+            ("DROP INDEX members_email;", None),
+            ("INSERT INTO members VALUES (2, 'a@x');", Some("acme")),
+        ] {
+            executor.execute(&run(sql), tenant).await.expect(sql);
+        }
+        let err = executor
+            .execute(&run("CREATE UNIQUE INDEX members_email ON members (email);"), None)
+            .await
+            .expect_err("tenant duplicates");
+        assert_eq!(err.code(), "23505");
+    }
+
+    #[tokio::test]
+    async fn index_backfill_waits_for_transactions_that_wrote_without_it() {
+        let executor = setup_memory_executor();
+        let run = |sql: &str| parse_sql(sql).expect("parse").remove(0);
+        for sql in [
+            "CREATE TABLE items (id INT PRIMARY KEY, tag TEXT);",
+            "INSERT INTO items VALUES (1, 'a');",
+        ] {
+            executor.execute(&run(sql), None).await.expect(sql);
+        }
+        let txn = executor.begin(IsolationLevel::Snapshot);
+        executor
+            .execute_in(&run("INSERT INTO items VALUES (2, 'b');"), None, txn)
+            .await
+            .expect("insert");
+        let create = run("CREATE INDEX items_tag ON items (tag);");
+        let (created, committed) =
+            tokio::join!(executor.execute(&create, None), executor.commit(txn));
+        created.expect("create index");
+        committed.expect("commit");
+        let result = executor
+            .execute(&run("SELECT id FROM items WHERE tag = 'b';"), None)
+            .await
+            .expect("select");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values[0], DataValue::Int64(2));
+    }
 }
@@ -0,0 +1,192 @@
+use datacave_core::mvcc::{KeyRange, Snapshot};
+use datacave_lsm::storage::{BatchOp, WriteBatch};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+
+/// Handle of an open transaction on a [`crate::SqlExecutor`].
+pub type TxnId = u64;
+
+/// Guarantee a transaction runs under. READ COMMITTED and REPEATABLE READ both run as
+/// snapshot isolation, which is stronger than either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    #[default]
+    Snapshot,
+    /// Snapshot isolation plus tracking of the keys read, so commits that would make the
+    /// outcome differ from every serial order fail.
+    Serializable,
+}
+
+/// Snapshot and private write set of an open transaction. Statements stage their writes
+/// here and read the snapshot with the staged writes laid over it, so they see what earlier
+/// statements of the transaction wrote; nothing reaches storage until it commits.
+#[derive(Debug)]
+pub(crate) struct Transaction {
+    pub(crate) id: TxnId,
+    pub(crate) snapshot: Snapshot,
+    pub(crate) isolation: IsolationLevel,
+    /// Whether a statement has read or written rows; the isolation level is fixed from then.
+    pub(crate) started: bool,
+    /// Keys read, tracked under [`IsolationLevel::Serializable`] only.
+    reads: Vec<KeyRange>,
+    /// Staged value of each written key, `None` for a delete. Later writes to a key
+    /// replace earlier ones.
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// Written keys left out of conflict detection: row-id sequences, which every insert
+    /// into a table advances without the rows themselves conflicting.
+    unchecked: HashSet<Vec<u8>>,
+    /// Open savepoints, oldest first, each with the write set as it stood when it was set.
+    /// Names may repeat; the newest savepoint of a name is the one addressed.
+    savepoints: Vec<Savepoint>,
+}
+
+#[derive(Debug)]
+struct Savepoint {
+    name: String,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    unchecked: HashSet<Vec<u8>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(id: TxnId, snapshot: Snapshot, isolation: IsolationLevel) -> Self {
+        Self {
+            id,
+            snapshot,
+            isolation,
+            started: false,
+            reads: Vec::new(),
+            writes: BTreeMap::new(),
+            unchecked: HashSet::new(),
+            savepoints: Vec::new(),
+        }
+    }
+
+    pub(crate) fn savepoint(&mut self, name: String) {
+        self.savepoints.push(Savepoint {
+            name,
+            writes: self.writes.clone(),
+            unchecked: self.unchecked.clone(),
+        });
+    }
+
+    /// Forgets savepoint `name` and every savepoint set after it, keeping their writes.
+    /// Returns false when there is no such savepoint.
+    pub(crate) fn release_savepoint(&mut self, name: &str) -> bool {
+        match self.find_savepoint(name) {
+            Some(idx) => {
+                self.savepoints.truncate(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Discards the writes staged since savepoint `name` was set and the savepoints set
+    /// after it; `name` itself stays. Keys read since are still tracked, as the reads may
+    /// have shaped the writes kept. Returns false when there is no such savepoint.
+    pub(crate) fn rollback_to_savepoint(&mut self, name: &str) -> bool {
+        let Some(idx) = self.find_savepoint(name) else {
+            return false;
+        };
+        self.savepoints.truncate(idx + 1);
+        let savepoint = &self.savepoints[idx];
+        self.writes = savepoint.writes.clone();
+        self.unchecked = savepoint.unchecked.clone();
+        true
+    }
+
+    fn find_savepoint(&self, name: &str) -> Option<usize> {
+        self.savepoints.iter().rposition(|savepoint| savepoint.name == name)
+    }
+
+    pub(crate) fn record_read(&mut self, range: KeyRange) {
+        if self.isolation == IsolationLevel::Serializable {
+            self.reads.push(range);
+        }
+    }
+
+    pub(crate) fn reads(&self) -> &[KeyRange] {
+        &self.reads
+    }
+
+    pub(crate) fn take_reads(&mut self) -> Vec<KeyRange> {
+        std::mem::take(&mut self.reads)
+    }
+
+    /// Stages every operation of `batch`.
+    pub(crate) fn stage(&mut self, batch: WriteBatch) {
+        for op in batch.ops() {
+            match op {
+                BatchOp::Put { key, value } => {
+                    self.writes.insert(key.clone(), Some(value.clone()));
+                }
+                BatchOp::Delete { key } => {
+                    self.writes.insert(key.clone(), None);
+                }
+                BatchOp::DeleteRange { .. } => {
+                    unreachable!("statements stage single-key writes only")
+                }
+            }
+        }
+    }
+
+    pub(crate) fn stage_unchecked(&mut self, key: &[u8], value: &[u8]) {
+        self.writes.insert(key.to_vec(), Some(value.to_vec()));
+        self.unchecked.insert(key.to_vec());
+    }
+
+    /// The staged write of `key`: `Some(None)` when the transaction deleted it, `None` when
+    /// it has not written it.
+    pub(crate) fn staged(&self, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.writes.get(key).map(Option::as_deref)
+    }
+
+    /// `entries`, read from the snapshot in key order over `range`, with the staged writes
+    /// in `range` applied.
+    pub(crate) fn overlay(
+        &self,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        range: &KeyRange,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let bounds: (Bound<&[u8]>, Bound<&[u8]>) = (
+            Bound::Included(range.start.as_slice()),
+            range.end.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+        );
+        let mut staged = self.writes.range::<[u8], _>(bounds).peekable();
+        if staged.peek().is_none() {
+            return entries;
+        }
+        let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = entries.into_iter().collect();
+        for (key, value) in staged {
+            match value {
+                Some(value) => merged.insert(key.clone(), value.clone()),
+                None => merged.remove(key),
+            };
+        }
+        merged.into_iter().collect()
+    }
+
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Keys that conflict with writes committed by other transactions since the snapshot.
+    pub(crate) fn conflict_keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.writes
+            .keys()
+            .filter(|key| !self.unchecked.contains(*key))
+            .map(Vec::as_slice)
+    }
+
+    /// The write set as one batch, applied at the commit version.
+    pub(crate) fn to_batch(&self) -> WriteBatch {
+        let mut batch = WriteBatch::new();
+        for (key, value) in &self.writes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+        batch
+    }
+}